@@ -0,0 +1,177 @@
+use reqwest::{RequestBuilder, Response};
+use rsduck_types::{NDJSON_CONTENT_TYPE, QueryRequest, QueryResponse};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::time::Duration;
+
+use crate::rows::{Lines, QueryData, RowStream};
+use crate::{Error, Result};
+
+/// Header carrying the API key
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// Async client for an RSDuck server
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    max_retries: u32,
+    retry_backoff: Duration,
+}
+
+impl Client {
+    /// Create a client for the server at `base_url`, e.g. `http://localhost:3001`.
+    /// Requests turned away because the server is busy or rate limited are retried up to
+    /// 3 times, starting with a 100 ms backoff.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(100),
+        }
+    }
+
+    /// Send `key` as the `X-API-Key` header
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Retry retryable failures up to `max_retries` times, doubling `backoff` after each attempt.
+    /// A `Retry-After` header from the server takes precedence over the backoff.
+    pub fn with_retries(mut self, max_retries: u32, backoff: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Use a preconfigured HTTP client, e.g. one with timeouts or TLS settings
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Run a query and deserialize each row into `T` from an object keyed by column name.
+    /// `params` binds the statement's `?` or `$1` placeholders and must serialize to a JSON
+    /// array, e.g. a tuple such as `(42, "EU")`, a `Vec`, or `()` for none.
+    pub async fn query<T: DeserializeOwned>(
+        &self,
+        sql: &str,
+        params: impl Serialize,
+    ) -> Result<Vec<T>> {
+        self.query_data(sql, params).await?.deserialize_rows()
+    }
+
+    /// Run a query and return its columns and rows as JSON values
+    pub async fn query_data(&self, sql: &str, params: impl Serialize) -> Result<QueryData> {
+        let request = QueryRequest {
+            sql: sql.to_string(),
+            params: bind_params(params)?,
+            ..QueryRequest::default()
+        };
+        data(self.query_request(&request).await?)
+    }
+
+    /// Send a `/query` request with every option available
+    pub async fn query_request(&self, request: &QueryRequest) -> Result<QueryResponse> {
+        let response = self
+            .send(|| self.http.post(self.url("/query")).json(request))
+            .await?;
+        Ok(response.json().await?)
+    }
+
+    /// Run a command such as `INSERT` or `CREATE TABLE` and return the number of rows it changed
+    pub async fn execute(&self, sql: &str, params: impl Serialize) -> Result<u64> {
+        let request = QueryRequest {
+            sql: sql.to_string(),
+            params: bind_params(params)?,
+            ..QueryRequest::default()
+        };
+        let response = self
+            .send(|| self.http.post(self.url("/execute")).json(&request))
+            .await?;
+        Ok(data(response.json().await?)?.rows_affected)
+    }
+
+    /// Stream a query's rows as the server produces them, deserializing each into `T`.
+    /// Up to `limit` rows are sent (the server default when None).
+    pub async fn stream<T: DeserializeOwned + Send + 'static>(
+        &self,
+        sql: &str,
+        params: impl Serialize,
+        limit: Option<usize>,
+    ) -> Result<RowStream<T>> {
+        let request = QueryRequest {
+            sql: sql.to_string(),
+            limit,
+            params: bind_params(params)?,
+            ..QueryRequest::default()
+        };
+        let response = self
+            .send(|| {
+                self.http
+                    .post(self.url("/query/stream"))
+                    .header(reqwest::header::ACCEPT, NDJSON_CONTENT_TYPE)
+                    .json(&request)
+            })
+            .await?;
+        RowStream::new(Lines::new(response)).await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Send a request, retrying retryable failures
+    async fn send(&self, request: impl Fn() -> RequestBuilder) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let mut builder = request();
+            if let Some(key) = &self.api_key {
+                builder = builder.header(API_KEY_HEADER, key);
+            }
+
+            let error = match builder.send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => Error::from_response(response).await,
+                Err(e) => Error::Http(e),
+            };
+            if attempt >= self.max_retries || !error.is_retryable() {
+                return Err(error);
+            }
+
+            let delay = match &error {
+                Error::Api {
+                    retry_after: Some(retry_after),
+                    ..
+                } => *retry_after,
+                _ => self.retry_backoff * 2u32.saturating_pow(attempt),
+            };
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Serialize bind parameters to a JSON array
+fn bind_params(params: impl Serialize) -> Result<Vec<Value>> {
+    match serde_json::to_value(params)? {
+        Value::Null => Ok(Vec::new()),
+        Value::Array(values) => Ok(values),
+        other => Err(Error::Params(format!(
+            "parameters must serialize to an array, got {}",
+            other
+        ))),
+    }
+}
+
+fn data(response: QueryResponse) -> Result<QueryData> {
+    let data = response
+        .data
+        .ok_or_else(|| Error::Protocol("response has no data".to_string()))?;
+    Ok(serde_json::from_value(data)?)
+}
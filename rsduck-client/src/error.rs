@@ -0,0 +1,219 @@
+use rsduck_types::ErrorResponse;
+use std::fmt;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Error codes the server reports in `error.code`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    RateLimited,
+    ServerBusy,
+    QueryCancelled,
+    InternalServerError,
+    DatabasePoolError,
+    DatabaseQueryError,
+    CatalogError,
+    SyntaxError,
+    BinderError,
+    ConstraintViolation,
+    ConversionError,
+    InvalidInput,
+    OutOfMemory,
+    QueryInterrupted,
+    IoError,
+    TransactionConflict,
+    DependencyError,
+    PermissionDenied,
+    NotImplemented,
+    MissingExtension,
+    InternalDatabaseError,
+    TaskExecutionError,
+    JsonSerializationError,
+    /// A code this client does not know, or `HTTP_<status>` for responses without one
+    Other(String),
+}
+
+impl ErrorCode {
+    /// Map a server error code
+    pub fn parse(code: &str) -> Self {
+        match code {
+            "BAD_REQUEST" => ErrorCode::BadRequest,
+            "UNAUTHORIZED" => ErrorCode::Unauthorized,
+            "FORBIDDEN" => ErrorCode::Forbidden,
+            "NOT_FOUND" => ErrorCode::NotFound,
+            "CONFLICT" => ErrorCode::Conflict,
+            "RATE_LIMITED" => ErrorCode::RateLimited,
+            "SERVER_BUSY" => ErrorCode::ServerBusy,
+            "QUERY_CANCELLED" => ErrorCode::QueryCancelled,
+            "INTERNAL_SERVER_ERROR" => ErrorCode::InternalServerError,
+            "DATABASE_POOL_ERROR" => ErrorCode::DatabasePoolError,
+            "DATABASE_QUERY_ERROR" => ErrorCode::DatabaseQueryError,
+            "CATALOG_ERROR" => ErrorCode::CatalogError,
+            "SYNTAX_ERROR" => ErrorCode::SyntaxError,
+            "BINDER_ERROR" => ErrorCode::BinderError,
+            "CONSTRAINT_VIOLATION" => ErrorCode::ConstraintViolation,
+            "CONVERSION_ERROR" => ErrorCode::ConversionError,
+            "INVALID_INPUT" => ErrorCode::InvalidInput,
+            "OUT_OF_MEMORY" => ErrorCode::OutOfMemory,
+            "QUERY_INTERRUPTED" => ErrorCode::QueryInterrupted,
+            "IO_ERROR" => ErrorCode::IoError,
+            "TRANSACTION_CONFLICT" => ErrorCode::TransactionConflict,
+            "DEPENDENCY_ERROR" => ErrorCode::DependencyError,
+            "PERMISSION_DENIED" => ErrorCode::PermissionDenied,
+            "NOT_IMPLEMENTED" => ErrorCode::NotImplemented,
+            "MISSING_EXTENSION" => ErrorCode::MissingExtension,
+            "INTERNAL_DATABASE_ERROR" => ErrorCode::InternalDatabaseError,
+            "TASK_EXECUTION_ERROR" => ErrorCode::TaskExecutionError,
+            "JSON_SERIALIZATION_ERROR" => ErrorCode::JsonSerializationError,
+            other => ErrorCode::Other(other.to_string()),
+        }
+    }
+
+    /// The code as the server writes it
+    pub fn as_str(&self) -> &str {
+        match self {
+            ErrorCode::BadRequest => "BAD_REQUEST",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::Conflict => "CONFLICT",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::ServerBusy => "SERVER_BUSY",
+            ErrorCode::QueryCancelled => "QUERY_CANCELLED",
+            ErrorCode::InternalServerError => "INTERNAL_SERVER_ERROR",
+            ErrorCode::DatabasePoolError => "DATABASE_POOL_ERROR",
+            ErrorCode::DatabaseQueryError => "DATABASE_QUERY_ERROR",
+            ErrorCode::CatalogError => "CATALOG_ERROR",
+            ErrorCode::SyntaxError => "SYNTAX_ERROR",
+            ErrorCode::BinderError => "BINDER_ERROR",
+            ErrorCode::ConstraintViolation => "CONSTRAINT_VIOLATION",
+            ErrorCode::ConversionError => "CONVERSION_ERROR",
+            ErrorCode::InvalidInput => "INVALID_INPUT",
+            ErrorCode::OutOfMemory => "OUT_OF_MEMORY",
+            ErrorCode::QueryInterrupted => "QUERY_INTERRUPTED",
+            ErrorCode::IoError => "IO_ERROR",
+            ErrorCode::TransactionConflict => "TRANSACTION_CONFLICT",
+            ErrorCode::DependencyError => "DEPENDENCY_ERROR",
+            ErrorCode::PermissionDenied => "PERMISSION_DENIED",
+            ErrorCode::NotImplemented => "NOT_IMPLEMENTED",
+            ErrorCode::MissingExtension => "MISSING_EXTENSION",
+            ErrorCode::InternalDatabaseError => "INTERNAL_DATABASE_ERROR",
+            ErrorCode::TaskExecutionError => "TASK_EXECUTION_ERROR",
+            ErrorCode::JsonSerializationError => "JSON_SERIALIZATION_ERROR",
+            ErrorCode::Other(code) => code,
+        }
+    }
+
+    /// Whether the request was rejected before running and may succeed if sent again
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorCode::RateLimited | ErrorCode::ServerBusy | ErrorCode::DatabasePoolError
+        )
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Errors returned by the client
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{code}: {message}")]
+    Api {
+        /// HTTP status, or 200 for errors reported inside a result stream
+        status: u16,
+        code: ErrorCode,
+        message: String,
+        details: Option<String>,
+        query_id: Option<String>,
+        /// Wait suggested by the server's `Retry-After` header
+        retry_after: Option<Duration>,
+    },
+
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("Invalid bind parameters: {0}")]
+    Params(String),
+
+    #[error("Failed to decode response: {0}")]
+    Decode(#[from] serde_json::Error),
+
+    #[error("Unexpected response: {0}")]
+    Protocol(String),
+}
+
+impl Error {
+    /// Server error code, for errors reported by the server
+    pub fn code(&self) -> Option<&ErrorCode> {
+        match self {
+            Error::Api { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+
+    /// Whether sending the request again may succeed: the server turned it away before running
+    /// it, or the connection could not be established
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Api { code, .. } => code.is_retryable(),
+            Error::Http(e) => e.is_connect(),
+            _ => false,
+        }
+    }
+
+    /// Build an error from a server error body
+    pub fn from_error_response(
+        status: u16,
+        response: ErrorResponse,
+        retry_after: Option<Duration>,
+    ) -> Self {
+        Error::Api {
+            status,
+            code: ErrorCode::parse(&response.error.code),
+            message: response.error.message,
+            details: response.error.details,
+            query_id: response.query_id,
+            retry_after,
+        }
+    }
+
+    /// Build an error from an unsuccessful HTTP response
+    pub async fn from_response(response: reqwest::Response) -> Self {
+        let status = response.status().as_u16();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs);
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(e) => return Error::Http(e),
+        };
+
+        match serde_json::from_str::<ErrorResponse>(&body) {
+            Ok(error) => Error::from_error_response(status, error, retry_after),
+            Err(_) => Error::Api {
+                status,
+                code: ErrorCode::Other(format!("HTTP_{}", status)),
+                message: body,
+                details: None,
+                query_id: None,
+                retry_after,
+            },
+        }
+    }
+}
+
+/// Result type of client operations
+pub type Result<T> = std::result::Result<T, Error>;
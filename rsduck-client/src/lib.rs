@@ -0,0 +1,16 @@
+//! Async Rust client for the RSDuck HTTP API
+//!
+//! Runs queries and commands with bound parameters, deserializes rows into your own types
+//! by column name, and streams large results row by row.
+
+/// HTTP client
+mod client;
+/// Client errors and server error codes
+mod error;
+/// Query results and streamed rows
+mod rows;
+
+pub use client::Client;
+pub use error::{Error, ErrorCode, Result};
+pub use rows::{QueryData, RowStream, deserialize_row};
+pub use rsduck_types::{ErrorDetail, ErrorResponse, QueryProfile, QueryRequest, QueryResponse};
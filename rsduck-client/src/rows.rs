@@ -0,0 +1,182 @@
+use futures::{Stream, StreamExt};
+use rsduck_types::StreamEvent;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::{Error, Result};
+
+/// The `data` of a `/query` or `/execute` response
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct QueryData {
+    /// Column names
+    #[serde(default)]
+    pub columns: Vec<String>,
+    /// SQL type names of the columns
+    #[serde(default)]
+    pub column_types: Vec<String>,
+    /// Row values in column order
+    #[serde(default)]
+    pub rows: Vec<Vec<Value>>,
+    /// Number of rows returned
+    #[serde(default)]
+    pub row_count: usize,
+    /// Row limit that was in effect
+    #[serde(default)]
+    pub limit_applied: usize,
+    /// Whether rows beyond the limit were left out
+    #[serde(default)]
+    pub truncated: bool,
+    /// Rows changed by a command (`/execute` only)
+    #[serde(default)]
+    pub rows_affected: u64,
+}
+
+impl QueryData {
+    /// Deserialize each row into `T` from an object keyed by column name
+    pub fn deserialize_rows<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
+        self.rows
+            .iter()
+            .map(|row| deserialize_row(&self.columns, row.clone()))
+            .collect()
+    }
+}
+
+/// Deserialize a row into `T` from an object keyed by column name
+pub fn deserialize_row<T: DeserializeOwned>(columns: &[String], row: Vec<Value>) -> Result<T> {
+    let object: Map<String, Value> = columns.iter().cloned().zip(row).collect();
+    Ok(T::deserialize(Value::Object(object))?)
+}
+
+type ByteStream = Pin<Box<dyn Stream<Item = reqwest::Result<bytes::Bytes>> + Send>>;
+
+/// Splits a response body into lines
+pub struct Lines {
+    body: ByteStream,
+    buffer: Vec<u8>,
+}
+
+impl Lines {
+    /// Read the lines of a response body
+    pub fn new(response: reqwest::Response) -> Self {
+        Self {
+            body: Box::pin(response.bytes_stream()),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Next non-empty line, or None at the end of the body
+    pub async fn next_line(&mut self) -> Result<Option<String>> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line).trim().to_string();
+                if line.is_empty() {
+                    continue;
+                }
+                return Ok(Some(line));
+            }
+            match self.body.next().await {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk?),
+                None if self.buffer.iter().all(u8::is_ascii_whitespace) => return Ok(None),
+                None => self.buffer.push(b'\n'),
+            }
+        }
+    }
+}
+
+/// Rows of a streamed query, deserialized into `T` as they arrive
+pub struct RowStream<T> {
+    columns: Vec<String>,
+    column_types: Vec<String>,
+    rows: Pin<Box<dyn Stream<Item = Result<T>> + Send>>,
+}
+
+impl<T: DeserializeOwned + Send + 'static> RowStream<T> {
+    /// Read the `columns` line of a `/query/stream` response and stream the rows after it
+    pub async fn new(mut lines: Lines) -> Result<Self> {
+        let first = lines
+            .next_line()
+            .await?
+            .ok_or_else(|| Error::Protocol("empty result stream".to_string()))?;
+        let (columns, column_types) = match serde_json::from_str(&first)? {
+            StreamEvent::Columns {
+                columns,
+                column_types,
+            } => (columns, column_types),
+            StreamEvent::Error { error } => return Err(stream_error(error)),
+            StreamEvent::End { .. } => {
+                return Err(Error::Protocol(
+                    "result stream ended before its columns".to_string(),
+                ));
+            }
+        };
+
+        let row_columns = columns.clone();
+        let rows = futures::stream::try_unfold(
+            (lines, row_columns, false),
+            |(mut lines, columns, ended)| async move {
+                if ended {
+                    return Ok(None);
+                }
+                let Some(line) = lines.next_line().await? else {
+                    return Err(Error::Protocol(
+                        "result stream ended without an end line".to_string(),
+                    ));
+                };
+                if line.starts_with('[') {
+                    let row = serde_json::from_str(&line)?;
+                    let row = deserialize_row(&columns, row)?;
+                    return Ok(Some((Some(row), (lines, columns, false))));
+                }
+                match serde_json::from_str(&line)? {
+                    StreamEvent::End { .. } => Ok(Some((None, (lines, columns, true)))),
+                    StreamEvent::Error { error } => Err(stream_error(error)),
+                    StreamEvent::Columns { .. } => Err(Error::Protocol(
+                        "unexpected columns line in result stream".to_string(),
+                    )),
+                }
+            },
+        )
+        .filter_map(|item| async move { item.transpose() });
+
+        Ok(Self {
+            columns,
+            column_types,
+            rows: Box::pin(rows),
+        })
+    }
+}
+
+impl<T> RowStream<T> {
+    /// Column names of the result
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// SQL type names of the columns
+    pub fn column_types(&self) -> &[String] {
+        &self.column_types
+    }
+}
+
+impl<T> Stream for RowStream<T> {
+    type Item = Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rows.as_mut().poll_next(cx)
+    }
+}
+
+fn stream_error(error: rsduck_types::ErrorDetail) -> Error {
+    Error::Api {
+        status: 200,
+        code: crate::ErrorCode::parse(&error.code),
+        message: error.message,
+        details: error.details,
+        query_id: None,
+        retry_after: None,
+    }
+}
@@ -0,0 +1,155 @@
+use clap::Parser;
+use futures::TryStreamExt;
+use rsduck::{AppState, Args};
+use rsduck_client::{Client, ErrorCode};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Serve the API on a local port and return a client for it
+async fn start_server(args: Args) -> Client {
+    let state = AppState::new(&args).expect("Failed to create state");
+    let app = rsduck::router(state, rsduck::RouterOptions::default());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    Client::new(format!("http://{}/", addr))
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Order {
+    id: i64,
+    region: String,
+    amount: f64,
+}
+
+#[tokio::test]
+async fn test_execute_and_typed_query() {
+    let client = start_server(Args::parse_from(["rsduck"])).await;
+
+    client
+        .execute(
+            "CREATE TABLE orders (id INTEGER, region VARCHAR, amount DOUBLE)",
+            (),
+        )
+        .await
+        .unwrap();
+    let inserted = client
+        .execute(
+            "INSERT INTO orders VALUES (?, ?, ?), (?, ?, ?)",
+            (1, "EU", 10.5, 2, "US", 20.0),
+        )
+        .await
+        .unwrap();
+    assert_eq!(inserted, 2);
+
+    let orders: Vec<Order> = client
+        .query(
+            "SELECT amount, region, id FROM orders WHERE region = $1",
+            ["EU"],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        orders,
+        vec![Order {
+            id: 1,
+            region: "EU".to_string(),
+            amount: 10.5
+        }]
+    );
+
+    let data = client
+        .query_data("SELECT count(*) AS n FROM orders", ())
+        .await
+        .unwrap();
+    assert_eq!(data.columns, vec!["n"]);
+    assert_eq!(data.row_count, 1);
+}
+
+#[tokio::test]
+async fn test_invalid_params_are_rejected() {
+    let client = start_server(Args::parse_from(["rsduck"])).await;
+
+    let error = client
+        .query::<Order>("SELECT ?", "not an array")
+        .await
+        .unwrap_err();
+    assert!(matches!(error, rsduck_client::Error::Params(_)));
+}
+
+#[tokio::test]
+async fn test_stream_rows() {
+    #[derive(Deserialize)]
+    struct Row {
+        n: i64,
+    }
+
+    let client = start_server(Args::parse_from(["rsduck"])).await;
+
+    let stream = client
+        .stream::<Row>("SELECT range AS n FROM range(?)", [1200], Some(2000))
+        .await
+        .unwrap();
+    assert_eq!(stream.columns(), ["n"]);
+    assert_eq!(stream.column_types(), ["BIGINT"]);
+
+    let rows: Vec<Row> = stream.try_collect().await.unwrap();
+    assert_eq!(rows.len(), 1200);
+    assert_eq!(rows[1199].n, 1199);
+}
+
+#[tokio::test]
+async fn test_error_codes() {
+    let client = start_server(Args::parse_from(["rsduck"])).await;
+
+    let error = client
+        .query::<Order>("SELECT * FROM missing_table", ())
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), Some(&ErrorCode::CatalogError));
+    assert!(!error.is_retryable());
+
+    let error = client
+        .stream::<Order>("SELECT 1; SELECT 2", (), None)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(error.code(), Some(&ErrorCode::BadRequest));
+}
+
+#[tokio::test]
+async fn test_api_key() {
+    let client = start_server(Args::parse_from(["rsduck", "--api-key", "secret"])).await;
+
+    let error = client.execute("SELECT 1", ()).await.unwrap_err();
+    assert_eq!(error.code(), Some(&ErrorCode::Unauthorized));
+
+    let client = client.with_api_key("secret");
+    assert_eq!(client.execute("SELECT 1", ()).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_retries_rate_limited_requests() {
+    let client = start_server(Args::parse_from([
+        "rsduck",
+        "--rate-limit-rps",
+        "5",
+        "--rate-limit-burst",
+        "1",
+    ]))
+    .await;
+
+    client.query_data("SELECT 1", ()).await.unwrap();
+    let error = client
+        .clone()
+        .with_retries(0, Duration::ZERO)
+        .query_data("SELECT 1", ())
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), Some(&ErrorCode::RateLimited));
+    assert!(error.is_retryable());
+
+    // Honors Retry-After and succeeds once the bucket refills
+    client.query_data("SELECT 1", ()).await.unwrap();
+}
@@ -0,0 +1,196 @@
+//! Request and response types shared by the RSDuck server and its clients
+//!
+//! These types define the JSON wire format of the HTTP API.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+/// Content type of streamed query results
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Request body for POST requests
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct QueryRequest {
+    /// SQL query to execute
+    #[schema(example = "SELECT * FROM users")]
+    pub sql: String,
+    /// Maximum number of rows to return
+    #[schema(example = 100)]
+    pub limit: Option<usize>,
+    /// Include a timing profile in the response (`/query` only)
+    #[serde(default)]
+    pub profile: bool,
+    /// Return results in pages of this many rows behind a cursor (`/query` only)
+    #[schema(example = 1000)]
+    pub page_size: Option<usize>,
+    /// Values bound to the statement's `?` or `$1` placeholders, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(example = json!([42]))]
+    pub params: Vec<Value>,
+}
+
+/// Response structure for query results
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct QueryResponse {
+    /// Whether the query was successful
+    pub success: bool,
+    /// Query result data (if successful)
+    pub data: Option<Value>,
+    /// Error message (if failed)
+    pub error: Option<String>,
+    /// Unique identifier for this query
+    #[schema(example = "123e4567-e89b-12d3-a456-426614174000")]
+    pub query_id: String,
+    /// Query execution time in milliseconds
+    #[schema(example = 42)]
+    pub execution_time_ms: u64,
+    /// Timing breakdown, present when the request asked for `profile`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<QueryProfile>,
+}
+
+/// Where the time of a profiled `/query` request went
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct QueryProfile {
+    /// Time waiting for an execution slot in the admission queue, in milliseconds
+    #[schema(example = 0.02)]
+    pub queue_wait_ms: f64,
+    /// Time waiting for a pooled connection, in milliseconds
+    #[schema(example = 0.01)]
+    pub connection_wait_ms: f64,
+    /// Time parsing and planning the statement, in milliseconds
+    #[schema(example = 0.4)]
+    pub prepare_ms: f64,
+    /// Time DuckDB spent producing result rows, in milliseconds
+    #[schema(example = 19.8)]
+    pub execute_ms: f64,
+    /// Time converting result values to JSON, in milliseconds
+    #[schema(example = 1.3)]
+    pub serialize_ms: f64,
+    /// DuckDB's own profile, unavailable in sandbox mode where settings are locked
+    pub duckdb: Option<DuckDbProfile>,
+}
+
+/// Statement profile collected by DuckDB
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DuckDbProfile {
+    /// Total time DuckDB spent on the statement, in milliseconds
+    #[schema(example = 19.7)]
+    pub latency_ms: f64,
+    /// CPU time summed over all worker threads, in milliseconds
+    #[schema(example = 16.8)]
+    pub cpu_time_ms: f64,
+    /// Peak memory held by DuckDB's buffer manager, in bytes
+    #[schema(example = 3444736)]
+    pub peak_buffer_memory_bytes: u64,
+    /// Operator tree with actual cardinalities and timings
+    pub operators: Vec<PlanNode>,
+}
+
+/// One operator of a query plan
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlanNode {
+    /// Operator name, e.g. `HASH_GROUP_BY` or `SEQ_SCAN`
+    #[schema(example = "SEQ_SCAN")]
+    pub operator: String,
+    /// Rows the optimizer expects the operator to produce
+    #[schema(example = 200)]
+    pub estimated_cardinality: Option<u64>,
+    /// Rows the operator produced (`analyze` only)
+    #[schema(example = 989)]
+    pub actual_cardinality: Option<u64>,
+    /// Rows the operator read from storage (`analyze` only)
+    #[schema(example = 1000)]
+    pub rows_scanned: Option<u64>,
+    /// Time spent in the operator in milliseconds (`analyze` only)
+    #[schema(example = 0.13)]
+    pub timing_ms: Option<f64>,
+    /// Operator details such as the table, filters, projections or aggregates
+    #[schema(value_type = Object)]
+    pub extra_info: Map<String, Value>,
+    /// Input operators
+    #[schema(no_recursion)]
+    pub children: Vec<PlanNode>,
+}
+
+/// Structured error response sent to clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub success: bool,
+    pub error: ErrorDetail,
+    pub query_id: Option<String>,
+    pub timestamp: u64,
+}
+
+/// Detailed error information
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorDetail {
+    pub code: String,
+    pub message: String,
+    pub details: Option<String>,
+}
+
+/// Media type of HTTP error bodies
+pub const PROBLEM_JSON_CONTENT_TYPE: &str = "application/problem+json";
+
+/// HTTP error body: an RFC 7807 problem document whose extension members are the fields of
+/// [`ErrorResponse`], so clients reading either shape work
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProblemDetails {
+    /// URI identifying the kind of problem, e.g. `urn:rsduck:error:catalog-error`
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Short summary of the kind of problem
+    pub title: String,
+    /// HTTP status code
+    pub status: u16,
+    /// Explanation of this occurrence
+    pub detail: String,
+    /// Where in the SQL the error was found, for syntax and binder errors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<ErrorPosition>,
+    /// 0-based index of the statement that failed, for `/sql` scripts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statement: Option<usize>,
+    #[serde(flatten)]
+    pub response: ErrorResponse,
+}
+
+/// Position of an error in the SQL text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorPosition {
+    /// 1-based line number
+    pub line: u32,
+    /// 1-based column on that line, when DuckDB did not shorten the line in its message
+    pub column: Option<u32>,
+}
+
+/// A metadata line of a `/query/stream` response.
+/// Rows are sent between these lines as bare JSON arrays.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    /// First line: the result's columns
+    Columns {
+        /// Column names
+        columns: Vec<String>,
+        /// SQL type names of the columns
+        column_types: Vec<String>,
+    },
+    /// Last line of a complete result
+    End {
+        /// Number of rows sent
+        row_count: usize,
+        /// Row limit that was in effect
+        limit_applied: usize,
+        /// Whether rows beyond the limit were left out
+        truncated: bool,
+    },
+    /// Last line of a result that failed after streaming began
+    Error {
+        /// What went wrong
+        #[schema(value_type = Object)]
+        error: ErrorDetail,
+    },
+}
@@ -0,0 +1,203 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+use crate::Metrics;
+
+/// Header clients use to request a priority class
+pub const PRIORITY_HEADER: &str = "x-query-priority";
+
+/// Scheduling class for a statement; each class has its own queue and pool capacity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Latency-sensitive dashboard traffic (default)
+    #[default]
+    Interactive,
+    /// Background exports and other long-running work
+    Batch,
+}
+
+impl Priority {
+    /// Lowercase name used in headers and logs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Interactive => "interactive",
+            Priority::Batch => "batch",
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "interactive" => Ok(Priority::Interactive),
+            "batch" => Ok(Priority::Batch),
+            other => Err(format!(
+                "Invalid priority '{}', expected 'interactive' or 'batch'",
+                other
+            )),
+        }
+    }
+}
+
+/// Admission control configuration for the blocking query pool
+#[derive(Debug, Clone)]
+pub struct AdmissionConfig {
+    /// Maximum number of statements executing at once (normally the pool size)
+    pub max_in_flight: usize,
+    /// Maximum number of statements waiting for an execution slot
+    pub max_queue_length: usize,
+    /// How long a statement may wait in the queue before being rejected
+    pub queue_timeout: Duration,
+}
+
+/// Reason a statement was not admitted for execution
+#[derive(Debug, PartialEq)]
+pub enum AdmissionError {
+    /// The queue already holds `max_queue_length` statements
+    QueueFull,
+    /// No execution slot became free within `queue_timeout`
+    Timeout(Duration),
+}
+
+impl std::fmt::Display for AdmissionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdmissionError::QueueFull => write!(f, "Query queue is full, try again later"),
+            AdmissionError::Timeout(waited) => write!(
+                f,
+                "Timed out after {}ms waiting for a free execution slot",
+                waited.as_millis()
+            ),
+        }
+    }
+}
+
+/// Bounded execution queue in front of `spawn_blocking`, sized to the connection pool
+#[derive(Debug)]
+pub struct AdmissionController {
+    config: AdmissionConfig,
+    slots: Arc<Semaphore>,
+    in_flight: Arc<AtomicUsize>,
+    queued: AtomicUsize,
+}
+
+/// An execution slot; released when dropped
+#[derive(Debug)]
+pub struct AdmissionPermit {
+    _permit: OwnedSemaphorePermit,
+    in_flight: Arc<AtomicUsize>,
+    metrics: Arc<Metrics>,
+}
+
+impl Drop for AdmissionPermit {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        Metrics::decr(&self.metrics.queries_in_flight);
+    }
+}
+
+/// Decrements the queued count however the wait ends
+struct QueuedGuard<'a> {
+    queued: &'a AtomicUsize,
+    metrics: &'a Metrics,
+}
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        Metrics::decr(&self.metrics.queries_queued);
+    }
+}
+
+impl AdmissionController {
+    /// Create a new admission controller
+    pub fn new(config: AdmissionConfig) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(config.max_in_flight.max(1))),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            queued: AtomicUsize::new(0),
+            config,
+        }
+    }
+
+    /// Number of statements currently executing
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Number of statements waiting for an execution slot
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Wait for an execution slot, rejecting immediately when the queue is full
+    pub async fn acquire(&self, metrics: &Arc<Metrics>) -> Result<AdmissionPermit, AdmissionError> {
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Ok(self.admit(permit, metrics, Duration::ZERO));
+        }
+
+        let position = self.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        Metrics::incr(&metrics.queries_queued);
+        let _queued = QueuedGuard {
+            queued: &self.queued,
+            metrics,
+        };
+
+        if position > self.config.max_queue_length {
+            warn!(position, "Query queue full, rejecting statement");
+            Metrics::incr(&metrics.queue_rejected_total);
+            return Err(AdmissionError::QueueFull);
+        }
+
+        debug!(position, "Waiting for execution slot");
+        metrics.queue_position.observe(position as u64);
+        let start = Instant::now();
+
+        match tokio::time::timeout(
+            self.config.queue_timeout,
+            self.slots.clone().acquire_owned(),
+        )
+        .await
+        {
+            Ok(Ok(permit)) => Ok(self.admit(permit, metrics, start.elapsed())),
+            // The semaphore is never closed, but treat it like a timeout if it ever is
+            Ok(Err(_)) | Err(_) => {
+                let waited = start.elapsed();
+                warn!(
+                    position,
+                    waited_ms = waited.as_millis() as u64,
+                    "Timed out waiting for execution slot"
+                );
+                Metrics::incr(&metrics.queue_timeouts_total);
+                metrics.queue_wait_micros.observe(waited.as_micros() as u64);
+                Err(AdmissionError::Timeout(waited))
+            }
+        }
+    }
+
+    fn admit(
+        &self,
+        permit: OwnedSemaphorePermit,
+        metrics: &Arc<Metrics>,
+        waited: Duration,
+    ) -> AdmissionPermit {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        Metrics::incr(&metrics.queries_in_flight);
+        metrics.queue_wait_micros.observe(waited.as_micros() as u64);
+
+        AdmissionPermit {
+            _permit: permit,
+            in_flight: self.in_flight.clone(),
+            metrics: metrics.clone(),
+        }
+    }
+}
@@ -0,0 +1,526 @@
+use axum::extract::ConnectInfo;
+use axum::http::{Extensions, HeaderMap};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, mpsc};
+use std::time::{Instant, SystemTime};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::database::{leading_keyword, replace_string_literals, split_statements};
+use crate::rate_limit::request_client;
+use crate::{ApiError, AppState, Args, Metrics, QueryHistory};
+
+/// `--audit-log` value that writes records to stdout
+const STDOUT_PATH: &str = "-";
+
+/// Numeric literals that are not part of an identifier
+static NUMERIC_LITERAL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b\d+(?:\.\d+)?(?:[eE][+-]?\d+)?\b").expect("valid regex"));
+
+/// Outcome of an audited statement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditStatus {
+    /// The statement ran
+    Ok,
+    /// The statement failed or could not be scheduled
+    Error,
+    /// The statement was refused by read-only or sandbox mode
+    Rejected,
+    /// The client cancelled the statement or went away before it finished
+    Cancelled,
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Unix time the statement finished, in milliseconds
+    pub timestamp_ms: u64,
+    pub query_id: String,
+    /// Protocol the statement arrived over: `http`, `websocket`, `pgwire` or `flight`
+    pub interface: String,
+    /// HTTP path, for statements sent over HTTP
+    pub endpoint: Option<String>,
+    /// Client identity as used for rate limiting, e.g. `key:…`, `cert:…` or `ip:…`
+    pub client: String,
+    pub source_ip: Option<String>,
+    /// Full SQL, or with `--audit-redact` the SQL with its literals replaced by `?`
+    pub sql: String,
+    pub redacted: bool,
+    /// Leading keyword of the statement, e.g. `SELECT` or `INSERT`
+    pub statement_type: String,
+    /// Hash of the bound parameter values, when there are any
+    pub params_hash: Option<String>,
+    pub rows_returned: Option<u64>,
+    pub rows_affected: Option<u64>,
+    pub duration_ms: u64,
+    pub status: AuditStatus,
+    /// Error code reported to the client: an rsduck code, SQLSTATE or gRPC status
+    pub error_code: Option<String>,
+}
+
+/// Where audit records go
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditSink {
+    Stdout,
+    /// JSON lines file rotated to `{path}.1` … `{path}.{max_files}` once it reaches `max_bytes`
+    File {
+        path: PathBuf,
+        max_bytes: u64,
+        max_files: usize,
+    },
+}
+
+enum AuditWriter {
+    Stdout,
+    File {
+        path: PathBuf,
+        file: File,
+        written: u64,
+        max_bytes: u64,
+        max_files: usize,
+    },
+}
+
+impl AuditWriter {
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        match self {
+            AuditWriter::Stdout => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(line)?;
+                stdout.flush()
+            }
+            AuditWriter::File {
+                path,
+                file,
+                written,
+                max_bytes,
+                max_files,
+            } => {
+                if *max_bytes > 0 && *written > 0 && *written + line.len() as u64 > *max_bytes {
+                    rotate(path, *max_files)?;
+                    *file = open_append(path)?;
+                    *written = 0;
+                }
+                file.write_all(line)?;
+                *written += line.len() as u64;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Shift `{path}.N` to `{path}.N+1`, dropping the oldest, and move `path` to `{path}.1`
+fn rotate(path: &Path, max_files: usize) -> io::Result<()> {
+    let numbered = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+
+    if max_files == 0 {
+        return std::fs::remove_file(path);
+    }
+    let _ = std::fs::remove_file(numbered(max_files));
+    for n in (1..max_files).rev() {
+        let from = numbered(n);
+        if from.exists() {
+            std::fs::rename(&from, numbered(n + 1))?;
+        }
+    }
+    std::fs::rename(path, numbered(1))
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Work for the audit writer thread
+enum AuditMessage {
+    Line {
+        line: Vec<u8>,
+        query_id: String,
+    },
+    /// Acknowledge once every line sent before it is written
+    Flush(mpsc::SyncSender<()>),
+}
+
+/// Write lines until every sender is gone, so file writes and rotation never block a runtime
+/// thread
+fn run_writer(mut writer: AuditWriter, messages: mpsc::Receiver<AuditMessage>) {
+    for message in messages {
+        match message {
+            AuditMessage::Line { line, query_id } => {
+                if let Err(e) = writer.write_line(&line) {
+                    warn!(error = %e, query_id = %query_id, "Failed to write audit record");
+                }
+            }
+            AuditMessage::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// Audit log of executed statements. Records are written by a dedicated thread.
+pub struct AuditLog {
+    sender: mpsc::SyncSender<AuditMessage>,
+    redact: bool,
+    metrics: Arc<Metrics>,
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuditLog")
+            .field("redact", &self.redact)
+            .finish()
+    }
+}
+
+impl AuditLog {
+    /// Open the audit sink; records are appended to an existing file. At most `queue_size`
+    /// records wait for the writer; further records are dropped and counted in `metrics`.
+    pub fn open(
+        sink: AuditSink,
+        redact: bool,
+        queue_size: usize,
+        metrics: Arc<Metrics>,
+    ) -> io::Result<Self> {
+        let writer = match sink {
+            AuditSink::Stdout => AuditWriter::Stdout,
+            AuditSink::File {
+                path,
+                max_bytes,
+                max_files,
+            } => {
+                let file = open_append(&path)?;
+                let written = file.metadata()?.len();
+                AuditWriter::File {
+                    path,
+                    file,
+                    written,
+                    max_bytes,
+                    max_files,
+                }
+            }
+        };
+        let (sender, messages) = mpsc::sync_channel(queue_size);
+        std::thread::Builder::new()
+            .name("rsduck-audit".to_string())
+            .spawn(move || run_writer(writer, messages))?;
+        Ok(Self {
+            sender,
+            redact,
+            metrics,
+        })
+    }
+
+    /// The audit log configured by `--audit-log`, if any
+    pub fn from_args(args: &Args, metrics: Arc<Metrics>) -> io::Result<Option<Self>> {
+        let Some(path) = &args.audit_log else {
+            return Ok(None);
+        };
+        let sink = if path.as_os_str() == STDOUT_PATH {
+            AuditSink::Stdout
+        } else {
+            AuditSink::File {
+                path: path.clone(),
+                max_bytes: args.audit_log_max_mb.saturating_mul(1024 * 1024),
+                max_files: args.audit_log_max_files,
+            }
+        };
+        info!(sink = ?sink, redact = args.audit_redact, "Audit log enabled");
+        Self::open(sink, args.audit_redact, args.audit_queue_size, metrics).map(Some)
+    }
+
+    /// Queue one record for appending. Failures are logged rather than failing the statement, and
+    /// a full queue drops the record so a stalled sink never blocks query execution.
+    pub fn write(&self, record: &AuditRecord) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!(error = %e, "Failed to serialize audit record");
+                return;
+            }
+        };
+        line.push(b'\n');
+        let message = AuditMessage::Line {
+            line,
+            query_id: record.query_id.clone(),
+        };
+        match self.sender.try_send(message) {
+            Ok(()) => {}
+            Err(mpsc::TrySendError::Full(_)) => {
+                Metrics::incr(&self.metrics.audit_dropped_total);
+                warn!(query_id = %record.query_id, "Audit queue is full; record dropped");
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                warn!(query_id = %record.query_id, "Audit writer has stopped; record dropped");
+            }
+        }
+    }
+
+    /// Block until the records queued so far are written; waits for room if the queue is full
+    pub fn flush(&self) {
+        let (done, written) = mpsc::sync_channel(1);
+        if self.sender.send(AuditMessage::Flush(done)).is_ok() {
+            let _ = written.recv();
+        }
+    }
+}
+
+/// Replace string and numeric literals with `?`
+pub fn redact_sql(sql: &str) -> String {
+    let without_strings = replace_string_literals(sql, "?");
+    NUMERIC_LITERAL
+        .replace_all(&without_strings, "?")
+        .into_owned()
+}
+
+/// Who sent a statement and over which protocol
+#[derive(Debug, Clone)]
+pub struct AuditSource {
+    pub interface: &'static str,
+    pub endpoint: Option<String>,
+    pub client: String,
+    pub source_ip: Option<IpAddr>,
+}
+
+impl AuditSource {
+    /// Source of an HTTP request to `endpoint`
+    pub fn http(
+        state: &AppState,
+        endpoint: &str,
+        headers: &HeaderMap,
+        extensions: &Extensions,
+    ) -> Self {
+        Self {
+            interface: "http",
+            endpoint: Some(endpoint.to_string()),
+            client: request_client(state, headers, extensions),
+            source_ip: extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip()),
+        }
+    }
+}
+
+/// A statement of an audited request
+struct AuditStatement {
+    record: AuditRecord,
+    /// Full SQL, even when the record is redacted
+    sql: String,
+}
+
+/// A request being audited
+struct AuditEntry {
+    log: Option<Arc<AuditLog>>,
+    history: Option<Arc<QueryHistory>>,
+    /// Statements whose record has not been written yet
+    statements: Vec<Option<AuditStatement>>,
+    /// When the request started or its previous statement was recorded on its own
+    since: Instant,
+}
+
+impl AuditEntry {
+    /// Write the record of statement `index`, unless it has been written already
+    fn write(&mut self, index: usize, update: impl FnOnce(&mut AuditRecord)) {
+        let Some(AuditStatement { mut record, sql }) =
+            self.statements.get_mut(index).and_then(Option::take)
+        else {
+            return;
+        };
+        update(&mut record);
+        record.duration_ms = self.since.elapsed().as_millis() as u64;
+        record.timestamp_ms = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        if let Some(log) = &self.log {
+            log.write(&record);
+        }
+        if let Some(history) = &self.history {
+            history.record(&record, &sql);
+        }
+    }
+}
+
+/// The statements of a request being audited. Exactly one record per statement is written to
+/// the audit log and the query history: when the request finishes, or with status `cancelled`
+/// if it is dropped unfinished, e.g. because the client went away.
+#[derive(Default)]
+pub struct Audit {
+    entry: Option<Box<AuditEntry>>,
+}
+
+impl Audit {
+    /// Start auditing the statements of `sql`; does nothing unless the audit log or query
+    /// history is enabled
+    pub fn begin(state: &AppState, source: &AuditSource, query_id: &str, sql: &str) -> Self {
+        if state.audit.is_none() && state.history.is_none() {
+            return Self::default();
+        }
+        let redact = state.audit.as_ref().is_some_and(|log| log.redact);
+        let mut statements = split_statements(sql);
+        if statements.is_empty() {
+            statements.push(sql.to_string());
+        }
+        let statements = statements
+            .into_iter()
+            .map(|sql| {
+                Some(AuditStatement {
+                    record: AuditRecord {
+                        timestamp_ms: 0,
+                        query_id: query_id.to_string(),
+                        interface: source.interface.to_string(),
+                        endpoint: source.endpoint.clone(),
+                        client: source.client.clone(),
+                        source_ip: source.source_ip.map(|ip| ip.to_string()),
+                        sql: if redact {
+                            redact_sql(&sql)
+                        } else {
+                            sql.clone()
+                        },
+                        redacted: redact,
+                        statement_type: leading_keyword(&sql),
+                        params_hash: None,
+                        rows_returned: None,
+                        rows_affected: None,
+                        duration_ms: 0,
+                        status: AuditStatus::Cancelled,
+                        error_code: None,
+                    },
+                    sql,
+                })
+            })
+            .collect();
+        Self {
+            entry: Some(Box::new(AuditEntry {
+                log: state.audit.clone(),
+                history: state.history.clone(),
+                statements,
+                since: Instant::now(),
+            })),
+        }
+    }
+
+    /// Record a hash of the bound parameter values
+    pub fn with_params<T: Debug>(mut self, params: &[T]) -> Self {
+        if let Some(entry) = &mut self.entry
+            && !params.is_empty()
+        {
+            let mut hasher = DefaultHasher::new();
+            format!("{:?}", params).hash(&mut hasher);
+            let params_hash = format!("{:016x}", hasher.finish());
+            for statement in entry.statements.iter_mut().flatten() {
+                statement.record.params_hash = Some(params_hash.clone());
+            }
+        }
+        self
+    }
+
+    /// Move the audit out, e.g. into the task that finishes the statement
+    pub fn take(&mut self) -> Self {
+        Self {
+            entry: self.entry.take(),
+        }
+    }
+
+    /// Record a statement that ran, from a result summary with `row_count` or `rows_affected`
+    pub fn succeeded(&mut self, data: &serde_json::Value) {
+        match data.get("rows_affected").and_then(|v| v.as_u64()) {
+            Some(affected) => self.completed(None, Some(affected)),
+            None => self.completed(data.get("row_count").and_then(|v| v.as_u64()), None),
+        }
+    }
+
+    /// Record statements that ran. The row counts are those of the last statement, whose
+    /// result the client receives.
+    pub fn completed(&mut self, rows_returned: Option<u64>, rows_affected: Option<u64>) {
+        self.finish(|record, last| {
+            record.status = AuditStatus::Ok;
+            if last {
+                record.rows_returned = rows_returned;
+                record.rows_affected = rows_affected;
+            }
+        });
+    }
+
+    /// Record statements that failed; read-only and sandbox refusals are recorded as rejected
+    pub fn failed(&mut self, error: &ApiError) {
+        self.failed_with(failure_status(error), error.error_code());
+    }
+
+    /// Record statement `index` as soon as it has run, for requests that run their statements
+    /// one at a time
+    pub fn statement_completed(
+        &mut self,
+        index: usize,
+        rows_returned: Option<u64>,
+        rows_affected: Option<u64>,
+    ) {
+        if let Some(entry) = &mut self.entry {
+            entry.write(index, |record| {
+                record.status = AuditStatus::Ok;
+                record.rows_returned = rows_returned;
+                record.rows_affected = rows_affected;
+            });
+            entry.since = Instant::now();
+        }
+    }
+
+    /// Record statement `index` as failed. The statements after it did not run and get no
+    /// record.
+    pub fn statement_failed(&mut self, index: usize, error: &ApiError) {
+        if let Some(entry) = &mut self.entry {
+            entry.write(index, |record| {
+                record.status = failure_status(error);
+                record.error_code = Some(error.error_code().to_string());
+            });
+            entry.statements.truncate(index + 1);
+            entry.since = Instant::now();
+        }
+    }
+
+    /// Record statements that did not run successfully, with a protocol-specific error code
+    pub fn failed_with(&mut self, status: AuditStatus, error_code: &str) {
+        self.finish(|record, _| {
+            record.status = status;
+            record.error_code = Some(error_code.to_string());
+        });
+    }
+
+    /// Write the records not written yet; `update` also learns whether it is the last statement
+    fn finish(&mut self, update: impl Fn(&mut AuditRecord, bool)) {
+        let Some(mut entry) = self.entry.take() else {
+            return;
+        };
+        let last = entry.statements.len().saturating_sub(1);
+        for index in 0..entry.statements.len() {
+            entry.write(index, |record| update(record, index == last));
+        }
+    }
+}
+
+/// Audit status of a request that failed with `error`
+fn failure_status(error: &ApiError) -> AuditStatus {
+    match error {
+        ApiError::Forbidden { .. } => AuditStatus::Rejected,
+        ApiError::Cancelled { .. } => AuditStatus::Cancelled,
+        _ => AuditStatus::Error,
+    }
+}
+
+impl Drop for Audit {
+    fn drop(&mut self) {
+        self.finish(|record, _| record.status = AuditStatus::Cancelled);
+    }
+}
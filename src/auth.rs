@@ -0,0 +1,167 @@
+use axum::{
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashSet;
+use tracing::warn;
+
+use crate::rate_limit::API_KEY_HEADER;
+use crate::{ApiError, AppState, ClientIdentity, ClientScope, PRIORITY_HEADER};
+
+/// API keys accepted by the HTTP and PostgreSQL listeners
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    keys: HashSet<String>,
+}
+
+impl ApiKeys {
+    /// Create a key set; an empty set disables authentication
+    pub fn new(keys: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            keys: keys.into_iter().filter(|key| !key.is_empty()).collect(),
+        }
+    }
+
+    /// Whether clients must present a key
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// Check a presented key without short-circuiting on the first differing byte
+    pub fn verify(&self, presented: &str) -> bool {
+        self.keys
+            .iter()
+            .fold(false, |found, key| found | constant_time_eq(key, presented))
+    }
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.bytes()
+        .zip(b.bytes())
+        .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+        == 0
+}
+
+/// Which credentials an endpoint group accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    /// Full API keys and certificates
+    Full,
+    /// Also keys and certificates restricted to saved queries
+    SavedQueries,
+    /// Only admin API keys and certificates
+    Admin,
+}
+
+/// Middleware requiring a valid `X-API-Key` header when API keys are configured.
+/// A verified client certificate authenticates the request instead, within its scope.
+pub async fn auth_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    authenticate(&state, request, next, Access::Full).await
+}
+
+/// Like `auth_middleware`, but also accepting keys restricted to saved queries
+pub async fn saved_query_auth_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    authenticate(&state, request, next, Access::SavedQueries).await
+}
+
+/// Like `auth_middleware`, but only accepting admin API keys and certificates. Without API keys
+/// every endpoint is open, these included.
+pub async fn admin_auth_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    authenticate(&state, request, next, Access::Admin).await
+}
+
+async fn authenticate(state: &AppState, request: Request, next: Next, access: Access) -> Response {
+    if let Some(identity) = request.extensions().get::<ClientIdentity>().cloned() {
+        return authenticate_certificate(identity, request, next, access).await;
+    }
+
+    if !state.api_keys.is_enabled() {
+        return next.run(request).await;
+    }
+
+    let presented = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    match presented {
+        Some(key) if access == Access::Admin && state.admin_keys.verify(key) => {
+            next.run(request).await
+        }
+        Some(key) if access == Access::Admin && state.api_keys.verify(key) => {
+            warn!("Request rejected: API key is not an admin key");
+            ApiError::forbidden("This endpoint requires an admin API key").into_response()
+        }
+        Some(key) if state.api_keys.verify(key) => next.run(request).await,
+        Some(key) if state.saved_query_keys.verify(key) => {
+            if access == Access::SavedQueries {
+                next.run(request).await
+            } else {
+                warn!("Request rejected: API key restricted to saved queries");
+                ApiError::forbidden("This API key may only run saved queries at /q/{name}")
+                    .into_response()
+            }
+        }
+        Some(_) => {
+            warn!("Request rejected: invalid API key");
+            ApiError::unauthorized("Invalid API key").into_response()
+        }
+        None => {
+            warn!("Request rejected: missing API key");
+            ApiError::unauthorized("Missing X-API-Key header").into_response()
+        }
+    }
+}
+
+async fn authenticate_certificate(
+    identity: ClientIdentity,
+    mut request: Request,
+    next: Next,
+    access: Access,
+) -> Response {
+    match identity.scope {
+        Some(ClientScope::Admin) => next.run(request).await,
+        Some(ClientScope::Full | ClientScope::Batch) if access == Access::Admin => {
+            warn!(client_cert = %identity.name, "Request rejected: certificate is not an admin certificate");
+            ApiError::forbidden("This endpoint requires an admin client certificate")
+                .into_response()
+        }
+        Some(ClientScope::Full) => next.run(request).await,
+        Some(ClientScope::Batch) => {
+            // Like batch API keys, batch certificates override any requested priority
+            request
+                .headers_mut()
+                .insert(PRIORITY_HEADER, HeaderValue::from_static("batch"));
+            next.run(request).await
+        }
+        Some(ClientScope::SavedQueries) if access == Access::SavedQueries => {
+            next.run(request).await
+        }
+        Some(ClientScope::SavedQueries) => {
+            warn!(client_cert = %identity.name, "Request rejected: certificate restricted to saved queries");
+            ApiError::forbidden("This client certificate may only run saved queries at /q/{name}")
+                .into_response()
+        }
+        None => {
+            warn!(client_cert = %identity.name, subject = %identity.subject, "Request rejected: client certificate has no scope");
+            ApiError::forbidden("Client certificate is not authorized").into_response()
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::Metrics;
+use crate::database::normalize_sql;
+
+/// Result cache configuration
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// How long a cached result stays fresh
+    pub ttl: Duration,
+    /// Maximum number of cached results
+    pub max_entries: usize,
+    /// Maximum total size of cached results, measured as serialized JSON bytes
+    pub max_bytes: usize,
+}
+
+/// Response encoding a cached result was produced for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheFormat {
+    /// `QueryResponse` JSON, as returned by `/query` and saved queries
+    Json,
+}
+
+/// Identifies a cacheable query: normalized SQL plus everything else that shapes the result,
+/// including the encoding it is served in so one format is never answered with another's entry
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    sql: String,
+    params: String,
+    limit: Option<usize>,
+    format: CacheFormat,
+}
+
+impl CacheKey {
+    /// Build a key from raw SQL, stripping comments and normalizing whitespace
+    pub fn new(sql: &str, limit: Option<usize>, format: CacheFormat) -> Self {
+        Self::with_params(sql, "", limit, format)
+    }
+
+    /// Build a key for a parameterized query from its SQL and a canonical encoding of its parameters
+    pub fn with_params(sql: &str, params: &str, limit: Option<usize>, format: CacheFormat) -> Self {
+        Self {
+            sql: normalize_sql(sql),
+            params: params.to_string(),
+            limit,
+            format,
+        }
+    }
+}
+
+/// A cached query result
+#[derive(Debug, Clone)]
+pub struct CachedResult {
+    /// The `data` section of the original response
+    pub data: Arc<serde_json::Value>,
+    /// Time left before the entry expires
+    pub remaining_ttl: Duration,
+}
+
+#[derive(Debug)]
+struct Entry {
+    data: Arc<serde_json::Value>,
+    size: usize,
+    ttl: Duration,
+    inserted: Instant,
+    last_access: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    map: HashMap<CacheKey, Entry>,
+    total_bytes: usize,
+    /// Bumped on every invalidation so results computed before a write are never stored
+    generation: u64,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.map.remove(key) {
+            self.total_bytes -= entry.size;
+        }
+    }
+}
+
+/// In-process TTL and size bounded cache of read-only query results
+#[derive(Debug)]
+pub struct ResultCache {
+    config: CacheConfig,
+    entries: Mutex<Entries>,
+    metrics: Arc<Metrics>,
+}
+
+impl ResultCache {
+    /// Create a new, empty result cache
+    pub fn new(config: CacheConfig, metrics: Arc<Metrics>) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(Entries::default()),
+            metrics,
+        }
+    }
+
+    /// Configured time-to-live for cached results
+    pub fn ttl(&self) -> Duration {
+        self.config.ttl
+    }
+
+    /// Current invalidation generation; pass it back to [`ResultCache::insert`]
+    pub fn generation(&self) -> u64 {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .generation
+    }
+
+    /// Look up a fresh cached result, recording a hit or miss
+    pub fn get(&self, key: &CacheKey) -> Option<CachedResult> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+
+        let expired = match entries.map.get_mut(key) {
+            Some(entry) => {
+                let age = now.duration_since(entry.inserted);
+                if age < entry.ttl {
+                    entry.last_access = now;
+                    Metrics::incr(&self.metrics.cache_hits_total);
+                    return Some(CachedResult {
+                        data: entry.data.clone(),
+                        remaining_ttl: entry.ttl - age,
+                    });
+                }
+                true
+            }
+            None => false,
+        };
+
+        if expired {
+            entries.remove(key);
+            self.update_gauges(&entries);
+        }
+        Metrics::incr(&self.metrics.cache_misses_total);
+        None
+    }
+
+    /// Store a result computed at `generation`, evicting least recently used entries
+    /// to stay within bounds. Results that raced with an invalidation are discarded.
+    pub fn insert(&self, key: CacheKey, data: serde_json::Value, generation: u64) {
+        self.insert_with_ttl(key, data, generation, self.config.ttl);
+    }
+
+    /// Store a result like [`ResultCache::insert`], keeping it fresh for `ttl` instead of
+    /// the configured time-to-live
+    pub fn insert_with_ttl(
+        &self,
+        key: CacheKey,
+        data: serde_json::Value,
+        generation: u64,
+        ttl: Duration,
+    ) {
+        let size = serde_json::to_vec(&data).map(|v| v.len()).unwrap_or(0);
+        if size > self.config.max_bytes || self.config.max_entries == 0 {
+            debug!(size, "Result too large to cache");
+            return;
+        }
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.generation != generation {
+            debug!("Result cache invalidated during execution, not caching");
+            return;
+        }
+        entries.remove(&key);
+
+        while !entries.map.is_empty()
+            && (entries.map.len() >= self.config.max_entries
+                || entries.total_bytes + size > self.config.max_bytes)
+        {
+            let oldest = entries
+                .map
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_access)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+                Metrics::incr(&self.metrics.cache_evictions_total);
+            }
+        }
+
+        entries.total_bytes += size;
+        entries.map.insert(
+            key,
+            Entry {
+                data: Arc::new(data),
+                size,
+                ttl,
+                inserted: now,
+                last_access: now,
+            },
+        );
+        self.update_gauges(&entries);
+    }
+
+    /// Drop every cached result, e.g. after a successful write
+    pub fn invalidate_all(&self) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        debug!(entries = entries.map.len(), "Invalidating result cache");
+        entries.generation += 1;
+        entries.map.clear();
+        entries.total_bytes = 0;
+        Metrics::incr(&self.metrics.cache_invalidations_total);
+        self.update_gauges(&entries);
+    }
+
+    fn update_gauges(&self, entries: &Entries) {
+        self.metrics
+            .cache_entries
+            .store(entries.map.len() as u64, Ordering::Relaxed);
+        self.metrics
+            .cache_bytes
+            .store(entries.total_bytes as u64, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,426 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use comfy_table::{Table, presets::UTF8_FULL_CONDENSED};
+use rsduck_client::{Client, QueryData, QueryRequest, QueryResponse};
+use rustyline::{DefaultEditor, error::ReadlineError};
+use serde_json::{Map, Value};
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use crate::database::{normalize_sql, split_statements};
+use crate::models::Args;
+
+/// Command line of the `rsduck` binary
+#[derive(Parser)]
+#[command(name = "rsduck")]
+#[command(about = "A DuckDB REST server and client")]
+#[command(version = "1.0")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Server options, used when no subcommand is given
+    #[command(flatten)]
+    pub serve: Args,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the server (the default when no subcommand is given)
+    Serve(Box<Args>),
+    /// Run SQL against a server and print the result
+    Query(QueryArgs),
+    /// Interactive SQL shell connected to a server
+    Repl(ReplArgs),
+}
+
+/// Server to connect to
+#[derive(clap::Args, Debug, Clone)]
+pub struct ConnectArgs {
+    /// Base URL of the rsduck server
+    #[arg(long, default_value = "http://localhost:3001", env = "RSDUCK_URL")]
+    pub url: String,
+
+    /// API key sent as X-API-Key
+    #[arg(long, env = "RSDUCK_API_KEY", hide_env_values = true)]
+    pub api_key: Option<String>,
+}
+
+impl ConnectArgs {
+    pub fn client(&self) -> Client {
+        let client = Client::new(&self.url);
+        match &self.api_key {
+            Some(key) => client.with_api_key(key),
+            None => client,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct QueryArgs {
+    #[command(flatten)]
+    pub connect: ConnectArgs,
+
+    /// Output format
+    #[arg(long, value_enum, default_value = "table")]
+    pub format: OutputFormat,
+
+    /// Maximum number of rows to return
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// SQL to run; read from stdin when omitted
+    pub sql: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ReplArgs {
+    #[command(flatten)]
+    pub connect: ConnectArgs,
+
+    /// Initial output format (change with .mode)
+    #[arg(long, value_enum, default_value = "table")]
+    pub format: OutputFormat,
+
+    /// File to keep the command history in (default: ~/.rsduck_history)
+    #[arg(long, value_name = "FILE")]
+    pub history_file: Option<PathBuf>,
+}
+
+/// How query results are printed
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Aligned table
+    Table,
+    /// Comma-separated values with a header row
+    Csv,
+    /// JSON array with one object per row
+    Json,
+}
+
+/// Render a result in the given format
+pub fn format_result(data: &QueryData, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Table => format_table(data),
+        OutputFormat::Csv => format_csv(data),
+        OutputFormat::Json => format_json(data),
+    }
+}
+
+fn format_table(data: &QueryData) -> String {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED);
+    table.set_header(&data.columns);
+    for row in &data.rows {
+        table.add_row(row.iter().map(|value| match value {
+            Value::Null => "NULL".to_string(),
+            other => cell_text(other),
+        }));
+    }
+    format!("{}\n", table)
+}
+
+fn format_csv(data: &QueryData) -> String {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    // Writing to memory cannot fail
+    let _ = writer.write_record(&data.columns);
+    for row in &data.rows {
+        let _ = writer.write_record(row.iter().map(|value| match value {
+            Value::Null => String::new(),
+            other => cell_text(other),
+        }));
+    }
+    String::from_utf8_lossy(&writer.into_inner().unwrap_or_default()).into_owned()
+}
+
+fn format_json(data: &QueryData) -> String {
+    let rows: Vec<Value> = data
+        .rows
+        .iter()
+        .map(|row| {
+            let object: Map<String, Value> = data
+                .columns
+                .iter()
+                .cloned()
+                .zip(row.iter().cloned())
+                .collect();
+            Value::Object(object)
+        })
+        .collect();
+    let mut out = serde_json::to_string_pretty(&rows).unwrap_or_default();
+    out.push('\n');
+    out
+}
+
+/// Strings without quotes, everything else as JSON
+fn cell_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// One line summarizing a result: the row count, or rows changed by a command
+pub fn result_summary(data: &QueryData) -> String {
+    if data.columns.is_empty() {
+        return format!("{} rows affected", data.rows_affected);
+    }
+    let mut summary = match data.row_count {
+        1 => "1 row".to_string(),
+        n => format!("{} rows", n),
+    };
+    if data.truncated {
+        summary.push_str(&format!(" (truncated at limit {})", data.limit_applied));
+    }
+    summary
+}
+
+/// Whether a REPL input buffer holds complete statements, i.e. ends with a semicolon outside
+/// any string, quoted identifier or comment
+pub fn is_complete(buffer: &str) -> bool {
+    // Text after a terminated statement starts a new one; in an unterminated statement, string
+    // or comment it is swallowed instead
+    let statements = split_statements(buffer).len();
+    statements > 0 && split_statements(&format!("{}\nSELECT 1", buffer)).len() == statements + 1
+}
+
+/// Why the REPL refuses a statement that relies on session state. Each statement is sent as its
+/// own request and may run on a different pooled connection, so a transaction, setting or
+/// temporary object would be gone by the next statement.
+pub fn session_state_error(sql: &str) -> Option<String> {
+    let normalized = normalize_sql(sql).to_ascii_uppercase();
+    let words: Vec<&str> = normalized
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+        .take(4)
+        .collect();
+    let what = match words.as_slice() {
+        [
+            "BEGIN" | "START" | "COMMIT" | "END" | "ROLLBACK" | "ABORT",
+            ..,
+        ] => "Transactions",
+        // Global settings apply to the whole database
+        ["SET" | "RESET", "GLOBAL", ..] => return None,
+        ["SET" | "RESET", ..] => "Session settings",
+        ["USE", ..] => "USE statements",
+        ["CREATE", "TEMP" | "TEMPORARY", ..]
+        | ["CREATE", "OR", "REPLACE", "TEMP" | "TEMPORARY", ..] => "Temporary objects",
+        _ => return None,
+    };
+    Some(format!(
+        "{} are not supported in the REPL: each statement is a separate request that may run on \
+         a different connection. Send the whole script to POST /sql instead.",
+        what
+    ))
+}
+
+/// Run `rsduck query`
+pub async fn run_query(args: QueryArgs) -> anyhow::Result<()> {
+    let sql = match args.sql {
+        Some(sql) => sql,
+        None => {
+            let mut sql = String::new();
+            std::io::stdin().read_to_string(&mut sql)?;
+            sql
+        }
+    };
+
+    let client = args.connect.client();
+    let request = QueryRequest {
+        sql,
+        limit: args.limit,
+        ..QueryRequest::default()
+    };
+    let data = response_data(client.query_request(&request).await?)?;
+    print!("{}", format_result(&data, args.format));
+    if data.truncated {
+        eprintln!("{}", result_summary(&data));
+    }
+    Ok(())
+}
+
+const REPL_HELP: &str = "\
+.tables            List tables
+.schema [TABLE]    Show CREATE statements, for all tables or one
+.mode MODE         Output format: table, csv or json
+.timer on|off      Show query timing
+.help              Show this help
+.quit              Exit (also .exit or Ctrl+D)
+
+SQL statements end with a semicolon and may span several lines. Each statement runs as a
+separate request, so transactions, SET, USE and temporary tables are not available.";
+
+/// Interactive shell state
+struct Repl {
+    client: Client,
+    format: OutputFormat,
+    timer: bool,
+}
+
+/// Run `rsduck repl`
+pub async fn run_repl(args: ReplArgs) -> anyhow::Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history_file = args.history_file.clone().or_else(|| {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rsduck_history"))
+    });
+    if let Some(path) = &history_file {
+        // A missing history file is normal on first use
+        let _ = editor.load_history(path);
+    }
+
+    let mut repl = Repl {
+        client: args.connect.client(),
+        format: args.format,
+        timer: true,
+    };
+    println!(
+        "Connected to {}. Enter \".help\" for usage hints.",
+        args.connect.url
+    );
+
+    let mut buffer = String::new();
+    loop {
+        let prompt = if buffer.is_empty() {
+            "rsduck> "
+        } else {
+            "   ...> "
+        };
+        // Reading a line blocks, so keep it off the async worker
+        let line = match tokio::task::block_in_place(|| editor.readline(prompt)) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        if buffer.is_empty() && line.trim_start().starts_with('.') {
+            let _ = editor.add_history_entry(line.trim());
+            match repl.meta_command(line.trim()).await {
+                Ok(true) => continue,
+                Ok(false) => break,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    continue;
+                }
+            }
+        }
+
+        if !buffer.is_empty() {
+            buffer.push('\n');
+        }
+        buffer.push_str(&line);
+        if buffer.trim().is_empty() {
+            buffer.clear();
+            continue;
+        }
+        if !is_complete(&buffer) {
+            continue;
+        }
+
+        let _ = editor.add_history_entry(buffer.trim());
+        let statements = split_statements(&buffer);
+        buffer.clear();
+        // Refuse the whole input rather than run the statements around a dropped `BEGIN`
+        if let Some(error) = statements.iter().find_map(|sql| session_state_error(sql)) {
+            eprintln!("Error: {}", error);
+            continue;
+        }
+        for statement in statements {
+            if let Err(e) = repl.run(&statement).await {
+                eprintln!("Error: {}", e);
+                break;
+            }
+        }
+    }
+
+    if let Some(path) = &history_file
+        && let Err(e) = editor.save_history(path)
+    {
+        eprintln!("Could not save history to {}: {}", path.display(), e);
+    }
+    Ok(())
+}
+
+impl Repl {
+    /// Run a statement and print its result
+    async fn run(&self, sql: &str) -> anyhow::Result<()> {
+        let start = Instant::now();
+        let request = QueryRequest {
+            sql: sql.to_string(),
+            ..QueryRequest::default()
+        };
+        let response = self.client.query_request(&request).await?;
+        let server_ms = response.execution_time_ms;
+        let data = response_data(response)?;
+
+        if !data.columns.is_empty() {
+            print!("{}", format_result(&data, self.format));
+        }
+        println!("{}", result_summary(&data));
+        if self.timer {
+            println!(
+                "Run Time: {} ms (server {} ms)",
+                start.elapsed().as_millis(),
+                server_ms
+            );
+        }
+        Ok(())
+    }
+
+    /// Handle a `.command`; returns false when the shell should exit
+    async fn meta_command(&mut self, line: &str) -> anyhow::Result<bool> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let argument = words.next();
+
+        match (command, argument) {
+            (".quit" | ".exit", _) => return Ok(false),
+            (".help", _) => println!("{}", REPL_HELP),
+            (".tables", _) => self.run("SHOW TABLES").await?,
+            (".schema", None) => {
+                self.print_schema("SELECT sql FROM duckdb_tables() ORDER BY table_name")
+                    .await?
+            }
+            (".schema", Some(table)) => {
+                let sql = format!(
+                    "SELECT sql FROM duckdb_tables() WHERE table_name = '{}'",
+                    table.replace('\'', "''")
+                );
+                self.print_schema(&sql).await?
+            }
+            (".mode", Some(mode)) => match OutputFormat::from_str(mode, true) {
+                Ok(format) => self.format = format,
+                Err(_) => eprintln!("Unknown mode \"{}\"; use table, csv or json", mode),
+            },
+            (".timer", Some("on")) => self.timer = true,
+            (".timer", Some("off")) => self.timer = false,
+            _ => eprintln!(
+                "Unknown or incomplete command \"{}\". Enter \".help\" for usage hints.",
+                line
+            ),
+        }
+        Ok(true)
+    }
+
+    /// Print the CREATE statements a schema query returns
+    async fn print_schema(&self, sql: &str) -> anyhow::Result<()> {
+        let data = self.client.query_data(sql, ()).await?;
+        for row in &data.rows {
+            if let Some(Value::String(create)) = row.first() {
+                println!("{};", create.trim_end_matches(';'));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn response_data(response: QueryResponse) -> anyhow::Result<QueryData> {
+    let data = response
+        .data
+        .ok_or_else(|| anyhow::anyhow!("Server response has no data"))?;
+    Ok(serde_json::from_value(data)?)
+}
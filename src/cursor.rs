@@ -0,0 +1,381 @@
+use axum::{
+    extract::{Path, State},
+    http::{Extensions, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use duckdb::Connection;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, error, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::database::{
+    BindParams, effective_row_limit, is_query_statement, split_statements, stream_sql_with_limit,
+};
+use crate::rate_limit::request_client;
+use crate::{ApiError, AppState, DatabaseError, Priority, QueryResponse};
+
+/// Temp table holding a cursor's result on its pinned connection
+const CURSOR_TABLE: &str = "rsduck_cursor";
+
+/// Limits on open result cursors
+#[derive(Debug, Clone)]
+pub struct CursorConfig {
+    /// Cursors not read for this long are closed
+    pub idle_timeout: Duration,
+    /// Maximum open cursors per client (verified API key or IP)
+    pub max_per_client: usize,
+    /// Maximum open cursors across all clients; each holds a materialized result in memory
+    pub max_open: usize,
+}
+
+/// A query result materialized on a private connection and read one page at a time
+pub struct Cursor {
+    conn: Connection,
+    priority: Priority,
+    page_size: usize,
+    offset: usize,
+    total_rows: usize,
+}
+
+impl Cursor {
+    /// Run `sql` into a temp table on a new connection to the pool's database
+    fn open(
+        state: &AppState,
+        sql: &str,
+        params: &BindParams,
+        page_size: usize,
+        priority: Priority,
+    ) -> Result<Self, DatabaseError> {
+        debug!("Opening pinned connection for cursor");
+        let conn = state.pool_for(priority).get()?.try_clone()?;
+        state.connections.register(&conn);
+        let mut create = conn.prepare(&format!("CREATE TEMP TABLE {} AS {}", CURSOR_TABLE, sql))?;
+        let values = params.values(&create)?;
+        create.execute(duckdb::params_from_iter(values))?;
+        drop(create);
+        let total_rows: i64 = conn.query_row(
+            &format!("SELECT count(*) FROM {}", CURSOR_TABLE),
+            [],
+            |row| row.get(0),
+        )?;
+
+        Ok(Self {
+            conn,
+            priority,
+            page_size,
+            offset: 0,
+            total_rows: total_rows as usize,
+        })
+    }
+
+    /// Read the next page and advance past it
+    /// The page has the same shape as a `/query` result plus its `offset` and `total_rows`
+    fn next_page(&mut self) -> Result<Value, DatabaseError> {
+        let sql = format!(
+            "SELECT * FROM {} LIMIT {} OFFSET {}",
+            CURSOR_TABLE, self.page_size, self.offset
+        );
+        let mut rows = Vec::new();
+        let mut page = stream_sql_with_limit(
+            &self.conn,
+            &sql,
+            Some(self.page_size),
+            self.page_size,
+            |chunk| {
+                rows.extend(chunk.rows);
+                true
+            },
+        )?;
+
+        page["offset"] = Value::from(self.offset);
+        page["total_rows"] = Value::from(self.total_rows);
+        self.offset += rows.len();
+        page["rows"] = Value::from(rows);
+        Ok(page)
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.offset >= self.total_rows
+    }
+}
+
+struct CursorEntry {
+    client: String,
+    last_used: Instant,
+    cursor: Arc<Mutex<Cursor>>,
+}
+
+/// Open cursors keyed by token
+pub struct CursorStore {
+    config: CursorConfig,
+    cursors: Mutex<HashMap<String, CursorEntry>>,
+}
+
+impl CursorStore {
+    /// Create an empty store
+    pub fn new(config: CursorConfig) -> Self {
+        Self {
+            config,
+            cursors: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fail when `client` already has the maximum number of open cursors, or the server does
+    pub fn check_capacity(&self, client: &str) -> Result<(), ApiError> {
+        let mut cursors = self.cursors.lock().unwrap_or_else(|e| e.into_inner());
+        self.prune(&mut cursors);
+        self.ensure_capacity(&cursors, client)
+    }
+
+    /// Register a cursor for `client` and return its token
+    pub fn insert(&self, client: &str, cursor: Cursor) -> Result<String, ApiError> {
+        let mut cursors = self.cursors.lock().unwrap_or_else(|e| e.into_inner());
+        self.prune(&mut cursors);
+        // Checked again under the same lock: other cursors may have opened meanwhile
+        self.ensure_capacity(&cursors, client)?;
+        let token = Uuid::new_v4().simple().to_string();
+        cursors.insert(
+            token.clone(),
+            CursorEntry {
+                client: client.to_string(),
+                last_used: Instant::now(),
+                cursor: Arc::new(Mutex::new(cursor)),
+            },
+        );
+        Ok(token)
+    }
+
+    fn ensure_capacity(
+        &self,
+        cursors: &HashMap<String, CursorEntry>,
+        client: &str,
+    ) -> Result<(), ApiError> {
+        if cursors.len() >= self.config.max_open {
+            warn!(open = cursors.len(), "Server-wide cursor limit reached");
+            return Err(ApiError::server_busy(format!(
+                "Too many open cursors on the server ({}); try again later",
+                cursors.len()
+            )));
+        }
+        let open = cursors
+            .values()
+            .filter(|entry| entry.client == client)
+            .count();
+        if open >= self.config.max_per_client {
+            warn!(client = %client, open, "Cursor limit reached");
+            return Err(ApiError::too_many_requests(
+                format!(
+                    "Too many open cursors ({}); read them to the end or close them with DELETE /cursors/{{token}}",
+                    open
+                ),
+                self.config.idle_timeout.as_secs().max(1),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Look up a cursor owned by `client`, marking it as used
+    pub fn get(&self, token: &str, client: &str) -> Option<Arc<Mutex<Cursor>>> {
+        let mut cursors = self.cursors.lock().unwrap_or_else(|e| e.into_inner());
+        self.prune(&mut cursors);
+        let entry = cursors
+            .get_mut(token)
+            .filter(|entry| entry.client == client)?;
+        entry.last_used = Instant::now();
+        Some(entry.cursor.clone())
+    }
+
+    /// Close a cursor owned by `client`; returns whether it existed
+    pub fn remove(&self, token: &str, client: &str) -> bool {
+        let mut cursors = self.cursors.lock().unwrap_or_else(|e| e.into_inner());
+        self.prune(&mut cursors);
+        match cursors.get(token) {
+            Some(entry) if entry.client == client => cursors.remove(token).is_some(),
+            _ => false,
+        }
+    }
+
+    fn prune(&self, cursors: &mut HashMap<String, CursorEntry>) {
+        let idle_timeout = self.config.idle_timeout;
+        cursors.retain(|token, entry| {
+            let keep = entry.last_used.elapsed() < idle_timeout;
+            if !keep {
+                debug!(cursor = %token, "Closing idle cursor");
+            }
+            keep
+        });
+    }
+}
+
+/// Run a paginated query: materialize its result, return the first page and,
+/// when more rows remain, a `next_cursor` token for `GET /cursors/{token}`
+pub async fn open_paginated(
+    state: &AppState,
+    sql: &str,
+    params: BindParams,
+    page_size: usize,
+    priority: Priority,
+    client: &str,
+) -> Result<Value, ApiError> {
+    let statements = split_statements(sql);
+    let [sql] = statements.as_slice() else {
+        return Err(ApiError::bad_request(
+            "page_size requires exactly one SQL statement",
+        ));
+    };
+    if !is_query_statement(sql) {
+        return Err(ApiError::bad_request(
+            "page_size is only supported for SELECT, WITH, VALUES and FROM queries",
+        ));
+    }
+    if page_size == 0 {
+        return Err(ApiError::bad_request("page_size must be at least 1"));
+    }
+    state.cursors.check_capacity(client)?;
+
+    let page_size = effective_row_limit(Some(page_size));
+    let permit = state
+        .admission_for(priority)
+        .acquire(&state.metrics)
+        .await
+        .map_err(|e| ApiError::server_busy(e.to_string()))?;
+
+    let blocking_state = state.clone();
+    let sql = sql.clone();
+    let (mut page, cursor) = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let mut cursor = Cursor::open(&blocking_state, &sql, &params, page_size, priority)?;
+        let page = cursor.next_page()?;
+        Ok::<_, DatabaseError>((page, cursor))
+    })
+    .await
+    .map_err(|e| ApiError::internal_server_error(format!("Task execution error: {}", e)))??;
+
+    // Results that fit in one page need no cursor
+    let next_cursor = if cursor.is_exhausted() {
+        None
+    } else {
+        let token = state.cursors.insert(client, cursor)?;
+        info!(cursor = %token, "Cursor opened");
+        Some(token)
+    };
+    page["next_cursor"] = Value::from(next_cursor);
+    Ok(page)
+}
+
+/// Fetch the next page of a paginated query
+/// The response has the same shape as `/query`; `data.next_cursor` is null once the result is exhausted
+#[utoipa::path(
+    get,
+    path = "/cursors/{token}",
+    params(
+        ("token" = String, Path, description = "Cursor token from `next_cursor`")
+    ),
+    responses(
+        (status = 200, description = "Next page of the result", body = QueryResponse),
+        (status = 404, description = "Unknown, closed or expired cursor"),
+        (status = 503, description = "Server busy - execution queue full or queue wait timed out")
+    ),
+    tag = "query"
+)]
+#[instrument(skip(state, headers, extensions))]
+pub async fn fetch_cursor(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+    extensions: Extensions,
+) -> Result<Json<QueryResponse>, Response> {
+    let query_id = Uuid::new_v4().to_string();
+    let start_time = SystemTime::now();
+    let client = request_client(&state, &headers, &extensions);
+
+    let Some(cursor) = state.cursors.get(&token, &client) else {
+        return Err(cursor_not_found(&token).to_response(Some(query_id)));
+    };
+
+    let priority = cursor.lock().unwrap_or_else(|e| e.into_inner()).priority;
+    let permit = match state.admission_for(priority).acquire(&state.metrics).await {
+        Ok(permit) => permit,
+        Err(e) => {
+            let error = ApiError::server_busy(e.to_string());
+            return Err(error.to_response(Some(query_id)));
+        }
+    };
+
+    let result = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let mut cursor = cursor.lock().unwrap_or_else(|e| e.into_inner());
+        let page = cursor.next_page()?;
+        Ok::<_, DatabaseError>((page, cursor.is_exhausted()))
+    })
+    .await;
+
+    let execution_time_ms = start_time.elapsed().unwrap_or_default().as_millis() as u64;
+
+    match result {
+        Ok(Ok((mut page, exhausted))) => {
+            if exhausted {
+                state.cursors.remove(&token, &client);
+                info!("Cursor exhausted and closed");
+            }
+            page["next_cursor"] = if exhausted {
+                Value::Null
+            } else {
+                Value::from(token)
+            };
+            Ok(Json(QueryResponse {
+                success: true,
+                data: Some(page),
+                error: None,
+                query_id,
+                execution_time_ms,
+                profile: None,
+            }))
+        }
+        Ok(Err(e)) => {
+            error!(execution_time_ms, error = %e, "Cursor fetch failed");
+            Err(ApiError::Database(e).to_response(Some(query_id)))
+        }
+        Err(e) => {
+            error!(execution_time_ms, error = %e, "Task execution failed");
+            let error = ApiError::internal_server_error(format!("Task execution error: {}", e));
+            Err(error.to_response(Some(query_id)))
+        }
+    }
+}
+
+/// Close a cursor before reading it to the end, releasing its connection
+#[utoipa::path(
+    delete,
+    path = "/cursors/{token}",
+    params(
+        ("token" = String, Path, description = "Cursor token from `next_cursor`")
+    ),
+    responses(
+        (status = 204, description = "Cursor closed"),
+        (status = 404, description = "Unknown, closed or expired cursor")
+    ),
+    tag = "query"
+)]
+#[instrument(skip(state, headers, extensions))]
+pub async fn close_cursor(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+    extensions: Extensions,
+) -> Response {
+    let client = request_client(&state, &headers, &extensions);
+    if state.cursors.remove(&token, &client) {
+        info!("Cursor closed by client");
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        cursor_not_found(&token).to_response(None)
+    }
+}
+
+fn cursor_not_found(token: &str) -> ApiError {
+    ApiError::not_found(format!("Cursor '{}' does not exist or has expired", token))
+}
@@ -1,11 +1,35 @@
-use crate::{AppState, DatabaseError};
+use crate::profile::{ProfilingGuard, duration_ms};
+use crate::{AppState, DatabaseError, Priority, QueryProfile, QueryProgress};
+use duckdb::Connection;
 use regex::Regex;
+use serde::Serialize;
 use serde_json;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use tracing::{debug, info, instrument, warn};
 
 const DEFAULT_ROW_LIMIT: usize = 10000;
 const MAX_ROW_LIMIT: usize = 100000;
 
+/// Statements whose result columns can be described without executing them
+const QUERY_KEYWORDS: [&str; 4] = ["SELECT", "WITH", "VALUES", "FROM"];
+
+/// Statements that always produce a result set, even when it looks like a command result
+const ROW_KEYWORDS: [&str; 11] = [
+    "SELECT",
+    "WITH",
+    "VALUES",
+    "FROM",
+    "TABLE",
+    "SHOW",
+    "DESCRIBE",
+    "EXPLAIN",
+    "PRAGMA",
+    "SUMMARIZE",
+    "CALL",
+];
+
 /// Validate that a SQL operation is allowed in read-only mode
 /// Returns an error message if the operation is not allowed, None otherwise
 #[instrument(skip(state))]
@@ -22,7 +46,37 @@ pub fn validate_readonly_operation(state: &AppState, sql: &str) -> Option<String
     }
 }
 
-fn is_write_operation(sql: &str) -> bool {
+/// Validate that a SQL operation is allowed in sandbox mode
+/// Returns an error message if the operation is not allowed, None otherwise
+#[instrument(skip(state))]
+pub fn validate_sandbox_operation(state: &AppState, sql: &str) -> Option<String> {
+    if !state.is_sandboxed {
+        return None;
+    }
+
+    let cleaned_sql = remove_sql_comments(sql);
+    let blocked = cleaned_sql
+        .split(';')
+        .map(|s| s.trim().to_uppercase())
+        .any(|statement| {
+            ["INSTALL", "FORCE INSTALL", "LOAD", "UPDATE EXTENSIONS"]
+                .iter()
+                .any(|pattern| statement.starts_with(pattern))
+        });
+
+    if blocked {
+        warn!("Extension management blocked in sandbox mode");
+        Some(
+            "Server is running in sandbox mode. Installing or loading extensions is not allowed."
+                .to_string(),
+        )
+    } else {
+        None
+    }
+}
+
+/// Whether any statement in `sql` may modify the database
+pub fn is_write_operation(sql: &str) -> bool {
     // Remove SQL comments and normalize whitespace
     let cleaned_sql = remove_sql_comments(sql);
 
@@ -43,21 +97,177 @@ fn is_write_operation(sql: &str) -> bool {
     false
 }
 
-fn remove_sql_comments(sql: &str) -> String {
-    // Remove /* */ block comments
-    let block_comment_regex = Regex::new(r"/\*.*?\*/").unwrap();
-    let mut cleaned = block_comment_regex.replace_all(sql, " ").to_string();
+/// Normalize SQL for comparison: strip comments and collapse whitespace
+pub fn normalize_sql(sql: &str) -> String {
+    remove_sql_comments(sql)
+}
+
+/// First keyword of a statement, uppercased
+pub fn leading_keyword(sql: &str) -> String {
+    normalize_sql(sql)
+        .trim_start_matches('(')
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .next()
+        .unwrap_or_default()
+        .to_ascii_uppercase()
+}
+
+/// Whether a statement is a plain query that can be wrapped in a subquery
+pub fn is_query_statement(sql: &str) -> bool {
+    QUERY_KEYWORDS.contains(&leading_keyword(sql).as_str())
+}
+
+/// Whether an executed statement produced rows rather than a command result.
+/// DuckDB reports commands as a single `Count` column (rows affected) or a `Success` column,
+/// or no columns at all; statements starting with a query keyword always return rows.
+pub fn returns_rows(keyword: &str, column_names: &[&str]) -> bool {
+    ROW_KEYWORDS.contains(&keyword) || !matches!(column_names, [] | ["Count"] | ["Success"])
+}
+
+/// Split a script into individual statements on top-level semicolons.
+/// Semicolons inside string literals, quoted identifiers, dollar-quoted strings
+/// and comments do not end a statement. Empty and comment-only statements are dropped.
+pub fn split_statements(sql: &str) -> Vec<String> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if let Some(end) = quoted_end(&chars, i).or_else(|| comment_end(&chars, i)) {
+            current.extend(&chars[i..end]);
+            i = end;
+            continue;
+        }
+        match chars[i] {
+            ';' => {
+                push_statement(&mut statements, &current);
+                current.clear();
+            }
+            c => current.push(c),
+        }
+        i += 1;
+    }
+
+    push_statement(&mut statements, &current);
+    statements
+}
+
+fn push_statement(statements: &mut Vec<String>, statement: &str) {
+    let statement = statement.trim();
+    // Skip empty and comment-only statements
+    if !remove_sql_comments(statement).is_empty() {
+        statements.push(statement.to_string());
+    }
+}
 
-    // Remove -- line comments
-    let line_comment_regex = Regex::new(r"--.*?(\n|$)").unwrap();
-    cleaned = line_comment_regex.replace_all(&cleaned, "\n").to_string();
+/// End of the string literal, quoted identifier or dollar-quoted string starting at `i`,
+/// or None if none starts there. Unterminated quotes run to the end of the input.
+fn quoted_end(chars: &[char], i: usize) -> Option<usize> {
+    match chars[i] {
+        quote @ ('\'' | '"') => {
+            // Doubled quotes are escapes
+            let mut j = i + 1;
+            while j < chars.len() {
+                if chars[j] == quote {
+                    if chars.get(j + 1) != Some(&quote) {
+                        return Some(j + 1);
+                    }
+                    j += 1;
+                }
+                j += 1;
+            }
+            Some(chars.len())
+        }
+        '$' => {
+            // Dollar quoting: $$...$$ or $tag$...$tag$ ($1 style parameters are not tags)
+            if chars.get(i + 1).is_some_and(|ch| ch.is_ascii_digit()) {
+                return None;
+            }
+            let tag_end = chars[i + 1..]
+                .iter()
+                .position(|ch| !(ch.is_alphanumeric() || *ch == '_'))
+                .map(|offset| i + 1 + offset)
+                .filter(|end| chars[*end] == '$')?;
+            let tag = &chars[i..=tag_end];
+            let body = tag_end + 1;
+            Some(
+                (body..chars.len())
+                    .find(|j| chars[*j..].starts_with(tag))
+                    .map_or(chars.len(), |j| j + tag.len()),
+            )
+        }
+        _ => None,
+    }
+}
 
-    // Normalize whitespace
-    let whitespace_regex = Regex::new(r"\s+").unwrap();
-    whitespace_regex
-        .replace_all(&cleaned, " ")
-        .trim()
-        .to_string()
+/// End of the `--` or `/* */` comment starting at `i`, or None if none starts there.
+/// A line comment ends before its newline.
+fn comment_end(chars: &[char], i: usize) -> Option<usize> {
+    match (chars[i], chars.get(i + 1)) {
+        ('-', Some('-')) => Some(
+            (i + 2..chars.len())
+                .find(|j| chars[*j] == '\n')
+                .unwrap_or(chars.len()),
+        ),
+        ('/', Some('*')) => Some(
+            (i + 2..chars.len())
+                .find(|j| chars[*j] == '*' && chars.get(j + 1) == Some(&'/'))
+                .map_or(chars.len(), |j| j + 2),
+        ),
+        _ => None,
+    }
+}
+
+/// Replace every string literal and dollar-quoted string with `replacement`.
+/// Quoted identifiers and comments are kept as written.
+pub fn replace_string_literals(sql: &str, replacement: &str) -> String {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut replaced = String::with_capacity(sql.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let end = comment_end(&chars, i)
+            .or_else(|| quoted_end(&chars, i))
+            .unwrap_or(i + 1);
+        if matches!(chars[i], '\'' | '$') && end > i + 1 {
+            replaced.push_str(replacement);
+        } else {
+            replaced.extend(&chars[i..end]);
+        }
+        i = end;
+    }
+    replaced
+}
+
+/// Strip comments and collapse whitespace runs to one space. Quoted strings and identifiers
+/// are kept byte for byte, so `'a  b'` and `'x--1'` survive unchanged.
+fn remove_sql_comments(sql: &str) -> String {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut cleaned = String::with_capacity(sql.len());
+    let mut separated = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        if let Some(end) = comment_end(&chars, i) {
+            separated = true;
+            i = end;
+            continue;
+        }
+        if chars[i].is_whitespace() {
+            separated = true;
+            i += 1;
+            continue;
+        }
+        if separated && !cleaned.is_empty() {
+            cleaned.push(' ');
+        }
+        separated = false;
+        let end = quoted_end(&chars, i).unwrap_or(i + 1);
+        cleaned.extend(&chars[i..end]);
+        i = end;
+    }
+    cleaned
 }
 
 fn is_single_statement_write_operation(statement: &str) -> bool {
@@ -102,10 +312,9 @@ fn is_single_statement_write_operation(statement: &str) -> bool {
                 }
                 if let (Some(from_pos), Some(to_pos)) =
                     (statement_trimmed.find("FROM"), statement_trimmed.find("TO"))
+                    && from_pos < to_pos
                 {
-                    if from_pos < to_pos {
-                        return true;
-                    }
+                    return true;
                 }
                 continue;
             }
@@ -128,44 +337,134 @@ fn is_single_statement_write_operation(statement: &str) -> bool {
 
 /// Execute a SQL query without a row limit
 pub fn execute_sql(state: &AppState, sql: &str) -> Result<serde_json::Value, DatabaseError> {
-    execute_sql_with_limit(state, sql, None)
+    execute_sql_with_limit(state, sql, None, Priority::Interactive)
+}
+
+/// Row limit applied to a query: the requested limit clamped to MAX_ROW_LIMIT,
+/// or DEFAULT_ROW_LIMIT when none is requested
+pub fn effective_row_limit(row_limit: Option<usize>) -> usize {
+    row_limit.unwrap_or(DEFAULT_ROW_LIMIT).min(MAX_ROW_LIMIT)
 }
 
 /// Execute a SQL query with an optional row limit
 /// If limit is provided, it will be clamped to MAX_ROW_LIMIT
 /// If no limit is provided, DEFAULT_ROW_LIMIT is used
-
+/// The connection is taken from the pool reserved for the given priority
 #[instrument(skip(state))]
 pub fn execute_sql_with_limit(
     state: &AppState,
     sql: &str,
     row_limit: Option<usize>,
+    priority: Priority,
+) -> Result<serde_json::Value, DatabaseError> {
+    execute_sql_with_progress(state, sql, row_limit, priority, None)
+}
+
+/// Execute a SQL query like `execute_sql_with_limit`, counting rows read into `progress`
+#[instrument(skip(state, progress))]
+pub fn execute_sql_with_progress(
+    state: &AppState,
+    sql: &str,
+    row_limit: Option<usize>,
+    priority: Priority,
+    progress: Option<&QueryProgress>,
+) -> Result<serde_json::Value, DatabaseError> {
+    execute_sql_with_params(
+        state,
+        sql,
+        &BindParams::default(),
+        row_limit,
+        priority,
+        progress,
+    )
+}
+
+/// Execute a SQL query like `execute_sql_with_progress`, binding `params` to its placeholders
+#[instrument(skip(state, params, progress))]
+pub fn execute_sql_with_params(
+    state: &AppState,
+    sql: &str,
+    params: &BindParams,
+    row_limit: Option<usize>,
+    priority: Priority,
+    progress: Option<&QueryProgress>,
+) -> Result<serde_json::Value, DatabaseError> {
+    run_query(state, sql, params, row_limit, priority, progress, None)
+}
+
+/// Execute a SQL query like `execute_sql_with_progress` with DuckDB profiling enabled for
+/// this statement only, returning where the time went alongside the result
+#[instrument(skip(state, params, progress))]
+pub fn execute_sql_with_profile(
+    state: &AppState,
+    sql: &str,
+    params: &BindParams,
+    row_limit: Option<usize>,
+    priority: Priority,
+    progress: Option<&QueryProgress>,
+) -> Result<(serde_json::Value, QueryProfile), DatabaseError> {
+    let mut profile = QueryProfile::default();
+    let response = run_query(
+        state,
+        sql,
+        params,
+        row_limit,
+        priority,
+        progress,
+        Some(&mut profile),
+    )?;
+    Ok((response, profile))
+}
+
+fn run_query(
+    state: &AppState,
+    sql: &str,
+    params: &BindParams,
+    row_limit: Option<usize>,
+    priority: Priority,
+    progress: Option<&QueryProgress>,
+    profile: Option<&mut QueryProfile>,
 ) -> Result<serde_json::Value, DatabaseError> {
-    let limit = row_limit.unwrap_or(DEFAULT_ROW_LIMIT).min(MAX_ROW_LIMIT);
+    let limit = effective_row_limit(row_limit);
 
     debug!("Acquiring database connection from pool");
-    let conn = state.pool.get()?;
+    let phase_start = Instant::now();
+    let conn = state.pool_for(priority).get()?;
+    let connection_wait = phase_start.elapsed();
+
+    // Sandboxed connections lock their settings, so DuckDB's profile is unavailable there
+    let profiler = match profile {
+        Some(_) if !state.is_sandboxed => ProfilingGuard::start(&conn),
+        _ => None,
+    };
+
     debug!("Preparing SQL statement");
+    let phase_start = Instant::now();
     let mut stmt = conn.prepare(sql)?;
+    let prepare = phase_start.elapsed();
+    let values = params.values(&stmt)?;
 
     debug!("Executing query");
-    let rows = stmt.query_map([], |row| {
+    let phase_start = Instant::now();
+    let serialize = Cell::new(Duration::ZERO);
+    let rows = stmt.query_map(duckdb::params_from_iter(values), |row| {
+        let convert_start = Instant::now();
         let column_count = row.as_ref().column_count();
         let mut row_data = Vec::new();
         for i in 0..column_count {
             let value = convert_value_to_json(row.get_ref(i))?;
             row_data.push(value);
         }
+        serialize.set(serialize.get() + convert_start.elapsed());
         Ok((column_count, row_data))
     })?;
 
     let mut result_rows = Vec::new();
     let mut detected_column_count = 0;
-    let mut row_count = 0;
     let mut truncated = false;
 
     debug!("Processing query results");
-    for row_result in rows {
+    for (row_count, row_result) in rows.enumerate() {
         if row_count >= limit {
             truncated = true;
             warn!("Query results truncated at {} rows", limit);
@@ -173,11 +472,13 @@ pub fn execute_sql_with_limit(
         }
 
         let (row_column_count, row_data) = row_result?;
+        if let Some(progress) = progress {
+            progress.add_rows(1);
+        }
         if detected_column_count == 0 {
             detected_column_count = row_column_count;
         }
         result_rows.push(row_data);
-        row_count += 1;
     }
 
     let column_count = if detected_column_count > 0 {
@@ -189,6 +490,17 @@ pub fn execute_sql_with_limit(
     let column_names = get_column_names(&stmt, column_count)?;
     let column_types = get_column_types(&stmt, column_count)?;
 
+    if let Some(profile) = profile {
+        let serialize = serialize.get();
+        profile.connection_wait_ms = duration_ms(connection_wait);
+        profile.prepare_ms = duration_ms(prepare);
+        profile.execute_ms = duration_ms(phase_start.elapsed().saturating_sub(serialize));
+        profile.serialize_ms = duration_ms(serialize);
+        // DuckDB writes the profile once the statement is finalized
+        drop(stmt);
+        profile.duckdb = profiler.and_then(ProfilingGuard::finish);
+    }
+
     let mut response = serde_json::json!({
         "columns": column_names,
         "column_types": column_types,
@@ -215,7 +527,207 @@ pub fn execute_sql_with_limit(
     Ok(response)
 }
 
-fn convert_value_to_json(
+/// Values for a statement's named `$name` parameters
+pub type NamedParams = HashMap<String, duckdb::types::Value>;
+
+/// Values bound to a statement's placeholders
+#[derive(Debug, Clone)]
+pub enum BindParams {
+    /// Values for `?` or `$1` placeholders, in order
+    Positional(Vec<duckdb::types::Value>),
+    /// Values for `$name` placeholders; missing names are bound as NULL
+    Named(NamedParams),
+}
+
+impl Default for BindParams {
+    fn default() -> Self {
+        BindParams::Positional(Vec::new())
+    }
+}
+
+impl BindParams {
+    /// Positional parameters from JSON values
+    pub fn from_json(values: &[serde_json::Value]) -> Self {
+        BindParams::Positional(values.iter().map(json_to_value).collect())
+    }
+
+    /// Values in the order of the prepared statement's parameters
+    pub fn values(
+        &self,
+        stmt: &duckdb::Statement<'_>,
+    ) -> Result<Vec<duckdb::types::Value>, DatabaseError> {
+        match self {
+            BindParams::Positional(values) => Ok(values.clone()),
+            BindParams::Named(params) => (1..=stmt.parameter_count())
+                .map(|index| {
+                    let name = stmt.parameter_name(index)?;
+                    Ok(params
+                        .get(&name)
+                        .cloned()
+                        .unwrap_or(duckdb::types::Value::Null))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// DuckDB value for a JSON bind parameter; arrays and objects are bound as JSON text
+fn json_to_value(value: &serde_json::Value) -> duckdb::types::Value {
+    use duckdb::types::Value as DuckValue;
+    match value {
+        serde_json::Value::Null => DuckValue::Null,
+        serde_json::Value::Bool(b) => DuckValue::Boolean(*b),
+        serde_json::Value::Number(n) => match (n.as_i64(), n.as_u64(), n.as_f64()) {
+            (Some(i), _, _) => DuckValue::BigInt(i),
+            (None, Some(u), _) => DuckValue::UBigInt(u),
+            (None, None, Some(f)) => DuckValue::Double(f),
+            _ => DuckValue::Null,
+        },
+        serde_json::Value::String(s) => DuckValue::Text(s.clone()),
+        other => DuckValue::Text(other.to_string()),
+    }
+}
+
+/// A batch of result rows produced by `stream_sql_with_limit`
+#[derive(Debug, Serialize)]
+pub struct ResultChunk {
+    /// Column names
+    pub columns: Vec<String>,
+    /// SQL type names of the columns
+    pub column_types: Vec<String>,
+    /// Position of the first row of this chunk within the whole result
+    pub offset: usize,
+    /// Row values
+    pub rows: Vec<Vec<serde_json::Value>>,
+}
+
+/// Execute a SQL query on the given connection, handing rows to `on_chunk` in
+/// batches of up to `chunk_size` as DuckDB produces them
+/// Returns the same summary as `execute_sql_with_limit`, without the `rows` array
+/// Reading stops early, without an error, when `on_chunk` returns false
+pub fn stream_sql_with_limit(
+    conn: &Connection,
+    sql: &str,
+    row_limit: Option<usize>,
+    chunk_size: usize,
+    on_chunk: impl FnMut(ResultChunk) -> bool,
+) -> Result<serde_json::Value, DatabaseError> {
+    stream_sql_with_params(
+        conn,
+        sql,
+        &BindParams::default(),
+        row_limit,
+        chunk_size,
+        on_chunk,
+    )
+}
+
+/// Stream a SQL query like `stream_sql_with_limit`, binding `params` to its placeholders
+#[instrument(skip(conn, params, on_chunk))]
+pub fn stream_sql_with_params(
+    conn: &Connection,
+    sql: &str,
+    params: &BindParams,
+    row_limit: Option<usize>,
+    chunk_size: usize,
+    mut on_chunk: impl FnMut(ResultChunk) -> bool,
+) -> Result<serde_json::Value, DatabaseError> {
+    let limit = effective_row_limit(row_limit);
+    let chunk_size = chunk_size.max(1);
+
+    debug!("Preparing SQL statement");
+    let mut stmt = conn.prepare(sql)?;
+    let values = params.values(&stmt)?;
+    debug!("Executing query");
+    let mut rows = stmt.query(duckdb::params_from_iter(values))?;
+
+    let mut columns: Option<(Vec<String>, Vec<String>)> = None;
+    let mut chunk = Vec::new();
+    let mut row_count = 0;
+    let mut truncated = false;
+
+    while let Some(row) = rows.next()? {
+        if row_count >= limit {
+            truncated = true;
+            warn!("Query results truncated at {} rows", limit);
+            break;
+        }
+
+        let column_count = row.as_ref().column_count();
+        let row_data = (0..column_count)
+            .map(|i| convert_value_to_json(row.get_ref(i)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (names, types) = match &columns {
+            Some(columns) => columns,
+            None => columns.insert((
+                get_column_names(row.as_ref(), column_count)?,
+                get_column_types(row.as_ref(), column_count)?,
+            )),
+        };
+
+        chunk.push(row_data);
+        row_count += 1;
+        if chunk.len() >= chunk_size {
+            let keep_reading = on_chunk(ResultChunk {
+                columns: names.clone(),
+                column_types: types.clone(),
+                offset: row_count - chunk.len(),
+                rows: std::mem::take(&mut chunk),
+            });
+            if !keep_reading {
+                debug!("Result consumer stopped reading");
+                break;
+            }
+        }
+    }
+    drop(rows);
+
+    let (names, types) = match columns {
+        Some(columns) => columns,
+        None => {
+            let column_count = stmt.column_count();
+            (
+                get_column_names(&stmt, column_count)?,
+                get_column_types(&stmt, column_count)?,
+            )
+        }
+    };
+
+    if !chunk.is_empty() {
+        on_chunk(ResultChunk {
+            columns: names.clone(),
+            column_types: types.clone(),
+            offset: row_count - chunk.len(),
+            rows: chunk,
+        });
+    }
+
+    let mut response = serde_json::json!({
+        "columns": names,
+        "column_types": types,
+        "row_count": row_count,
+        "limit_applied": limit
+    });
+
+    if truncated {
+        response["truncated"] = serde_json::Value::Bool(true);
+        response["message"] = serde_json::Value::String(format!(
+            "Results truncated to {} rows. Use limit parameter or streaming for larger datasets.",
+            limit
+        ));
+    }
+
+    info!(
+        row_count = row_count,
+        truncated = truncated,
+        "Streamed query execution completed"
+    );
+
+    Ok(response)
+}
+
+/// Convert a DuckDB value to JSON
+pub fn convert_value_to_json(
     value_ref_result: Result<duckdb::types::ValueRef, duckdb::Error>,
 ) -> Result<serde_json::Value, duckdb::Error> {
     match value_ref_result {
@@ -272,7 +784,11 @@ fn convert_value_to_json(
                     // Convert timestamp to string representation
                     serde_json::Value::String(ts.to_string())
                 }
-                ValueRef::Interval { months, days, nanos } => {
+                ValueRef::Interval {
+                    months,
+                    days,
+                    nanos,
+                } => {
                     // Convert interval to string representation
                     serde_json::Value::String(format!("{}M {}D {}ns", months, days, nanos))
                 }
@@ -281,7 +797,7 @@ fn convert_value_to_json(
                     serde_json::Value::String("<LIST>".to_string())
                 }
                 ValueRef::Map(_, _) => {
-                    // For now, convert map to basic string representation  
+                    // For now, convert map to basic string representation
                     serde_json::Value::String("<MAP>".to_string())
                 }
                 ValueRef::Struct(_, _) => {
@@ -300,7 +816,8 @@ fn convert_value_to_json(
     }
 }
 
-fn get_column_names(
+/// Names of the first `column_count` result columns of an executed statement
+pub fn get_column_names(
     stmt: &duckdb::Statement,
     column_count: usize,
 ) -> Result<Vec<String>, DatabaseError> {
@@ -315,7 +832,8 @@ fn get_column_names(
     Ok(column_names)
 }
 
-fn get_column_types(
+/// SQL type names of the first `column_count` result columns of an executed statement
+pub fn get_column_types(
     stmt: &duckdb::Statement,
     column_count: usize,
 ) -> Result<Vec<String>, DatabaseError> {
@@ -328,10 +846,10 @@ fn get_column_types(
         let sql_type = match type_str.as_str() {
             s if s.contains("Int32") => "INTEGER",
             s if s.contains("Int64") => "BIGINT",
-            s if s.contains("Int8") => "TINYINT", 
+            s if s.contains("Int8") => "TINYINT",
             s if s.contains("Int16") => "SMALLINT",
             s if s.contains("UInt8") => "UTINYINT",
-            s if s.contains("UInt16") => "USMALLINT", 
+            s if s.contains("UInt16") => "USMALLINT",
             s if s.contains("UInt32") => "UINTEGER",
             s if s.contains("UInt64") => "UBIGINT",
             s if s.contains("Float32") => "FLOAT",
@@ -357,16 +875,22 @@ fn get_column_types(
     Ok(column_types)
 }
 
+/// Execute a SQL command that does not return rows
+/// The connection is taken from the pool reserved for the given priority
 #[instrument(skip(state))]
 pub fn execute_sql_command(
     state: &AppState,
     sql: &str,
+    params: &BindParams,
+    priority: Priority,
 ) -> Result<serde_json::Value, DatabaseError> {
     debug!("Acquiring database connection from pool for command execution");
-    let conn = state.pool.get()?;
+    let conn = state.pool_for(priority).get()?;
 
     debug!("Executing SQL command");
-    let updated = conn.execute(sql, [])?;
+    let mut stmt = conn.prepare(sql)?;
+    let values = params.values(&stmt)?;
+    let updated = stmt.execute(duckdb::params_from_iter(values))?;
 
     info!(rows_affected = updated, "Command execution completed");
 
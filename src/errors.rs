@@ -1,7 +1,203 @@
-use axum::http::StatusCode;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::middleware::Next;
 use axum::response::{IntoResponse, Json, Response};
-use serde::Serialize;
 use thiserror::Error;
+use unicode_width::UnicodeWidthChar;
+
+pub use rsduck_types::{
+    ErrorDetail, ErrorPosition, ErrorResponse, PROBLEM_JSON_CONTENT_TYPE, ProblemDetails,
+};
+
+/// Largest error body `--verbose-errors` rewrites
+const MAX_ERROR_BODY: usize = 1024 * 1024;
+
+/// Kind of a DuckDB error, from the exception type DuckDB prefixes its messages with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuckDbErrorKind {
+    /// A table, view, schema or function does not exist, or already exists
+    Catalog,
+    /// The SQL could not be parsed
+    Parser,
+    /// Unknown column, ambiguous reference or wrong argument types
+    Binder,
+    /// Primary key, unique, foreign key, check or NOT NULL constraint violated
+    Constraint,
+    /// A value could not be cast or is out of range for its type
+    Conversion,
+    /// Invalid argument, parameter or setting
+    InvalidInput,
+    /// The statement exceeded DuckDB's memory limit
+    OutOfMemory,
+    /// The statement was interrupted, e.g. on cancellation or shutdown
+    Interrupt,
+    /// Reading or writing a file or remote resource failed
+    Io,
+    /// Write-write conflict or an aborted transaction
+    Transaction,
+    /// Dropping or altering an object other objects depend on
+    Dependency,
+    /// Operation disabled by DuckDB's configuration, e.g. in sandbox mode
+    Permission,
+    /// Feature DuckDB does not support
+    NotImplemented,
+    /// Extension that is not installed or loaded
+    MissingExtension,
+    /// Internal DuckDB failure
+    Internal,
+    /// Anything else
+    Other,
+}
+
+impl DuckDbErrorKind {
+    /// Classify a DuckDB error
+    pub fn of(error: &duckdb::Error) -> Self {
+        match error {
+            duckdb::Error::DuckDBFailure(..) => Self::from_message(&error.to_string()),
+            duckdb::Error::FromSqlConversionFailure(..)
+            | duckdb::Error::IntegralValueOutOfRange(..)
+            | duckdb::Error::InvalidColumnType(..)
+            | duckdb::Error::ToSqlConversionFailure(..) => Self::Conversion,
+            duckdb::Error::InvalidParameterName(_)
+            | duckdb::Error::InvalidParameterCount(..)
+            | duckdb::Error::MultipleStatement => Self::InvalidInput,
+            _ => Self::Other,
+        }
+    }
+
+    /// Classify a DuckDB error message by its `<Type> Error:` prefix
+    pub fn from_message(message: &str) -> Self {
+        let exception_type = message
+            .split_once(" Error:")
+            .map_or("", |(prefix, _)| prefix);
+        match exception_type {
+            "Catalog" => Self::Catalog,
+            "Parser" | "Syntax" => Self::Parser,
+            "Binder" | "Parameter Not Resolved" => Self::Binder,
+            "Constraint" => Self::Constraint,
+            "Conversion" | "Out of Range" | "Decimal" | "Mismatch Type" | "Divide by Zero"
+            | "Invalid type" | "Unknown Type" => Self::Conversion,
+            "Invalid Input"
+            | "Invalid"
+            | "Parameter Not Allowed"
+            | "Settings"
+            | "Invalid Configuration"
+            | "Sequence" => Self::InvalidInput,
+            "Out of Memory" => Self::OutOfMemory,
+            "INTERRUPT" => Self::Interrupt,
+            "IO" | "HTTP" => Self::Io,
+            "TransactionContext" => Self::Transaction,
+            "Dependency" => Self::Dependency,
+            "Permission" => Self::Permission,
+            "Not implemented" => Self::NotImplemented,
+            "Missing Extension" | "Extension Autoloading" => Self::MissingExtension,
+            "INTERNAL" | "FATAL" => Self::Internal,
+            _ => Self::Other,
+        }
+    }
+
+    /// Stable error code reported to clients
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            Self::Catalog => "CATALOG_ERROR",
+            Self::Parser => "SYNTAX_ERROR",
+            Self::Binder => "BINDER_ERROR",
+            Self::Constraint => "CONSTRAINT_VIOLATION",
+            Self::Conversion => "CONVERSION_ERROR",
+            Self::InvalidInput => "INVALID_INPUT",
+            Self::OutOfMemory => "OUT_OF_MEMORY",
+            Self::Interrupt => "QUERY_INTERRUPTED",
+            Self::Io => "IO_ERROR",
+            Self::Transaction => "TRANSACTION_CONFLICT",
+            Self::Dependency => "DEPENDENCY_ERROR",
+            Self::Permission => "PERMISSION_DENIED",
+            Self::NotImplemented => "NOT_IMPLEMENTED",
+            Self::MissingExtension => "MISSING_EXTENSION",
+            Self::Internal => "INTERNAL_DATABASE_ERROR",
+            Self::Other => "DATABASE_QUERY_ERROR",
+        }
+    }
+
+    /// HTTP status for this kind of error
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Constraint | Self::Transaction | Self::Dependency => StatusCode::CONFLICT,
+            Self::OutOfMemory => StatusCode::INSUFFICIENT_STORAGE,
+            // Same non-standard status as client cancellation
+            Self::Interrupt => StatusCode::from_u16(499).unwrap_or(StatusCode::BAD_REQUEST),
+            Self::Permission => StatusCode::FORBIDDEN,
+            Self::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// Message shown instead of DuckDB's, which may name tables and columns the client cannot see
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Catalog => {
+                "Referenced table, view, schema or function does not exist or already exists"
+            }
+            Self::Parser => "SQL syntax error",
+            Self::Binder => {
+                "Unknown or ambiguous column, or function called with the wrong argument types"
+            }
+            Self::Constraint => "Statement violates a constraint",
+            Self::Conversion => "Value cannot be converted to the required type",
+            Self::InvalidInput => "Invalid argument, parameter or setting",
+            Self::OutOfMemory => "Statement exceeded the database memory limit",
+            Self::Interrupt => "Statement was interrupted",
+            Self::Io => "Reading or writing a file failed",
+            Self::Transaction => "Transaction conflict; the transaction was rolled back",
+            Self::Dependency => "Other objects depend on this object",
+            Self::Permission => "Operation not permitted by the database configuration",
+            Self::NotImplemented => "Feature not supported by DuckDB",
+            Self::MissingExtension => "Required DuckDB extension is not loaded",
+            Self::Internal => "Internal database error",
+            Self::Other => "Database query failed",
+        }
+    }
+}
+
+/// Position of a syntax or binder error, from the `LINE n: ...` context DuckDB appends to its
+/// message with a `^` under the offending character
+pub fn error_position(message: &str) -> Option<ErrorPosition> {
+    let mut lines = message.lines();
+    let context = lines.find(|line| line.starts_with("LINE "))?;
+    let (label, snippet) = context.split_once(": ")?;
+    let line = label.strip_prefix("LINE ")?.parse().ok()?;
+    let caret_line = lines.next()?;
+    let caret = caret_line[..caret_line.find('^')?].chars().count();
+
+    // Long lines are shortened around the error, so the caret no longer gives the column
+    let prefix = label.chars().count() + 2;
+    if snippet.starts_with("...") || caret < prefix {
+        return Some(ErrorPosition { line, column: None });
+    }
+
+    // DuckDB places the caret by display width, where wide characters take two cells, so walk
+    // the snippet character by character rather than slicing it at that offset
+    let target = caret - prefix;
+    let mut width = 0;
+    let column = snippet
+        .chars()
+        .take_while(|c| {
+            let before = width;
+            width += c.width().unwrap_or(0);
+            before < target
+        })
+        .count() as u32
+        + 1;
+    Some(ErrorPosition {
+        line,
+        column: Some(column),
+    })
+}
+
+/// Raw message of a database error, attached to its response for `--verbose-errors`
+#[derive(Debug, Clone)]
+pub struct RawErrorMessage(pub String);
 
 /// Database-related errors
 #[derive(Error, Debug)]
@@ -19,15 +215,48 @@ pub enum DatabaseError {
     Json(#[from] serde_json::Error),
 }
 
+impl DatabaseError {
+    /// Error code reported to clients for this error
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            DatabaseError::Pool(_) => "DATABASE_POOL_ERROR",
+            DatabaseError::DuckDb(e) => DuckDbErrorKind::of(e).error_code(),
+            DatabaseError::TaskJoin(_) => "TASK_EXECUTION_ERROR",
+            DatabaseError::Json(_) => "JSON_SERIALIZATION_ERROR",
+        }
+    }
+}
+
 /// API-level errors with structured responses
 #[derive(Error, Debug)]
 pub enum ApiError {
     #[error("Bad Request: {message}")]
     BadRequest { message: String },
 
+    #[error("Unauthorized: {message}")]
+    Unauthorized { message: String },
+
     #[error("Forbidden: {message}")]
     Forbidden { message: String },
 
+    #[error("Not Found: {message}")]
+    NotFound { message: String },
+
+    #[error("Conflict: {message}")]
+    Conflict { message: String },
+
+    #[error("Too Many Requests: {message}")]
+    TooManyRequests {
+        message: String,
+        retry_after_secs: u64,
+    },
+
+    #[error("Service Unavailable: {message}")]
+    ServerBusy { message: String },
+
+    #[error("Query Cancelled: {message}")]
+    Cancelled { message: String },
+
     #[error("Internal Server Error: {message}")]
     InternalServerError { message: String },
 
@@ -35,23 +264,6 @@ pub enum ApiError {
     Database(#[from] DatabaseError),
 }
 
-/// Structured error response sent to clients
-#[derive(Serialize)]
-pub struct ErrorResponse {
-    pub success: bool,
-    pub error: ErrorDetail,
-    pub query_id: Option<String>,
-    pub timestamp: u64,
-}
-
-/// Detailed error information
-#[derive(Serialize)]
-pub struct ErrorDetail {
-    pub code: String,
-    pub message: String,
-    pub details: Option<String>,
-}
-
 impl ApiError {
     /// Create a bad request error
     pub fn bad_request(message: impl Into<String>) -> Self {
@@ -60,6 +272,13 @@ impl ApiError {
         }
     }
 
+    /// Create an unauthorized error for missing or invalid API keys
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::Unauthorized {
+            message: message.into(),
+        }
+    }
+
     /// Create a forbidden error  
     pub fn forbidden(message: impl Into<String>) -> Self {
         Self::Forbidden {
@@ -67,6 +286,42 @@ impl ApiError {
         }
     }
 
+    /// Create a not found error for unknown resources
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::NotFound {
+            message: message.into(),
+        }
+    }
+
+    /// Create a conflict error for resources that already exist
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::Conflict {
+            message: message.into(),
+        }
+    }
+
+    /// Create a too many requests error with a `Retry-After` hint
+    pub fn too_many_requests(message: impl Into<String>, retry_after_secs: u64) -> Self {
+        Self::TooManyRequests {
+            message: message.into(),
+            retry_after_secs,
+        }
+    }
+
+    /// Create a server busy error for statements rejected by admission control
+    pub fn server_busy(message: impl Into<String>) -> Self {
+        Self::ServerBusy {
+            message: message.into(),
+        }
+    }
+
+    /// Create an error for a query the client cancelled while it was running
+    pub fn cancelled(message: impl Into<String>) -> Self {
+        Self::Cancelled {
+            message: message.into(),
+        }
+    }
+
     /// Create an internal server error
     pub fn internal_server_error(message: impl Into<String>) -> Self {
         Self::InternalServerError {
@@ -78,11 +333,20 @@ impl ApiError {
     pub fn status_code(&self) -> StatusCode {
         match self {
             ApiError::BadRequest { .. } => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized { .. } => StatusCode::UNAUTHORIZED,
             ApiError::Forbidden { .. } => StatusCode::FORBIDDEN,
+            ApiError::NotFound { .. } => StatusCode::NOT_FOUND,
+            ApiError::Conflict { .. } => StatusCode::CONFLICT,
+            ApiError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::ServerBusy { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            // Non-standard "client closed request" status popularised by nginx
+            ApiError::Cancelled { .. } => {
+                StatusCode::from_u16(499).unwrap_or(StatusCode::BAD_REQUEST)
+            }
             ApiError::InternalServerError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::Database(db_err) => match db_err {
                 DatabaseError::Pool(_) => StatusCode::SERVICE_UNAVAILABLE,
-                DatabaseError::DuckDb(_) => StatusCode::BAD_REQUEST,
+                DatabaseError::DuckDb(e) => DuckDbErrorKind::of(e).status_code(),
                 DatabaseError::TaskJoin(_) => StatusCode::INTERNAL_SERVER_ERROR,
                 DatabaseError::Json(_) => StatusCode::INTERNAL_SERVER_ERROR,
             },
@@ -93,45 +357,143 @@ impl ApiError {
     pub fn error_code(&self) -> &'static str {
         match self {
             ApiError::BadRequest { .. } => "BAD_REQUEST",
+            ApiError::Unauthorized { .. } => "UNAUTHORIZED",
             ApiError::Forbidden { .. } => "FORBIDDEN",
+            ApiError::NotFound { .. } => "NOT_FOUND",
+            ApiError::Conflict { .. } => "CONFLICT",
+            ApiError::TooManyRequests { .. } => "RATE_LIMITED",
+            ApiError::ServerBusy { .. } => "SERVER_BUSY",
+            ApiError::Cancelled { .. } => "QUERY_CANCELLED",
             ApiError::InternalServerError { .. } => "INTERNAL_SERVER_ERROR",
-            ApiError::Database(db_err) => match db_err {
-                DatabaseError::Pool(_) => "DATABASE_POOL_ERROR",
-                DatabaseError::DuckDb(_) => "DATABASE_QUERY_ERROR",
-                DatabaseError::TaskJoin(_) => "TASK_EXECUTION_ERROR",
-                DatabaseError::Json(_) => "JSON_SERIALIZATION_ERROR",
-            },
+            ApiError::Database(db_err) => db_err.error_code(),
         }
     }
 
-    /// Convert error to HTTP response with optional query ID
-    pub fn to_response(&self, query_id: Option<String>) -> Response {
+    /// Build the structured error body with optional query ID
+    pub fn to_error_response(&self, query_id: Option<String>) -> ErrorResponse {
         let timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .map(|d| d.as_secs())
             .unwrap_or(0);
 
-        let error_response = ErrorResponse {
+        let message = match self {
+            // DuckDB's own message is only sent with `--verbose-errors`
+            ApiError::Database(DatabaseError::DuckDb(e)) => {
+                let kind = DuckDbErrorKind::of(e);
+                format!("{}: {}", self.title(), kind.description())
+            }
+            _ => self.to_string(),
+        };
+
+        ErrorResponse {
             success: false,
             error: ErrorDetail {
                 code: self.error_code().to_string(),
-                message: self.to_string(),
-                details: match self {
-                    ApiError::Database(DatabaseError::DuckDb(e)) => {
-                        // Provide sanitized database error details
-                        Some(sanitize_database_error(e))
-                    }
-                    _ => None,
-                },
+                message,
+                details: None,
             },
             query_id,
             timestamp,
+        }
+    }
+
+    /// Short summary of the kind of error, the `title` of its problem document
+    pub fn title(&self) -> String {
+        match self {
+            ApiError::Database(DatabaseError::DuckDb(e)) => {
+                let code = DuckDbErrorKind::of(e).error_code();
+                let mut title = code.replace('_', " ").to_ascii_lowercase();
+                title[..1].make_ascii_uppercase();
+                title
+            }
+            _ => self
+                .status_code()
+                .canonical_reason()
+                .unwrap_or("Error")
+                .to_string(),
+        }
+    }
+
+    /// Build the RFC 7807 problem document of this error
+    pub fn to_problem(&self, query_id: Option<String>) -> ProblemDetails {
+        let response = self.to_error_response(query_id);
+        let position = match self {
+            ApiError::Database(DatabaseError::DuckDb(e)) => error_position(&e.to_string()),
+            _ => None,
         };
+        ProblemDetails {
+            problem_type: format!(
+                "urn:rsduck:error:{}",
+                self.error_code().to_ascii_lowercase().replace('_', "-")
+            ),
+            title: self.title(),
+            status: self.status_code().as_u16(),
+            detail: response.error.message.clone(),
+            position,
+            statement: None,
+            response,
+        }
+    }
 
-        (self.status_code(), Json(error_response)).into_response()
+    /// Convert error to an `application/problem+json` HTTP response with optional query ID
+    pub fn to_response(&self, query_id: Option<String>) -> Response {
+        self.problem_response(self.to_problem(query_id))
+    }
+
+    /// Send `problem`, built by [`ApiError::to_problem`] and possibly extended, as this error's
+    /// HTTP response
+    pub fn problem_response(&self, problem: ProblemDetails) -> Response {
+        let mut response = (self.status_code(), Json(problem)).into_response();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(PROBLEM_JSON_CONTENT_TYPE),
+        );
+        if let ApiError::TooManyRequests {
+            retry_after_secs, ..
+        } = self
+        {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(*retry_after_secs));
+        }
+        if let ApiError::Database(e) = self {
+            let raw = match e {
+                DatabaseError::DuckDb(e) => e.to_string(),
+                other => other.to_string(),
+            };
+            response.extensions_mut().insert(RawErrorMessage(raw));
+        }
+        response
     }
 }
 
+/// Middleware for `--verbose-errors`: adds the raw database message to error responses, as
+/// `error.details` and appended to `detail`
+pub async fn verbose_errors_middleware(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let Some(RawErrorMessage(raw)) = response.extensions().get::<RawErrorMessage>().cloned() else {
+        return response;
+    };
+
+    let (parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_ERROR_BODY).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let body = match serde_json::from_slice::<ProblemDetails>(&bytes) {
+        Ok(mut problem) => {
+            problem.detail = format!("{} ({})", problem.detail, raw);
+            problem.response.error.details = Some(raw);
+            serde_json::to_vec(&problem)
+                .map(Body::from)
+                .unwrap_or(Body::from(bytes))
+        }
+        Err(_) => Body::from(bytes),
+    };
+    let mut response = Response::from_parts(parts, body);
+    response.headers_mut().remove(header::CONTENT_LENGTH);
+    response
+}
+
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
         self.to_response(None)
@@ -143,23 +505,3 @@ impl From<DatabaseError> for String {
         err.to_string()
     }
 }
-
-fn sanitize_database_error(error: &duckdb::Error) -> String {
-    let error_str = error.to_string();
-
-    // Remove potentially sensitive information from error messages
-    if error_str.contains("does not exist") {
-        return "Referenced table or column does not exist".to_string();
-    }
-
-    if error_str.contains("syntax error") || error_str.contains("parse") {
-        return "SQL syntax error".to_string();
-    }
-
-    if error_str.contains("permission") || error_str.contains("access") {
-        return "Access denied".to_string();
-    }
-
-    // For other errors, provide a generic message to avoid information leakage
-    "Database query failed".to_string()
-}
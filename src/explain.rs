@@ -0,0 +1,323 @@
+use axum::{
+    extract::State,
+    http::{Extensions, HeaderMap},
+    response::Json,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::SystemTime;
+use tracing::{debug, error, info, instrument, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+pub use rsduck_types::PlanNode;
+
+use crate::database::{
+    is_write_operation, leading_keyword, split_statements, validate_readonly_operation,
+    validate_sandbox_operation,
+};
+use crate::handlers::request_priority;
+use crate::{ApiError, AppState, Audit, AuditSource, DatabaseError, Priority};
+
+/// `extra_info` key DuckDB uses for the optimizer's row estimate
+const ESTIMATED_CARDINALITY_KEY: &str = "Estimated Cardinality";
+
+/// Output format of `/explain`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExplainFormat {
+    /// Operator tree as structured JSON
+    #[default]
+    Json,
+    /// DuckDB's rendered text plan
+    Text,
+}
+
+/// Request body for `/explain`
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ExplainRequest {
+    /// Statement to explain
+    #[schema(example = "SELECT region, sum(amount) FROM sales GROUP BY region")]
+    pub sql: String,
+    /// Run the statement and report actual cardinalities and timings
+    #[serde(default)]
+    pub analyze: bool,
+    /// Plan format, `json` (default) or `text`
+    #[serde(default)]
+    pub format: ExplainFormat,
+}
+
+/// Response structure for `/explain`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ExplainResponse {
+    /// Whether the plan was produced
+    pub success: bool,
+    /// Whether the statement was executed to collect actual statistics
+    pub analyze: bool,
+    /// Format of the plan
+    pub format: ExplainFormat,
+    /// Root operators of the plan (`json` format)
+    pub plan: Option<Vec<PlanNode>>,
+    /// Rendered plan (`text` format)
+    pub text: Option<String>,
+    /// Unique identifier for this request
+    #[schema(example = "123e4567-e89b-12d3-a456-426614174000")]
+    pub query_id: String,
+    /// Time taken to plan (and with `analyze`, run) the statement in milliseconds
+    #[schema(example = 42)]
+    pub execution_time_ms: u64,
+}
+
+/// Show the plan DuckDB chooses for a statement
+/// With `analyze`, the statement is executed and the plan includes actual cardinalities and timings
+#[utoipa::path(
+    post,
+    path = "/explain",
+    request_body = ExplainRequest,
+    params(
+        ("X-Query-Priority" = Option<String>, Header, description = "Priority class: interactive (default) or batch")
+    ),
+    responses(
+        (status = 200, description = "Plan produced successfully", body = ExplainResponse),
+        (status = 400, description = "Bad request - invalid SQL or more than one statement"),
+        (status = 403, description = "Operation forbidden in read-only or sandbox mode"),
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "Server busy - execution queue full or queue wait timed out")
+    ),
+    tag = "query"
+)]
+#[instrument(skip(state, headers, request), fields(query_id, sql_preview = %request.sql.chars().take(50).collect::<String>(), analyze = request.analyze, priority))]
+pub async fn explain_query(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    extensions: Extensions,
+    Json(request): Json<ExplainRequest>,
+) -> Result<Json<ExplainResponse>, Response> {
+    let query_id = Uuid::new_v4().to_string();
+    tracing::Span::current().record("query_id", &query_id);
+
+    // Only EXPLAIN ANALYZE executes the statement
+    let mut audit = if request.analyze {
+        let source = AuditSource::http(&state, "/explain", &headers, &extensions);
+        Audit::begin(&state, &source, &query_id, &request.sql)
+    } else {
+        Audit::default()
+    };
+    match explain_internal(state, headers, request, query_id.clone()).await {
+        Ok(response) => {
+            audit.completed(None, None);
+            Ok(Json(response))
+        }
+        Err(error) => {
+            audit.failed(&error);
+            Err(error.to_response(Some(query_id)))
+        }
+    }
+}
+
+async fn explain_internal(
+    state: AppState,
+    headers: HeaderMap,
+    request: ExplainRequest,
+    query_id: String,
+) -> Result<ExplainResponse, ApiError> {
+    let priority = request_priority(&state, &headers)?;
+    tracing::Span::current().record("priority", priority.as_str());
+
+    let start_time = SystemTime::now();
+    info!("Explain requested");
+
+    // EXPLAIN applies to a single statement; anything before it would run unexplained
+    let statements = split_statements(&request.sql);
+    let [sql] = statements.as_slice() else {
+        return Err(ApiError::bad_request(
+            "Explain accepts exactly one SQL statement",
+        ));
+    };
+    if leading_keyword(sql) == "EXPLAIN" {
+        return Err(ApiError::bad_request(
+            "Pass the statement without EXPLAIN; use the analyze and format options instead",
+        ));
+    }
+
+    // EXPLAIN ANALYZE runs the statement, so it is validated like /query
+    if let Some(error_msg) = validate_readonly_operation(&state, sql) {
+        warn!("Read-only violation detected");
+        return Err(ApiError::forbidden(error_msg));
+    }
+    if let Some(error_msg) = validate_sandbox_operation(&state, sql) {
+        warn!("Sandbox violation detected");
+        return Err(ApiError::forbidden(error_msg));
+    }
+
+    let permit = state
+        .admission_for(priority)
+        .acquire(&state.metrics)
+        .await
+        .map_err(|e| ApiError::server_busy(e.to_string()))?;
+
+    let analyze = request.analyze;
+    let format = request.format;
+    let explain_sql = explain_statement(sql, analyze, format);
+    let executes_write = analyze && is_write_operation(sql);
+    let cache = state.cache.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        run_explain(&state, &explain_sql, priority)
+    })
+    .await;
+
+    let execution_time_ms = start_time.elapsed().unwrap_or_default().as_millis() as u64;
+
+    // The analyzed statement has run even if its plan could not be rendered
+    if executes_write && let Some(cache) = &cache {
+        cache.invalidate_all();
+    }
+
+    let output = match result {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            error!(execution_time_ms, error = %e, "Explain failed");
+            return Err(ApiError::Database(e));
+        }
+        Err(e) => {
+            error!(execution_time_ms, error = %e, "Task execution failed");
+            return Err(ApiError::internal_server_error(format!(
+                "Task execution error: {}",
+                e
+            )));
+        }
+    };
+
+    let (plan, text) = match format {
+        ExplainFormat::Json => (Some(parse_plan(&output)?), None),
+        ExplainFormat::Text => (None, Some(output)),
+    };
+
+    info!(execution_time_ms, "Explain completed");
+
+    Ok(ExplainResponse {
+        success: true,
+        analyze,
+        format,
+        plan,
+        text,
+        query_id,
+        execution_time_ms,
+    })
+}
+
+/// Build the DuckDB `EXPLAIN` statement for `sql`
+pub fn explain_statement(sql: &str, analyze: bool, format: ExplainFormat) -> String {
+    match (analyze, format) {
+        (false, ExplainFormat::Json) => format!("EXPLAIN (FORMAT JSON) {}", sql),
+        (true, ExplainFormat::Json) => format!("EXPLAIN (ANALYZE, FORMAT JSON) {}", sql),
+        (false, ExplainFormat::Text) => format!("EXPLAIN {}", sql),
+        (true, ExplainFormat::Text) => format!("EXPLAIN ANALYZE {}", sql),
+    }
+}
+
+/// Run an `EXPLAIN` statement and join the plan column of its rows
+fn run_explain(
+    state: &AppState,
+    explain_sql: &str,
+    priority: Priority,
+) -> Result<String, DatabaseError> {
+    debug!("Acquiring database connection from pool");
+    let conn = state.pool_for(priority).get()?;
+    let mut stmt = conn.prepare(explain_sql)?;
+    let plans = stmt.query_map([], |row| row.get::<_, String>(1))?;
+
+    let mut output = Vec::new();
+    for plan in plans {
+        output.push(plan?);
+    }
+    Ok(output.join("\n"))
+}
+
+/// Convert DuckDB's JSON plan or JSON profile into plan nodes
+pub fn parse_plan(output: &str) -> Result<Vec<PlanNode>, ApiError> {
+    let value: Value = serde_json::from_str(output).map_err(DatabaseError::from)?;
+
+    let roots = match &value {
+        // EXPLAIN: a list of root operators
+        Value::Array(roots) => roots.iter().map(plan_node).collect(),
+        // EXPLAIN ANALYZE: a profile whose children start with the EXPLAIN_ANALYZE operator
+        Value::Object(profile) if profile.contains_key("children") => profile_operators(&value),
+        _ => {
+            return Err(ApiError::bad_request(
+                "DuckDB could not produce a JSON plan for this statement; use the text format instead",
+            ));
+        }
+    };
+
+    Ok(roots)
+}
+
+/// Root operators of a DuckDB JSON profile, skipping the `EXPLAIN_ANALYZE` wrapper if present
+pub fn profile_operators(profile: &Value) -> Vec<PlanNode> {
+    let mut roots = Vec::new();
+    for child in children(profile) {
+        if child.get("operator_type").and_then(Value::as_str) == Some("EXPLAIN_ANALYZE") {
+            roots.extend(children(child).iter().map(plan_node));
+        } else {
+            roots.push(plan_node(child));
+        }
+    }
+    roots
+}
+
+fn plan_node(value: &Value) -> PlanNode {
+    let operator = value
+        .get("name")
+        .or_else(|| value.get("operator_name"))
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+
+    let mut extra_info = value
+        .get("extra_info")
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default();
+    let estimated_cardinality = extra_info
+        .remove(ESTIMATED_CARDINALITY_KEY)
+        .and_then(|estimate| parse_cardinality(&estimate));
+
+    PlanNode {
+        operator,
+        estimated_cardinality,
+        actual_cardinality: value.get("operator_cardinality").and_then(Value::as_u64),
+        rows_scanned: value.get("operator_rows_scanned").and_then(Value::as_u64),
+        timing_ms: value
+            .get("operator_timing")
+            .and_then(Value::as_f64)
+            .map(|seconds| seconds * 1000.0),
+        extra_info,
+        children: children(value).iter().map(plan_node).collect(),
+    }
+}
+
+fn children(value: &Value) -> &[Value] {
+    value
+        .get("children")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+/// Estimates are rendered as strings such as `"200"` or `"~1,000"`
+fn parse_cardinality(estimate: &Value) -> Option<u64> {
+    match estimate {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s
+            .chars()
+            .filter(char::is_ascii_digit)
+            .collect::<String>()
+            .parse()
+            .ok(),
+        _ => None,
+    }
+}
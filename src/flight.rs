@@ -0,0 +1,1248 @@
+// Every Flight SQL handler returns `tonic::Status`, which is larger than clippy would like
+#![allow(clippy::result_large_err)]
+
+use arrow_flight::decode::FlightRecordBatchStream;
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::error::FlightError;
+use arrow_flight::flight_service_server::FlightServiceServer;
+use arrow_flight::sql::metadata::{SqlInfoData, SqlInfoDataBuilder};
+use arrow_flight::sql::server::{FlightSqlService, PeekableFlightDataStream};
+use arrow_flight::sql::{
+    ActionClosePreparedStatementRequest, ActionCreatePreparedStatementRequest,
+    ActionCreatePreparedStatementResult, Any, CommandGetCatalogs, CommandGetDbSchemas,
+    CommandGetSqlInfo, CommandGetTableTypes, CommandGetTables, CommandPreparedStatementQuery,
+    CommandPreparedStatementUpdate, CommandStatementIngest, CommandStatementQuery,
+    CommandStatementUpdate, DoPutPreparedStatementResult, ProstMessageExt, SqlInfo,
+    SqlSupportedTransaction, TableExistsOption, TableNotExistOption, TicketStatementQuery,
+};
+use arrow_flight::{
+    Action, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo, HandshakeRequest,
+    HandshakeResponse, IpcMessage, SchemaAsIpc, Ticket,
+};
+use arrow_ipc::writer::IpcWriteOptions;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use duckdb::Connection;
+use duckdb::arrow::array::{Array, AsArray};
+use duckdb::arrow::datatypes::{self, DataType, Field, Schema};
+use duckdb::arrow::error::ArrowError;
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::arrow::util::display::array_value_to_string;
+use duckdb::types::{TimeUnit, Value};
+use futures::{Stream, StreamExt, TryStreamExt, stream};
+use prost::Message;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::transport::Server;
+use tonic::{Code, Request, Response, Status, Streaming};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::database::{
+    is_query_statement, is_write_operation, validate_readonly_operation, validate_sandbox_operation,
+};
+use crate::rate_limit::{API_KEY_HEADER, key_identity};
+use crate::{AppState, Audit, AuditSource, AuditStatus, Priority};
+
+/// Largest gRPC message accepted from clients, so bulk ingest batches are not
+/// rejected by tonic's 4 MiB default
+const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+
+/// Record batches buffered between the blocking DuckDB task and the gRPC stream
+const BATCH_CHANNEL_CAPACITY: usize = 4;
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + 'static>>;
+
+/// A statement created with `CreatePreparedStatement` and its bound parameters
+#[derive(Debug, Clone)]
+struct PreparedStatement {
+    sql: String,
+    dataset_schema: Schema,
+    params: Vec<Vec<Value>>,
+    /// Client that created the handle; other clients cannot see it
+    owner: String,
+    last_used: Instant,
+}
+
+/// Arrow Flight SQL service backed by the server's connection pools
+pub struct FlightSqlHandler {
+    state: AppState,
+    prepared: Mutex<HashMap<String, PreparedStatement>>,
+}
+
+/// Accept Arrow Flight SQL clients until the server shuts down, then wait for the requests
+/// in flight
+pub async fn serve(listener: TcpListener, state: AppState) -> Result<(), tonic::transport::Error> {
+    let shutdown = state.shutdown.started();
+    let incoming = stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await.map(|(socket, _)| {
+            let _ = socket.set_nodelay(true);
+            socket
+        });
+        Some((accepted, listener))
+    });
+    let service = FlightServiceServer::new(FlightSqlHandler::new(state))
+        .max_decoding_message_size(MAX_MESSAGE_LEN);
+
+    Server::builder()
+        .add_service(service)
+        .serve_with_incoming_shutdown(incoming, shutdown)
+        .await
+}
+
+impl FlightSqlHandler {
+    /// Create a Flight SQL service sharing the server's pools, admission queues and API keys
+    pub fn new(state: AppState) -> Self {
+        Self {
+            state,
+            prepared: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check the request's credentials and pick the priority its queries run at
+    fn authorize<T>(&self, request: &Request<T>) -> Result<Priority, Status> {
+        if !self.state.api_keys.is_enabled() {
+            return Ok(Priority::Interactive);
+        }
+
+        let Some(key) = presented_key(request.metadata()) else {
+            warn!("Flight SQL request rejected: missing API key");
+            return Err(Status::unauthenticated("Missing API key"));
+        };
+        if !self.state.api_keys.verify(&key) {
+            warn!("Flight SQL request rejected: invalid API key");
+            return Err(Status::unauthenticated("Invalid API key"));
+        }
+
+        if self.state.batch_api_keys.contains(&key) {
+            Ok(Priority::Batch)
+        } else {
+            Ok(Priority::Interactive)
+        }
+    }
+
+    /// Identify the client by API key when it sends one the server accepts, otherwise by
+    /// source IP, like HTTP clients
+    fn client<T>(&self, request: &Request<T>) -> String {
+        if let Some(key) = presented_key(request.metadata())
+            && self.state.api_keys.verify(&key)
+        {
+            return key_identity(&self.state, &key);
+        }
+        match request.remote_addr() {
+            Some(addr) => format!("ip:{}", addr.ip()),
+            None => "ip:unknown".to_string(),
+        }
+    }
+
+    /// Who sent the request, for the audit log
+    fn audit_source<T>(&self, request: &Request<T>) -> AuditSource {
+        AuditSource {
+            interface: "flight",
+            endpoint: None,
+            client: self.client(request),
+            source_ip: request.remote_addr().map(|addr| addr.ip()),
+        }
+    }
+
+    /// Apply the same read-only and sandbox rules as HTTP queries
+    fn check_statement(&self, sql: &str) -> Result<(), Status> {
+        if let Some(message) = validate_readonly_operation(&self.state, sql) {
+            return Err(Status::permission_denied(message));
+        }
+        if let Some(message) = validate_sandbox_operation(&self.state, sql) {
+            return Err(Status::permission_denied(message));
+        }
+        Ok(())
+    }
+
+    /// Pass admission control, then run `f` on a pooled connection in the blocking pool
+    async fn spawn_on_connection<T, F>(
+        &self,
+        priority: Priority,
+        f: F,
+    ) -> Result<JoinHandle<Result<T, Status>>, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T, Status> + Send + 'static,
+    {
+        let permit = self
+            .state
+            .admission_for(priority)
+            .acquire(&self.state.metrics)
+            .await
+            .map_err(|e| Status::resource_exhausted(e.to_string()))?;
+        let pool = self.state.pool_for(priority).clone();
+
+        Ok(tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let conn = pool.get().map_err(|e| Status::unavailable(e.to_string()))?;
+            f(&conn)
+        }))
+    }
+
+    /// Run `f` on a pooled connection and wait for its result
+    async fn with_connection<T, F>(&self, priority: Priority, f: F) -> Result<T, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T, Status> + Send + 'static,
+    {
+        self.spawn_on_connection(priority, f)
+            .await?
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+    }
+
+    /// Execute a query once per parameter row and stream its record batches to the client
+    async fn stream_query(
+        &self,
+        priority: Priority,
+        source: AuditSource,
+        sql: String,
+        params: Vec<Vec<Value>>,
+    ) -> Result<Response<ResponseStream<FlightData>>, Status> {
+        let query_id = Uuid::new_v4().to_string();
+        let mut audit = Audit::begin(&self.state, &source, &query_id, &sql).with_params(&params);
+        if let Err(status) = self.check_statement(&sql) {
+            audit_failure(&mut audit, &status);
+            return Err(status);
+        }
+        debug!(params = params.len(), "Executing Flight SQL query");
+
+        // Queries such as `INSERT ... RETURNING` write too
+        let cache = self
+            .state
+            .cache
+            .clone()
+            .filter(|_| is_write_operation(&sql));
+        let (tx, rx) = mpsc::channel(BATCH_CHANNEL_CAPACITY);
+        let sender = tx.clone();
+        let task = self
+            .spawn_on_connection(priority, move |conn| {
+                let result = send_query_batches(conn, &sql, &params, &sender);
+                if let Err(status) = &result {
+                    let error = FlightError::Tonic(Box::new(status.clone()));
+                    let _ = sender.blocking_send(Err(error));
+                }
+                result
+            })
+            .await
+            .inspect_err(|status| audit_failure(&mut audit, status))?;
+
+        // End the stream only after the query is audited and the cache invalidated, so a
+        // client that saw the last batch cannot read a result cached before its write
+        tokio::spawn(async move {
+            match task.await {
+                Ok(Ok(rows)) => audit.completed(Some(rows), None),
+                Ok(Err(status)) => audit_failure(&mut audit, &status),
+                Err(_) => {}
+            }
+            if let Some(cache) = cache {
+                cache.invalidate_all();
+            }
+            drop(tx);
+        });
+
+        let batches = stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|batch| (batch, rx))
+        });
+        let stream = FlightDataEncoderBuilder::new()
+            .build(batches)
+            .map_err(Status::from);
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    /// Execute a statement once per parameter row and return the total affected row count
+    async fn execute_update(
+        &self,
+        priority: Priority,
+        source: AuditSource,
+        sql: String,
+        params: Vec<Vec<Value>>,
+    ) -> Result<i64, Status> {
+        let query_id = Uuid::new_v4().to_string();
+        let mut audit = Audit::begin(&self.state, &source, &query_id, &sql).with_params(&params);
+        if let Err(status) = self.check_statement(&sql) {
+            audit_failure(&mut audit, &status);
+            return Err(status);
+        }
+        debug!(params = params.len(), "Executing Flight SQL update");
+
+        let result = self
+            .with_connection(priority, move |conn| {
+                let mut stmt = conn.prepare(&sql).map_err(sql_error)?;
+                let mut affected = 0;
+                for row in parameter_sets(&params) {
+                    affected += stmt
+                        .execute(duckdb::params_from_iter(row))
+                        .map_err(sql_error)? as i64;
+                }
+                Ok(affected)
+            })
+            .await;
+        match &result {
+            Ok(affected) => {
+                audit.completed(None, Some(*affected as u64));
+                self.invalidate_cache();
+            }
+            Err(status) => audit_failure(&mut audit, status),
+        }
+        result
+    }
+
+    /// Stream the request's record batches into `target` inside a single transaction
+    async fn ingest(
+        &self,
+        priority: Priority,
+        sql: &str,
+        target: IngestTarget,
+        command: CommandStatementIngest,
+        request: Request<PeekableFlightDataStream>,
+    ) -> Result<i64, Status> {
+        self.check_statement(sql)?;
+
+        let mut batches = FlightRecordBatchStream::new_from_flight_data(
+            request.into_inner().map_err(FlightError::from),
+        );
+        let first = batches.try_next().await.map_err(Status::from)?;
+        let schema = match (&first, batches.schema()) {
+            (Some(batch), _) => batch.schema(),
+            (None, Some(schema)) => schema.clone(),
+            (None, None) => {
+                return Err(Status::invalid_argument(
+                    "Ingest stream did not include a schema",
+                ));
+            }
+        };
+        info!(table = %target.qualified_name(), "Starting Flight SQL bulk ingest");
+
+        let (tx, rx) = mpsc::channel(BATCH_CHANNEL_CAPACITY);
+        let options = command.table_definition_options.unwrap_or_default();
+        let task = self
+            .spawn_on_connection(priority, move |conn| {
+                ingest_batches(
+                    conn,
+                    &target,
+                    options.if_not_exist(),
+                    options.if_exists(),
+                    &schema,
+                    rx,
+                )
+            })
+            .await?;
+
+        let mut next = first.map(Ok);
+        while let Some(item) = next {
+            let failed = item.is_err();
+            if tx.send(item).await.is_err() || failed {
+                break;
+            }
+            next = batches.next().await.map(|item| item.map_err(Status::from));
+        }
+        drop(tx);
+
+        let rows = task.await.map_err(|e| Status::internal(e.to_string()))??;
+        self.invalidate_cache();
+        Ok(rows)
+    }
+
+    /// Drop results cached by the HTTP endpoints after a write
+    fn invalidate_cache(&self) {
+        if let Some(cache) = &self.state.cache {
+            cache.invalidate_all();
+        }
+    }
+
+    /// Run a metadata query and collect its rows as strings
+    async fn metadata_rows(
+        &self,
+        priority: Priority,
+        sql: &'static str,
+    ) -> Result<Vec<Vec<String>>, Status> {
+        self.with_connection(priority, move |conn| {
+            let mut stmt = conn.prepare(sql).map_err(sql_error)?;
+            let mut rows = stmt.query([]).map_err(sql_error)?;
+            let mut result = Vec::new();
+            while let Some(row) = rows.next().map_err(sql_error)? {
+                let column_count = row.as_ref().column_count();
+                let values = (0..column_count)
+                    .map(|i| row.get::<_, String>(i))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(sql_error)?;
+                result.push(values);
+            }
+            Ok(result)
+        })
+        .await
+    }
+
+    fn prepared_statement(&self, handle: &[u8], client: &str) -> Result<PreparedStatement, Status> {
+        self.with_prepared(handle, client, |statement| statement.clone())
+    }
+
+    /// Run `f` on a prepared statement created by `client`, after dropping statements
+    /// left unused for longer than the idle timeout
+    fn with_prepared<T>(
+        &self,
+        handle: &[u8],
+        client: &str,
+        f: impl FnOnce(&mut PreparedStatement) -> T,
+    ) -> Result<T, Status> {
+        let handle = String::from_utf8_lossy(handle);
+        let mut prepared = self.prepared.lock().unwrap_or_else(|e| e.into_inner());
+        self.expire_prepared(&mut prepared);
+        let statement = prepared
+            .get_mut(handle.as_ref())
+            .filter(|statement| statement.owner == client)
+            .ok_or_else(|| Status::not_found(format!("Unknown prepared statement: {}", handle)))?;
+        statement.last_used = Instant::now();
+        Ok(f(statement))
+    }
+
+    fn expire_prepared(&self, prepared: &mut HashMap<String, PreparedStatement>) {
+        let idle_timeout = self.state.flight_prepared_idle_timeout;
+        prepared.retain(|_, statement| statement.last_used.elapsed() < idle_timeout);
+    }
+
+    fn sql_info(&self) -> Result<SqlInfoData, Status> {
+        let mut builder = SqlInfoDataBuilder::new();
+        builder.append(SqlInfo::FlightSqlServerName, "rsduck");
+        builder.append(SqlInfo::FlightSqlServerVersion, env!("CARGO_PKG_VERSION"));
+        builder.append(SqlInfo::FlightSqlServerArrowVersion, "1.3");
+        builder.append(SqlInfo::FlightSqlServerReadOnly, self.state.is_readonly);
+        builder.append(SqlInfo::FlightSqlServerSql, true);
+        builder.append(SqlInfo::FlightSqlServerSubstrait, false);
+        builder.append(
+            SqlInfo::FlightSqlServerTransaction,
+            SqlSupportedTransaction::None as i32,
+        );
+        builder.append(SqlInfo::FlightSqlServerCancel, false);
+        builder.append(SqlInfo::FlightSqlServerBulkIngestion, true);
+        builder.append(SqlInfo::FlightSqlServerIngestTransactionsSupported, false);
+        builder.append(SqlInfo::SqlIdentifierQuoteChar, "\"");
+        builder.build().map_err(Status::from)
+    }
+}
+
+#[tonic::async_trait]
+impl FlightSqlService for FlightSqlHandler {
+    type FlightService = FlightSqlHandler;
+
+    /// Exchange HTTP Basic credentials (the password is the API key) for a bearer token
+    async fn do_handshake(
+        &self,
+        request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<ResponseStream<HandshakeResponse>>, Status> {
+        self.authorize(&request)?;
+        let token = presented_key(request.metadata()).unwrap_or_default();
+        info!("Flight SQL client authenticated");
+
+        let payload = HandshakeResponse {
+            protocol_version: 0,
+            payload: token.clone().into(),
+        };
+        let mut response: Response<ResponseStream<HandshakeResponse>> =
+            Response::new(Box::pin(stream::iter([Ok(payload)])));
+        if !token.is_empty() {
+            let bearer = MetadataValue::try_from(format!("Bearer {}", token))
+                .map_err(|e| Status::internal(e.to_string()))?;
+            response.metadata_mut().insert("authorization", bearer);
+        }
+        Ok(response)
+    }
+
+    async fn get_flight_info_statement(
+        &self,
+        query: CommandStatementQuery,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let priority = self.authorize(&request)?;
+        self.check_statement(&query.query)?;
+
+        let sql = query.query.clone();
+        let (_, schema) = self
+            .with_connection(priority, move |conn| describe_statement(conn, &sql))
+            .await?;
+        let ticket = TicketStatementQuery {
+            statement_handle: query.query.into(),
+        };
+        flight_info(&schema, ticket.as_any(), request.into_inner())
+    }
+
+    async fn do_get_statement(
+        &self,
+        ticket: TicketStatementQuery,
+        request: Request<Ticket>,
+    ) -> Result<Response<ResponseStream<FlightData>>, Status> {
+        let priority = self.authorize(&request)?;
+        let sql = String::from_utf8(ticket.statement_handle.to_vec())
+            .map_err(|_| Status::invalid_argument("Statement ticket is not valid UTF-8"))?;
+        let source = self.audit_source(&request);
+        self.stream_query(priority, source, sql, Vec::new()).await
+    }
+
+    async fn do_action_create_prepared_statement(
+        &self,
+        query: ActionCreatePreparedStatementRequest,
+        request: Request<Action>,
+    ) -> Result<ActionCreatePreparedStatementResult, Status> {
+        let priority = self.authorize(&request)?;
+        self.check_statement(&query.query)?;
+
+        let sql = query.query.clone();
+        let (param_count, dataset_schema) = self
+            .with_connection(priority, move |conn| describe_statement(conn, &sql))
+            .await?;
+        // DuckDB does not expose parameter types, so parameters are described as untyped
+        let parameter_schema = Schema::new(
+            (1..=param_count)
+                .map(|i| Field::new(format!("${}", i), DataType::Null, true))
+                .collect::<Vec<_>>(),
+        );
+
+        let handle = Uuid::new_v4().to_string();
+        let result = ActionCreatePreparedStatementResult {
+            prepared_statement_handle: handle.clone().into(),
+            dataset_schema: ipc_schema(&dataset_schema)?,
+            parameter_schema: ipc_schema(&parameter_schema)?,
+        };
+        let mut prepared = self.prepared.lock().unwrap_or_else(|e| e.into_inner());
+        self.expire_prepared(&mut prepared);
+        prepared.insert(
+            handle,
+            PreparedStatement {
+                sql: query.query,
+                dataset_schema,
+                params: Vec::new(),
+                owner: self.client(&request),
+                last_used: Instant::now(),
+            },
+        );
+        Ok(result)
+    }
+
+    async fn do_action_close_prepared_statement(
+        &self,
+        query: ActionClosePreparedStatementRequest,
+        request: Request<Action>,
+    ) -> Result<(), Status> {
+        self.authorize(&request)?;
+        let client = self.client(&request);
+        let handle = String::from_utf8_lossy(&query.prepared_statement_handle);
+        let mut prepared = self.prepared.lock().unwrap_or_else(|e| e.into_inner());
+        if prepared
+            .get(handle.as_ref())
+            .is_some_and(|statement| statement.owner == client)
+        {
+            prepared.remove(handle.as_ref());
+        }
+        Ok(())
+    }
+
+    /// Bind parameters to a prepared statement; every row of the batches is one parameter set
+    async fn do_put_prepared_statement_query(
+        &self,
+        query: CommandPreparedStatementQuery,
+        request: Request<PeekableFlightDataStream>,
+    ) -> Result<DoPutPreparedStatementResult, Status> {
+        self.authorize(&request)?;
+        let client = self.client(&request);
+        let batches: Vec<RecordBatch> = FlightRecordBatchStream::new_from_flight_data(
+            request.into_inner().map_err(FlightError::from),
+        )
+        .try_collect()
+        .await
+        .map_err(Status::from)?;
+        let params = parameter_rows(&batches)?;
+
+        self.with_prepared(&query.prepared_statement_handle, &client, |statement| {
+            statement.params = params;
+        })?;
+
+        Ok(DoPutPreparedStatementResult {
+            prepared_statement_handle: Some(query.prepared_statement_handle),
+        })
+    }
+
+    async fn get_flight_info_prepared_statement(
+        &self,
+        query: CommandPreparedStatementQuery,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        self.authorize(&request)?;
+        let client = self.client(&request);
+        let statement = self.prepared_statement(&query.prepared_statement_handle, &client)?;
+        flight_info(
+            &statement.dataset_schema,
+            query.as_any(),
+            request.into_inner(),
+        )
+    }
+
+    async fn do_get_prepared_statement(
+        &self,
+        query: CommandPreparedStatementQuery,
+        request: Request<Ticket>,
+    ) -> Result<Response<ResponseStream<FlightData>>, Status> {
+        let priority = self.authorize(&request)?;
+        let source = self.audit_source(&request);
+        let statement =
+            self.prepared_statement(&query.prepared_statement_handle, &source.client)?;
+        self.stream_query(priority, source, statement.sql, statement.params)
+            .await
+    }
+
+    async fn do_put_prepared_statement_update(
+        &self,
+        query: CommandPreparedStatementUpdate,
+        request: Request<PeekableFlightDataStream>,
+    ) -> Result<i64, Status> {
+        let priority = self.authorize(&request)?;
+        let source = self.audit_source(&request);
+        let statement =
+            self.prepared_statement(&query.prepared_statement_handle, &source.client)?;
+        self.execute_update(priority, source, statement.sql, statement.params)
+            .await
+    }
+
+    async fn do_put_statement_update(
+        &self,
+        command: CommandStatementUpdate,
+        request: Request<PeekableFlightDataStream>,
+    ) -> Result<i64, Status> {
+        let priority = self.authorize(&request)?;
+        let source = self.audit_source(&request);
+        self.execute_update(priority, source, command.query, Vec::new())
+            .await
+    }
+
+    /// Stream record batches into a table inside a single transaction
+    async fn do_put_statement_ingest(
+        &self,
+        command: CommandStatementIngest,
+        request: Request<PeekableFlightDataStream>,
+    ) -> Result<i64, Status> {
+        let priority = self.authorize(&request)?;
+        let target = IngestTarget::new(&command);
+        // The ingest is audited as the insert it performs
+        let sql = format!("INSERT INTO {}", target.qualified_name());
+        let source = self.audit_source(&request);
+        let query_id = Uuid::new_v4().to_string();
+        let mut audit = Audit::begin(&self.state, &source, &query_id, &sql);
+        let rows = self.ingest(priority, &sql, target, command, request).await;
+        match &rows {
+            Ok(rows) => audit.completed(None, Some(*rows as u64)),
+            Err(status) => audit_failure(&mut audit, status),
+        }
+        rows
+    }
+
+    async fn get_flight_info_catalogs(
+        &self,
+        query: CommandGetCatalogs,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        self.authorize(&request)?;
+        let schema = query.into_builder().schema();
+        flight_info(&schema, query.as_any(), request.into_inner())
+    }
+
+    async fn do_get_catalogs(
+        &self,
+        query: CommandGetCatalogs,
+        request: Request<Ticket>,
+    ) -> Result<Response<ResponseStream<FlightData>>, Status> {
+        let priority = self.authorize(&request)?;
+        let rows = self
+            .metadata_rows(
+                priority,
+                "SELECT DISTINCT catalog_name FROM information_schema.schemata ORDER BY 1",
+            )
+            .await?;
+
+        let mut builder = query.into_builder();
+        for row in rows {
+            builder.append(&row[0]);
+        }
+        batch_stream(builder.build())
+    }
+
+    async fn get_flight_info_schemas(
+        &self,
+        query: CommandGetDbSchemas,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        self.authorize(&request)?;
+        let schema = query.clone().into_builder().schema();
+        flight_info(&schema, query.as_any(), request.into_inner())
+    }
+
+    async fn do_get_schemas(
+        &self,
+        query: CommandGetDbSchemas,
+        request: Request<Ticket>,
+    ) -> Result<Response<ResponseStream<FlightData>>, Status> {
+        let priority = self.authorize(&request)?;
+        let rows = self
+            .metadata_rows(
+                priority,
+                "SELECT catalog_name, schema_name FROM information_schema.schemata ORDER BY 1, 2",
+            )
+            .await?;
+
+        // The builder applies the catalog and schema filters
+        let mut builder = query.into_builder();
+        for row in rows {
+            builder.append(&row[0], &row[1]);
+        }
+        batch_stream(builder.build())
+    }
+
+    async fn get_flight_info_tables(
+        &self,
+        query: CommandGetTables,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        self.authorize(&request)?;
+        let schema = query.clone().into_builder().schema();
+        flight_info(&schema, query.as_any(), request.into_inner())
+    }
+
+    async fn do_get_tables(
+        &self,
+        query: CommandGetTables,
+        request: Request<Ticket>,
+    ) -> Result<Response<ResponseStream<FlightData>>, Status> {
+        let priority = self.authorize(&request)?;
+        let include_schema = query.include_schema;
+        let tables = self
+            .with_connection(priority, move |conn| {
+                let mut stmt = conn
+                    .prepare(
+                        "SELECT table_catalog, table_schema, table_name, table_type \
+                         FROM information_schema.tables ORDER BY 1, 2, 3",
+                    )
+                    .map_err(sql_error)?;
+                let rows = stmt
+                    .query_map([], |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, String>(2)?,
+                            row.get::<_, String>(3)?,
+                        ))
+                    })
+                    .map_err(sql_error)?
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(sql_error)?;
+
+                rows.into_iter()
+                    .map(|(catalog, schema, table, table_type)| {
+                        let table_schema = if include_schema {
+                            table_arrow_schema(conn, &catalog, &schema, &table)?
+                        } else {
+                            Schema::empty()
+                        };
+                        let table_type = match table_type.as_str() {
+                            "BASE TABLE" => "TABLE".to_string(),
+                            _ => table_type,
+                        };
+                        Ok((catalog, schema, table, table_type, table_schema))
+                    })
+                    .collect::<Result<Vec<_>, Status>>()
+            })
+            .await?;
+
+        // The builder applies the catalog, name pattern and table type filters
+        let mut builder = query.into_builder();
+        for (catalog, schema, table, table_type, table_schema) in tables {
+            builder
+                .append(catalog, schema, table, table_type, &table_schema)
+                .map_err(Status::from)?;
+        }
+        batch_stream(builder.build())
+    }
+
+    async fn get_flight_info_table_types(
+        &self,
+        query: CommandGetTableTypes,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        self.authorize(&request)?;
+        let schema = query.into_builder().schema();
+        flight_info(&schema, query.as_any(), request.into_inner())
+    }
+
+    async fn do_get_table_types(
+        &self,
+        query: CommandGetTableTypes,
+        request: Request<Ticket>,
+    ) -> Result<Response<ResponseStream<FlightData>>, Status> {
+        self.authorize(&request)?;
+        let mut builder = query.into_builder();
+        for table_type in ["LOCAL TEMPORARY", "TABLE", "VIEW"] {
+            builder.append(table_type);
+        }
+        batch_stream(builder.build())
+    }
+
+    async fn get_flight_info_sql_info(
+        &self,
+        query: CommandGetSqlInfo,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        self.authorize(&request)?;
+        let schema = query.clone().into_builder(&self.sql_info()?).schema();
+        flight_info(&schema, query.as_any(), request.into_inner())
+    }
+
+    async fn do_get_sql_info(
+        &self,
+        query: CommandGetSqlInfo,
+        request: Request<Ticket>,
+    ) -> Result<Response<ResponseStream<FlightData>>, Status> {
+        self.authorize(&request)?;
+        batch_stream(query.into_builder(&self.sql_info()?).build())
+    }
+
+    async fn register_sql_info(&self, _id: i32, _result: &SqlInfo) {}
+}
+
+/// Table targeted by a bulk ingest command
+struct IngestTarget {
+    catalog: Option<String>,
+    schema: Option<String>,
+    table: String,
+    temporary: bool,
+}
+
+impl IngestTarget {
+    fn new(command: &CommandStatementIngest) -> Self {
+        // DuckDB keeps temporary tables in their own catalog
+        let (catalog, schema) = if command.temporary {
+            (Some("temp".to_string()), Some("main".to_string()))
+        } else {
+            (command.catalog.clone(), command.schema.clone())
+        };
+        Self {
+            catalog,
+            schema,
+            table: command.table.clone(),
+            temporary: command.temporary,
+        }
+    }
+
+    fn qualified_name(&self) -> String {
+        [&self.catalog, &self.schema]
+            .into_iter()
+            .flatten()
+            .chain([&self.table])
+            .map(|part| quote_identifier(part))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    fn appender<'a>(&self, conn: &'a Connection) -> duckdb::Result<duckdb::Appender<'a>> {
+        match (&self.catalog, &self.schema) {
+            (Some(catalog), schema) => conn.appender_to_catalog_and_db(
+                &self.table,
+                catalog,
+                schema.as_deref().unwrap_or("main"),
+            ),
+            (None, Some(schema)) => conn.appender_to_db(&self.table, schema),
+            (None, None) => conn.appender(&self.table),
+        }
+    }
+}
+
+/// Create or replace the target table as requested, then append every batch
+/// received on `batches`. Runs in one transaction so a failed ingest leaves no rows behind.
+fn ingest_batches(
+    conn: &Connection,
+    target: &IngestTarget,
+    if_not_exist: TableNotExistOption,
+    if_exists: TableExistsOption,
+    schema: &Schema,
+    mut batches: mpsc::Receiver<Result<RecordBatch, Status>>,
+) -> Result<i64, Status> {
+    conn.execute_batch("BEGIN TRANSACTION").map_err(sql_error)?;
+
+    let result = (|| {
+        let name = target.qualified_name();
+        let exists = conn
+            .prepare(&format!("SELECT * FROM {} LIMIT 0", name))
+            .is_ok();
+        let create = match (exists, if_exists, if_not_exist) {
+            (true, TableExistsOption::Replace, _) => {
+                conn.execute_batch(&format!("DROP TABLE {}", name))
+                    .map_err(sql_error)?;
+                true
+            }
+            (true, TableExistsOption::Append, _) => false,
+            (true, _, _) => {
+                return Err(Status::already_exists(format!(
+                    "Table {} already exists",
+                    name
+                )));
+            }
+            (false, _, TableNotExistOption::Create) => true,
+            (false, _, _) => {
+                return Err(Status::not_found(format!("Table {} does not exist", name)));
+            }
+        };
+        if create {
+            conn.execute_batch(&create_table_sql(target, schema)?)
+                .map_err(sql_error)?;
+        }
+
+        let mut appender = target.appender(conn).map_err(sql_error)?;
+        let mut rows = 0;
+        while let Some(batch) = batches.blocking_recv() {
+            let batch = batch?;
+            rows += batch.num_rows() as i64;
+            appender.append_record_batch(batch).map_err(sql_error)?;
+        }
+        appender.flush().map_err(sql_error)?;
+        Ok(rows)
+    })();
+
+    match result {
+        Ok(rows) => {
+            conn.execute_batch("COMMIT").map_err(sql_error)?;
+            info!(table = %target.qualified_name(), rows, "Flight SQL bulk ingest complete");
+            Ok(rows)
+        }
+        Err(status) => {
+            let _ = conn.execute_batch("ROLLBACK");
+            Err(status)
+        }
+    }
+}
+
+/// `CREATE TABLE` statement matching an Arrow schema
+fn create_table_sql(target: &IngestTarget, schema: &Schema) -> Result<String, Status> {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            let column_type = duckdb_type(field.data_type()).ok_or_else(|| {
+                Status::invalid_argument(format!(
+                    "Column {} has unsupported type {}",
+                    field.name(),
+                    field.data_type()
+                ))
+            })?;
+            Ok(format!(
+                "{} {}",
+                quote_identifier(field.name()),
+                column_type
+            ))
+        })
+        .collect::<Result<Vec<_>, Status>>()?;
+
+    let name = if target.temporary {
+        quote_identifier(&target.table)
+    } else {
+        target.qualified_name()
+    };
+    Ok(format!(
+        "CREATE {}TABLE {} ({})",
+        if target.temporary { "TEMPORARY " } else { "" },
+        name,
+        columns.join(", ")
+    ))
+}
+
+/// DuckDB column type for an Arrow data type, if the appender can load it
+fn duckdb_type(data_type: &DataType) -> Option<String> {
+    let name = match data_type {
+        DataType::Boolean => "BOOLEAN",
+        DataType::Int8 => "TINYINT",
+        DataType::Int16 => "SMALLINT",
+        DataType::Int32 => "INTEGER",
+        DataType::Int64 => "BIGINT",
+        DataType::UInt8 => "UTINYINT",
+        DataType::UInt16 => "USMALLINT",
+        DataType::UInt32 => "UINTEGER",
+        DataType::UInt64 => "UBIGINT",
+        DataType::Float32 => "FLOAT",
+        DataType::Float64 => "DOUBLE",
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => "VARCHAR",
+        DataType::Binary
+        | DataType::LargeBinary
+        | DataType::BinaryView
+        | DataType::FixedSizeBinary(_) => "BLOB",
+        DataType::Date32 | DataType::Date64 => "DATE",
+        DataType::Time32(_) | DataType::Time64(_) => "TIME",
+        DataType::Timestamp(_, None) => "TIMESTAMP",
+        DataType::Timestamp(_, Some(_)) => "TIMESTAMPTZ",
+        DataType::Duration(_) | DataType::Interval(_) => "INTERVAL",
+        DataType::Decimal128(precision, scale) if *scale >= 0 => {
+            return Some(format!("DECIMAL({}, {})", precision, scale));
+        }
+        DataType::List(field) | DataType::LargeList(field) => {
+            return Some(format!("{}[]", duckdb_type(field.data_type())?));
+        }
+        DataType::Struct(fields) => {
+            let members = fields
+                .iter()
+                .map(|f| {
+                    Some(format!(
+                        "{} {}",
+                        quote_identifier(f.name()),
+                        duckdb_type(f.data_type())?
+                    ))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            return Some(format!("STRUCT({})", members.join(", ")));
+        }
+        DataType::Dictionary(_, value_type) => return duckdb_type(value_type),
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Execute `sql` for every parameter set and send the resulting batches on `tx`.
+/// Stops early when the client goes away. Returns the number of rows sent.
+fn send_query_batches(
+    conn: &Connection,
+    sql: &str,
+    params: &[Vec<Value>],
+    tx: &mpsc::Sender<Result<RecordBatch, FlightError>>,
+) -> Result<u64, Status> {
+    let mut stmt = conn.prepare(sql).map_err(sql_error)?;
+    let mut schema = None;
+    let mut sent = false;
+    let mut rows = 0;
+
+    for row in parameter_sets(params) {
+        let batches = stmt
+            .query_arrow(duckdb::params_from_iter(row))
+            .map_err(sql_error)?;
+        schema = Some(batches.get_schema());
+        for batch in batches {
+            let num_rows = batch.num_rows() as u64;
+            if tx.blocking_send(Ok(batch)).is_err() {
+                return Ok(rows);
+            }
+            sent = true;
+            rows += num_rows;
+        }
+    }
+
+    // Always send the schema, even for empty results
+    if !sent && let Some(schema) = schema {
+        let _ = tx.blocking_send(Ok(RecordBatch::new_empty(schema)));
+    }
+    Ok(rows)
+}
+
+/// Record a failed statement in the audit log; permission errors are read-only or
+/// sandbox refusals
+fn audit_failure(audit: &mut Audit, status: &Status) {
+    let outcome = if status.code() == Code::PermissionDenied {
+        AuditStatus::Rejected
+    } else {
+        AuditStatus::Error
+    };
+    audit.failed_with(outcome, &format!("{:?}", status.code()));
+}
+
+/// Parameter count and result schema of a statement. Queries are described by
+/// running them wrapped in `LIMIT 0` with NULL parameters; other statements
+/// report an empty schema.
+fn describe_statement(conn: &Connection, sql: &str) -> Result<(usize, Schema), Status> {
+    let param_count = conn.prepare(sql).map_err(sql_error)?.parameter_count();
+    if !is_query_statement(sql) {
+        return Ok((param_count, Schema::empty()));
+    }
+
+    let wrapped = format!(
+        "SELECT * FROM ({}) AS described LIMIT 0",
+        sql.trim().trim_end_matches(';')
+    );
+    let mut stmt = conn.prepare(&wrapped).map_err(sql_error)?;
+    let nulls = vec![Value::Null; param_count];
+    let schema = stmt
+        .query_arrow(duckdb::params_from_iter(&nulls))
+        .map_err(sql_error)?
+        .get_schema();
+    Ok((param_count, schema.as_ref().clone()))
+}
+
+/// Arrow schema of an existing table or view
+fn table_arrow_schema(
+    conn: &Connection,
+    catalog: &str,
+    schema: &str,
+    table: &str,
+) -> Result<Schema, Status> {
+    let sql = format!(
+        "SELECT * FROM {}.{}.{} LIMIT 0",
+        quote_identifier(catalog),
+        quote_identifier(schema),
+        quote_identifier(table)
+    );
+    let mut stmt = conn.prepare(&sql).map_err(sql_error)?;
+    let schema = stmt.query_arrow([]).map_err(sql_error)?.get_schema();
+    Ok(schema.as_ref().clone())
+}
+
+/// Parameter sets to execute with; a statement without bound parameters runs once
+fn parameter_sets(params: &[Vec<Value>]) -> Vec<&[Value]> {
+    if params.is_empty() {
+        vec![&[]]
+    } else {
+        params.iter().map(Vec::as_slice).collect()
+    }
+}
+
+/// Convert bound parameter batches into one DuckDB parameter set per row
+fn parameter_rows(batches: &[RecordBatch]) -> Result<Vec<Vec<Value>>, Status> {
+    let mut rows = Vec::new();
+    for batch in batches {
+        for row in 0..batch.num_rows() {
+            let values = batch
+                .columns()
+                .iter()
+                .map(|column| parameter_value(column.as_ref(), row))
+                .collect::<Result<Vec<_>, _>>()?;
+            rows.push(values);
+        }
+    }
+    Ok(rows)
+}
+
+/// One Arrow value as a DuckDB parameter. Types DuckDB cannot bind directly
+/// are passed as their text form and cast by DuckDB.
+fn parameter_value(array: &dyn Array, row: usize) -> Result<Value, Status> {
+    if array.is_null(row) {
+        return Ok(Value::Null);
+    }
+
+    let value = match array.data_type() {
+        DataType::Boolean => Value::Boolean(array.as_boolean().value(row)),
+        DataType::Int8 => Value::TinyInt(array.as_primitive::<datatypes::Int8Type>().value(row)),
+        DataType::Int16 => Value::SmallInt(array.as_primitive::<datatypes::Int16Type>().value(row)),
+        DataType::Int32 => Value::Int(array.as_primitive::<datatypes::Int32Type>().value(row)),
+        DataType::Int64 => Value::BigInt(array.as_primitive::<datatypes::Int64Type>().value(row)),
+        DataType::UInt8 => Value::UTinyInt(array.as_primitive::<datatypes::UInt8Type>().value(row)),
+        DataType::UInt16 => {
+            Value::USmallInt(array.as_primitive::<datatypes::UInt16Type>().value(row))
+        }
+        DataType::UInt32 => Value::UInt(array.as_primitive::<datatypes::UInt32Type>().value(row)),
+        DataType::UInt64 => {
+            Value::UBigInt(array.as_primitive::<datatypes::UInt64Type>().value(row))
+        }
+        DataType::Float32 => {
+            Value::Float(array.as_primitive::<datatypes::Float32Type>().value(row))
+        }
+        DataType::Float64 => {
+            Value::Double(array.as_primitive::<datatypes::Float64Type>().value(row))
+        }
+        DataType::Utf8 => Value::Text(array.as_string::<i32>().value(row).to_string()),
+        DataType::LargeUtf8 => Value::Text(array.as_string::<i64>().value(row).to_string()),
+        DataType::Utf8View => Value::Text(array.as_string_view().value(row).to_string()),
+        DataType::Binary => Value::Blob(array.as_binary::<i32>().value(row).to_vec()),
+        DataType::LargeBinary => Value::Blob(array.as_binary::<i64>().value(row).to_vec()),
+        DataType::Timestamp(unit, None) => {
+            let (unit, value) = match unit {
+                datatypes::TimeUnit::Second => (
+                    TimeUnit::Second,
+                    array
+                        .as_primitive::<datatypes::TimestampSecondType>()
+                        .value(row),
+                ),
+                datatypes::TimeUnit::Millisecond => (
+                    TimeUnit::Millisecond,
+                    array
+                        .as_primitive::<datatypes::TimestampMillisecondType>()
+                        .value(row),
+                ),
+                datatypes::TimeUnit::Microsecond => (
+                    TimeUnit::Microsecond,
+                    array
+                        .as_primitive::<datatypes::TimestampMicrosecondType>()
+                        .value(row),
+                ),
+                datatypes::TimeUnit::Nanosecond => (
+                    TimeUnit::Nanosecond,
+                    array
+                        .as_primitive::<datatypes::TimestampNanosecondType>()
+                        .value(row),
+                ),
+            };
+            Value::Timestamp(unit, value)
+        }
+        _ => Value::Text(array_value_to_string(array, row).map_err(arrow_error)?),
+    };
+    Ok(value)
+}
+
+/// API key presented as a bearer token, HTTP Basic password or `x-api-key` header
+fn presented_key(metadata: &MetadataMap) -> Option<String> {
+    if let Some(key) = metadata.get(API_KEY_HEADER).and_then(|v| v.to_str().ok()) {
+        return Some(key.to_string());
+    }
+
+    let authorization = metadata.get("authorization")?.to_str().ok()?;
+    if let Some(token) = authorization.strip_prefix("Bearer ") {
+        return Some(token.to_string());
+    }
+    let credentials = authorization.strip_prefix("Basic ")?;
+    let decoded = BASE64_STANDARD.decode(credentials).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (_user, password) = decoded.split_once(':')?;
+    Some(password.to_string())
+}
+
+/// FlightInfo with a single endpoint whose ticket is `command`
+fn flight_info(
+    schema: &Schema,
+    command: Any,
+    descriptor: FlightDescriptor,
+) -> Result<Response<FlightInfo>, Status> {
+    let endpoint = FlightEndpoint::new().with_ticket(Ticket::new(command.encode_to_vec()));
+    let info = FlightInfo::new()
+        .try_with_schema(schema)
+        .map_err(arrow_error)?
+        .with_endpoint(endpoint)
+        .with_descriptor(descriptor);
+    Ok(Response::new(info))
+}
+
+/// Stream a single record batch
+fn batch_stream(
+    batch: Result<RecordBatch, FlightError>,
+) -> Result<Response<ResponseStream<FlightData>>, Status> {
+    let batch = batch?;
+    let stream = FlightDataEncoderBuilder::new()
+        .build(stream::iter([Ok(batch)]))
+        .map_err(Status::from);
+    Ok(Response::new(Box::pin(stream)))
+}
+
+fn ipc_schema(schema: &Schema) -> Result<prost::bytes::Bytes, Status> {
+    let IpcMessage(bytes) = SchemaAsIpc::new(schema, &IpcWriteOptions::default())
+        .try_into()
+        .map_err(arrow_error)?;
+    Ok(bytes)
+}
+
+fn sql_error(err: duckdb::Error) -> Status {
+    Status::invalid_argument(err.to_string())
+}
+
+fn arrow_error(err: ArrowError) -> Status {
+    Status::internal(err.to_string())
+}
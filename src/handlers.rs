@@ -1,14 +1,36 @@
 use axum::{
-    extract::{Query, State},
-    response::{Json, Response},
+    extract::{Path, Query, State},
+    http::{Extensions, HeaderMap, HeaderValue, header},
+    response::{
+        IntoResponse, Json, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
-use std::time::SystemTime;
+use futures::Stream;
+use serde_json::Value;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 use tracing::{error, info, instrument, warn};
 use utoipa;
 use uuid::Uuid;
 
-use crate::database::{execute_sql_command, execute_sql_with_limit, validate_readonly_operation};
-use crate::{ApiError, AppState, HealthResponse, QueryParams, QueryRequest, QueryResponse};
+use crate::cursor::open_paginated;
+use crate::database::{
+    BindParams, execute_sql_command, execute_sql_with_params, execute_sql_with_profile,
+    is_write_operation, validate_readonly_operation, validate_sandbox_operation,
+};
+use crate::profile::duration_ms;
+use crate::progress::{parse_query_id, progress_events};
+use crate::rate_limit::API_KEY_HEADER;
+use crate::{
+    ApiError, AppState, Audit, AuditSource, CacheFormat, CacheKey, HealthResponse, PRIORITY_HEADER,
+    Priority, ProgressSnapshot, QUERY_ID_HEADER, QueryParams, QueryProgress, QueryRequest,
+    QueryResponse,
+};
+
+/// Response header reporting whether a result came from the cache
+const X_CACHE_HEADER: &str = "x-cache";
 
 /// Health check endpoint handler
 /// Returns server status, timestamp, database info, and read-only mode status
@@ -37,30 +59,59 @@ pub async fn health_check(State(state): State<AppState>) -> Json<HealthResponse>
             .as_ref()
             .map(|p| p.to_string_lossy().to_string()),
         readonly_mode: state.is_readonly,
+        queries_in_flight: state.admission.in_flight(),
+        queries_queued: state.admission.queued(),
+        batch_queries_in_flight: state.batch_admission.in_flight(),
+        batch_queries_queued: state.batch_admission.queued(),
     })
 }
 
+/// Metrics endpoint handler
+/// Returns server metrics in the Prometheus text exposition format
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Prometheus metrics", body = String, content_type = "text/plain")
+    ),
+    tag = "health"
+)]
+pub async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
+
 /// POST endpoint handler for SQL query execution
 /// Accepts SQL queries in request body with optional row limit
 #[utoipa::path(
     post,
     path = "/query",
     request_body = QueryRequest,
+    params(
+        ("X-Query-Priority" = Option<String>, Header, description = "Priority class: interactive (default) or batch"),
+        ("X-Query-Id" = Option<String>, Header, description = "Client-chosen query ID, for following progress at /queries/{id}/progress")
+    ),
     responses(
         (status = 200, description = "Query executed successfully", body = QueryResponse),
         (status = 400, description = "Bad request"),
-        (status = 403, description = "Operation forbidden in read-only mode"),
-        (status = 500, description = "Internal server error")
+        (status = 403, description = "Operation forbidden in read-only or sandbox mode"),
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "Server busy - execution queue full or queue wait timed out")
     ),
     tag = "query"
 )]
 #[instrument(skip(state, request), fields(sql_length = request.sql.len(), limit = request.limit))]
 pub async fn execute_query_post(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    extensions: Extensions,
     Json(request): Json<QueryRequest>,
-) -> Result<Json<QueryResponse>, Response> {
+) -> Result<(HeaderMap, Json<QueryResponse>), Response> {
     info!("Query execution requested via POST");
-    execute_query_internal(state, request.sql, request.limit).await
+    let source = AuditSource::http(&state, "/query", &headers, &extensions);
+    execute_query_internal(state, headers, source, request).await
 }
 
 /// GET endpoint handler for SQL query execution
@@ -70,24 +121,39 @@ pub async fn execute_query_post(
     path = "/query",
     params(
         ("sql" = Option<String>, Query, description = "SQL query to execute"),
-        ("limit" = Option<usize>, Query, description = "Maximum number of rows to return")
+        ("limit" = Option<usize>, Query, description = "Maximum number of rows to return"),
+        ("X-Query-Priority" = Option<String>, Header, description = "Priority class: interactive (default) or batch"),
+        ("X-Query-Id" = Option<String>, Header, description = "Client-chosen query ID, for following progress at /queries/{id}/progress")
     ),
     responses(
         (status = 200, description = "Query executed successfully", body = QueryResponse),
         (status = 400, description = "Bad request - missing SQL parameter"),
-        (status = 403, description = "Operation forbidden in read-only mode"),
-        (status = 500, description = "Internal server error")
+        (status = 403, description = "Operation forbidden in read-only or sandbox mode"),
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "Server busy - execution queue full or queue wait timed out")
     ),
     tag = "query"
 )]
 #[instrument(skip(state, params), fields(sql_length = params.sql.as_ref().map(|s| s.len()), limit = params.limit))]
 pub async fn execute_query_get(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    extensions: Extensions,
     Query(params): Query<QueryParams>,
-) -> Result<Json<QueryResponse>, Response> {
+) -> Result<(HeaderMap, Json<QueryResponse>), Response> {
     info!("Query execution requested via GET");
     match params.sql {
-        Some(sql) => execute_query_internal(state, sql, params.limit).await,
+        Some(sql) => {
+            let request = QueryRequest {
+                sql,
+                limit: params.limit,
+                profile: params.profile.unwrap_or(false),
+                page_size: params.page_size,
+                params: Vec::new(),
+            };
+            let source = AuditSource::http(&state, "/query", &headers, &extensions);
+            execute_query_internal(state, headers, source, request).await
+        }
         None => {
             let query_id = Uuid::new_v4().to_string();
             warn!("Query request missing SQL parameter");
@@ -97,15 +163,72 @@ pub async fn execute_query_get(
     }
 }
 
-#[instrument(skip(state, sql), fields(query_id, sql_preview = %sql.chars().take(50).collect::<String>(), limit))]
+#[instrument(skip(state, headers, source, request), fields(query_id, sql_preview = %request.sql.chars().take(50).collect::<String>(), limit = request.limit, page_size = request.page_size, profile = request.profile, priority))]
 async fn execute_query_internal(
     state: AppState,
-    sql: String,
-    limit: Option<usize>,
-) -> Result<Json<QueryResponse>, Response> {
-    let query_id = Uuid::new_v4().to_string();
+    headers: HeaderMap,
+    source: AuditSource,
+    request: QueryRequest,
+) -> Result<(HeaderMap, Json<QueryResponse>), Response> {
+    let query_id = match request_query_id(&headers) {
+        Ok(query_id) => query_id,
+        Err(error) => return Err(error.to_response(None)),
+    };
     tracing::Span::current().record("query_id", &query_id);
-    tracing::Span::current().record("limit", &limit);
+
+    // Register the query so clients can follow it at /queries/{id}/progress
+    let Some(tracked) = state.queries.start(&query_id) else {
+        warn!("Query ID already in use");
+        let error = ApiError::bad_request("Query ID is already in use");
+        return Err(error.to_response(Some(query_id)));
+    };
+    let progress = tracked.progress().clone();
+
+    let mut audit =
+        Audit::begin(&state, &source, &query_id, &request.sql).with_params(&request.params);
+    match run_query(
+        state,
+        headers,
+        source.client,
+        request,
+        query_id.clone(),
+        progress,
+    )
+    .await
+    {
+        Ok((response_headers, response)) => {
+            if let Some(data) = &response.data {
+                audit.succeeded(data);
+            }
+            tracked.complete(&response);
+            Ok((response_headers, Json(response)))
+        }
+        Err(error) => {
+            audit.failed(&error);
+            tracked.fail(error.to_error_response(Some(query_id.clone())));
+            Err(error.to_response(Some(query_id)))
+        }
+    }
+}
+
+async fn run_query(
+    state: AppState,
+    headers: HeaderMap,
+    client: String,
+    request: QueryRequest,
+    query_id: String,
+    progress: Arc<QueryProgress>,
+) -> Result<(HeaderMap, QueryResponse), ApiError> {
+    let QueryRequest {
+        sql,
+        limit,
+        profile,
+        page_size,
+        params,
+    } = request;
+
+    let priority = request_priority(&state, &headers)?;
+    tracing::Span::current().record("priority", priority.as_str());
 
     let start_time = SystemTime::now();
     info!("Starting query execution");
@@ -113,19 +236,113 @@ async fn execute_query_internal(
     // Validate read-only operations
     if let Some(error_msg) = validate_readonly_operation(&state, &sql) {
         warn!("Read-only violation detected");
-        let error = ApiError::forbidden(error_msg);
-        return Err(error.to_response(Some(query_id)));
+        return Err(ApiError::forbidden(error_msg));
     }
 
+    // Validate sandbox restrictions
+    if let Some(error_msg) = validate_sandbox_operation(&state, &sql) {
+        warn!("Sandbox violation detected");
+        return Err(ApiError::forbidden(error_msg));
+    }
+
+    if profile && page_size.is_some() {
+        return Err(ApiError::bad_request(
+            "profile cannot be combined with page_size",
+        ));
+    }
+
+    // Serve read-only queries from the result cache when possible; profiled and
+    // paginated queries always run so their timings and cursors describe a real execution
+    let is_write = is_write_operation(&sql);
+    let cache = state.cache.clone();
+    let mut response_headers = HeaderMap::new();
+    let cache_entry = match &cache {
+        Some(cache) if !is_write && !profile && page_size.is_none() => {
+            let key = if params.is_empty() {
+                CacheKey::new(&sql, limit, CacheFormat::Json)
+            } else {
+                CacheKey::with_params(
+                    &sql,
+                    &Value::from(params.clone()).to_string(),
+                    limit,
+                    CacheFormat::Json,
+                )
+            };
+            if let Some(hit) = cache.get(&key) {
+                let execution_time_ms = start_time.elapsed().unwrap_or_default().as_millis() as u64;
+                info!(execution_time_ms, "Query served from result cache");
+                set_cache_headers(&mut response_headers, "HIT", hit.remaining_ttl.as_secs());
+                return Ok((
+                    response_headers,
+                    QueryResponse {
+                        success: true,
+                        data: Some((*hit.data).clone()),
+                        error: None,
+                        query_id,
+                        execution_time_ms,
+                        profile: None,
+                    },
+                ));
+            }
+            set_cache_headers(&mut response_headers, "MISS", cache.ttl().as_secs());
+            Some((key, cache.generation()))
+        }
+        Some(_) => {
+            set_cache_headers(&mut response_headers, "BYPASS", 0);
+            None
+        }
+        None => None,
+    };
+
+    let params = BindParams::from_json(&params);
+    if let Some(page_size) = page_size {
+        let data = open_paginated(&state, &sql, params, page_size, priority, &client).await?;
+        let execution_time_ms = start_time.elapsed().unwrap_or_default().as_millis() as u64;
+        info!(execution_time_ms, "Paginated query executed successfully");
+        return Ok((
+            response_headers,
+            QueryResponse {
+                success: true,
+                data: Some(data),
+                error: None,
+                query_id,
+                execution_time_ms,
+                profile: None,
+            },
+        ));
+    }
+
+    // Wait for a free execution slot before spawning a blocking task
+    let queue_start = Instant::now();
+    let permit = state
+        .admission_for(priority)
+        .acquire(&state.metrics)
+        .await
+        .map_err(|e| ApiError::server_busy(e.to_string()))?;
+    let queue_wait = queue_start.elapsed();
+
     // Execute query in blocking task
-    let result =
-        tokio::task::spawn_blocking(move || execute_sql_with_limit(&state, &sql, limit)).await;
+    let result = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        if profile {
+            execute_sql_with_profile(&state, &sql, &params, limit, priority, Some(&progress)).map(
+                |(data, mut profile)| {
+                    profile.queue_wait_ms = duration_ms(queue_wait);
+                    (data, Some(profile))
+                },
+            )
+        } else {
+            execute_sql_with_params(&state, &sql, &params, limit, priority, Some(&progress))
+                .map(|data| (data, None))
+        }
+    })
+    .await;
 
     let execution_time_ms = start_time.elapsed().unwrap_or_default().as_millis() as u64;
 
     match result {
         Ok(sql_result) => match sql_result {
-            Ok(data) => {
+            Ok((data, profile)) => {
                 let row_count = data.get("row_count").and_then(|v| v.as_u64()).unwrap_or(0);
                 let truncated = data
                     .get("truncated")
@@ -139,13 +356,25 @@ async fn execute_query_internal(
                     "Query executed successfully"
                 );
 
-                Ok(Json(QueryResponse {
-                    success: true,
-                    data: Some(data),
-                    error: None,
-                    query_id,
-                    execution_time_ms,
-                }))
+                if let Some(cache) = &cache {
+                    match cache_entry {
+                        Some((key, generation)) => cache.insert(key, data.clone(), generation),
+                        None if is_write => cache.invalidate_all(),
+                        None => {}
+                    }
+                }
+
+                Ok((
+                    response_headers,
+                    QueryResponse {
+                        success: true,
+                        data: Some(data),
+                        error: None,
+                        query_id,
+                        execution_time_ms,
+                        profile,
+                    },
+                ))
             }
             Err(e) => {
                 error!(
@@ -153,8 +382,7 @@ async fn execute_query_internal(
                     error = %e,
                     "Query execution failed"
                 );
-                let error = ApiError::Database(e);
-                Err(error.to_response(Some(query_id)))
+                Err(ApiError::Database(e))
             }
         },
         Err(e) => {
@@ -163,7 +391,44 @@ async fn execute_query_internal(
                 error = %e,
                 "Task execution failed"
             );
-            let error = ApiError::internal_server_error(format!("Task execution error: {}", e));
+            Err(ApiError::internal_server_error(format!(
+                "Task execution error: {}",
+                e
+            )))
+        }
+    }
+}
+
+/// Server-Sent Events stream reporting the progress of a `/query` request
+/// Emits `progress` events while the query runs, then one `result` event with the
+/// response summary (without rows) or one `error` event
+#[utoipa::path(
+    get,
+    path = "/queries/{id}/progress",
+    params(
+        ("id" = String, Path, description = "Query ID returned by /query or chosen with the X-Query-Id header")
+    ),
+    responses(
+        (status = 200, description = "Event stream of progress events followed by a result or error event", body = ProgressSnapshot, content_type = "text/event-stream"),
+        (status = 404, description = "No running or recently finished query with this ID")
+    ),
+    tag = "query"
+)]
+#[instrument(skip(state))]
+pub async fn query_progress(
+    State(state): State<AppState>,
+    Path(query_id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    match state.queries.get(&query_id) {
+        Some(progress) => {
+            info!("Progress stream requested");
+            Ok(Sse::new(progress_events(progress)).keep_alive(KeepAlive::default()))
+        }
+        None => {
+            let error = ApiError::not_found(format!(
+                "No running or recently finished query with ID '{}'",
+                query_id
+            ));
             Err(error.to_response(Some(query_id)))
         }
     }
@@ -173,45 +438,65 @@ async fn execute_query_internal(
     post,
     path = "/execute",
     request_body = QueryRequest,
+    params(
+        ("X-Query-Priority" = Option<String>, Header, description = "Priority class: interactive (default) or batch")
+    ),
     responses(
         (status = 200, description = "Command executed successfully", body = QueryResponse),
         (status = 400, description = "Bad request"),
-        (status = 403, description = "Operation forbidden in read-only mode"),
-        (status = 500, description = "Internal server error")
+        (status = 403, description = "Operation forbidden in read-only or sandbox mode"),
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "Server busy - execution queue full or queue wait timed out")
     ),
     tag = "execute"
 )]
 #[instrument(skip(state, request), fields(sql_length = request.sql.len()))]
 pub async fn execute_command_post(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    extensions: Extensions,
     Json(request): Json<QueryRequest>,
 ) -> Result<Json<QueryResponse>, Response> {
     info!("Command execution requested via POST");
-    execute_command_internal(state, request.sql).await
+    let source = AuditSource::http(&state, "/execute", &headers, &extensions);
+    let query_id = Uuid::new_v4().to_string();
+    let audit = Audit::begin(&state, &source, &query_id, &request.sql).with_params(&request.params);
+    let params = BindParams::from_json(&request.params);
+    execute_command_internal(state, headers, request.sql, params, query_id, audit).await
 }
 
 #[utoipa::path(
     get,
     path = "/execute",
     params(
-        ("sql" = Option<String>, Query, description = "SQL command to execute")
+        ("sql" = Option<String>, Query, description = "SQL command to execute"),
+        ("X-Query-Priority" = Option<String>, Header, description = "Priority class: interactive (default) or batch")
     ),
     responses(
         (status = 200, description = "Command executed successfully", body = QueryResponse),
         (status = 400, description = "Bad request - missing SQL parameter"),
-        (status = 403, description = "Operation forbidden in read-only mode"),
-        (status = 500, description = "Internal server error")
+        (status = 403, description = "Operation forbidden in read-only or sandbox mode"),
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "Server busy - execution queue full or queue wait timed out")
     ),
     tag = "execute"
 )]
 #[instrument(skip(state, params), fields(sql_length = params.sql.as_ref().map(|s| s.len())))]
 pub async fn execute_command_get(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    extensions: Extensions,
     Query(params): Query<QueryParams>,
 ) -> Result<Json<QueryResponse>, Response> {
     info!("Command execution requested via GET");
     match params.sql {
-        Some(sql) => execute_command_internal(state, sql).await,
+        Some(sql) => {
+            let source = AuditSource::http(&state, "/execute", &headers, &extensions);
+            let query_id = Uuid::new_v4().to_string();
+            let audit = Audit::begin(&state, &source, &query_id, &sql);
+            execute_command_internal(state, headers, sql, BindParams::default(), query_id, audit)
+                .await
+        }
         None => {
             let query_id = Uuid::new_v4().to_string();
             warn!("Command request missing SQL parameter");
@@ -221,13 +506,23 @@ pub async fn execute_command_get(
     }
 }
 
-#[instrument(skip(state, sql), fields(query_id, sql_preview = %sql.chars().take(50).collect::<String>()))]
+#[instrument(skip(state, headers, sql, params, audit), fields(query_id = %query_id, sql_preview = %sql.chars().take(50).collect::<String>(), priority))]
 async fn execute_command_internal(
     state: AppState,
+    headers: HeaderMap,
     sql: String,
+    params: BindParams,
+    query_id: String,
+    mut audit: Audit,
 ) -> Result<Json<QueryResponse>, Response> {
-    let query_id = Uuid::new_v4().to_string();
-    tracing::Span::current().record("query_id", &query_id);
+    let priority = match request_priority(&state, &headers) {
+        Ok(priority) => priority,
+        Err(error) => {
+            audit.failed(&error);
+            return Err(error.to_response(Some(query_id)));
+        }
+    };
+    tracing::Span::current().record("priority", priority.as_str());
 
     let start_time = SystemTime::now();
     info!("Starting command execution");
@@ -236,11 +531,35 @@ async fn execute_command_internal(
     if let Some(error_msg) = validate_readonly_operation(&state, &sql) {
         warn!("Read-only violation detected");
         let error = ApiError::forbidden(error_msg);
+        audit.failed(&error);
         return Err(error.to_response(Some(query_id)));
     }
 
+    // Validate sandbox restrictions
+    if let Some(error_msg) = validate_sandbox_operation(&state, &sql) {
+        warn!("Sandbox violation detected");
+        let error = ApiError::forbidden(error_msg);
+        audit.failed(&error);
+        return Err(error.to_response(Some(query_id)));
+    }
+
+    // Wait for a free execution slot before spawning a blocking task
+    let permit = match state.admission_for(priority).acquire(&state.metrics).await {
+        Ok(permit) => permit,
+        Err(e) => {
+            let error = ApiError::server_busy(e.to_string());
+            audit.failed(&error);
+            return Err(error.to_response(Some(query_id)));
+        }
+    };
+
     // Execute command in blocking task
-    let result = tokio::task::spawn_blocking(move || execute_sql_command(&state, &sql)).await;
+    let cache = state.cache.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        execute_sql_command(&state, &sql, &params, priority)
+    })
+    .await;
 
     let execution_time_ms = start_time.elapsed().unwrap_or_default().as_millis() as u64;
 
@@ -257,6 +576,12 @@ async fn execute_command_internal(
                     rows_affected = rows_affected,
                     "Command executed successfully"
                 );
+                audit.succeeded(&data);
+
+                // Any successful command may have changed data behind cached results
+                if let Some(cache) = &cache {
+                    cache.invalidate_all();
+                }
 
                 Ok(Json(QueryResponse {
                     success: true,
@@ -264,6 +589,7 @@ async fn execute_command_internal(
                     error: None,
                     query_id,
                     execution_time_ms,
+                    profile: None,
                 }))
             }
             Err(e) => {
@@ -273,6 +599,7 @@ async fn execute_command_internal(
                     "Command execution failed"
                 );
                 let error = ApiError::Database(e);
+                audit.failed(&error);
                 Err(error.to_response(Some(query_id)))
             }
         },
@@ -283,7 +610,50 @@ async fn execute_command_internal(
                 "Task execution failed"
             );
             let error = ApiError::internal_server_error(format!("Task execution error: {}", e));
+            audit.failed(&error);
             Err(error.to_response(Some(query_id)))
         }
     }
 }
+
+/// Set `X-Cache` and `Cache-Control` headers for a cacheable query response
+pub fn set_cache_headers(headers: &mut HeaderMap, status: &'static str, max_age_secs: u64) {
+    headers.insert(X_CACHE_HEADER, HeaderValue::from_static(status));
+    let cache_control = if max_age_secs > 0 {
+        HeaderValue::from_str(&format!("private, max-age={}", max_age_secs))
+            .unwrap_or(HeaderValue::from_static("no-store"))
+    } else {
+        HeaderValue::from_static("no-store")
+    };
+    headers.insert(header::CACHE_CONTROL, cache_control);
+}
+
+/// Resolve the priority class for a request.
+/// API keys configured with `--batch-api-key` always run at batch priority;
+/// other clients may opt into batch priority with the `X-Query-Priority` header.
+pub fn request_priority(state: &AppState, headers: &HeaderMap) -> Result<Priority, ApiError> {
+    let is_batch_key = headers
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|key| state.batch_api_keys.contains(key));
+    if is_batch_key {
+        return Ok(Priority::Batch);
+    }
+
+    match headers.get(PRIORITY_HEADER) {
+        Some(value) => value
+            .to_str()
+            .map_err(|_| ApiError::bad_request("Invalid X-Query-Priority header"))?
+            .parse()
+            .map_err(ApiError::bad_request),
+        None => Ok(Priority::Interactive),
+    }
+}
+
+/// Query ID for a `/query` request: the client's `X-Query-Id` when sent, otherwise a new UUID
+fn request_query_id(headers: &HeaderMap) -> Result<String, ApiError> {
+    match headers.get(QUERY_ID_HEADER) {
+        Some(value) => parse_query_id(value.to_str().unwrap_or_default()),
+        None => Ok(Uuid::new_v4().to_string()),
+    }
+}
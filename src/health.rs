@@ -0,0 +1,291 @@
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde::Serialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{instrument, warn};
+use utoipa::ToSchema;
+
+use crate::{AppState, DuckDbPool};
+
+/// How long `/health/ready` waits for a pooled connection before reporting the pool exhausted
+const READY_POOL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Statistics of one connection pool
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PoolStats {
+    /// Maximum number of connections
+    #[schema(example = 10)]
+    pub max_size: u32,
+    /// Connections currently open, idle or in use
+    #[schema(example = 4)]
+    pub connections: u32,
+    /// Open connections not in use
+    #[schema(example = 2)]
+    pub idle_connections: u32,
+    /// Statements currently executing
+    #[schema(example = 2)]
+    pub queries_in_flight: usize,
+    /// Statements waiting for a free connection
+    #[schema(example = 0)]
+    pub queries_queued: usize,
+}
+
+/// Response structure for `/health/live`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LivenessResponse {
+    /// Always `alive`
+    #[schema(example = "alive")]
+    pub status: String,
+    /// Seconds since the server started
+    #[schema(example = 3600)]
+    pub uptime_secs: u64,
+}
+
+/// Response structure for `/health/ready`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReadinessResponse {
+    /// `ready` or `not_ready`
+    #[schema(example = "ready")]
+    pub status: String,
+    /// Why the server is not ready
+    #[schema(example = "No database connection available within 1s")]
+    pub reason: Option<String>,
+    /// Unix timestamp
+    #[schema(example = 1698765432)]
+    pub timestamp: u64,
+    /// Seconds since the server started
+    #[schema(example = 3600)]
+    pub uptime_secs: u64,
+    /// Whether the server is shutting down and draining requests
+    pub draining: bool,
+    /// Version of the embedded DuckDB
+    #[schema(example = "v1.4.2")]
+    pub duckdb_version: Option<String>,
+    /// Extensions loaded into the database
+    pub loaded_extensions: Vec<String>,
+    /// Bytes used by the database file, excluding the write-ahead log
+    #[schema(example = 1048576)]
+    pub database_size_bytes: Option<u64>,
+    /// Directory DuckDB spills to when a query exceeds its memory limit
+    #[schema(example = "/path/to/database.duckdb.tmp")]
+    pub temp_directory: Option<String>,
+    /// Bytes available on the file system holding the temp directory
+    #[schema(example = 53687091200u64)]
+    pub free_disk_bytes: Option<u64>,
+    /// Interactive connection pool
+    pub pool: PoolStats,
+    /// Batch connection pool
+    pub batch_pool: PoolStats,
+}
+
+/// What `/health/ready` learned from the database
+#[derive(Default)]
+struct DatabaseStatus {
+    duckdb_version: Option<String>,
+    loaded_extensions: Vec<String>,
+    database_size_bytes: Option<u64>,
+    temp_directory: Option<PathBuf>,
+    free_disk_bytes: Option<u64>,
+}
+
+/// Liveness probe
+/// Succeeds whenever the process is serving requests; does not touch the database
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    responses(
+        (status = 200, description = "The server process is up", body = LivenessResponse)
+    ),
+    tag = "health"
+)]
+pub async fn liveness(State(state): State<AppState>) -> Json<LivenessResponse> {
+    Json(LivenessResponse {
+        status: "alive".to_string(),
+        uptime_secs: state.started_at.elapsed().as_secs(),
+    })
+}
+
+/// Readiness probe
+/// Runs a trivial query on a pooled connection and checks free disk space in DuckDB's temp directory
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    responses(
+        (status = 200, description = "The server can run queries", body = ReadinessResponse),
+        (status = 503, description = "The server is draining, the pool is exhausted or the database check failed", body = ReadinessResponse)
+    ),
+    tag = "health"
+)]
+#[instrument(skip(state))]
+pub async fn readiness(State(state): State<AppState>) -> (StatusCode, Json<ReadinessResponse>) {
+    let draining = state.shutdown.is_started();
+
+    let (database, reason) = if draining {
+        (
+            DatabaseStatus::default(),
+            Some("Server is shutting down".to_string()),
+        )
+    } else {
+        let pool = state.pool.clone();
+        let min_free_disk = state.ready_min_free_disk;
+        match tokio::task::spawn_blocking(move || check_database(&pool, min_free_disk)).await {
+            Ok(result) => result,
+            Err(e) => (
+                DatabaseStatus::default(),
+                Some(format!("Readiness check failed: {}", e)),
+            ),
+        }
+    };
+
+    if let Some(reason) = &reason {
+        warn!(reason = %reason, "Readiness check failed");
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let status = if reason.is_none() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let response = ReadinessResponse {
+        status: if reason.is_none() {
+            "ready"
+        } else {
+            "not_ready"
+        }
+        .to_string(),
+        reason,
+        timestamp,
+        uptime_secs: state.started_at.elapsed().as_secs(),
+        draining,
+        duckdb_version: database.duckdb_version,
+        loaded_extensions: database.loaded_extensions,
+        database_size_bytes: database.database_size_bytes,
+        temp_directory: database
+            .temp_directory
+            .map(|dir| dir.to_string_lossy().to_string()),
+        free_disk_bytes: database.free_disk_bytes,
+        pool: pool_stats(&state.pool, &state, false),
+        batch_pool: pool_stats(&state.batch_pool, &state, true),
+    };
+    (status, Json(response))
+}
+
+fn pool_stats(pool: &DuckDbPool, state: &AppState, batch: bool) -> PoolStats {
+    let pool_state = pool.state();
+    let admission = if batch {
+        &state.batch_admission
+    } else {
+        &state.admission
+    };
+    PoolStats {
+        max_size: pool.max_size(),
+        connections: pool_state.connections,
+        idle_connections: pool_state.idle_connections,
+        queries_in_flight: admission.in_flight(),
+        queries_queued: admission.queued(),
+    }
+}
+
+/// Check the database on a pooled connection; returns what was learned and, if the server is
+/// not ready, why
+fn check_database(pool: &DuckDbPool, min_free_disk: u64) -> (DatabaseStatus, Option<String>) {
+    let mut status = DatabaseStatus::default();
+
+    let conn = match pool.get_timeout(READY_POOL_TIMEOUT) {
+        Ok(conn) => conn,
+        Err(_) => {
+            return (
+                status,
+                Some(format!(
+                    "No database connection available within {}s",
+                    READY_POOL_TIMEOUT.as_secs()
+                )),
+            );
+        }
+    };
+
+    match conn.query_row("SELECT version()", [], |row| row.get::<_, String>(0)) {
+        Ok(version) => status.duckdb_version = Some(version),
+        Err(e) => return (status, Some(format!("Database query failed: {}", e))),
+    }
+
+    status.loaded_extensions = conn
+        .prepare("SELECT extension_name FROM duckdb_extensions() WHERE loaded ORDER BY 1")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<_, _>>()
+        })
+        .unwrap_or_default();
+
+    status.database_size_bytes = conn
+        .query_row(
+            "SELECT (block_size * used_blocks)::UBIGINT FROM pragma_database_size() \
+             WHERE database_name = current_database()",
+            [],
+            |row| row.get::<_, u64>(0),
+        )
+        .ok();
+
+    let temp_directory = conn
+        .query_row("SELECT current_setting('temp_directory')", [], |row| {
+            row.get::<_, String>(0)
+        })
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    drop(conn);
+
+    let free_disk = available_disk_space(&temp_directory);
+    status.temp_directory = Some(temp_directory);
+    match free_disk {
+        Ok(free) => {
+            status.free_disk_bytes = free;
+            if free.is_some_and(|free| free < min_free_disk) {
+                return (
+                    status,
+                    Some(format!(
+                        "Less than {} MB free in the temp directory",
+                        min_free_disk / (1024 * 1024)
+                    )),
+                );
+            }
+        }
+        Err(e) => return (status, Some(format!("Cannot check free disk space: {}", e))),
+    }
+
+    (status, None)
+}
+
+/// Bytes available to unprivileged users on the file system holding `path`. DuckDB creates its
+/// temp directory on first use, so the nearest existing ancestor is checked.
+#[cfg(unix)]
+fn available_disk_space(path: &Path) -> io::Result<Option<u64>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path
+        .ancestors()
+        .find(|dir| dir.exists())
+        .unwrap_or(Path::new("."));
+    let c_path = CString::new(existing.as_os_str().as_bytes())?;
+    // SAFETY: `c_path` is a valid NUL-terminated string and `stat` is a valid out pointer
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+/// Free disk space is not checked on this platform
+#[cfg(not(unix))]
+fn available_disk_space(_path: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
@@ -0,0 +1,619 @@
+use axum::{
+    extract::{Query, State},
+    response::{Json, Response},
+};
+use duckdb::Connection;
+use r2d2::ManageConnection;
+use regex::Regex;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, instrument, warn};
+use utoipa::ToSchema;
+
+use crate::audit::redact_sql;
+use crate::database::{normalize_sql, split_statements};
+use crate::explain::explain_statement;
+use crate::{ApiError, AppState, Args, AuditRecord, AuditStatus, DuckDbConnectionManager};
+
+/// Entries returned by `/admin/history` when no `limit` is given
+const DEFAULT_HISTORY_LIMIT: usize = 100;
+/// Persisted entries are written once this many are pending...
+const FLUSH_BATCH: usize = 100;
+/// ...or once the oldest has waited this long
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Lists of placeholders left by redaction, e.g. the values of `IN (1, 2, 3)`
+static PLACEHOLDER_LIST: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\?(?:\s*,\s*\?)+").expect("valid regex"));
+/// Table names accepted by `--history-table`, optionally qualified by a schema
+static TABLE_NAME: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*(\.[A-Za-z_][A-Za-z0-9_]*)?$").expect("valid regex")
+});
+
+/// One statement in the query history
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct HistoryEntry {
+    /// Unix time the statement finished, in milliseconds
+    #[schema(example = 1698765432123u64)]
+    pub timestamp_ms: u64,
+    #[schema(example = "123e4567-e89b-12d3-a456-426614174000")]
+    pub query_id: String,
+    /// Hash of `query`; statements differing only in their literals share a fingerprint
+    #[schema(example = "3f9a0c1d2b4e5f60")]
+    pub fingerprint: String,
+    /// SQL with comments removed, whitespace collapsed and literals replaced by `?`
+    #[schema(example = "SELECT * FROM orders WHERE id = ?")]
+    pub query: String,
+    #[schema(example = "SELECT")]
+    pub statement_type: String,
+    /// `http`, `websocket`, `pgwire` or `flight`
+    #[schema(example = "http")]
+    pub interface: String,
+    #[schema(example = "ip:127.0.0.1")]
+    pub client: String,
+    #[schema(example = 42)]
+    pub duration_ms: u64,
+    pub rows_returned: Option<u64>,
+    pub rows_affected: Option<u64>,
+    pub status: AuditStatus,
+    pub error_code: Option<String>,
+}
+
+/// Statistics of the statements sharing one fingerprint
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FingerprintStats {
+    #[schema(example = "3f9a0c1d2b4e5f60")]
+    pub fingerprint: String,
+    #[schema(example = "SELECT * FROM orders WHERE id = ?")]
+    pub query: String,
+    #[schema(example = "SELECT")]
+    pub statement_type: String,
+    /// Statements in the history with this fingerprint
+    #[schema(example = 120)]
+    pub count: usize,
+    /// Statements that did not finish with status `ok`
+    #[schema(example = 2)]
+    pub errors: usize,
+    #[schema(example = 5040)]
+    pub total_time_ms: u64,
+    #[schema(example = 35)]
+    pub p50_ms: u64,
+    #[schema(example = 120)]
+    pub p95_ms: u64,
+    #[schema(example = 310)]
+    pub max_ms: u64,
+    /// Unix time the most recent statement finished, in milliseconds
+    #[schema(example = 1698765432123u64)]
+    pub last_seen_ms: u64,
+}
+
+/// Response structure for `/admin/history`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HistoryResponse {
+    /// Statements in the history that match the filters
+    #[schema(example = 250)]
+    pub matched: usize,
+    /// Matching statements, most recent first; empty when grouped
+    pub entries: Vec<HistoryEntry>,
+    /// Matching statements grouped by fingerprint, by total time descending; empty unless
+    /// `group_by=fingerprint`
+    pub fingerprints: Vec<FingerprintStats>,
+}
+
+/// Filters of `/admin/history`
+#[derive(Debug, Default, Deserialize)]
+pub struct HistoryFilter {
+    pub fingerprint: Option<String>,
+    pub status: Option<AuditStatus>,
+    pub client: Option<String>,
+    pub interface: Option<String>,
+    pub statement_type: Option<String>,
+    pub min_duration_ms: Option<u64>,
+    pub since_ms: Option<u64>,
+    pub limit: Option<usize>,
+    pub group_by: Option<HistoryGrouping>,
+}
+
+/// How `/admin/history` groups statements
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryGrouping {
+    Fingerprint,
+}
+
+impl HistoryFilter {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.fingerprint
+            .as_ref()
+            .is_none_or(|f| *f == entry.fingerprint)
+            && self.status.is_none_or(|s| s == entry.status)
+            && self.client.as_ref().is_none_or(|c| *c == entry.client)
+            && self
+                .interface
+                .as_ref()
+                .is_none_or(|i| *i == entry.interface)
+            && self
+                .statement_type
+                .as_ref()
+                .is_none_or(|t| t.eq_ignore_ascii_case(&entry.statement_type))
+            && self.min_duration_ms.is_none_or(|d| entry.duration_ms >= d)
+            && self.since_ms.is_none_or(|t| entry.timestamp_ms >= t)
+    }
+}
+
+/// Settings of the query history and slow query log
+#[derive(Debug, Clone, Default)]
+pub struct HistoryConfig {
+    /// Recent statements kept in memory; 0 keeps none
+    pub capacity: usize,
+    /// Table the history is also written to
+    pub table: Option<String>,
+    /// Statements at least this slow are logged at WARN with their plan
+    pub slow_threshold: Option<Duration>,
+}
+
+impl HistoryConfig {
+    /// Settings from `--history-size`, `--history-table` and `--slow-query-threshold-ms`
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            capacity: args.history_size,
+            table: args.history_table.clone(),
+            slow_threshold: args.slow_query_threshold_ms.map(Duration::from_millis),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.capacity > 0 || self.table.is_some() || self.slow_threshold.is_some()
+    }
+}
+
+/// Entries waiting to be written to the history table
+struct Pending {
+    entries: Vec<HistoryEntry>,
+    since: Instant,
+}
+
+/// Bounded history of recent statements, optionally persisted to a DuckDB table
+pub struct QueryHistory {
+    config: HistoryConfig,
+    /// Connection of its own for the history table and slow statement plans, so they never
+    /// take a pooled connection that admission control has promised to a statement
+    conn: Option<Mutex<Connection>>,
+    entries: Mutex<VecDeque<HistoryEntry>>,
+    pending: Mutex<Pending>,
+}
+
+impl std::fmt::Debug for QueryHistory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueryHistory")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl QueryHistory {
+    /// Create the history, or `None` when history, persistence and the slow query log are all
+    /// disabled. With a table, the table is created if missing and its most recent entries are
+    /// loaded. The writes and the `EXPLAIN` of slow statements run on a connection `manager`
+    /// opens for the history alone.
+    pub fn open(
+        config: HistoryConfig,
+        manager: &DuckDbConnectionManager,
+        is_readonly: bool,
+    ) -> anyhow::Result<Option<Self>> {
+        if !config.is_enabled() {
+            return Ok(None);
+        }
+
+        let mut entries = VecDeque::with_capacity(config.capacity);
+        if let Some(table) = &config.table {
+            if !TABLE_NAME.is_match(table) {
+                anyhow::bail!("--history-table: invalid table name {}", table);
+            }
+            if is_readonly {
+                anyhow::bail!("--history-table requires a writable database");
+            }
+        }
+        let conn = if config.table.is_some() || config.slow_threshold.is_some() {
+            Some(manager.connect()?)
+        } else {
+            None
+        };
+        if let (Some(table), Some(conn)) = (&config.table, &conn) {
+            create_table(conn, table)?;
+            entries = load_entries(conn, table, config.capacity)?;
+            info!(table = %table, loaded = entries.len(), "Query history persisted to table");
+        }
+        if let Some(threshold) = config.slow_threshold {
+            info!(
+                threshold_ms = threshold.as_millis() as u64,
+                "Slow query log enabled"
+            );
+        }
+
+        Ok(Some(Self {
+            config,
+            conn: conn.map(Mutex::new),
+            entries: Mutex::new(entries),
+            pending: Mutex::new(Pending {
+                entries: Vec::new(),
+                since: Instant::now(),
+            }),
+        }))
+    }
+
+    /// Add a finished statement; `sql` is its full text, used to explain slow statements
+    pub fn record(self: &Arc<Self>, record: &AuditRecord, sql: &str) {
+        let query = fingerprint_query(sql);
+        let entry = HistoryEntry {
+            timestamp_ms: record.timestamp_ms,
+            query_id: record.query_id.clone(),
+            fingerprint: fingerprint(&query),
+            query,
+            statement_type: record.statement_type.clone(),
+            interface: record.interface.clone(),
+            client: record.client.clone(),
+            duration_ms: record.duration_ms,
+            rows_returned: record.rows_returned,
+            rows_affected: record.rows_affected,
+            status: record.status,
+            error_code: record.error_code.clone(),
+        };
+
+        if let Some(threshold) = self.config.slow_threshold
+            && entry.duration_ms >= threshold.as_millis() as u64
+        {
+            self.log_slow(&entry, sql);
+        }
+
+        if self.config.table.is_some() {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            if pending.entries.is_empty() {
+                pending.since = Instant::now();
+            }
+            pending.entries.push(entry.clone());
+            if pending.entries.len() >= FLUSH_BATCH || pending.since.elapsed() >= FLUSH_INTERVAL {
+                let batch = std::mem::take(&mut pending.entries);
+                drop(pending);
+                self.spawn_blocking(move |history| history.write(batch));
+            }
+        }
+
+        if self.config.capacity > 0 {
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            if entries.len() >= self.config.capacity {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+    }
+
+    /// Write entries still waiting for the history table, e.g. on shutdown
+    pub fn flush(&self) {
+        let batch = std::mem::take(
+            &mut self
+                .pending
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entries,
+        );
+        self.write(batch);
+    }
+
+    /// Entries matching `filter`, most recent first, or their per-fingerprint statistics
+    pub fn query(&self, filter: &HistoryFilter) -> HistoryResponse {
+        let limit = filter.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+        let matching: Vec<HistoryEntry> = self
+            .entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .rev()
+            .filter(|entry| filter.matches(entry))
+            .cloned()
+            .collect();
+        let matched = matching.len();
+
+        match filter.group_by {
+            Some(HistoryGrouping::Fingerprint) => {
+                let mut fingerprints = fingerprint_stats(matching);
+                fingerprints.truncate(limit);
+                HistoryResponse {
+                    matched,
+                    entries: Vec::new(),
+                    fingerprints,
+                }
+            }
+            None => HistoryResponse {
+                matched,
+                entries: matching.into_iter().take(limit).collect(),
+                fingerprints: Vec::new(),
+            },
+        }
+    }
+
+    /// Run `f` on the blocking pool, or inline outside a Tokio runtime
+    fn spawn_blocking(self: &Arc<Self>, f: impl FnOnce(&Self) + Send + 'static) {
+        let history = self.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(move || f(&history));
+            }
+            Err(_) => f(&history),
+        }
+    }
+
+    fn write(&self, batch: Vec<HistoryEntry>) {
+        let Some(table) = &self.config.table else {
+            return;
+        };
+        if batch.is_empty() {
+            return;
+        }
+        let result = self
+            .connection()
+            .and_then(|conn| insert_entries(&conn, table, &batch).map_err(anyhow::Error::from));
+        match result {
+            Ok(()) => debug!(entries = batch.len(), "Query history written"),
+            Err(e) => warn!(
+                error = %e,
+                dropped = batch.len(),
+                "Failed to write query history"
+            ),
+        }
+    }
+
+    /// The history's own connection; writes and plans take turns on it
+    fn connection(&self) -> anyhow::Result<MutexGuard<'_, Connection>> {
+        let conn = self
+            .conn
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("the query history has no connection"))?;
+        Ok(conn.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn log_slow(self: &Arc<Self>, entry: &HistoryEntry, sql: &str) {
+        let entry = entry.clone();
+        let sql = sql.to_string();
+        let threshold_ms = self
+            .config
+            .slow_threshold
+            .map(|t| t.as_millis() as u64)
+            .unwrap_or_default();
+        self.spawn_blocking(move |history| {
+            let plan = match history.plan(&sql) {
+                Ok(plan) => plan,
+                Err(e) => format!("unavailable: {}", e),
+            };
+            warn!(
+                query_id = %entry.query_id,
+                fingerprint = %entry.fingerprint,
+                query = %entry.query,
+                client = %entry.client,
+                duration_ms = entry.duration_ms,
+                threshold_ms,
+                status = ?entry.status,
+                plan = %plan,
+                "Slow query"
+            );
+        });
+    }
+
+    /// Text plan of a single statement
+    fn plan(&self, sql: &str) -> anyhow::Result<String> {
+        let statements = split_statements(sql);
+        let [statement] = statements.as_slice() else {
+            anyhow::bail!("only single statements are explained");
+        };
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare(&explain_statement(
+            statement,
+            false,
+            crate::ExplainFormat::Text,
+        ))?;
+        let plans = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(plans.join("\n"))
+    }
+}
+
+/// Statement text identifying statements that differ only in their literals
+pub fn fingerprint_query(sql: &str) -> String {
+    let redacted = redact_sql(&normalize_sql(sql));
+    PLACEHOLDER_LIST
+        .replace_all(&redacted, "?")
+        .trim_end_matches([';', ' '])
+        .to_string()
+}
+
+/// SHA-256 of the fingerprint query, shortened; stable across builds, so fingerprints persisted
+/// in the history table keep matching new entries
+fn fingerprint(query: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, query.as_bytes());
+    hash.as_ref()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Group entries by fingerprint, by total time descending
+fn fingerprint_stats(entries: Vec<HistoryEntry>) -> Vec<FingerprintStats> {
+    let mut groups: HashMap<String, Vec<HistoryEntry>> = HashMap::new();
+    for entry in entries {
+        groups
+            .entry(entry.fingerprint.clone())
+            .or_default()
+            .push(entry);
+    }
+
+    let mut stats: Vec<FingerprintStats> = groups
+        .into_values()
+        .map(|group| {
+            let mut durations: Vec<u64> = group.iter().map(|e| e.duration_ms).collect();
+            durations.sort_unstable();
+            let first = &group[0];
+            FingerprintStats {
+                fingerprint: first.fingerprint.clone(),
+                query: first.query.clone(),
+                statement_type: first.statement_type.clone(),
+                count: group.len(),
+                errors: group.iter().filter(|e| e.status != AuditStatus::Ok).count(),
+                total_time_ms: durations.iter().sum(),
+                p50_ms: percentile(&durations, 0.50),
+                p95_ms: percentile(&durations, 0.95),
+                max_ms: durations.last().copied().unwrap_or_default(),
+                last_seen_ms: group.iter().map(|e| e.timestamp_ms).max().unwrap_or(0),
+            }
+        })
+        .collect();
+    stats.sort_by(|a, b| {
+        b.total_time_ms
+            .cmp(&a.total_time_ms)
+            .then_with(|| a.fingerprint.cmp(&b.fingerprint))
+    });
+    stats
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn create_table(conn: &Connection, table: &str) -> duckdb::Result<()> {
+    conn.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            finished_at TIMESTAMP,
+            query_id VARCHAR,
+            fingerprint VARCHAR,
+            query VARCHAR,
+            statement_type VARCHAR,
+            interface VARCHAR,
+            client VARCHAR,
+            duration_ms UBIGINT,
+            rows_returned UBIGINT,
+            rows_affected UBIGINT,
+            status VARCHAR,
+            error_code VARCHAR
+        )",
+        table
+    ))
+}
+
+/// The `capacity` most recent entries of the history table, oldest first
+fn load_entries(
+    conn: &Connection,
+    table: &str,
+    capacity: usize,
+) -> anyhow::Result<VecDeque<HistoryEntry>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT epoch_ms(finished_at)::UBIGINT, query_id, fingerprint, query, statement_type,
+                interface, client, duration_ms, rows_returned, rows_affected, status, error_code
+         FROM {} ORDER BY finished_at DESC LIMIT ?",
+        table
+    ))?;
+    let rows = stmt.query_map([capacity as u64], |row| {
+        Ok((
+            HistoryEntry {
+                timestamp_ms: row.get(0)?,
+                query_id: row.get(1)?,
+                fingerprint: row.get(2)?,
+                query: row.get(3)?,
+                statement_type: row.get(4)?,
+                interface: row.get(5)?,
+                client: row.get(6)?,
+                duration_ms: row.get(7)?,
+                rows_returned: row.get(8)?,
+                rows_affected: row.get(9)?,
+                status: AuditStatus::Ok,
+                error_code: row.get(11)?,
+            },
+            row.get::<_, String>(10)?,
+        ))
+    })?;
+
+    let mut entries = VecDeque::new();
+    for row in rows {
+        let (mut entry, status) = row?;
+        entry.status = serde_json::from_value(serde_json::Value::String(status))?;
+        entries.push_front(entry);
+    }
+    Ok(entries)
+}
+
+fn insert_entries(conn: &Connection, table: &str, entries: &[HistoryEntry]) -> duckdb::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare(&format!(
+            "INSERT INTO {} VALUES (epoch_ms(?::BIGINT), ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            table
+        ))?;
+        for entry in entries {
+            let status = serde_json::to_value(entry.status)
+                .ok()
+                .and_then(|v| v.as_str().map(str::to_string))
+                .unwrap_or_default();
+            stmt.execute(duckdb::params![
+                entry.timestamp_ms as i64,
+                entry.query_id,
+                entry.fingerprint,
+                entry.query,
+                entry.statement_type,
+                entry.interface,
+                entry.client,
+                entry.duration_ms,
+                entry.rows_returned,
+                entry.rows_affected,
+                status,
+                entry.error_code,
+            ])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Recent statement history
+/// Filters combine with AND. With `group_by=fingerprint`, statements that differ only in their
+/// literals are aggregated, most total time first. When API keys are configured, only admin keys
+/// and certificates may read it.
+#[utoipa::path(
+    get,
+    path = "/admin/history",
+    params(
+        ("fingerprint" = Option<String>, Query, description = "Only statements with this fingerprint"),
+        ("status" = Option<String>, Query, description = "Only statements with this status: ok, error, rejected or cancelled"),
+        ("client" = Option<String>, Query, description = "Only statements from this client, e.g. ip:10.0.0.7"),
+        ("interface" = Option<String>, Query, description = "Only statements sent over http, websocket, pgwire or flight"),
+        ("statement_type" = Option<String>, Query, description = "Only statements of this type, e.g. SELECT"),
+        ("min_duration_ms" = Option<u64>, Query, description = "Only statements that took at least this long"),
+        ("since_ms" = Option<u64>, Query, description = "Only statements finished at or after this Unix time in milliseconds"),
+        ("limit" = Option<usize>, Query, description = "Maximum entries or fingerprints returned (default 100)"),
+        ("group_by" = Option<String>, Query, description = "`fingerprint` to aggregate count, p50/p95 and total time per fingerprint")
+    ),
+    responses(
+        (status = 200, description = "Matching statements", body = HistoryResponse),
+        (status = 400, description = "Bad request - invalid filter"),
+        (status = 403, description = "Forbidden - API key or client certificate without admin scope"),
+        (status = 404, description = "Query history is disabled")
+    ),
+    tag = "admin"
+)]
+#[instrument(skip(state))]
+pub async fn query_history(
+    State(state): State<AppState>,
+    Query(filter): Query<HistoryFilter>,
+) -> Result<Json<HistoryResponse>, Response> {
+    match &state.history {
+        Some(history) if history.config.capacity > 0 => Ok(Json(history.query(&filter))),
+        _ => Err(
+            ApiError::not_found("Query history is disabled (--history-size 0)").to_response(None),
+        ),
+    }
+}
@@ -3,16 +3,95 @@
 //! This crate provides a REST API server for DuckDB with security features,
 //! connection pooling, and comprehensive logging.
 
+/// Admission control for the blocking query pool
+pub mod admission;
+/// Audit log of executed statements
+pub mod audit;
+/// API key authentication
+pub mod auth;
+/// Result cache for read-only queries
+pub mod cache;
+/// Command line: server, one-shot query and interactive shell subcommands
+pub mod cli;
+/// Server-side result cursors for paginated queries
+pub mod cursor;
 /// Database operations and connection management
 pub mod database;
 /// Error types and handling
 pub mod errors;
+/// Structured EXPLAIN and EXPLAIN ANALYZE plans
+pub mod explain;
+/// Arrow Flight SQL listener
+pub mod flight;
 /// HTTP request handlers
 pub mod handlers;
+/// Liveness and readiness probes
+pub mod health;
+/// Recent statement history and slow query log
+pub mod history;
+/// HTTP listeners: TCP, Unix domain sockets and systemd socket activation
+pub mod listener;
+/// Prometheus-style server metrics
+pub mod metrics;
 /// Data models and configuration
 pub mod models;
+/// PostgreSQL wire protocol listener
+pub mod pgwire;
+/// Per-query profiling of `/query` requests
+pub mod profile;
+/// Progress tracking for running queries
+pub mod progress;
+/// Per-client rate limiting and concurrency quotas
+pub mod rate_limit;
+/// Router assembly for the server binary and for embedding in other axum applications
+pub mod router;
+/// Saved parameterized queries exposed as named endpoints
+pub mod saved;
+/// Multi-statement scripts at `/sql`, with per-statement result routing
+pub mod script;
+/// Graceful shutdown: draining requests, interrupting queries and closing the database
+pub mod shutdown;
+/// Newline-delimited JSON streaming of query results
+pub mod stream;
+/// HTTPS and mutual TLS termination
+pub mod tls;
+/// WebSocket interactive query sessions
+pub mod ws;
 
+pub use admission::{
+    AdmissionConfig, AdmissionController, AdmissionError, AdmissionPermit, PRIORITY_HEADER,
+    Priority,
+};
+pub use audit::{Audit, AuditLog, AuditRecord, AuditSink, AuditSource, AuditStatus};
+pub use auth::{ApiKeys, admin_auth_middleware, auth_middleware, saved_query_auth_middleware};
+pub use cache::{CacheConfig, CacheFormat, CacheKey, ResultCache};
+pub use cli::{Cli, Command, OutputFormat};
+pub use cursor::{CursorConfig, CursorStore};
 pub use database::*;
-pub use errors::{ApiError, DatabaseError};
+pub use errors::{
+    ApiError, DatabaseError, DuckDbErrorKind, ErrorPosition, PROBLEM_JSON_CONTENT_TYPE,
+    ProblemDetails,
+};
+pub use explain::{ExplainFormat, ExplainRequest, ExplainResponse, PlanNode};
 pub use handlers::*;
+pub use health::{LivenessResponse, PoolStats, ReadinessResponse};
+pub use history::{
+    FingerprintStats, HistoryConfig, HistoryEntry, HistoryFilter, HistoryResponse, QueryHistory,
+};
+pub use listener::HttpListener;
+#[cfg(unix)]
+pub use listener::{UNIX_PEER, unix_socket_app};
+pub use metrics::Metrics;
 pub use models::*;
+pub use profile::{DuckDbProfile, QueryProfile};
+pub use progress::{ProgressSnapshot, QUERY_ID_HEADER, QueryProgress, QueryRegistry, TrackedQuery};
+pub use rate_limit::{IdentitySecret, RateLimitConfig, RateLimiter, rate_limit_middleware};
+pub use router::{ApiDoc, Endpoint, RouterHook, RouterOptions, RsduckBuilder, openapi_doc, router};
+pub use rsduck_types::{NDJSON_CONTENT_TYPE, StreamEvent};
+pub use saved::{ParamDecl, ParamType, SavedQueries, SavedQuery};
+pub use script::{ScriptRequest, ScriptResponse, StatementResult};
+pub use shutdown::Shutdown;
+pub use tls::{
+    ClientIdentity, ClientScope, ClientScopes, ReloadableTlsConfig, TlsConfig, TlsListener,
+    TlsMakeService,
+};
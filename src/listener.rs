@@ -0,0 +1,208 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+use crate::models::Args;
+
+#[cfg(unix)]
+use axum::{Extension, Router, extract::ConnectInfo};
+#[cfg(unix)]
+use std::net::{IpAddr, Ipv4Addr};
+#[cfg(unix)]
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+/// First file descriptor passed by systemd socket activation
+#[cfg(unix)]
+pub const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Peer address given to clients of a Unix domain socket, which have no IP address of their own
+#[cfg(unix)]
+pub const UNIX_PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// Prepare `app` for serving on a Unix domain socket: every client gets [`UNIX_PEER`] as its
+/// `ConnectInfo`, so they share the `ip:127.0.0.1` identity in rate limits and the audit log
+#[cfg(unix)]
+pub fn unix_socket_app(app: Router) -> Router {
+    app.layer(Extension(ConnectInfo(UNIX_PEER)))
+}
+
+/// Socket the HTTP API is served on
+#[derive(Debug)]
+pub enum HttpListener {
+    /// TCP socket bound to `--host` and `--port`, or passed by systemd
+    Tcp(TcpListener),
+    /// Unix domain socket
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        /// Socket file created by this process, removed again on shutdown; None for sockets
+        /// passed by systemd
+        path: Option<PathBuf>,
+    },
+}
+
+impl HttpListener {
+    /// Listener for the HTTP API: the first socket passed by systemd if any, otherwise
+    /// `--unix-socket`, otherwise `--host` and `--port`
+    pub async fn bind(args: &Args) -> anyhow::Result<Self> {
+        #[cfg(unix)]
+        {
+            let mut activated = systemd_listeners()?;
+            if !activated.is_empty() {
+                if activated.len() > 1 {
+                    warn!(
+                        ignored = activated.len() - 1,
+                        "Only the first socket passed by systemd serves the HTTP API"
+                    );
+                }
+                let listener = activated.remove(0);
+                info!("Using socket passed by systemd: {}", listener);
+                return Ok(listener);
+            }
+            if let Some(path) = &args.unix_socket {
+                return Ok(Self::bind_unix(path, args.unix_socket_mode)?);
+            }
+        }
+        #[cfg(not(unix))]
+        if args.unix_socket.is_some() {
+            anyhow::bail!("--unix-socket is only supported on Unix");
+        }
+
+        let bind_addr = format!("{}:{}", args.host, args.port);
+        Ok(Self::Tcp(TcpListener::bind(&bind_addr).await?))
+    }
+
+    /// Bind a Unix domain socket and set its file permissions. A socket file left behind by a
+    /// previous run is replaced, unless a server still accepts connections on it.
+    #[cfg(unix)]
+    pub fn bind_unix(path: &Path, mode: u32) -> io::Result<Self> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        if let Ok(metadata) = std::fs::symlink_metadata(path)
+            && metadata.file_type().is_socket()
+        {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use by another server", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+
+        // Create the socket file with no more than `mode` so clients cannot connect before its
+        // permissions are set. The umask is process-wide, so only ever tighten it and restore
+        // it straight away.
+        // SAFETY: umask only swaps the process file mode creation mask
+        let previous = unsafe { libc::umask(0o777) };
+        unsafe { libc::umask(previous | (!(mode as libc::mode_t) & 0o777)) };
+        let bound = UnixListener::bind(path);
+        unsafe { libc::umask(previous) };
+        let listener = bound?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        Ok(Self::Unix {
+            listener,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    /// Take ownership of an inherited listening socket, telling Unix domain and TCP sockets apart
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open listening socket that nothing else owns or closes.
+    #[cfg(unix)]
+    pub unsafe fn from_raw_fd(fd: RawFd) -> io::Result<Self> {
+        // SAFETY: the caller hands over ownership of `fd`
+        let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+        // Reading the address as a Unix socket address fails for other address families
+        if unix.local_addr().is_ok() {
+            unix.set_nonblocking(true)?;
+            return Ok(Self::Unix {
+                listener: UnixListener::from_std(unix)?,
+                path: None,
+            });
+        }
+
+        // SAFETY: ownership of `fd` moves on from the Unix listener
+        let tcp = unsafe { std::net::TcpListener::from_raw_fd(unix.into_raw_fd()) };
+        tcp.set_nonblocking(true)?;
+        Ok(Self::Tcp(TcpListener::from_std(tcp)?))
+    }
+
+    /// Address of a TCP listener
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            Self::Unix { .. } => None,
+        }
+    }
+
+    /// Socket file this process created, which should be removed on shutdown
+    pub fn socket_path(&self) -> Option<&Path> {
+        match self {
+            Self::Tcp(_) => None,
+            #[cfg(unix)]
+            Self::Unix { path, .. } => path.as_deref(),
+        }
+    }
+}
+
+impl fmt::Display for HttpListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{}", addr),
+                Err(_) => write!(f, "TCP socket"),
+            },
+            #[cfg(unix)]
+            Self::Unix { listener, .. } => {
+                match listener
+                    .local_addr()
+                    .ok()
+                    .and_then(|addr| addr.as_pathname().map(|path| path.display().to_string()))
+                {
+                    Some(path) => write!(f, "unix:{}", path),
+                    None => write!(f, "unnamed Unix socket"),
+                }
+            }
+        }
+    }
+}
+
+/// Sockets passed by systemd socket activation through `LISTEN_PID` and `LISTEN_FDS`, in order.
+/// Takes ownership of the file descriptors, so call it at most once.
+#[cfg(unix)]
+pub fn systemd_listeners() -> io::Result<Vec<HttpListener>> {
+    let for_this_process = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .unwrap_or(0);
+    if !for_this_process || count <= 0 {
+        return Ok(Vec::new());
+    }
+
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+        // SAFETY: systemd passes these descriptors to this process for it to own
+        .map(|fd| unsafe { HttpListener::from_raw_fd(fd) })
+        .collect()
+}
+
+/// Parse an octal file mode such as `660` or `0o600`
+pub fn parse_mode(value: &str) -> Result<u32, String> {
+    let digits = value.trim_start_matches("0o");
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("invalid octal file mode \"{}\"", value)),
+    }
+}
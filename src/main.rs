@@ -1,49 +1,34 @@
-use axum::{
-    Router,
-    routing::{get, post},
-};
 use clap::Parser;
+use futures::future::BoxFuture;
+use futures::{FutureExt, TryFutureExt};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use utoipa::OpenApi;
-use utoipa_swagger_ui::SwaggerUi;
 
 use rsduck::{
-    AppState, Args, HealthResponse, QueryParams, QueryRequest, QueryResponse, execute_command_get,
-    execute_command_post, execute_query_get, execute_query_post, health_check,
+    Args, Cli, ClientScopes, Command, HttpListener, ReloadableTlsConfig, RsduckBuilder, TlsConfig,
+    TlsListener, TlsMakeService, cli, flight, pgwire, shutdown, tls,
 };
 
-#[derive(OpenApi)]
-#[openapi(
-    paths(
-        rsduck::health_check,
-        rsduck::execute_query_post,
-        rsduck::execute_query_get,
-        rsduck::execute_command_post,
-        rsduck::execute_command_get
-    ),
-    components(
-        schemas(QueryRequest, QueryResponse, HealthResponse, QueryParams)
-    ),
-    tags(
-        (name = "health", description = "Health check endpoints"),
-        (name = "query", description = "SQL query execution endpoints"),
-        (name = "execute", description = "SQL command execution endpoints")
-    ),
-    info(
-        title = "RSDuck - DuckDB REST API",
-        description = "A REST API for executing SQL queries and commands against DuckDB",
-        version = "1.0.0",
-        contact(
-            name = "RSDuck API",
-            url = "https://github.com/your-org/rsduck"
-        )
-    )
-)]
-struct ApiDoc;
+#[cfg(unix)]
+use rsduck::unix_socket_app;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        None => serve(cli.serve).await,
+        Some(Command::Serve(args)) => serve(*args).await,
+        Some(Command::Query(args)) => cli::run_query(args).await,
+        Some(Command::Repl(args)) => cli::run_repl(args).await,
+    }
+}
+
+/// Run the server until it is shut down
+async fn serve(args: Args) -> anyhow::Result<()> {
     // Initialize tracing
     tracing_subscriber::registry()
         .with(
@@ -53,27 +38,53 @@ async fn main() -> anyhow::Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let args = Args::parse();
-    let state = AppState::new(&args)?;
-
-    let app = Router::new()
-        .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
-        .route("/health", get(health_check))
-        .route("/query", post(execute_query_post))
-        .route("/query", get(execute_query_get))
-        .route("/execute", post(execute_command_post))
-        .route("/execute", get(execute_command_get))
-        .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::permissive())
-        .with_state(state);
-
-    let bind_addr = format!("{}:{}", args.host, args.port);
-    let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
-
-    tracing::info!("DuckDB REST server starting on http://{}", bind_addr);
-    tracing::info!("Swagger UI available at: http://{}/swagger-ui", bind_addr);
+    let (app, state) = RsduckBuilder::new(args.clone()).build_with_state()?;
+
+    // Listeners besides HTTP; they stop on the same shutdown and are drained with it
+    let mut servers: Vec<BoxFuture<'static, std::io::Result<()>>> = Vec::new();
+    if let Some(pg_port) = args.pg_port {
+        let pg_addr = format!("{}:{}", args.host, pg_port);
+        let pg_listener = tokio::net::TcpListener::bind(&pg_addr).await?;
+        tracing::info!("PostgreSQL wire protocol listening on {}", pg_addr);
+        servers.push(pgwire::serve(pg_listener, state.clone()).boxed());
+    }
+
+    if let Some(flight_port) = args.flight_port {
+        let flight_addr = format!("{}:{}", args.host, flight_port);
+        let flight_listener = tokio::net::TcpListener::bind(&flight_addr).await?;
+        tracing::info!("Arrow Flight SQL listening on {}", flight_addr);
+        servers.push(
+            flight::serve(flight_listener, state.clone())
+                .map_err(std::io::Error::other)
+                .boxed(),
+        );
+    }
+
+    let app = app
+        .layer(TraceLayer::new_for_http().make_span_with(tls::request_span))
+        .layer(CorsLayer::permissive());
+
+    let listener = HttpListener::bind(&args).await?;
+    let socket_path = listener.socket_path().map(Path::to_path_buf);
+
+    let tls_config = TlsConfig::from_args(&args);
+    let scheme = if tls_config.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    match listener.tcp_addr() {
+        Some(addr) => {
+            tracing::info!("DuckDB REST server starting on {}://{}", scheme, addr);
+            tracing::info!("Swagger UI available at: {}://{}/swagger-ui", scheme, addr);
+        }
+        None => tracing::info!("DuckDB REST server starting on {}", listener),
+    }
     tracing::info!("Available endpoints:");
     tracing::info!("  GET  /health - Health check");
+    tracing::info!("  GET  /health/live - Liveness probe");
+    tracing::info!("  GET  /health/ready - Readiness probe (checks the database)");
+    tracing::info!("  GET  /metrics - Prometheus metrics");
     tracing::info!("  POST /query  - Execute SQL query that returns data (JSON body)");
     tracing::info!(
         "  GET  /query?sql=<query> - Execute SQL query that returns data (URL parameter)"
@@ -82,24 +93,76 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!(
         "  GET  /execute?sql=<command> - Execute SQL command (CREATE, INSERT, etc.) (URL parameter)"
     );
+    tracing::info!("  POST /sql - Run a multi-statement script on one connection");
+    tracing::info!("  POST /query/stream - Stream query rows as newline-delimited JSON");
+    tracing::info!("  GET  /cursors/{{token}} - Next page of a paginated query (DELETE closes it)");
+    tracing::info!("  POST /explain - Structured query plan, optionally with EXPLAIN ANALYZE");
+    tracing::info!("  GET  /queries/{{id}}/progress - Query progress (Server-Sent Events)");
+    tracing::info!("  GET  /ws - Interactive query session (WebSocket)");
+    tracing::info!("  GET  /q/{{name}} - Run a saved query (POST takes JSON parameters)");
+    tracing::info!("  GET  /saved-queries - List saved queries (POST, PUT, DELETE manage them)");
+    tracing::info!("  GET  /admin/history - Recent statements and slow queries (admin API key)");
     tracing::info!("Usage examples:");
     tracing::info!("  cargo run                                    # In-memory database");
     tracing::info!("  cargo run -- --database mydb.duckdb         # Read-only file");
     tracing::info!("  cargo run -- --database mydb.duckdb --readwrite  # Read-write file");
     tracing::info!("  cargo run -- --port 8080                    # Custom port");
+    tracing::info!("  cargo run -- --pg-port 5433                 # Also accept psql/JDBC clients");
+    tracing::info!("  cargo run -- --flight-port 50051            # Also accept ADBC clients");
+    tracing::info!("  cargo run -- --tls-cert cert.pem --tls-key key.pem  # Serve HTTPS");
+    tracing::info!("  cargo run -- --unix-socket /run/rsduck.sock  # No TCP port");
     tracing::info!("Press Ctrl+C to stop the server");
 
     // Set up graceful shutdown
-    let server = axum::serve(listener, app);
-    
+    let shutdown = state.shutdown.clone();
+    let server = match (listener, tls_config) {
+        (HttpListener::Tcp(listener), Some(config)) => {
+            if config.client_ca.is_some() {
+                tracing::info!(
+                    optional = config.client_cert_optional,
+                    "Client certificate verification (mutual TLS) enabled"
+                );
+            }
+            let tls = Arc::new(ReloadableTlsConfig::new(config)?);
+            #[cfg(unix)]
+            tls.clone().reload_on_sighup()?;
+            let listener = TlsListener::new(listener, tls)?;
+            let make_service = TlsMakeService::new(app, ClientScopes::from_args(&args));
+            axum::serve(listener, make_service)
+                .with_graceful_shutdown(shutdown.started())
+                .into_future()
+                .boxed()
+        }
+        (HttpListener::Tcp(listener), None) => axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown.started())
+        .into_future()
+        .boxed(),
+        #[cfg(unix)]
+        (HttpListener::Unix { listener, .. }, None) => {
+            axum::serve(listener, unix_socket_app(app).into_make_service())
+                .with_graceful_shutdown(shutdown.started())
+                .into_future()
+                .boxed()
+        }
+        #[cfg(unix)]
+        (HttpListener::Unix { .. }, Some(_)) => {
+            anyhow::bail!("TLS is not supported on Unix domain sockets")
+        }
+    };
+
     // Handle Ctrl+C for graceful shutdown on both Unix and Windows
     let shutdown_signal = async {
         #[cfg(unix)]
         {
-            use tokio::signal::unix::{signal, SignalKind};
-            let mut sigterm = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
-            let mut sigint = signal(SignalKind::interrupt()).expect("failed to install SIGINT handler");
-            
+            use tokio::signal::unix::{SignalKind, signal};
+            let mut sigterm =
+                signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+            let mut sigint =
+                signal(SignalKind::interrupt()).expect("failed to install SIGINT handler");
+
             tokio::select! {
                 _ = sigterm.recv() => {
                     tracing::info!("Received SIGTERM, shutting down gracefully...");
@@ -109,17 +172,20 @@ async fn main() -> anyhow::Result<()> {
                 }
             }
         }
-        
+
         #[cfg(windows)]
         {
-            use tokio::signal::windows::{ctrl_c, ctrl_break, ctrl_close, ctrl_logoff, ctrl_shutdown};
-            
+            use tokio::signal::windows::{
+                ctrl_break, ctrl_c, ctrl_close, ctrl_logoff, ctrl_shutdown,
+            };
+
             let mut ctrl_c = ctrl_c().expect("failed to install Ctrl+C handler");
             let mut ctrl_break = ctrl_break().expect("failed to install Ctrl+Break handler");
             let mut ctrl_close = ctrl_close().expect("failed to install Ctrl+Close handler");
             let mut ctrl_logoff = ctrl_logoff().expect("failed to install Ctrl+Logoff handler");
-            let mut ctrl_shutdown = ctrl_shutdown().expect("failed to install Ctrl+Shutdown handler");
-            
+            let mut ctrl_shutdown =
+                ctrl_shutdown().expect("failed to install Ctrl+Shutdown handler");
+
             tokio::select! {
                 _ = ctrl_c.recv() => {
                     tracing::info!("Received Ctrl+C, shutting down gracefully...");
@@ -138,26 +204,41 @@ async fn main() -> anyhow::Result<()> {
                 }
             }
         }
-        
+
         #[cfg(not(any(unix, windows)))]
         {
             // Fallback for other platforms
-            tokio::signal::ctrl_c().await.expect("failed to install Ctrl+C handler");
+            tokio::signal::ctrl_c()
+                .await
+                .expect("failed to install Ctrl+C handler");
             tracing::info!("Received Ctrl+C, shutting down gracefully...");
         }
     };
 
-    // Run server with graceful shutdown
-    tokio::select! {
-        result = server => {
-            if let Err(err) = result {
-                tracing::error!("Server error: {}", err);
-                return Err(err.into());
-            }
-        }
+    // Run server with graceful shutdown: stop accepting connections, let running queries
+    // finish within the drain timeout, then checkpoint and close the database
+    servers.push(server);
+    let server = futures::future::try_join_all(servers).map_ok(|_| ());
+    let mut server = tokio::spawn(server);
+    let result = tokio::select! {
+        result = &mut server => result.unwrap_or_else(|e| Err(std::io::Error::other(e))),
         _ = shutdown_signal => {
-            tracing::info!("Shutdown signal received, server is stopping...");
+            tracing::info!(
+                drain_timeout_secs = args.drain_timeout_secs,
+                "Shutdown signal received, draining in-flight requests..."
+            );
+            shutdown.begin();
+            shutdown::drain(server, &state, Duration::from_secs(args.drain_timeout_secs)).await
         }
+    };
+    shutdown::close_database(&state);
+
+    if let Some(path) = socket_path {
+        let _ = std::fs::remove_file(path);
+    }
+    if let Err(err) = result {
+        tracing::error!("Server error: {}", err);
+        return Err(err.into());
     }
 
     Ok(())
@@ -0,0 +1,217 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Process-wide counters and gauges exposed in Prometheus text format at `/metrics`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Requests rejected because the client's token bucket was empty
+    pub rate_limited_total: AtomicU64,
+    /// Requests rejected because the client had too many queries in flight
+    pub concurrency_limited_total: AtomicU64,
+    /// Requests currently holding a per-client concurrency slot
+    pub client_requests_in_flight: AtomicU64,
+    /// Statements currently executing on the blocking pool
+    pub queries_in_flight: AtomicU64,
+    /// Statements waiting in the admission queue
+    pub queries_queued: AtomicU64,
+    /// Statements rejected because the admission queue was full
+    pub queue_rejected_total: AtomicU64,
+    /// Statements rejected after waiting `queue_timeout` for a slot
+    pub queue_timeouts_total: AtomicU64,
+    /// Time spent waiting for an execution slot, in microseconds
+    pub queue_wait_micros: Summary,
+    /// Queue position at the time a statement started waiting
+    pub queue_position: Summary,
+    /// Queries answered from the result cache
+    pub cache_hits_total: AtomicU64,
+    /// Cacheable queries that had to be executed
+    pub cache_misses_total: AtomicU64,
+    /// Cached results evicted to stay within size bounds
+    pub cache_evictions_total: AtomicU64,
+    /// Times the whole cache was invalidated by a write
+    pub cache_invalidations_total: AtomicU64,
+    /// Number of cached results
+    pub cache_entries: AtomicU64,
+    /// Total size of cached results in bytes
+    pub cache_bytes: AtomicU64,
+    /// Open WebSocket query sessions
+    pub ws_sessions: AtomicU64,
+    /// Audit records dropped because the writer queue was full
+    pub audit_dropped_total: AtomicU64,
+}
+
+/// Running sum and count of observations, rendered as a Prometheus summary
+#[derive(Debug, Default)]
+pub struct Summary {
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Summary {
+    /// Record one observation
+    pub fn observe(&self, value: u64) {
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// Create a new, zeroed metrics registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Increment a counter by one
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Decrement a gauge by one
+    pub fn decr(gauge: &AtomicU64) {
+        gauge.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        write_metric(
+            &mut out,
+            "rsduck_rate_limited_requests_total",
+            "counter",
+            "Requests rejected by the per-client rate limit",
+            &self.rate_limited_total,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_concurrency_limited_requests_total",
+            "counter",
+            "Requests rejected by the per-client concurrency limit",
+            &self.concurrency_limited_total,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_client_requests_in_flight",
+            "gauge",
+            "Requests currently holding a per-client concurrency slot",
+            &self.client_requests_in_flight,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_queries_in_flight",
+            "gauge",
+            "Statements currently executing on the blocking pool",
+            &self.queries_in_flight,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_queries_queued",
+            "gauge",
+            "Statements waiting in the admission queue",
+            &self.queries_queued,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_queue_rejected_total",
+            "counter",
+            "Statements rejected because the admission queue was full",
+            &self.queue_rejected_total,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_queue_timeouts_total",
+            "counter",
+            "Statements rejected after timing out in the admission queue",
+            &self.queue_timeouts_total,
+        );
+        write_summary(
+            &mut out,
+            "rsduck_queue_wait_seconds",
+            "Time spent waiting for an execution slot",
+            &self.queue_wait_micros,
+            1_000_000.0,
+        );
+        write_summary(
+            &mut out,
+            "rsduck_queue_position",
+            "Queue position when a statement started waiting",
+            &self.queue_position,
+            1.0,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_cache_hits_total",
+            "counter",
+            "Queries answered from the result cache",
+            &self.cache_hits_total,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_cache_misses_total",
+            "counter",
+            "Cacheable queries that had to be executed",
+            &self.cache_misses_total,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_cache_evictions_total",
+            "counter",
+            "Cached results evicted to stay within size bounds",
+            &self.cache_evictions_total,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_cache_invalidations_total",
+            "counter",
+            "Times the result cache was invalidated by a write",
+            &self.cache_invalidations_total,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_cache_entries",
+            "gauge",
+            "Number of cached results",
+            &self.cache_entries,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_cache_bytes",
+            "gauge",
+            "Total size of cached results in bytes",
+            &self.cache_bytes,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_ws_sessions",
+            "gauge",
+            "Open WebSocket query sessions",
+            &self.ws_sessions,
+        );
+        write_metric(
+            &mut out,
+            "rsduck_audit_dropped_total",
+            "counter",
+            "Audit records dropped because the writer queue was full",
+            &self.audit_dropped_total,
+        );
+        out
+    }
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, value: &AtomicU64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+}
+
+fn write_summary(out: &mut String, name: &str, help: &str, summary: &Summary, scale: f64) {
+    let sum = summary.sum.load(Ordering::Relaxed) as f64 / scale;
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} summary", name);
+    let _ = writeln!(out, "{}_sum {}", name, sum);
+    let _ = writeln!(
+        out,
+        "{}_count {}",
+        name,
+        summary.count.load(Ordering::Relaxed)
+    );
+}
@@ -1,29 +1,322 @@
 use clap::Parser;
-use duckdb::{Config, Connection};
+use duckdb::{Config, Connection, InterruptHandle};
 use r2d2::{Pool, PooledConnection};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
 use tracing::{debug, info};
 use utoipa::ToSchema;
 
+pub use rsduck_types::{QueryRequest, QueryResponse};
+
+use crate::{
+    AdmissionConfig, AdmissionController, ApiKeys, AuditLog, CacheConfig, ClientScope,
+    CursorConfig, CursorStore, HistoryConfig, IdentitySecret, Metrics, Priority, QueryHistory,
+    QueryRegistry, RateLimitConfig, RateLimiter, ResultCache, SavedQueries, Shutdown,
+};
+
 /// Type alias for the DuckDB connection pool
 pub type DuckDbPool = Pool<DuckDbConnectionManager>;
 /// Type alias for a pooled DuckDB connection
 pub type DuckDbConnection = PooledConnection<DuckDbConnectionManager>;
 
+/// Sandbox restrictions applied to every pooled DuckDB connection
+#[derive(Debug, Clone, Default)]
+pub struct SandboxConfig {
+    /// Directories that table functions such as `read_csv` may still access
+    pub allowed_directories: Vec<PathBuf>,
+}
+
+impl SandboxConfig {
+    /// Sandbox configured by `--sandbox` and `--allowed-directory`, if enabled
+    pub fn from_args(args: &Args) -> Option<Self> {
+        let sandbox = args.sandbox.then(|| SandboxConfig {
+            allowed_directories: args.allowed_directories.clone(),
+        });
+        if let Some(sandbox) = &sandbox {
+            info!(
+                allowed_directories = ?sandbox.allowed_directories,
+                "Sandbox mode enabled: external access, extension loading and configuration changes disabled"
+            );
+        }
+        sandbox
+    }
+
+    /// Build the SQL statements that lock down a freshly opened connection.
+    /// `allowed_directories` must be set before external access is disabled,
+    /// and `lock_configuration` must come last so clients cannot `SET` their way out.
+    fn setup_statements(&self) -> Vec<String> {
+        let directories = self
+            .allowed_directories
+            .iter()
+            .map(|dir| {
+                let mut dir = dir.to_string_lossy().to_string();
+                if !dir.ends_with(std::path::MAIN_SEPARATOR) {
+                    dir.push(std::path::MAIN_SEPARATOR);
+                }
+                format!("'{}'", dir.replace('\'', "''"))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        vec![
+            format!("SET allowed_directories = [{}]", directories),
+            "SET enable_external_access = false".to_string(),
+            "SET autoinstall_known_extensions = false".to_string(),
+            "SET autoload_known_extensions = false".to_string(),
+            "SET allow_community_extensions = false".to_string(),
+            "SET lock_configuration = true".to_string(),
+        ]
+    }
+}
+
+/// Prepares every connection a pool opens, e.g. by registering scalar and table functions or
+/// applying settings. Closures taking a `&Connection` implement it.
+pub trait ConnectionHook: Send + Sync {
+    /// Called for each new connection, before sandbox restrictions are applied
+    fn on_connect(&self, conn: &Connection) -> Result<(), duckdb::Error>;
+}
+
+impl<F> ConnectionHook for F
+where
+    F: Fn(&Connection) -> Result<(), duckdb::Error> + Send + Sync,
+{
+    fn on_connect(&self, conn: &Connection) -> Result<(), duckdb::Error> {
+        self(conn)
+    }
+}
+
+/// Connections opened by a manager and its copies, so that their statements can be interrupted
+/// and the pools closed on shutdown
+#[derive(Default)]
+pub struct ConnectionTracker {
+    interrupts: Mutex<Vec<Weak<InterruptHandle>>>,
+    closed: AtomicBool,
+}
+
+impl std::fmt::Debug for ConnectionTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionTracker")
+            .field("open", &self.open_connections())
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+impl ConnectionTracker {
+    /// Track a connection so shutdown can interrupt it; pooled connections are registered
+    /// when opened, private clones must be registered by whoever makes them
+    pub fn register(&self, conn: &Connection) {
+        let mut interrupts = self.interrupts.lock().unwrap_or_else(|e| e.into_inner());
+        interrupts.retain(|handle| handle.strong_count() > 0);
+        interrupts.push(Arc::downgrade(&conn.interrupt_handle()));
+    }
+
+    /// Number of connections that are still open
+    pub fn open_connections(&self) -> usize {
+        let interrupts = self.interrupts.lock().unwrap_or_else(|e| e.into_inner());
+        interrupts
+            .iter()
+            .filter(|handle| handle.strong_count() > 0)
+            .count()
+    }
+
+    /// Interrupt whatever statement each open connection is running; returns the number of
+    /// connections interrupted
+    pub fn interrupt_all(&self) -> usize {
+        let interrupts = self.interrupts.lock().unwrap_or_else(|e| e.into_inner());
+        interrupts
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|handle| handle.interrupt())
+            .count()
+    }
+
+    /// Stop handing out connections: opening new ones fails, and connections are closed
+    /// instead of returning to their pool
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether [`ConnectionTracker::close`] was called
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
 /// Connection manager for r2d2 pool to manage DuckDB connections
-#[derive(Debug)]
+#[derive(Clone)]
 pub struct DuckDbConnectionManager {
     database_path: Option<PathBuf>,
     is_readonly: bool,
+    sandbox: Option<SandboxConfig>,
+    hooks: Vec<Arc<dyn ConnectionHook>>,
+    /// Database handle that connections are cloned from, instead of opening `database_path`
+    shared: Option<Arc<Mutex<Connection>>>,
+    /// In-memory database that connections are cloned from, opened with the first connection
+    memory: Arc<Mutex<Option<Connection>>>,
+    /// Whether the sandbox has been applied to the shared database
+    shared_sandboxed: Arc<Mutex<bool>>,
+    tracker: Arc<ConnectionTracker>,
+}
+
+impl std::fmt::Debug for DuckDbConnectionManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DuckDbConnectionManager")
+            .field("database_path", &self.database_path)
+            .field("is_readonly", &self.is_readonly)
+            .field("sandbox", &self.sandbox)
+            .field("hooks", &self.hooks.len())
+            .field("shared", &self.shared.is_some())
+            .finish()
+    }
 }
 
 impl DuckDbConnectionManager {
-    /// Create a new connection manager
+    /// Create a new connection manager. Without a database path, every connection shares one
+    /// in-memory database that lives as long as the manager and its copies.
     pub fn new(database_path: Option<PathBuf>, is_readonly: bool) -> Self {
         Self {
             database_path,
             is_readonly,
+            sandbox: None,
+            hooks: Vec::new(),
+            shared: None,
+            memory: Arc::new(Mutex::new(None)),
+            shared_sandboxed: Arc::new(Mutex::new(false)),
+            tracker: Arc::new(ConnectionTracker::default()),
+        }
+    }
+
+    /// Create a connection manager from the `--database`, `--readwrite` and `--sandbox` arguments
+    pub fn from_args(args: &Args) -> Self {
+        let is_readonly = args.database.is_some() && !args.readwrite;
+
+        if let Some(path) = &args.database {
+            if args.readwrite {
+                info!("Opening database file: {:?} (read-write)", path);
+            } else {
+                info!("Opening database file: {:?} (read-only)", path);
+            }
+        } else {
+            info!("Using in-memory database (read-write)");
+        }
+
+        debug!("Creating connection manager");
+        Self::new(args.database.clone(), is_readonly).with_sandbox(SandboxConfig::from_args(args))
+    }
+
+    /// Create a connection manager that hands out clones of an existing connection, so every
+    /// pooled connection shares its database instance, including the catalog and the functions
+    /// registered on it. With a sandbox, the
+    /// database configuration is locked after the first connection, so hooks cannot change
+    /// settings on later ones.
+    pub fn from_connection(conn: Connection, is_readonly: bool) -> Self {
+        let database_path = conn
+            .path()
+            .filter(|path| *path != Path::new(":memory:"))
+            .map(PathBuf::from);
+        Self {
+            shared: Some(Arc::new(Mutex::new(conn))),
+            ..Self::new(database_path, is_readonly)
+        }
+    }
+
+    /// Create a connection manager for an existing connection, configured from the `--readwrite`
+    /// and `--sandbox` arguments. A file database is read-only unless `--readwrite` is given.
+    pub fn from_args_and_connection(args: &Args, conn: Connection) -> Self {
+        let is_file = conn
+            .path()
+            .is_some_and(|path| path != Path::new(":memory:"));
+        let manager = Self::from_connection(conn, is_file && !args.readwrite)
+            .with_sandbox(SandboxConfig::from_args(args));
+        info!(
+            database_path = ?manager.database_path(),
+            readonly = manager.is_readonly(),
+            "Serving an existing database handle"
+        );
+        manager
+    }
+
+    /// Run `hook` on every connection this manager opens; hooks run in the order they are added
+    pub fn with_on_connect(mut self, hook: impl ConnectionHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Run `SET threads` on every connection this manager opens. DuckDB only has a
+    /// database-wide thread count, so this also limits the other connections to the same
+    /// database; it is skipped once a sandbox has locked the configuration.
+    pub fn with_threads(self, threads: i64) -> Self {
+        self.with_on_connect(move |conn: &Connection| {
+            let locked: bool =
+                conn.query_row("SELECT current_setting('lock_configuration')", [], |row| {
+                    row.get(0)
+                })?;
+            if locked {
+                debug!(
+                    threads,
+                    "Configuration is locked; keeping the database's threads"
+                );
+                return Ok(());
+            }
+            conn.execute_batch(&format!("SET threads = {}", threads))
+        })
+    }
+
+    /// Apply sandbox restrictions to every connection this manager opens
+    pub fn with_sandbox(mut self, sandbox: Option<SandboxConfig>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Database file, or None for an in-memory database
+    pub fn database_path(&self) -> Option<&PathBuf> {
+        self.database_path.as_ref()
+    }
+
+    /// Whether connections are opened read-only (never the case for in-memory databases)
+    pub fn is_readonly(&self) -> bool {
+        (self.database_path.is_some() || self.shared.is_some()) && self.is_readonly
+    }
+
+    /// Whether connections are sandboxed
+    pub fn is_sandboxed(&self) -> bool {
+        self.sandbox.is_some()
+    }
+
+    /// Connections opened by this manager and its clones
+    pub fn tracker(&self) -> &Arc<ConnectionTracker> {
+        &self.tracker
+    }
+
+    fn open(&self) -> Result<Connection, duckdb::Error> {
+        if let Some(shared) = &self.shared {
+            return shared.lock().unwrap_or_else(|e| e.into_inner()).try_clone();
+        }
+
+        let mut config = Config::default();
+
+        match &self.database_path {
+            Some(path) => {
+                if self.is_readonly {
+                    config = config.access_mode(duckdb::AccessMode::ReadOnly)?;
+                }
+                Connection::open_with_flags(path, config)
+            }
+            None => {
+                // Every connection of an in-memory database must see the same catalog, so open
+                // it once and clone that handle for this manager and its copies
+                let mut memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+                if memory.is_none() {
+                    *memory = Some(Connection::open_in_memory_with_flags(config)?);
+                }
+                memory.as_ref().expect("opened above").try_clone()
+            }
         }
     }
 }
@@ -33,17 +326,35 @@ impl r2d2::ManageConnection for DuckDbConnectionManager {
     type Error = duckdb::Error;
 
     fn connect(&self) -> Result<Self::Connection, Self::Error> {
-        match &self.database_path {
-            Some(path) => {
-                if self.is_readonly {
-                    let config = Config::default().access_mode(duckdb::AccessMode::ReadOnly)?;
-                    Connection::open_with_flags(path, config)
-                } else {
-                    Connection::open(path)
+        if self.tracker.is_closed() {
+            return Err(duckdb::Error::DuckDBFailure(
+                duckdb::ffi::Error::new(duckdb::ffi::DuckDBError),
+                Some("The database is shutting down".to_string()),
+            ));
+        }
+        let conn = self.open()?;
+        self.tracker.register(&conn);
+
+        for hook in &self.hooks {
+            hook.on_connect(&conn)?;
+        }
+
+        if let Some(sandbox) = &self.sandbox {
+            // Sandbox settings are database-wide, and a shared database rejects them once
+            // `lock_configuration` is set, so apply them there only once
+            let mut shared_sandboxed = self
+                .shared_sandboxed
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            if !*shared_sandboxed {
+                for statement in sandbox.setup_statements() {
+                    conn.execute_batch(&statement)?;
                 }
+                *shared_sandboxed = self.shared.is_some() || self.database_path.is_none();
             }
-            None => Connection::open_in_memory(),
         }
+
+        Ok(conn)
     }
 
     fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
@@ -52,12 +363,12 @@ impl r2d2::ManageConnection for DuckDbConnectionManager {
     }
 
     fn has_broken(&self, _conn: &mut Self::Connection) -> bool {
-        false
+        self.tracker.is_closed()
     }
 }
 
 /// Command line arguments for the RSDuck server
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 #[command(name = "rsduck")]
 #[command(about = "A DuckDB REST server")]
 #[command(version = "1.0")]
@@ -77,46 +388,436 @@ pub struct Args {
     /// Server host
     #[arg(long, default_value = "0.0.0.0")]
     pub host: String,
+
+    /// Serve the HTTP API on this Unix domain socket instead of a TCP port
+    #[arg(long, value_name = "PATH", conflicts_with = "tls_cert")]
+    pub unix_socket: Option<PathBuf>,
+
+    /// File permissions of `--unix-socket`, in octal
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "660",
+        value_parser = crate::listener::parse_mode
+    )]
+    pub unix_socket_mode: u32,
+
+    /// On shutdown, wait this many seconds for running queries before interrupting them
+    #[arg(long, default_value = "30")]
+    pub drain_timeout_secs: u64,
+
+    /// `/health/ready` fails when less than this many megabytes are free in DuckDB's temp directory
+    #[arg(long, default_value = "100")]
+    pub ready_min_free_disk_mb: u64,
+
+    /// Write a JSON lines audit record for every statement to this file; `-` writes to stdout
+    #[arg(long, value_name = "PATH")]
+    pub audit_log: Option<PathBuf>,
+
+    /// Rotate the audit log once it reaches this many megabytes; 0 never rotates
+    #[arg(long, default_value = "100", requires = "audit_log")]
+    pub audit_log_max_mb: u64,
+
+    /// Rotated audit log files to keep
+    #[arg(long, default_value = "10", requires = "audit_log")]
+    pub audit_log_max_files: usize,
+
+    /// Replace string and numeric literals in audited SQL with `?`
+    #[arg(long, requires = "audit_log")]
+    pub audit_redact: bool,
+
+    /// Audit records waiting for the writer before new records are dropped
+    #[arg(long, default_value = "10000", requires = "audit_log")]
+    pub audit_queue_size: usize,
+
+    /// Recent statements kept in memory for `/admin/history`; 0 disables the history
+    #[arg(long, default_value = "1000")]
+    pub history_size: usize,
+
+    /// Also write the query history to this DuckDB table, created if missing. Requires a writable database
+    #[arg(long, value_name = "TABLE")]
+    pub history_table: Option<String>,
+
+    /// Log statements that take at least this many milliseconds at WARN, with their plan
+    #[arg(long, value_name = "MS")]
+    pub slow_query_threshold_ms: Option<u64>,
+
+    /// Include DuckDB's raw error message in HTTP error responses. It can reveal table and
+    /// column names, so leave this off when clients are not trusted
+    #[arg(long)]
+    pub verbose_errors: bool,
+
+    /// Sandbox DuckDB: disable filesystem/network access, extension loading and configuration changes
+    #[arg(long)]
+    pub sandbox: bool,
+
+    /// Directory that file-reading functions may access in sandbox mode (repeatable)
+    #[arg(long = "allowed-directory", value_name = "DIR", requires = "sandbox")]
+    pub allowed_directories: Vec<PathBuf>,
+
+    /// Sustained requests per second allowed per client (API key or IP); 0 disables rate limiting
+    #[arg(long, default_value = "0")]
+    pub rate_limit_rps: f64,
+
+    /// Number of requests a client may burst above the sustained rate
+    #[arg(long, default_value = "10")]
+    pub rate_limit_burst: u32,
+
+    /// Maximum concurrent queries per client (API key or IP); 0 disables the limit
+    #[arg(long, default_value = "0")]
+    pub max_concurrent_per_client: usize,
+
+    /// Maximum number of statements waiting for a free connection before new ones are rejected
+    #[arg(long, default_value = "100")]
+    pub max_queue_length: usize,
+
+    /// Maximum time a statement may wait for a free connection, in milliseconds
+    #[arg(long, default_value = "10000")]
+    pub queue_timeout_ms: u64,
+
+    /// Number of connections reserved for batch-priority queries
+    #[arg(long, default_value = "2")]
+    pub batch_pool_size: u32,
+
+    /// DuckDB worker threads set by each batch-priority connection; DuckDB applies it database-wide
+    #[arg(long, default_value = "1")]
+    pub batch_threads: i64,
+
+    /// API key whose queries always run at batch priority (repeatable)
+    #[arg(long = "batch-api-key", value_name = "KEY")]
+    pub batch_api_keys: Vec<String>,
+
+    /// API key clients must present via `X-API-Key`, as the PostgreSQL password or as the Flight SQL
+    /// password or bearer token (repeatable).
+    /// When set, batch API keys are accepted as well.
+    #[arg(long = "api-key", value_name = "KEY")]
+    pub api_keys: Vec<String>,
+
+    /// Port for the PostgreSQL wire protocol listener (disabled if not specified)
+    #[arg(long)]
+    pub pg_port: Option<u16>,
+
+    /// Roll back and close PostgreSQL sessions that stay idle inside a transaction block for this
+    /// many seconds; 0 disables the timeout
+    #[arg(long, default_value = "300")]
+    pub pg_idle_in_transaction_timeout_secs: u64,
+
+    /// Port for the Arrow Flight SQL listener (disabled if not specified)
+    #[arg(long)]
+    pub flight_port: Option<u16>,
+
+    /// Drop Flight SQL prepared statements that are not used for this many seconds
+    #[arg(long, default_value = "1800")]
+    pub flight_prepared_idle_timeout_secs: u64,
+
+    /// Cache read-only query results for this many seconds; 0 disables the cache
+    #[arg(long, default_value = "0")]
+    pub cache_ttl_secs: u64,
+
+    /// Maximum number of cached query results
+    #[arg(long, default_value = "1000")]
+    pub cache_max_entries: usize,
+
+    /// Maximum total size of cached query results in megabytes
+    #[arg(long, default_value = "64")]
+    pub cache_max_mb: usize,
+
+    /// Close WebSocket sessions that stay idle for this many seconds
+    #[arg(long, default_value = "300")]
+    pub ws_idle_timeout_secs: u64,
+
+    /// Maximum open WebSocket sessions; further upgrades are answered with 503
+    #[arg(long, default_value = "64")]
+    pub max_ws_sessions: usize,
+
+    /// Close result cursors that are not read for this many seconds
+    #[arg(long, default_value = "300")]
+    pub cursor_idle_timeout_secs: u64,
+
+    /// Maximum open result cursors per client (API key or IP)
+    #[arg(long, default_value = "8")]
+    pub max_cursors_per_client: usize,
+
+    /// Maximum open result cursors across all clients
+    #[arg(long, default_value = "64")]
+    pub max_cursors: usize,
+
+    /// Directory of saved query files (`<name>.sql`) served at `/q/{name}`;
+    /// queries managed through `/saved-queries` are written back to it
+    #[arg(long, value_name = "DIR")]
+    pub saved_queries_dir: Option<PathBuf>,
+
+    /// API key that may only run saved queries at `/q/{name}` (repeatable).
+    /// Requires `--api-key`, since without it every endpoint is open.
+    #[arg(
+        long = "saved-query-api-key",
+        value_name = "KEY",
+        requires = "api_keys"
+    )]
+    pub saved_query_api_keys: Vec<String>,
+
+    /// API key that may also use the administration endpoints such as `/admin/history`
+    /// (repeatable). Accepted everywhere `--api-key` is; requires `--api-key`.
+    #[arg(long = "admin-api-key", value_name = "KEY", requires = "api_keys")]
+    pub admin_api_keys: Vec<String>,
+
+    /// Secret that API keys are hashed with to identify clients in rate limits, quotas and the
+    /// audit log. Without it a random secret is generated, so identities change on restart.
+    #[arg(
+        long,
+        value_name = "SECRET",
+        env = "RSDUCK_KEY_IDENTITY_SECRET",
+        hide_env_values = true
+    )]
+    pub key_identity_secret: Option<String>,
+
+    /// PEM certificate chain to serve HTTPS with; reloaded on SIGHUP
+    #[arg(long, value_name = "FILE", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for `--tls-cert`
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM bundle of CAs that client certificates must chain to; enables mutual TLS
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Also accept clients without a certificate; they authenticate with an API key instead
+    #[arg(long, requires = "tls_client_ca")]
+    pub tls_client_cert_optional: bool,
+
+    /// Scope of a client certificate by common name or full subject: full, batch, saved or admin
+    /// (repeatable). When given, certificates that are not listed are rejected.
+    #[arg(
+        long = "tls-client-scope",
+        value_name = "NAME=SCOPE",
+        value_parser = crate::tls::parse_client_scope,
+        requires = "tls_client_ca"
+    )]
+    pub tls_client_scopes: Vec<(String, ClientScope)>,
 }
 
 /// Application state containing database pool and configuration
 #[derive(Clone)]
 pub struct AppState {
     pub pool: DuckDbPool,
+    pub batch_pool: DuckDbPool,
     pub db_path: Option<PathBuf>,
     pub is_readonly: bool,
+    pub is_sandboxed: bool,
+    pub metrics: Arc<Metrics>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    pub admission: Arc<AdmissionController>,
+    pub batch_admission: Arc<AdmissionController>,
+    pub batch_api_keys: Arc<HashSet<String>>,
+    pub api_keys: Arc<ApiKeys>,
+    pub cache: Option<Arc<ResultCache>>,
+    pub ws_idle_timeout: Duration,
+    /// One permit per WebSocket session that may be open at once
+    pub ws_sessions: Arc<Semaphore>,
+    pub pg_idle_in_transaction_timeout: Duration,
+    pub flight_prepared_idle_timeout: Duration,
+    pub queries: Arc<QueryRegistry>,
+    pub cursors: Arc<CursorStore>,
+    pub saved_queries: Arc<SavedQueries>,
+    /// Keys restricted to running saved queries
+    pub saved_query_keys: Arc<ApiKeys>,
+    /// Keys that may also use the administration endpoints
+    pub admin_keys: Arc<ApiKeys>,
+    /// Secret API keys are hashed with to identify clients
+    pub identity_secret: IdentitySecret,
+    /// Connections of both pools, for interrupting queries and closing the pools on shutdown
+    pub connections: Arc<ConnectionTracker>,
+    /// Started when the server begins draining; `/health/ready` then reports not ready
+    pub shutdown: Shutdown,
+    /// When the state was created, for reporting uptime
+    pub started_at: Instant,
+    /// Free space in DuckDB's temp directory below which `/health/ready` fails, in bytes
+    pub ready_min_free_disk: u64,
+    /// Audit log of executed statements, when `--audit-log` is given
+    pub audit: Option<Arc<AuditLog>>,
+    /// Recent statements and the slow query log
+    pub history: Option<Arc<QueryHistory>>,
+    /// Include raw database error messages in HTTP error responses
+    pub verbose_errors: bool,
 }
 
 impl AppState {
     /// Create new application state from command line arguments
     pub fn new(args: &Args) -> anyhow::Result<Self> {
-        let is_readonly = args.database.is_some() && !args.readwrite;
+        Self::with_connection_manager(args, DuckDbConnectionManager::from_args(args))
+    }
 
-        if let Some(path) = &args.database {
-            if args.readwrite {
-                info!("Opening database file: {:?} (read-write)", path);
-            } else {
-                info!("Opening database file: {:?} (read-only)", path);
-            }
-        } else {
-            info!("Using in-memory database (read-write)");
-        }
+    /// Create application state serving an existing database handle. Every pooled connection
+    /// is a clone of `conn`, so tables, settings and functions registered on its database are
+    /// visible to queries. A file database is read-only unless `--readwrite` is given.
+    pub fn from_connection(args: &Args, conn: Connection) -> anyhow::Result<Self> {
+        let manager = DuckDbConnectionManager::from_args_and_connection(args, conn);
+        Self::with_connection_manager(args, manager)
+    }
 
-        debug!("Creating connection manager");
-        let manager = DuckDbConnectionManager::new(args.database.clone(), is_readonly);
+    /// Create application state whose connections are opened by `manager` instead of from the
+    /// `--database`, `--readwrite` and `--sandbox` arguments. The batch pool uses a copy of the
+    /// manager, sharing its database, that sets `--batch-threads` on each of its connections.
+    pub fn with_connection_manager(
+        args: &Args,
+        manager: DuckDbConnectionManager,
+    ) -> anyhow::Result<Self> {
+        let db_path = manager.database_path().cloned();
+        let is_readonly = manager.is_readonly();
+        let is_sandboxed = manager.is_sandboxed();
+        let connections = manager.tracker().clone();
+        let batch_manager = manager.clone().with_threads(args.batch_threads);
+        let history = QueryHistory::open(HistoryConfig::from_args(args), &manager, is_readonly)?;
 
         debug!("Building connection pool with max size 10");
         let pool = Pool::builder()
             .max_size(10) // Maximum 10 connections in the pool
             .build(manager)?;
 
+        debug!(
+            "Building batch connection pool with max size {} and {} threads per connection",
+            args.batch_pool_size, args.batch_threads
+        );
+        let batch_pool = Pool::builder()
+            .max_size(args.batch_pool_size.max(1))
+            .min_idle(Some(0))
+            .build(batch_manager)?;
+
         info!("Database connection pool initialized successfully");
 
-        Ok(Self {
+        let admission = AdmissionController::new(AdmissionConfig {
+            max_in_flight: pool.max_size() as usize,
+            max_queue_length: args.max_queue_length,
+            queue_timeout: Duration::from_millis(args.queue_timeout_ms),
+        });
+        let batch_admission = AdmissionController::new(AdmissionConfig {
+            max_in_flight: batch_pool.max_size() as usize,
+            max_queue_length: args.max_queue_length,
+            queue_timeout: Duration::from_millis(args.queue_timeout_ms),
+        });
+
+        let rate_limit = RateLimitConfig {
+            requests_per_second: args.rate_limit_rps,
+            burst: args.rate_limit_burst,
+            max_concurrent: args.max_concurrent_per_client,
+        };
+        let rate_limiter = if rate_limit.is_enabled() {
+            info!(
+                requests_per_second = rate_limit.requests_per_second,
+                burst = rate_limit.burst,
+                max_concurrent = rate_limit.max_concurrent,
+                "Per-client rate limiting enabled"
+            );
+            Some(Arc::new(RateLimiter::new(rate_limit)))
+        } else {
+            None
+        };
+
+        let metrics = Arc::new(Metrics::new());
+
+        let cache = if args.cache_ttl_secs > 0 {
+            info!(
+                ttl_secs = args.cache_ttl_secs,
+                max_entries = args.cache_max_entries,
+                max_mb = args.cache_max_mb,
+                "Result cache enabled"
+            );
+            let config = CacheConfig {
+                ttl: Duration::from_secs(args.cache_ttl_secs),
+                max_entries: args.cache_max_entries,
+                max_bytes: args.cache_max_mb.saturating_mul(1024 * 1024),
+            };
+            Some(Arc::new(ResultCache::new(config, metrics.clone())))
+        } else {
+            None
+        };
+        let audit = AuditLog::from_args(args, metrics.clone())?;
+
+        let api_keys = if args.api_keys.is_empty() {
+            ApiKeys::default()
+        } else {
+            info!(
+                keys = args.api_keys.len() + args.batch_api_keys.len() + args.admin_api_keys.len(),
+                "API key authentication enabled"
+            );
+            ApiKeys::new(
+                args.api_keys
+                    .iter()
+                    .chain(&args.batch_api_keys)
+                    .chain(&args.admin_api_keys)
+                    .cloned(),
+            )
+        };
+
+        if !args.saved_query_api_keys.is_empty() {
+            info!(
+                keys = args.saved_query_api_keys.len(),
+                "Saved-query-only API keys enabled"
+            );
+        }
+
+        let state = Self {
             pool,
-            db_path: args.database.clone(),
+            batch_pool,
+            db_path,
             is_readonly,
-        })
+            is_sandboxed,
+            metrics,
+            rate_limiter,
+            admission: Arc::new(admission),
+            batch_admission: Arc::new(batch_admission),
+            batch_api_keys: Arc::new(args.batch_api_keys.iter().cloned().collect()),
+            api_keys: Arc::new(api_keys),
+            cache,
+            ws_idle_timeout: Duration::from_secs(args.ws_idle_timeout_secs),
+            ws_sessions: Arc::new(Semaphore::new(args.max_ws_sessions)),
+            pg_idle_in_transaction_timeout: Duration::from_secs(
+                args.pg_idle_in_transaction_timeout_secs,
+            ),
+            flight_prepared_idle_timeout: Duration::from_secs(
+                args.flight_prepared_idle_timeout_secs,
+            ),
+            queries: Arc::new(QueryRegistry::new()),
+            cursors: Arc::new(CursorStore::new(CursorConfig {
+                idle_timeout: Duration::from_secs(args.cursor_idle_timeout_secs),
+                max_per_client: args.max_cursors_per_client,
+                max_open: args.max_cursors,
+            })),
+            saved_queries: Arc::new(SavedQueries::new(args.saved_queries_dir.clone())),
+            saved_query_keys: Arc::new(ApiKeys::new(args.saved_query_api_keys.iter().cloned())),
+            admin_keys: Arc::new(ApiKeys::new(args.admin_api_keys.iter().cloned())),
+            identity_secret: match &args.key_identity_secret {
+                Some(secret) => IdentitySecret::new(secret.as_bytes()),
+                None => IdentitySecret::random(),
+            },
+            connections,
+            shutdown: Shutdown::new(),
+            started_at: Instant::now(),
+            ready_min_free_disk: args.ready_min_free_disk_mb.saturating_mul(1024 * 1024),
+            audit: audit.map(Arc::new),
+            history: history.map(Arc::new),
+            verbose_errors: args.verbose_errors,
+        };
+        state.saved_queries.load(&state)?;
+        Ok(state)
+    }
+
+    /// Connection pool serving statements of the given priority
+    pub fn pool_for(&self, priority: Priority) -> &DuckDbPool {
+        match priority {
+            Priority::Interactive => &self.pool,
+            Priority::Batch => &self.batch_pool,
+        }
+    }
+
+    /// Admission queue for statements of the given priority
+    pub fn admission_for(&self, priority: Priority) -> &Arc<AdmissionController> {
+        match priority {
+            Priority::Interactive => &self.admission,
+            Priority::Batch => &self.batch_admission,
+        }
     }
 }
 
@@ -129,34 +830,11 @@ pub struct QueryParams {
     /// Maximum number of rows to return
     #[schema(example = 100)]
     pub limit: Option<usize>,
-}
-
-/// Request body for POST requests
-#[derive(Debug, Serialize, Deserialize, ToSchema)]
-pub struct QueryRequest {
-    /// SQL query to execute
-    #[schema(example = "SELECT * FROM users")]
-    pub sql: String,
-    /// Maximum number of rows to return
-    #[schema(example = 100)]
-    pub limit: Option<usize>,
-}
-
-/// Response structure for query results
-#[derive(Debug, Serialize, ToSchema)]
-pub struct QueryResponse {
-    /// Whether the query was successful
-    pub success: bool,
-    /// Query result data (if successful)
-    pub data: Option<serde_json::Value>,
-    /// Error message (if failed)
-    pub error: Option<String>,
-    /// Unique identifier for this query
-    #[schema(example = "123e4567-e89b-12d3-a456-426614174000")]
-    pub query_id: String,
-    /// Query execution time in milliseconds
-    #[schema(example = 42)]
-    pub execution_time_ms: u64,
+    /// Include a timing profile in the response (`/query` only)
+    pub profile: Option<bool>,
+    /// Return results in pages of this many rows behind a cursor (`/query` only)
+    #[schema(example = 1000)]
+    pub page_size: Option<usize>,
 }
 
 /// Response structure for health check endpoint
@@ -173,4 +851,16 @@ pub struct HealthResponse {
     pub database_path: Option<String>,
     /// Whether database is in read-only mode
     pub readonly_mode: bool,
+    /// Number of statements currently executing
+    #[schema(example = 3)]
+    pub queries_in_flight: usize,
+    /// Number of statements waiting for a free connection
+    #[schema(example = 0)]
+    pub queries_queued: usize,
+    /// Number of batch-priority statements currently executing
+    #[schema(example = 1)]
+    pub batch_queries_in_flight: usize,
+    /// Number of batch-priority statements waiting for a free connection
+    #[schema(example = 0)]
+    pub batch_queries_queued: usize,
 }
@@ -0,0 +1,1704 @@
+use duckdb::Connection;
+use duckdb::arrow::datatypes::DataType;
+use duckdb::types::{TimeUnit, Value};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tracing::{debug, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::database::{
+    effective_row_limit, is_query_statement, is_write_operation, leading_keyword, normalize_sql,
+    returns_rows, split_statements, validate_readonly_operation, validate_sandbox_operation,
+};
+use crate::rate_limit::key_identity;
+use crate::{
+    AdmissionPermit, AppState, Audit, AuditSource, AuditStatus, DuckDbConnection, Priority,
+};
+
+const PROTOCOL_VERSION_3: i32 = 196608;
+const SSL_REQUEST_CODE: i32 = 80877103;
+const GSSENC_REQUEST_CODE: i32 = 80877104;
+const CANCEL_REQUEST_CODE: i32 = 80877102;
+
+/// Largest startup or frontend message accepted, in bytes
+const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+
+/// Version reported to clients; drivers use it to pick protocol features
+const SERVER_VERSION: &str = "14.0 (rsduck)";
+
+// PostgreSQL type OIDs (see pg_type.dat)
+const BOOL_OID: u32 = 16;
+const BYTEA_OID: u32 = 17;
+const NAME_OID: u32 = 19;
+const INT8_OID: u32 = 20;
+const INT2_OID: u32 = 21;
+const INT4_OID: u32 = 23;
+const TEXT_OID: u32 = 25;
+const FLOAT4_OID: u32 = 700;
+const FLOAT8_OID: u32 = 701;
+const UNKNOWN_OID: u32 = 705;
+const BPCHAR_OID: u32 = 1042;
+const VARCHAR_OID: u32 = 1043;
+const DATE_OID: u32 = 1082;
+const TIME_OID: u32 = 1083;
+const TIMESTAMP_OID: u32 = 1114;
+const TIMESTAMPTZ_OID: u32 = 1184;
+const INTERVAL_OID: u32 = 1186;
+const NUMERIC_OID: u32 = 1700;
+
+/// Days between the Unix epoch and the PostgreSQL epoch (2000-01-01)
+const PG_EPOCH_DAYS: i64 = 10_957;
+const MICROS_PER_DAY: i64 = 86_400_000_000;
+
+/// SQLSTATE codes used in error responses
+mod sqlstate {
+    pub const PROTOCOL_VIOLATION: &str = "08P01";
+    pub const FEATURE_NOT_SUPPORTED: &str = "0A000";
+    pub const INVALID_PASSWORD: &str = "28P01";
+    pub const READ_ONLY_SQL_TRANSACTION: &str = "25006";
+    pub const IN_FAILED_TRANSACTION: &str = "25P02";
+    pub const INSUFFICIENT_PRIVILEGE: &str = "42501";
+    pub const SYNTAX_ERROR: &str = "42601";
+    pub const UNDEFINED_TABLE: &str = "42P01";
+    pub const UNDEFINED_OBJECT: &str = "42704";
+    pub const DUPLICATE_PREPARED_STATEMENT: &str = "42P05";
+    pub const INVALID_SQL_STATEMENT_NAME: &str = "26000";
+    pub const INVALID_CURSOR_NAME: &str = "34000";
+    pub const DATA_EXCEPTION: &str = "22000";
+    pub const INVALID_TEXT_REPRESENTATION: &str = "22P02";
+    pub const INVALID_BINARY_REPRESENTATION: &str = "22P03";
+    pub const INTEGRITY_CONSTRAINT_VIOLATION: &str = "23000";
+    pub const INSUFFICIENT_RESOURCES: &str = "53000";
+    pub const ADMIN_SHUTDOWN: &str = "57P01";
+    pub const IDLE_IN_TRANSACTION_TIMEOUT: &str = "25P03";
+    pub const CONFIGURATION_LIMIT_EXCEEDED: &str = "53400";
+    pub const INTERNAL_ERROR: &str = "XX000";
+}
+
+/// Error reported to a PostgreSQL client as an ErrorResponse
+#[derive(Debug)]
+struct PgError {
+    severity: &'static str,
+    code: &'static str,
+    message: String,
+}
+
+impl PgError {
+    fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity: "ERROR",
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn fatal(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity: "FATAL",
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn protocol(message: impl Into<String>) -> Self {
+        Self::error(sqlstate::PROTOCOL_VIOLATION, message)
+    }
+
+    fn is_fatal(&self) -> bool {
+        self.severity == "FATAL"
+    }
+}
+
+impl From<duckdb::Error> for PgError {
+    fn from(err: duckdb::Error) -> Self {
+        let message = err.to_string();
+        // DuckDB prefixes messages with its exception type
+        let code = match message.split(" Error:").next().unwrap_or_default() {
+            "Parser" => sqlstate::SYNTAX_ERROR,
+            "Catalog" => sqlstate::UNDEFINED_TABLE,
+            "Binder" => sqlstate::UNDEFINED_OBJECT,
+            "Constraint" => sqlstate::INTEGRITY_CONSTRAINT_VIOLATION,
+            "Conversion" | "Invalid Input" => sqlstate::INVALID_TEXT_REPRESENTATION,
+            "Out of Range" => sqlstate::DATA_EXCEPTION,
+            "TransactionContext" => sqlstate::IN_FAILED_TRANSACTION,
+            "Permission" => sqlstate::INSUFFICIENT_PRIVILEGE,
+            "Out of Memory" => sqlstate::INSUFFICIENT_RESOURCES,
+            _ => sqlstate::INTERNAL_ERROR,
+        };
+        Self::error(code, message)
+    }
+}
+
+impl From<r2d2::Error> for PgError {
+    fn from(err: r2d2::Error) -> Self {
+        Self::error(
+            sqlstate::INSUFFICIENT_RESOURCES,
+            format!("Database connection pool error: {}", err),
+        )
+    }
+}
+
+/// Transaction state reported in ReadyForQuery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransactionStatus {
+    Idle,
+    InBlock,
+    Failed,
+}
+
+impl TransactionStatus {
+    fn indicator(self) -> u8 {
+        match self {
+            TransactionStatus::Idle => b'I',
+            TransactionStatus::InBlock => b'T',
+            TransactionStatus::Failed => b'E',
+        }
+    }
+}
+
+/// A result column with its PostgreSQL type
+#[derive(Debug, Clone)]
+struct Column {
+    name: String,
+    oid: u32,
+}
+
+/// Outcome of running one statement
+#[derive(Debug)]
+struct StatementResult {
+    /// Result columns; empty for commands
+    columns: Vec<Column>,
+    rows: Vec<Vec<Value>>,
+    /// CommandComplete tag, e.g. `SELECT 3` or `INSERT 0 1`
+    tag: String,
+    /// Set when the row limit cut the result short
+    truncated_at: Option<usize>,
+}
+
+/// A statement created by Parse
+#[derive(Debug, Clone)]
+struct PreparedStatement {
+    sql: String,
+    param_types: Vec<u32>,
+}
+
+/// A bound statement created by Bind; executed on first Describe or Execute
+#[derive(Debug)]
+struct Portal {
+    sql: String,
+    params: Vec<Value>,
+    result_formats: Vec<i16>,
+    result: Option<StatementResult>,
+    position: usize,
+}
+
+/// Accept PostgreSQL wire protocol clients until the server shuts down, then wait for their
+/// sessions, which end before their next message
+pub async fn serve(listener: TcpListener, state: AppState) -> io::Result<()> {
+    let shutdown = state.shutdown.started();
+    tokio::pin!(shutdown);
+    let mut sessions = JoinSet::new();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Some(_) = sessions.join_next() => continue,
+            _ = &mut shutdown => break,
+        };
+        let (socket, addr) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!(error = %e, "Failed to accept PostgreSQL connection");
+                continue;
+            }
+        };
+        let _ = socket.set_nodelay(true);
+        let state = state.clone();
+        sessions.spawn(async move {
+            if let Err(e) = handle_connection(socket, state, Some(addr)).await {
+                debug!(client = %addr, error = %e, "PostgreSQL connection closed with error");
+            }
+        });
+    }
+
+    drop(listener);
+    info!(
+        sessions = sessions.len(),
+        "PostgreSQL listener stopped, waiting for sessions"
+    );
+    while sessions.join_next().await.is_some() {}
+    Ok(())
+}
+
+/// Serve one PostgreSQL client over an established stream; `peer` is recorded in the audit log
+#[instrument(skip_all, fields(user))]
+pub async fn handle_connection<S>(
+    mut stream: S,
+    state: AppState,
+    peer: Option<SocketAddr>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let Some(startup) = read_startup(&mut stream).await? else {
+        return Ok(());
+    };
+    let user = startup.get("user").cloned().unwrap_or_default();
+    tracing::Span::current().record("user", user.as_str());
+
+    let mut out = Vec::new();
+    let mut priority = Priority::Interactive;
+    let mut source = AuditSource {
+        interface: "pgwire",
+        endpoint: None,
+        client: match peer {
+            Some(addr) => format!("ip:{}", addr.ip()),
+            None => "ip:unknown".to_string(),
+        },
+        source_ip: peer.map(|addr| addr.ip()),
+    };
+
+    if state.api_keys.is_enabled() {
+        Message::new(b'R').i32(3).write_to(&mut out);
+        stream.write_all(&out).await?;
+        stream.flush().await?;
+        out.clear();
+
+        let password = match read_message(&mut stream).await? {
+            Some((b'p', body)) => Reader::new(&body).cstr().ok(),
+            _ => None,
+        };
+        match password {
+            Some(key) if state.api_keys.verify(&key) => {
+                if state.batch_api_keys.contains(&key) {
+                    priority = Priority::Batch;
+                }
+                source.client = key_identity(&state, &key);
+            }
+            _ => {
+                warn!("PostgreSQL authentication failed");
+                let error = PgError::fatal(
+                    sqlstate::INVALID_PASSWORD,
+                    format!("password authentication failed for user \"{}\"", user),
+                );
+                write_error(&mut out, &error);
+                stream.write_all(&out).await?;
+                return stream.flush().await;
+            }
+        }
+    }
+
+    info!(priority = priority.as_str(), "PostgreSQL client connected");
+
+    Message::new(b'R').i32(0).write_to(&mut out);
+    for (name, value) in [
+        ("server_version", SERVER_VERSION),
+        ("server_encoding", "UTF8"),
+        ("client_encoding", "UTF8"),
+        ("DateStyle", "ISO, MDY"),
+        ("IntervalStyle", "postgres"),
+        ("TimeZone", "UTC"),
+        ("integer_datetimes", "on"),
+        ("standard_conforming_strings", "on"),
+        ("is_superuser", "off"),
+    ] {
+        Message::new(b'S').cstr(name).cstr(value).write_to(&mut out);
+    }
+    if let Some(application_name) = startup.get("application_name") {
+        Message::new(b'S')
+            .cstr("application_name")
+            .cstr(application_name)
+            .write_to(&mut out);
+    }
+
+    let mut session = Session {
+        stream,
+        out,
+        state,
+        priority,
+        source,
+        statements: HashMap::new(),
+        portals: HashMap::new(),
+        conn: None,
+        permit: None,
+        transaction: TransactionStatus::Idle,
+        skip_until_sync: false,
+    };
+    session.ready_for_query();
+    session.flush().await?;
+    session.run().await
+}
+
+/// Read the startup packet, declining SSL and GSSAPI encryption requests.
+/// Returns `None` when the client goes away or sends a cancel request.
+async fn read_startup<S>(stream: &mut S) -> io::Result<Option<HashMap<String, String>>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        let len = match stream.read_i32().await {
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if !(8..=10_000).contains(&len) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid startup packet length",
+            ));
+        }
+        let mut body = vec![0; len as usize - 4];
+        stream.read_exact(&mut body).await?;
+        let mut reader = Reader::new(&body);
+        let code = reader.i32().map_err(invalid_data)?;
+
+        match code {
+            SSL_REQUEST_CODE | GSSENC_REQUEST_CODE => {
+                stream.write_all(b"N").await?;
+                stream.flush().await?;
+            }
+            CANCEL_REQUEST_CODE => return Ok(None),
+            code if code >> 16 == PROTOCOL_VERSION_3 >> 16 => {
+                let mut params = HashMap::new();
+                let mut unrecognized = Vec::new();
+                loop {
+                    let name = reader.cstr().map_err(invalid_data)?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    let value = reader.cstr().map_err(invalid_data)?;
+                    if name.starts_with("_pq_.") {
+                        unrecognized.push(name);
+                    } else {
+                        params.insert(name, value);
+                    }
+                }
+
+                // Newer minor versions and protocol extensions are negotiated down to 3.0
+                if code != PROTOCOL_VERSION_3 || !unrecognized.is_empty() {
+                    let mut message = Message::new(b'v');
+                    message
+                        .i32(PROTOCOL_VERSION_3)
+                        .i32(unrecognized.len() as i32);
+                    for name in &unrecognized {
+                        message.cstr(name);
+                    }
+                    let mut out = Vec::new();
+                    message.write_to(&mut out);
+                    stream.write_all(&out).await?;
+                }
+                return Ok(Some(params));
+            }
+            _ => {
+                let mut out = Vec::new();
+                let error = PgError::fatal(
+                    sqlstate::FEATURE_NOT_SUPPORTED,
+                    "unsupported frontend protocol",
+                );
+                write_error(&mut out, &error);
+                stream.write_all(&out).await?;
+                stream.flush().await?;
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Read one tagged frontend message; `None` on a clean disconnect
+async fn read_message<S>(stream: &mut S) -> io::Result<Option<(u8, Vec<u8>)>>
+where
+    S: AsyncRead + Unpin,
+{
+    let tag = match stream.read_u8().await {
+        Ok(tag) => tag,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let len = stream.read_i32().await?;
+    if len < 4 || len as usize > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid message length",
+        ));
+    }
+    let mut body = vec![0; len as usize - 4];
+    stream.read_exact(&mut body).await?;
+    Ok(Some((tag, body)))
+}
+
+fn invalid_data(err: PgError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.message)
+}
+
+/// Per-connection protocol state
+struct Session<S> {
+    stream: S,
+    out: Vec<u8>,
+    state: AppState,
+    priority: Priority,
+    source: AuditSource,
+    statements: HashMap<String, PreparedStatement>,
+    portals: HashMap<String, Portal>,
+    /// Pooled connection pinned while a transaction block is open
+    conn: Option<DuckDbConnection>,
+    /// Admission slot held for as long as the connection is pinned
+    permit: Option<AdmissionPermit>,
+    transaction: TransactionStatus,
+    /// After an error in the extended protocol, messages are discarded until Sync
+    skip_until_sync: bool,
+}
+
+impl<S> Session<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    async fn run(mut self) -> io::Result<()> {
+        let shutdown = self.state.shutdown.started();
+        tokio::pin!(shutdown);
+        loop {
+            // Once the server shuts down, the session ends before its next message
+            // An open transaction block pins a pooled connection and an admission slot
+            let idle_timeout = Some(self.state.pg_idle_in_transaction_timeout)
+                .filter(|timeout| self.conn.is_some() && !timeout.is_zero());
+            let message = tokio::select! {
+                message = read_message(&mut self.stream) => Ok(message?),
+                _ = &mut shutdown => Err(PgError::fatal(
+                    sqlstate::ADMIN_SHUTDOWN,
+                    "terminating connection due to administrator command",
+                )),
+                _ = sleep_or_pending(idle_timeout) => Err(PgError::fatal(
+                    sqlstate::IDLE_IN_TRANSACTION_TIMEOUT,
+                    "terminating connection due to idle-in-transaction timeout",
+                )),
+            };
+            let message = match message {
+                Ok(message) => message,
+                Err(error) => {
+                    write_error(&mut self.out, &error);
+                    break;
+                }
+            };
+            let Some((tag, body)) = message else {
+                break;
+            };
+            if self.skip_until_sync && !matches!(tag, b'S' | b'X') {
+                continue;
+            }
+
+            let result = match tag {
+                b'Q' => {
+                    if let Err(error) = self.simple_query(&body).await {
+                        write_error(&mut self.out, &error);
+                    }
+                    self.ready_for_query();
+                    Ok(())
+                }
+                b'P' => self.parse(&body),
+                b'B' => self.bind(&body),
+                b'D' => self.describe(&body).await,
+                b'E' => self.execute(&body).await,
+                b'C' => self.close(&body),
+                b'S' => {
+                    self.sync();
+                    Ok(())
+                }
+                b'H' => Ok(()),
+                b'X' => break,
+                other => Err(PgError::error(
+                    sqlstate::FEATURE_NOT_SUPPORTED,
+                    format!("unsupported message type '{}'", other as char),
+                )),
+            };
+
+            if let Err(error) = result {
+                write_error(&mut self.out, &error);
+                self.skip_until_sync = true;
+                if error.is_fatal() {
+                    self.flush().await?;
+                    break;
+                }
+            }
+
+            if matches!(tag, b'Q' | b'S' | b'H') || self.out.len() > 64 * 1024 {
+                self.flush().await?;
+            }
+        }
+        self.rollback_pinned().await;
+        self.flush().await
+    }
+
+    /// Roll back an open transaction so its pooled connection returns clean, then release the
+    /// connection and its admission slot
+    async fn rollback_pinned(&mut self) {
+        self.permit = None;
+        self.transaction = TransactionStatus::Idle;
+        let Some(conn) = self.conn.take() else {
+            return;
+        };
+        let rolled_back = tokio::task::spawn_blocking(move || conn.execute_batch("ROLLBACK")).await;
+        if let Ok(Err(e)) = rolled_back {
+            debug!("Rolling back abandoned transaction failed: {}", e);
+        }
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        if !self.out.is_empty() {
+            self.stream.write_all(&self.out).await?;
+            self.out.clear();
+        }
+        self.stream.flush().await
+    }
+
+    fn ready_for_query(&mut self) {
+        Message::new(b'Z')
+            .u8(self.transaction.indicator())
+            .write_to(&mut self.out);
+    }
+
+    /// Simple query protocol: run each statement of the script with text results
+    async fn simple_query(&mut self, body: &[u8]) -> Result<(), PgError> {
+        let sql = Reader::new(body).cstr()?;
+        let statements = split_statements(&sql);
+        if statements.is_empty() {
+            Message::new(b'I').write_to(&mut self.out);
+            return Ok(());
+        }
+
+        for statement in statements {
+            let result = self.execute_statement(statement, Vec::new()).await?;
+            if !result.columns.is_empty() {
+                write_row_description(&mut self.out, &result.columns, &[])?;
+                write_data_rows(&mut self.out, &result.columns, &result.rows, &[])?;
+            }
+            self.complete(&result);
+        }
+        Ok(())
+    }
+
+    fn parse(&mut self, body: &[u8]) -> Result<(), PgError> {
+        let mut reader = Reader::new(body);
+        let name = reader.cstr()?;
+        let sql = reader.cstr()?;
+        let count = reader.i16()?;
+        let param_types = (0..count)
+            .map(|_| reader.u32())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if !name.is_empty() && self.statements.contains_key(&name) {
+            return Err(PgError::error(
+                sqlstate::DUPLICATE_PREPARED_STATEMENT,
+                format!("prepared statement \"{}\" already exists", name),
+            ));
+        }
+        if split_statements(&sql).len() > 1 {
+            return Err(PgError::error(
+                sqlstate::SYNTAX_ERROR,
+                "cannot insert multiple commands into a prepared statement",
+            ));
+        }
+
+        debug!(statement = %name, params = param_types.len(), "Parse");
+        self.statements
+            .insert(name, PreparedStatement { sql, param_types });
+        Message::new(b'1').write_to(&mut self.out);
+        Ok(())
+    }
+
+    fn bind(&mut self, body: &[u8]) -> Result<(), PgError> {
+        let mut reader = Reader::new(body);
+        let portal = reader.cstr()?;
+        let name = reader.cstr()?;
+        let param_formats = reader.i16_list()?;
+        let count = reader.i16()? as usize;
+        let mut raw_params = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len = reader.i32()?;
+            raw_params.push(if len < 0 {
+                None
+            } else {
+                Some(reader.bytes(len as usize)?)
+            });
+        }
+        let result_formats = reader.i16_list()?;
+
+        let statement = self.statements.get(&name).ok_or_else(|| {
+            PgError::error(
+                sqlstate::INVALID_SQL_STATEMENT_NAME,
+                format!("prepared statement \"{}\" does not exist", name),
+            )
+        })?;
+
+        let mut params = Vec::with_capacity(count);
+        for (i, raw) in raw_params.into_iter().enumerate() {
+            let format = format_for(&param_formats, i)?;
+            let oid = statement.param_types.get(i).copied().unwrap_or(0);
+            params.push(decode_param(raw, format, oid)?);
+        }
+
+        self.portals.insert(
+            portal,
+            Portal {
+                sql: statement.sql.clone(),
+                params,
+                result_formats,
+                result: None,
+                position: 0,
+            },
+        );
+        Message::new(b'2').write_to(&mut self.out);
+        Ok(())
+    }
+
+    async fn describe(&mut self, body: &[u8]) -> Result<(), PgError> {
+        let mut reader = Reader::new(body);
+        let kind = reader.u8()?;
+        let name = reader.cstr()?;
+
+        match kind {
+            b'S' => {
+                let statement = self.statements.get(&name).cloned().ok_or_else(|| {
+                    PgError::error(
+                        sqlstate::INVALID_SQL_STATEMENT_NAME,
+                        format!("prepared statement \"{}\" does not exist", name),
+                    )
+                })?;
+
+                let (param_count, columns) = if split_statements(&statement.sql).is_empty() {
+                    (0, None)
+                } else {
+                    let sql = statement.sql.clone();
+                    let described = self
+                        .with_connection(move |conn| describe_statement(conn, &sql))
+                        .await;
+                    self.update_transaction("", described.is_ok());
+                    described?
+                };
+
+                let mut message = Message::new(b't');
+                message.i16(param_count as i16);
+                for i in 0..param_count {
+                    let oid = match statement.param_types.get(i) {
+                        Some(0) | None => TEXT_OID,
+                        Some(oid) => *oid,
+                    };
+                    message.u32(oid);
+                }
+                message.write_to(&mut self.out);
+
+                match columns {
+                    Some(columns) => write_row_description(&mut self.out, &columns, &[])?,
+                    None => Message::new(b'n').write_to(&mut self.out),
+                }
+                Ok(())
+            }
+            b'P' => {
+                let mut portal = self.take_portal(&name)?;
+                let outcome = self.ensure_executed(&mut portal).await;
+                let described = outcome.and_then(|()| match &portal.result {
+                    Some(result) if !result.columns.is_empty() => write_row_description(
+                        &mut self.out,
+                        &result.columns,
+                        &portal.result_formats,
+                    ),
+                    _ => {
+                        Message::new(b'n').write_to(&mut self.out);
+                        Ok(())
+                    }
+                });
+                self.portals.insert(name, portal);
+                described
+            }
+            other => Err(PgError::protocol(format!(
+                "invalid Describe target '{}'",
+                other as char
+            ))),
+        }
+    }
+
+    async fn execute(&mut self, body: &[u8]) -> Result<(), PgError> {
+        let mut reader = Reader::new(body);
+        let name = reader.cstr()?;
+        let max_rows = reader.i32()?;
+
+        let mut portal = self.take_portal(&name)?;
+        let outcome = self.send_portal_rows(&mut portal, max_rows).await;
+        self.portals.insert(name, portal);
+        outcome
+    }
+
+    async fn send_portal_rows(
+        &mut self,
+        portal: &mut Portal,
+        max_rows: i32,
+    ) -> Result<(), PgError> {
+        if split_statements(&portal.sql).is_empty() {
+            Message::new(b'I').write_to(&mut self.out);
+            return Ok(());
+        }
+        self.ensure_executed(portal).await?;
+        let Some(result) = &portal.result else {
+            return Ok(());
+        };
+
+        if result.columns.is_empty() {
+            self.complete(result);
+            return Ok(());
+        }
+
+        let remaining = result.rows.len() - portal.position;
+        let batch = if max_rows > 0 {
+            remaining.min(max_rows as usize)
+        } else {
+            remaining
+        };
+        let end = portal.position + batch;
+        write_data_rows(
+            &mut self.out,
+            &result.columns,
+            &result.rows[portal.position..end],
+            &portal.result_formats,
+        )?;
+        portal.position = end;
+
+        if end < result.rows.len() {
+            Message::new(b's').write_to(&mut self.out);
+        } else {
+            self.complete(result);
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, body: &[u8]) -> Result<(), PgError> {
+        let mut reader = Reader::new(body);
+        let kind = reader.u8()?;
+        let name = reader.cstr()?;
+        match kind {
+            b'S' => {
+                self.statements.remove(&name);
+            }
+            b'P' => {
+                self.portals.remove(&name);
+            }
+            other => {
+                return Err(PgError::protocol(format!(
+                    "invalid Close target '{}'",
+                    other as char
+                )));
+            }
+        }
+        Message::new(b'3').write_to(&mut self.out);
+        Ok(())
+    }
+
+    fn sync(&mut self) {
+        self.skip_until_sync = false;
+        // Portals only live until the end of the transaction
+        if self.transaction == TransactionStatus::Idle {
+            self.portals.clear();
+        }
+        self.ready_for_query();
+    }
+
+    fn take_portal(&mut self, name: &str) -> Result<Portal, PgError> {
+        self.portals.remove(name).ok_or_else(|| {
+            PgError::error(
+                sqlstate::INVALID_CURSOR_NAME,
+                format!("portal \"{}\" does not exist", name),
+            )
+        })
+    }
+
+    async fn ensure_executed(&mut self, portal: &mut Portal) -> Result<(), PgError> {
+        if portal.result.is_none() && !split_statements(&portal.sql).is_empty() {
+            let params = std::mem::take(&mut portal.params);
+            portal.result = Some(self.execute_statement(portal.sql.clone(), params).await?);
+        }
+        Ok(())
+    }
+
+    /// Write CommandComplete, preceded by a warning when the row limit truncated the result
+    fn complete(&mut self, result: &StatementResult) {
+        if let Some(limit) = result.truncated_at {
+            let notice = format!("Results truncated to {} rows", limit);
+            Message::new(b'N')
+                .u8(b'S')
+                .cstr("WARNING")
+                .u8(b'V')
+                .cstr("WARNING")
+                .u8(b'C')
+                .cstr("01000")
+                .u8(b'M')
+                .cstr(&notice)
+                .u8(0)
+                .write_to(&mut self.out);
+        }
+        Message::new(b'C').cstr(&result.tag).write_to(&mut self.out);
+    }
+
+    /// Run one statement with the same validation, admission control and row
+    /// limit as HTTP queries
+    async fn execute_statement(
+        &mut self,
+        sql: String,
+        params: Vec<Value>,
+    ) -> Result<StatementResult, PgError> {
+        let query_id = Uuid::new_v4().to_string();
+        let mut audit =
+            Audit::begin(&self.state, &self.source, &query_id, &sql).with_params(&params);
+        if let Some(message) = validate_readonly_operation(&self.state, &sql) {
+            audit.failed_with(AuditStatus::Rejected, sqlstate::READ_ONLY_SQL_TRANSACTION);
+            return Err(PgError::error(sqlstate::READ_ONLY_SQL_TRANSACTION, message));
+        }
+        if let Some(message) = validate_sandbox_operation(&self.state, &sql) {
+            audit.failed_with(AuditStatus::Rejected, sqlstate::INSUFFICIENT_PRIVILEGE);
+            return Err(PgError::error(sqlstate::INSUFFICIENT_PRIVILEGE, message));
+        }
+
+        let keyword = leading_keyword(&sql);
+        let limit = effective_row_limit(None);
+        let is_write = is_write_operation(&sql);
+        debug!(keyword = %keyword, params = params.len(), "Executing statement");
+
+        let result = self
+            .with_connection(move |conn| run_statement(conn, &sql, &params, limit))
+            .await;
+        self.update_transaction(&keyword, result.is_ok());
+
+        // Results cached by the HTTP endpoints may no longer match the data
+        if is_write
+            && result.is_ok()
+            && let Some(cache) = &self.state.cache
+        {
+            cache.invalidate_all();
+        }
+        match &result {
+            Ok(result) if result.columns.is_empty() => {
+                // Command tags end with the affected row count, e.g. `INSERT 0 3`
+                let affected = result.tag.rsplit(' ').next().and_then(|n| n.parse().ok());
+                audit.completed(None, affected);
+            }
+            Ok(result) => audit.completed(Some(result.rows.len() as u64), None),
+            Err(error) => audit.failed_with(AuditStatus::Error, error.code),
+        }
+        result
+    }
+
+    /// Run `f` on the pinned connection, or on a pooled one after passing admission control
+    async fn with_connection<T, F>(&mut self, f: F) -> Result<T, PgError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T, PgError> + Send + 'static,
+    {
+        // A pinned connection keeps the slot it was admitted with until the transaction ends
+        let permit = match self.permit.take() {
+            Some(permit) => permit,
+            None => self
+                .state
+                .admission_for(self.priority)
+                .acquire(&self.state.metrics)
+                .await
+                .map_err(|e| {
+                    PgError::error(sqlstate::CONFIGURATION_LIMIT_EXCEEDED, e.to_string())
+                })?,
+        };
+        let pinned = self.conn.take();
+        let pool = self.state.pool_for(self.priority).clone();
+
+        let (conn, permit, result) = tokio::task::spawn_blocking(move || {
+            let conn = match pinned {
+                Some(conn) => conn,
+                None => pool.get()?,
+            };
+            let result = f(&conn);
+            Ok::<_, PgError>((conn, permit, result))
+        })
+        .await
+        .map_err(|e| PgError::error(sqlstate::INTERNAL_ERROR, e.to_string()))??;
+
+        self.conn = Some(conn);
+        self.permit = Some(permit);
+        result
+    }
+
+    /// Track transaction blocks so their statements share one pooled connection
+    fn update_transaction(&mut self, keyword: &str, succeeded: bool) {
+        self.transaction = match (keyword, succeeded) {
+            ("BEGIN" | "START", true) => TransactionStatus::InBlock,
+            ("COMMIT" | "END" | "ROLLBACK" | "ABORT", _) => TransactionStatus::Idle,
+            (_, false) if self.transaction == TransactionStatus::InBlock => {
+                TransactionStatus::Failed
+            }
+            _ => self.transaction,
+        };
+        if self.transaction == TransactionStatus::Idle {
+            self.conn = None;
+            self.permit = None;
+        }
+    }
+}
+
+/// Sleep for `timeout`, or forever when there is none
+async fn sleep_or_pending(timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => tokio::time::sleep(timeout).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Execute a statement and collect up to `limit` rows
+fn run_statement(
+    conn: &Connection,
+    sql: &str,
+    params: &[Value],
+    limit: usize,
+) -> Result<StatementResult, PgError> {
+    let keyword = leading_keyword(sql);
+    let mut stmt = conn.prepare(sql)?;
+
+    let mut rows = stmt.query(duckdb::params_from_iter(params))?;
+    let mut result_rows = Vec::new();
+    let mut truncated_at = None;
+    while let Some(row) = rows.next()? {
+        if result_rows.len() >= limit {
+            warn!("Query results truncated at {} rows", limit);
+            truncated_at = Some(limit);
+            break;
+        }
+        let column_count = row.as_ref().column_count();
+        let values = (0..column_count)
+            .map(|i| row.get::<_, Value>(i))
+            .collect::<Result<Vec<_>, _>>()?;
+        result_rows.push(values);
+    }
+    drop(rows);
+
+    let columns = statement_columns(&stmt);
+    let names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+
+    if returns_rows(&keyword, &names) {
+        return Ok(StatementResult {
+            tag: format!("SELECT {}", result_rows.len()),
+            columns,
+            rows: result_rows,
+            truncated_at,
+        });
+    }
+
+    let affected = match (
+        names.as_slice(),
+        result_rows.first().and_then(|r| r.first()),
+    ) {
+        (["Count"], Some(value)) => as_i64(value).unwrap_or(0),
+        _ => 0,
+    };
+    Ok(StatementResult {
+        columns: Vec::new(),
+        rows: Vec::new(),
+        tag: command_tag(sql, &keyword, affected),
+        truncated_at: None,
+    })
+}
+
+/// Parameter count and, for plain queries, result columns of a statement.
+/// Queries are described by running them wrapped in `LIMIT 0` with NULL parameters.
+fn describe_statement(
+    conn: &Connection,
+    sql: &str,
+) -> Result<(usize, Option<Vec<Column>>), PgError> {
+    let param_count = conn.prepare(sql)?.parameter_count();
+    if !is_query_statement(sql) {
+        return Ok((param_count, None));
+    }
+
+    let wrapped = format!(
+        "SELECT * FROM ({}) AS described LIMIT 0",
+        sql.trim().trim_end_matches(';')
+    );
+    let mut stmt = conn.prepare(&wrapped)?;
+    let nulls = vec![Value::Null; param_count];
+    stmt.query(duckdb::params_from_iter(&nulls))?.next()?;
+    Ok((param_count, Some(statement_columns(&stmt))))
+}
+
+/// Columns of an executed statement
+fn statement_columns(stmt: &duckdb::Statement) -> Vec<Column> {
+    (0..stmt.column_count())
+        .map(|i| Column {
+            name: stmt
+                .column_name(i)
+                .map(|name| name.to_string())
+                .unwrap_or_else(|_| format!("column_{}", i)),
+            oid: type_oid(&stmt.column_type(i)),
+        })
+        .collect()
+}
+
+/// PostgreSQL-style CommandComplete tag for a non-query statement
+fn command_tag(sql: &str, keyword: &str, affected: i64) -> String {
+    match keyword {
+        "INSERT" => format!("INSERT 0 {}", affected),
+        "UPDATE" | "DELETE" | "MERGE" | "COPY" => format!("{} {}", keyword, affected),
+        "CREATE" | "DROP" | "ALTER" => {
+            let normalized = normalize_sql(sql).to_ascii_uppercase();
+            let object = normalized
+                .split_whitespace()
+                .skip(1)
+                .find(|word| {
+                    !matches!(
+                        *word,
+                        "OR" | "REPLACE" | "TEMP" | "TEMPORARY" | "UNIQUE" | "PERSISTENT"
+                    )
+                })
+                .unwrap_or_default();
+            format!("{} {}", keyword, object).trim_end().to_string()
+        }
+        "START" => "START TRANSACTION".to_string(),
+        "END" => "COMMIT".to_string(),
+        "ABORT" => "ROLLBACK".to_string(),
+        _ => keyword.to_string(),
+    }
+}
+
+/// PostgreSQL type OID for a DuckDB result column
+fn type_oid(data_type: &DataType) -> u32 {
+    match data_type {
+        DataType::Boolean => BOOL_OID,
+        DataType::Int8 | DataType::Int16 | DataType::UInt8 => INT2_OID,
+        DataType::Int32 | DataType::UInt16 => INT4_OID,
+        DataType::Int64 | DataType::UInt32 => INT8_OID,
+        DataType::UInt64 | DataType::Decimal128(_, _) | DataType::Decimal256(_, _) => NUMERIC_OID,
+        DataType::Float16 | DataType::Float32 => FLOAT4_OID,
+        DataType::Float64 => FLOAT8_OID,
+        DataType::Binary
+        | DataType::LargeBinary
+        | DataType::BinaryView
+        | DataType::FixedSizeBinary(_) => BYTEA_OID,
+        DataType::Date32 | DataType::Date64 => DATE_OID,
+        DataType::Time32(_) | DataType::Time64(_) => TIME_OID,
+        DataType::Timestamp(_, None) => TIMESTAMP_OID,
+        DataType::Timestamp(_, Some(_)) => TIMESTAMPTZ_OID,
+        DataType::Interval(_) => INTERVAL_OID,
+        // Strings, enums and nested types are sent as text
+        _ => TEXT_OID,
+    }
+}
+
+/// Fixed size of a type in bytes, or -1 for variable-length types
+fn type_size(oid: u32) -> i16 {
+    match oid {
+        BOOL_OID => 1,
+        INT2_OID => 2,
+        INT4_OID | FLOAT4_OID | DATE_OID => 4,
+        INT8_OID | FLOAT8_OID | TIME_OID | TIMESTAMP_OID | TIMESTAMPTZ_OID => 8,
+        INTERVAL_OID => 16,
+        _ => -1,
+    }
+}
+
+/// Format code for column or parameter `index` from a Bind format list
+fn format_for(formats: &[i16], index: usize) -> Result<i16, PgError> {
+    let format = match formats {
+        [] => 0,
+        [format] => *format,
+        formats => *formats
+            .get(index)
+            .ok_or_else(|| PgError::protocol("format code count does not match"))?,
+    };
+    match format {
+        0 | 1 => Ok(format),
+        other => Err(PgError::protocol(format!("invalid format code {}", other))),
+    }
+}
+
+fn write_row_description(
+    out: &mut Vec<u8>,
+    columns: &[Column],
+    formats: &[i16],
+) -> Result<(), PgError> {
+    let mut message = Message::new(b'T');
+    message.i16(columns.len() as i16);
+    for (i, column) in columns.iter().enumerate() {
+        message
+            .cstr(&column.name)
+            .u32(0) // table OID
+            .i16(0) // column attribute number
+            .u32(column.oid)
+            .i16(type_size(column.oid))
+            .i32(-1) // type modifier
+            .i16(format_for(formats, i)?);
+    }
+    message.write_to(out);
+    Ok(())
+}
+
+fn write_data_rows(
+    out: &mut Vec<u8>,
+    columns: &[Column],
+    rows: &[Vec<Value>],
+    formats: &[i16],
+) -> Result<(), PgError> {
+    let formats = (0..columns.len())
+        .map(|i| format_for(formats, i))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for row in rows {
+        let mut message = Message::new(b'D');
+        message.i16(row.len() as i16);
+        for ((value, column), format) in row.iter().zip(columns).zip(&formats) {
+            let encoded = match (value, format) {
+                (Value::Null, _) => None,
+                (value, 0) => Some(encode_text(value, column.oid).into_bytes()),
+                (value, _) => Some(encode_binary(value, column.oid)?),
+            };
+            match encoded {
+                Some(bytes) => message.i32(bytes.len() as i32).bytes(&bytes),
+                None => message.i32(-1),
+            };
+        }
+        message.write_to(out);
+    }
+    Ok(())
+}
+
+fn write_error(out: &mut Vec<u8>, error: &PgError) {
+    Message::new(b'E')
+        .u8(b'S')
+        .cstr(error.severity)
+        .u8(b'V')
+        .cstr(error.severity)
+        .u8(b'C')
+        .cstr(error.code)
+        .u8(b'M')
+        .cstr(&error.message)
+        .u8(0)
+        .write_to(out);
+}
+
+/// Decode a Bind parameter into a value DuckDB can bind.
+/// Numeric and boolean parameters keep their declared width so the result types
+/// match what Describe reported; other text is bound as VARCHAR and cast by DuckDB.
+fn decode_param(raw: Option<&[u8]>, format: i16, oid: u32) -> Result<Value, PgError> {
+    let Some(raw) = raw else {
+        return Ok(Value::Null);
+    };
+    let invalid = |what: &str| {
+        PgError::error(
+            if format == 0 {
+                sqlstate::INVALID_TEXT_REPRESENTATION
+            } else {
+                sqlstate::INVALID_BINARY_REPRESENTATION
+            },
+            format!("invalid {} parameter value", what),
+        )
+    };
+
+    if format == 0 {
+        let text = std::str::from_utf8(raw).map_err(|_| invalid("UTF-8"))?;
+        return match oid {
+            INT2_OID => text
+                .trim()
+                .parse()
+                .map(Value::SmallInt)
+                .map_err(|_| invalid("int2")),
+            INT4_OID => text
+                .trim()
+                .parse()
+                .map(Value::Int)
+                .map_err(|_| invalid("int4")),
+            INT8_OID => text
+                .trim()
+                .parse()
+                .map(Value::BigInt)
+                .map_err(|_| invalid("int8")),
+            FLOAT4_OID => text
+                .trim()
+                .parse()
+                .map(Value::Float)
+                .map_err(|_| invalid("float4")),
+            FLOAT8_OID => text
+                .trim()
+                .parse()
+                .map(Value::Double)
+                .map_err(|_| invalid("float8")),
+            BOOL_OID => match text.trim().to_ascii_lowercase().as_str() {
+                "t" | "true" | "y" | "yes" | "on" | "1" => Ok(Value::Boolean(true)),
+                "f" | "false" | "n" | "no" | "off" | "0" => Ok(Value::Boolean(false)),
+                _ => Err(invalid("boolean")),
+            },
+            _ => Ok(Value::Text(text.to_string())),
+        };
+    }
+
+    match oid {
+        BOOL_OID => match raw {
+            [b] => Ok(Value::Boolean(*b != 0)),
+            _ => Err(invalid("boolean")),
+        },
+        INT2_OID => raw
+            .try_into()
+            .map(|b| Value::SmallInt(i16::from_be_bytes(b)))
+            .map_err(|_| invalid("int2")),
+        INT4_OID => raw
+            .try_into()
+            .map(|b| Value::Int(i32::from_be_bytes(b)))
+            .map_err(|_| invalid("int4")),
+        INT8_OID => raw
+            .try_into()
+            .map(|b| Value::BigInt(i64::from_be_bytes(b)))
+            .map_err(|_| invalid("int8")),
+        FLOAT4_OID => raw
+            .try_into()
+            .map(|b| Value::Float(f32::from_be_bytes(b)))
+            .map_err(|_| invalid("float4")),
+        FLOAT8_OID => raw
+            .try_into()
+            .map(|b| Value::Double(f64::from_be_bytes(b)))
+            .map_err(|_| invalid("float8")),
+        BYTEA_OID => Ok(Value::Blob(raw.to_vec())),
+        // duckdb-rs cannot bind dates directly, so bind the ISO text and let DuckDB cast it
+        DATE_OID => raw
+            .try_into()
+            .map(|b| {
+                let (date, bc) = format_date(i32::from_be_bytes(b) as i64 + PG_EPOCH_DAYS);
+                Value::Text(format!("{}{}", date, bc))
+            })
+            .map_err(|_| invalid("date")),
+        TIMESTAMP_OID | TIMESTAMPTZ_OID => raw
+            .try_into()
+            .map(|b| {
+                let micros = i64::from_be_bytes(b) + PG_EPOCH_DAYS * MICROS_PER_DAY;
+                Value::Timestamp(TimeUnit::Microsecond, micros)
+            })
+            .map_err(|_| invalid("timestamp")),
+        0 | TEXT_OID | VARCHAR_OID | BPCHAR_OID | NAME_OID | UNKNOWN_OID => {
+            std::str::from_utf8(raw)
+                .map(|text| Value::Text(text.to_string()))
+                .map_err(|_| invalid("UTF-8"))
+        }
+        other => Err(PgError::error(
+            sqlstate::FEATURE_NOT_SUPPORTED,
+            format!(
+                "binary format is not supported for parameter type {}",
+                other
+            ),
+        )),
+    }
+}
+
+/// Encode a non-null value in PostgreSQL text format
+fn encode_text(value: &Value, oid: u32) -> String {
+    match value {
+        Value::Boolean(b) => if *b { "t" } else { "f" }.to_string(),
+        Value::Text(s) | Value::Enum(s) => s.clone(),
+        Value::Blob(bytes) => {
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("\\x{}", hex)
+        }
+        Value::Timestamp(unit, ts) if oid == TIMESTAMPTZ_OID => {
+            let (timestamp, bc) = format_timestamp(unit.to_micros(*ts));
+            format!("{}+00{}", timestamp, bc)
+        }
+        value => render_value(value, false),
+    }
+}
+
+/// Render a value as text; nested values are quoted and NULLs spelled out, as DuckDB does
+fn render_value(value: &Value, nested: bool) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::TinyInt(i) => i.to_string(),
+        Value::SmallInt(i) => i.to_string(),
+        Value::Int(i) => i.to_string(),
+        Value::BigInt(i) => i.to_string(),
+        Value::HugeInt(i) => i.to_string(),
+        Value::UTinyInt(i) => i.to_string(),
+        Value::USmallInt(i) => i.to_string(),
+        Value::UInt(i) => i.to_string(),
+        Value::UBigInt(i) => i.to_string(),
+        Value::Float(f) => format_float(f64::from(*f)),
+        Value::Double(f) => format_float(*f),
+        Value::Decimal(d) => d.to_string(),
+        Value::Text(s) | Value::Enum(s) if nested => format!("'{}'", s.replace('\'', "''")),
+        Value::Text(s) | Value::Enum(s) => s.clone(),
+        Value::Blob(bytes) => bytes
+            .iter()
+            .map(|b| format!("\\x{:02X}", b))
+            .collect::<String>(),
+        Value::Date32(days) => {
+            let (date, bc) = format_date(i64::from(*days));
+            format!("{}{}", date, bc)
+        }
+        Value::Time64(unit, t) => format_time(unit.to_micros(*t)),
+        Value::Timestamp(unit, ts) => {
+            let (timestamp, bc) = format_timestamp(unit.to_micros(*ts));
+            format!("{}{}", timestamp, bc)
+        }
+        Value::Interval {
+            months,
+            days,
+            nanos,
+        } => format_interval(*months, *days, *nanos),
+        Value::List(items) | Value::Array(items) => format!(
+            "[{}]",
+            items
+                .iter()
+                .map(|item| render_value(item, true))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Value::Struct(fields) => format!(
+            "{{{}}}",
+            fields
+                .iter()
+                .map(|(name, field)| format!("'{}': {}", name, render_value(field, true)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Value::Map(entries) => format!(
+            "{{{}}}",
+            entries
+                .iter()
+                .map(|(key, value)| format!(
+                    "{}={}",
+                    render_value(key, true),
+                    render_value(value, true)
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Value::Union(inner) => render_value(inner, nested),
+    }
+}
+
+/// Encode a non-null value in PostgreSQL binary format for the column type
+fn encode_binary(value: &Value, oid: u32) -> Result<Vec<u8>, PgError> {
+    let mismatch = || {
+        PgError::error(
+            sqlstate::INTERNAL_ERROR,
+            format!("cannot encode value as binary type {}", oid),
+        )
+    };
+
+    let bytes = match (oid, value) {
+        (BOOL_OID, Value::Boolean(b)) => vec![u8::from(*b)],
+        (INT2_OID, value) => i16::try_from(as_i64(value).ok_or_else(mismatch)?)
+            .map_err(|_| mismatch())?
+            .to_be_bytes()
+            .to_vec(),
+        (INT4_OID, value) => i32::try_from(as_i64(value).ok_or_else(mismatch)?)
+            .map_err(|_| mismatch())?
+            .to_be_bytes()
+            .to_vec(),
+        (INT8_OID, value) => as_i64(value).ok_or_else(mismatch)?.to_be_bytes().to_vec(),
+        (FLOAT4_OID, value) => (as_f64(value).ok_or_else(mismatch)? as f32)
+            .to_be_bytes()
+            .to_vec(),
+        (FLOAT8_OID, value) => as_f64(value).ok_or_else(mismatch)?.to_be_bytes().to_vec(),
+        (BYTEA_OID, Value::Blob(bytes)) => bytes.clone(),
+        (DATE_OID, Value::Date32(days)) => ((i64::from(*days) - PG_EPOCH_DAYS) as i32)
+            .to_be_bytes()
+            .to_vec(),
+        (TIME_OID, Value::Time64(unit, t)) => unit.to_micros(*t).to_be_bytes().to_vec(),
+        (TIMESTAMP_OID | TIMESTAMPTZ_OID, Value::Timestamp(unit, ts)) => (unit.to_micros(*ts)
+            - PG_EPOCH_DAYS * MICROS_PER_DAY)
+            .to_be_bytes()
+            .to_vec(),
+        (
+            INTERVAL_OID,
+            Value::Interval {
+                months,
+                days,
+                nanos,
+            },
+        ) => {
+            let mut bytes = (nanos / 1000).to_be_bytes().to_vec();
+            bytes.extend(days.to_be_bytes());
+            bytes.extend(months.to_be_bytes());
+            bytes
+        }
+        (NUMERIC_OID, value) => encode_numeric(&render_value(value, false)).ok_or_else(mismatch)?,
+        (BOOL_OID | BYTEA_OID | DATE_OID | TIME_OID | TIMESTAMP_OID | TIMESTAMPTZ_OID, _)
+        | (INTERVAL_OID, _) => return Err(mismatch()),
+        // Text-like types have the same binary and text representation
+        (_, value) => encode_text(value, oid).into_bytes(),
+    };
+    Ok(bytes)
+}
+
+/// Encode a decimal string as a binary NUMERIC: base-10000 digits with weight and scale
+fn encode_numeric(text: &str) -> Option<Vec<u8>> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+    if !int_part
+        .bytes()
+        .chain(frac_part.bytes())
+        .all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let int_part = int_part.trim_start_matches('0');
+    let int_pad = (4 - int_part.len() % 4) % 4;
+    let frac_pad = (4 - frac_part.len() % 4) % 4;
+    let padded = format!(
+        "{}{}{}{}",
+        "0".repeat(int_pad),
+        int_part,
+        frac_part,
+        "0".repeat(frac_pad)
+    );
+    let mut groups: Vec<i16> = padded
+        .as_bytes()
+        .chunks(4)
+        .map(|chunk| std::str::from_utf8(chunk).ok()?.parse().ok())
+        .collect::<Option<_>>()?;
+
+    let mut weight = ((int_pad + int_part.len()) / 4) as i16 - 1;
+    while groups.first() == Some(&0) {
+        groups.remove(0);
+        weight -= 1;
+    }
+    while groups.last() == Some(&0) {
+        groups.pop();
+    }
+    if groups.is_empty() {
+        weight = 0;
+    }
+    let sign: u16 = if negative && !groups.is_empty() {
+        0x4000
+    } else {
+        0
+    };
+
+    let mut bytes = Vec::with_capacity(8 + groups.len() * 2);
+    bytes.extend((groups.len() as i16).to_be_bytes());
+    bytes.extend(weight.to_be_bytes());
+    bytes.extend(sign.to_be_bytes());
+    bytes.extend((frac_part.len() as u16).to_be_bytes());
+    for group in groups {
+        bytes.extend(group.to_be_bytes());
+    }
+    Some(bytes)
+}
+
+fn as_i64(value: &Value) -> Option<i64> {
+    match value {
+        Value::TinyInt(i) => Some((*i).into()),
+        Value::SmallInt(i) => Some((*i).into()),
+        Value::Int(i) => Some((*i).into()),
+        Value::BigInt(i) => Some(*i),
+        Value::HugeInt(i) => i64::try_from(*i).ok(),
+        Value::UTinyInt(i) => Some((*i).into()),
+        Value::USmallInt(i) => Some((*i).into()),
+        Value::UInt(i) => Some((*i).into()),
+        Value::UBigInt(i) => i64::try_from(*i).ok(),
+        _ => None,
+    }
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Float(f) => Some((*f).into()),
+        Value::Double(f) => Some(*f),
+        value => as_i64(value).map(|i| i as f64),
+    }
+}
+
+fn format_float(f: f64) -> String {
+    if f.is_nan() {
+        "NaN".to_string()
+    } else if f.is_infinite() {
+        if f > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else {
+        f.to_string()
+    }
+}
+
+/// Format days since the Unix epoch as `YYYY-MM-DD`, plus a ` BC` suffix for years before 1 AD
+fn format_date(days: i64) -> (String, &'static str) {
+    // Civil-from-days conversion for the proleptic Gregorian calendar
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    if year <= 0 {
+        (format!("{:04}-{:02}-{:02}", 1 - year, month, day), " BC")
+    } else {
+        (format!("{:04}-{:02}-{:02}", year, month, day), "")
+    }
+}
+
+/// Format microseconds since midnight as `HH:MM:SS[.ffffff]`
+fn format_time(micros: i64) -> String {
+    let secs = micros.div_euclid(1_000_000);
+    let fraction = micros.rem_euclid(1_000_000);
+    let time = format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+    if fraction == 0 {
+        time
+    } else {
+        format!(
+            "{}.{}",
+            time,
+            format!("{:06}", fraction).trim_end_matches('0')
+        )
+    }
+}
+
+fn format_timestamp(micros: i64) -> (String, &'static str) {
+    let (date, bc) = format_date(micros.div_euclid(MICROS_PER_DAY));
+    (
+        format!(
+            "{} {}",
+            date,
+            format_time(micros.rem_euclid(MICROS_PER_DAY))
+        ),
+        bc,
+    )
+}
+
+/// Format an interval in PostgreSQL's default output style, e.g. `1 year 2 mons 3 days 04:05:06`
+fn format_interval(months: i32, days: i32, nanos: i64) -> String {
+    let plural = |n: i32| if n.abs() == 1 { "" } else { "s" };
+    let mut parts = Vec::new();
+    let (years, months) = (months / 12, months % 12);
+    if years != 0 {
+        parts.push(format!("{} year{}", years, plural(years)));
+    }
+    if months != 0 {
+        parts.push(format!("{} mon{}", months, plural(months)));
+    }
+    if days != 0 {
+        parts.push(format!("{} day{}", days, plural(days)));
+    }
+    let micros = nanos / 1000;
+    if micros != 0 || parts.is_empty() {
+        let sign = if micros < 0 { "-" } else { "" };
+        parts.push(format!("{}{}", sign, format_time(micros.abs())));
+    }
+    parts.join(" ")
+}
+
+/// Builder for a tagged backend message
+struct Message {
+    tag: u8,
+    body: Vec<u8>,
+}
+
+impl Message {
+    fn new(tag: u8) -> Self {
+        Self {
+            tag,
+            body: Vec::new(),
+        }
+    }
+
+    fn u8(&mut self, value: u8) -> &mut Self {
+        self.body.push(value);
+        self
+    }
+
+    fn i16(&mut self, value: i16) -> &mut Self {
+        self.body.extend(value.to_be_bytes());
+        self
+    }
+
+    fn i32(&mut self, value: i32) -> &mut Self {
+        self.body.extend(value.to_be_bytes());
+        self
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.body.extend(value.to_be_bytes());
+        self
+    }
+
+    fn cstr(&mut self, value: &str) -> &mut Self {
+        self.body.extend(value.as_bytes());
+        self.body.push(0);
+        self
+    }
+
+    fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.body.extend(value);
+        self
+    }
+
+    fn write_to(&mut self, out: &mut Vec<u8>) {
+        out.push(self.tag);
+        out.extend((self.body.len() as i32 + 4).to_be_bytes());
+        out.append(&mut self.body);
+    }
+}
+
+/// Cursor over a frontend message body
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PgError> {
+        if self.buf.len() < len {
+            return Err(PgError::protocol("message is shorter than expected"));
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, PgError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16, PgError> {
+        Ok(i16::from_be_bytes(
+            self.bytes(2)?.try_into().unwrap_or_default(),
+        ))
+    }
+
+    fn i32(&mut self) -> Result<i32, PgError> {
+        Ok(i32::from_be_bytes(
+            self.bytes(4)?.try_into().unwrap_or_default(),
+        ))
+    }
+
+    fn u32(&mut self) -> Result<u32, PgError> {
+        Ok(u32::from_be_bytes(
+            self.bytes(4)?.try_into().unwrap_or_default(),
+        ))
+    }
+
+    fn i16_list(&mut self) -> Result<Vec<i16>, PgError> {
+        let count = self.i16()?;
+        (0..count).map(|_| self.i16()).collect()
+    }
+
+    fn cstr(&mut self) -> Result<String, PgError> {
+        let end = self
+            .buf
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| PgError::protocol("unterminated string in message"))?;
+        let value = String::from_utf8(self.buf[..end].to_vec())
+            .map_err(|_| PgError::protocol("invalid UTF-8 in message"))?;
+        self.buf = &self.buf[end + 1..];
+        Ok(value)
+    }
+}
@@ -0,0 +1,79 @@
+use duckdb::Connection;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::warn;
+use uuid::Uuid;
+
+pub use rsduck_types::{DuckDbProfile, QueryProfile};
+
+use crate::explain::profile_operators;
+
+/// Metrics DuckDB collects while a profiled statement runs
+const PROFILING_METRICS: &str = r#"{"LATENCY": "true", "CPU_TIME": "true", "SYSTEM_PEAK_BUFFER_MEMORY": "true", "ROWS_RETURNED": "true", "OPERATOR_TIMING": "true", "OPERATOR_CARDINALITY": "true", "OPERATOR_ROWS_SCANNED": "true", "EXTRA_INFO": "true"}"#;
+
+/// Fractional milliseconds, the unit of all profile timings
+pub fn duration_ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Enables DuckDB profiling on a pooled connection for one statement.
+/// Dropping it turns profiling off again so the next user of the connection is unaffected.
+pub struct ProfilingGuard<'a> {
+    conn: &'a Connection,
+    output: PathBuf,
+}
+
+impl<'a> ProfilingGuard<'a> {
+    /// Start profiling the next statement run on `conn`.
+    /// Must not be used on sandboxed connections, whose settings are locked.
+    pub fn start(conn: &'a Connection) -> Option<Self> {
+        let output = std::env::temp_dir().join(format!("rsduck-profile-{}.json", Uuid::new_v4()));
+        let setup = format!(
+            "SET profiling_output = '{}'; SET custom_profiling_settings = '{}'; SET enable_profiling = 'json';",
+            output.to_string_lossy().replace('\'', "''"),
+            PROFILING_METRICS
+        );
+
+        let guard = Self { conn, output };
+        match conn.execute_batch(&setup) {
+            Ok(()) => Some(guard),
+            Err(e) => {
+                warn!(error = %e, "Failed to enable DuckDB profiling");
+                None
+            }
+        }
+    }
+
+    /// Read the profile DuckDB wrote when the statement finished
+    pub fn finish(self) -> Option<DuckDbProfile> {
+        let output = std::fs::read_to_string(&self.output)
+            .map_err(|e| warn!(error = %e, "Failed to read DuckDB profile"))
+            .ok()?;
+        let profile: Value = serde_json::from_str(&output)
+            .map_err(|e| warn!(error = %e, "Failed to parse DuckDB profile"))
+            .ok()?;
+
+        let seconds = |key: &str| profile.get(key).and_then(Value::as_f64).unwrap_or(0.0);
+        Some(DuckDbProfile {
+            latency_ms: seconds("latency") * 1000.0,
+            cpu_time_ms: seconds("cpu_time") * 1000.0,
+            peak_buffer_memory_bytes: profile
+                .get("system_peak_buffer_memory")
+                .and_then(Value::as_u64)
+                .unwrap_or(0),
+            operators: profile_operators(&profile),
+        })
+    }
+}
+
+impl Drop for ProfilingGuard<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.conn.execute_batch(
+            "PRAGMA disable_profiling; RESET profiling_output; RESET custom_profiling_settings;",
+        ) {
+            warn!(error = %e, "Failed to disable DuckDB profiling");
+        }
+        let _ = std::fs::remove_file(&self.output);
+    }
+}
@@ -0,0 +1,242 @@
+use axum::response::sse::Event;
+use futures::{Stream, stream};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use utoipa::ToSchema;
+
+use crate::errors::ErrorResponse;
+use crate::{ApiError, QueryResponse};
+
+/// Header clients may use to choose a query's ID, so they can follow its progress while it runs
+pub const QUERY_ID_HEADER: &str = "x-query-id";
+
+/// Longest query ID accepted in `X-Query-Id`
+const MAX_QUERY_ID_LEN: usize = 64;
+
+/// How long finished queries stay visible to progress subscribers
+const FINISHED_RETENTION: Duration = Duration::from_secs(60);
+
+/// Interval between `progress` events
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Validate a client-chosen query ID
+pub fn parse_query_id(id: &str) -> Result<String, ApiError> {
+    let valid = (1..=MAX_QUERY_ID_LEN).contains(&id.len())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(id.to_string())
+    } else {
+        Err(ApiError::bad_request(format!(
+            "Invalid X-Query-Id header: use 1-{} letters, digits, '-', '_' or '.'",
+            MAX_QUERY_ID_LEN
+        )))
+    }
+}
+
+/// Point-in-time progress of a query, sent as a `progress` event
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProgressSnapshot {
+    /// Query being reported on
+    #[schema(example = "123e4567-e89b-12d3-a456-426614174000")]
+    pub query_id: String,
+    /// Result rows read so far
+    #[schema(example = 2048)]
+    pub rows_processed: u64,
+    /// Time since the query was received, in milliseconds
+    #[schema(example = 1500)]
+    pub elapsed_ms: u64,
+}
+
+/// Final state of a tracked query
+#[derive(Debug, Clone)]
+pub enum QueryOutcome {
+    /// The query's response with the rows removed from `data`
+    Completed(Arc<QueryResponse>),
+    /// The error body sent to the client
+    Failed(Arc<ErrorResponse>),
+}
+
+/// Progress of one query, updated while it executes and read by subscribers
+#[derive(Debug)]
+pub struct QueryProgress {
+    query_id: String,
+    started: Instant,
+    rows_processed: AtomicU64,
+    outcome: watch::Sender<Option<QueryOutcome>>,
+    finished_at: OnceLock<Instant>,
+}
+
+impl QueryProgress {
+    fn new(query_id: &str) -> Self {
+        Self {
+            query_id: query_id.to_string(),
+            started: Instant::now(),
+            rows_processed: AtomicU64::new(0),
+            outcome: watch::Sender::new(None),
+            finished_at: OnceLock::new(),
+        }
+    }
+
+    /// Count rows read from the result
+    pub fn add_rows(&self, rows: u64) {
+        self.rows_processed.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    /// Current progress of the query
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        ProgressSnapshot {
+            query_id: self.query_id.clone(),
+            rows_processed: self.rows_processed.load(Ordering::Relaxed),
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+        }
+    }
+
+    /// Record the outcome; only the first call has an effect
+    fn finish(&self, outcome: QueryOutcome) {
+        let first = self.outcome.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(outcome);
+            true
+        });
+        if first {
+            let _ = self.finished_at.set(Instant::now());
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.finished_at
+            .get()
+            .is_some_and(|at| at.elapsed() > FINISHED_RETENTION)
+    }
+}
+
+/// Registration of a running query.
+/// Dropping it before the query finished reports the query as cancelled.
+#[derive(Debug)]
+pub struct TrackedQuery {
+    progress: Arc<QueryProgress>,
+}
+
+impl TrackedQuery {
+    /// Shared progress handle for the executing task
+    pub fn progress(&self) -> &Arc<QueryProgress> {
+        &self.progress
+    }
+
+    /// Record a successful response; subscribers receive it without its rows
+    pub fn complete(self, response: &QueryResponse) {
+        let mut data = response.data.clone();
+        if let Some(serde_json::Value::Object(fields)) = &mut data {
+            fields.remove("rows");
+        }
+        self.progress
+            .finish(QueryOutcome::Completed(Arc::new(QueryResponse {
+                success: response.success,
+                data,
+                error: response.error.clone(),
+                query_id: response.query_id.clone(),
+                execution_time_ms: response.execution_time_ms,
+                profile: response.profile.clone(),
+            })));
+    }
+
+    /// Record the error returned to the client
+    pub fn fail(self, error: ErrorResponse) {
+        self.progress.finish(QueryOutcome::Failed(Arc::new(error)));
+    }
+}
+
+impl Drop for TrackedQuery {
+    fn drop(&mut self) {
+        let error = ApiError::cancelled("Client disconnected before the query completed");
+        let response = error.to_error_response(Some(self.progress.query_id.clone()));
+        self.progress
+            .finish(QueryOutcome::Failed(Arc::new(response)));
+    }
+}
+
+/// Running and recently finished queries, keyed by query ID
+#[derive(Debug, Default)]
+pub struct QueryRegistry {
+    queries: Mutex<HashMap<String, Arc<QueryProgress>>>,
+}
+
+impl QueryRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking a query. Returns `None` when the ID belongs to a query that
+    /// is still running or finished too recently to be forgotten.
+    pub fn start(&self, query_id: &str) -> Option<TrackedQuery> {
+        let mut queries = self.queries.lock().unwrap_or_else(|e| e.into_inner());
+        queries.retain(|_, progress| !progress.is_expired());
+        if queries.contains_key(query_id) {
+            return None;
+        }
+
+        let progress = Arc::new(QueryProgress::new(query_id));
+        queries.insert(query_id.to_string(), progress.clone());
+        Some(TrackedQuery { progress })
+    }
+
+    /// Progress of a running or recently finished query
+    pub fn get(&self, query_id: &str) -> Option<Arc<QueryProgress>> {
+        let queries = self.queries.lock().unwrap_or_else(|e| e.into_inner());
+        queries
+            .get(query_id)
+            .filter(|progress| !progress.is_expired())
+            .cloned()
+    }
+}
+
+/// Server-Sent Events for a query: `progress` events until it finishes, then a
+/// single `result` or `error` event
+pub fn progress_events(
+    progress: Arc<QueryProgress>,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let outcome = progress.outcome.subscribe();
+    let interval = tokio::time::interval(PROGRESS_INTERVAL);
+
+    stream::unfold(Some((progress, outcome, interval)), |state| async move {
+        let (progress, mut outcome, mut interval) = state?;
+        if outcome.borrow_and_update().is_none() {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = outcome.changed() => {}
+            }
+        }
+
+        let finished = outcome.borrow_and_update().clone();
+        match finished {
+            Some(result) => Some((Ok(outcome_event(result)), None)),
+            None => {
+                let event = Event::default()
+                    .event("progress")
+                    .json_data(progress.snapshot())
+                    .unwrap_or_default();
+                Some((Ok(event), Some((progress, outcome, interval))))
+            }
+        }
+    })
+}
+
+fn outcome_event(outcome: QueryOutcome) -> Event {
+    let event = match outcome {
+        QueryOutcome::Completed(response) => Event::default()
+            .event("result")
+            .json_data(response.as_ref()),
+        QueryOutcome::Failed(error) => Event::default().event("error").json_data(error.as_ref()),
+    };
+    event.unwrap_or_default()
+}
@@ -0,0 +1,237 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{Extensions, HeaderMap},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ring::hmac;
+use ring::rand::SystemRandom;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::warn;
+
+use crate::{ApiError, AppState, ClientIdentity, Metrics};
+
+/// Header clients use to identify themselves with an API key
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Number of tracked clients above which idle entries are pruned
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Per-client rate limiting configuration
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// Sustained requests per second; 0 disables the token bucket
+    pub requests_per_second: f64,
+    /// Maximum number of requests a client can burst above the sustained rate
+    pub burst: u32,
+    /// Maximum concurrent requests per client; 0 disables the limit
+    pub max_concurrent: usize,
+}
+
+impl RateLimitConfig {
+    /// Whether any limit is configured
+    pub fn is_enabled(&self) -> bool {
+        self.requests_per_second > 0.0 || self.max_concurrent > 0
+    }
+}
+
+#[derive(Debug)]
+struct ClientState {
+    tokens: f64,
+    last_refill: Instant,
+    in_flight: usize,
+}
+
+/// Token-bucket rate limiter and concurrency quota keyed by client identity
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    clients: Mutex<HashMap<String, ClientState>>,
+}
+
+/// Reason a request was rejected by the rate limiter
+#[derive(Debug, PartialEq)]
+pub enum Rejection {
+    /// The client's token bucket is empty
+    RateLimited { retry_after_secs: u64 },
+    /// The client already has `max_concurrent` requests in flight
+    TooManyConcurrent,
+}
+
+impl RateLimiter {
+    /// Create a new rate limiter
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Try to admit a request for `client`, reserving a concurrency slot on success.
+    /// Every successful call must be paired with [`RateLimiter::release`].
+    pub fn acquire(&self, client: &str) -> Result<(), Rejection> {
+        let now = Instant::now();
+        let burst = f64::from(self.config.burst.max(1));
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+
+        if clients.len() > PRUNE_THRESHOLD {
+            let rps = self.config.requests_per_second;
+            clients.retain(|_, state| {
+                let refilled =
+                    state.tokens + now.duration_since(state.last_refill).as_secs_f64() * rps;
+                state.in_flight > 0 || refilled < burst
+            });
+        }
+
+        let state = clients.entry(client.to_string()).or_insert(ClientState {
+            tokens: burst,
+            last_refill: now,
+            in_flight: 0,
+        });
+
+        if self.config.max_concurrent > 0 && state.in_flight >= self.config.max_concurrent {
+            return Err(Rejection::TooManyConcurrent);
+        }
+
+        if self.config.requests_per_second > 0.0 {
+            let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+            state.tokens = (state.tokens + elapsed * self.config.requests_per_second).min(burst);
+            state.last_refill = now;
+
+            if state.tokens < 1.0 {
+                let wait = (1.0 - state.tokens) / self.config.requests_per_second;
+                return Err(Rejection::RateLimited {
+                    retry_after_secs: wait.ceil().max(1.0) as u64,
+                });
+            }
+            state.tokens -= 1.0;
+        }
+
+        state.in_flight += 1;
+        Ok(())
+    }
+
+    /// Release a concurrency slot previously reserved with [`RateLimiter::acquire`]
+    pub fn release(&self, client: &str) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(state) = clients.get_mut(client) {
+            state.in_flight = state.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+/// Releases a client's concurrency slot when the request completes or is dropped
+struct SlotGuard {
+    limiter: Arc<RateLimiter>,
+    metrics: Arc<Metrics>,
+    client: String,
+}
+
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        self.limiter.release(&self.client);
+        Metrics::decr(&self.metrics.client_requests_in_flight);
+    }
+}
+
+/// Identify the client by API key when it sends one the server accepts, otherwise by source IP.
+/// Unverified keys are ignored, so inventing a new key per request does not buy a fresh quota.
+/// API keys are hashed so raw secrets never end up in logs or limiter state.
+pub fn client_identity(
+    state: &AppState,
+    headers: &HeaderMap,
+    remote_addr: Option<SocketAddr>,
+) -> String {
+    let key = headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok());
+    if let Some(key) = key
+        && (state.api_keys.verify(key) || state.saved_query_keys.verify(key))
+    {
+        return key_identity(state, key);
+    }
+
+    match remote_addr {
+        Some(addr) => format!("ip:{}", addr.ip()),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+/// Secret that API keys are hashed with to derive client identities, so a short key cannot be
+/// recovered from its identity by hashing candidates offline
+#[derive(Clone)]
+pub struct IdentitySecret(hmac::Key);
+
+impl IdentitySecret {
+    /// Use a configured secret, so identities stay the same across restarts and between
+    /// servers that share it
+    pub fn new(secret: &[u8]) -> Self {
+        Self(hmac::Key::new(hmac::HMAC_SHA256, secret))
+    }
+
+    /// Generate a secret for this process; identities change when the server restarts
+    pub fn random() -> Self {
+        let key = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+            .expect("system random number generator failed");
+        Self(key)
+    }
+}
+
+/// Client identity of an API key; the key itself is never used as an identity
+pub fn key_identity(state: &AppState, key: &str) -> String {
+    let tag = hmac::sign(&state.identity_secret.0, key.as_bytes());
+    let hex: String = tag.as_ref()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("key:{}", hex)
+}
+
+/// Client identity of a request, from its headers and the connection info in its extensions.
+/// Clients with a verified certificate are identified by its name.
+pub fn request_client(state: &AppState, headers: &HeaderMap, extensions: &Extensions) -> String {
+    if let Some(identity) = extensions.get::<ClientIdentity>() {
+        return format!("cert:{}", identity.name);
+    }
+    let remote_addr = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr);
+    client_identity(state, headers, remote_addr)
+}
+
+/// Middleware enforcing per-client rate limits and concurrency quotas
+pub async fn rate_limit_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(limiter) = state.rate_limiter.clone() else {
+        return next.run(request).await;
+    };
+
+    let client = request_client(&state, request.headers(), request.extensions());
+
+    match limiter.acquire(&client) {
+        Ok(()) => {
+            Metrics::incr(&state.metrics.client_requests_in_flight);
+            let _guard = SlotGuard {
+                limiter,
+                metrics: state.metrics.clone(),
+                client,
+            };
+            next.run(request).await
+        }
+        Err(Rejection::RateLimited { retry_after_secs }) => {
+            warn!(client = %client, retry_after_secs, "Request rate limited");
+            Metrics::incr(&state.metrics.rate_limited_total);
+            ApiError::too_many_requests("Rate limit exceeded", retry_after_secs).into_response()
+        }
+        Err(Rejection::TooManyConcurrent) => {
+            warn!(client = %client, "Concurrent query limit reached");
+            Metrics::incr(&state.metrics.concurrency_limited_total);
+            ApiError::too_many_requests("Too many concurrent queries for this client", 1)
+                .into_response()
+        }
+    }
+}
@@ -0,0 +1,459 @@
+use axum::{
+    Router, middleware,
+    routing::{get, post},
+};
+use std::collections::HashSet;
+use std::sync::Arc;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{
+    AppState, Args, AuditStatus, ConnectionHook, DuckDbConnectionManager, ExplainFormat,
+    ExplainRequest, ExplainResponse, FingerprintStats, HealthResponse, HistoryEntry,
+    HistoryResponse, LivenessResponse, ParamDecl, ParamType, PlanNode, PoolStats, ProgressSnapshot,
+    QueryParams, QueryRequest, QueryResponse, ReadinessResponse, SavedQuery, ScriptRequest,
+    ScriptResponse, StatementResult, StreamEvent, admin_auth_middleware, auth_middleware, cursor,
+    errors::verbose_errors_middleware, execute_command_get, execute_command_post,
+    execute_query_get, execute_query_post, explain, get_metrics, health, health_check, history,
+    query_progress, rate_limit_middleware, saved, saved_query_auth_middleware, script, stream, ws,
+};
+
+/// OpenAPI document covering every endpoint
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        crate::handlers::health_check,
+        crate::health::liveness,
+        crate::health::readiness,
+        crate::handlers::get_metrics,
+        crate::handlers::execute_query_post,
+        crate::handlers::execute_query_get,
+        crate::stream::stream_query,
+        crate::handlers::execute_command_post,
+        crate::handlers::execute_command_get,
+        crate::handlers::query_progress,
+        crate::explain::explain_query,
+        crate::script::run_sql_script,
+        crate::cursor::fetch_cursor,
+        crate::cursor::close_cursor,
+        crate::saved::list_saved_queries,
+        crate::saved::create_saved_query,
+        crate::saved::get_saved_query,
+        crate::saved::put_saved_query,
+        crate::saved::delete_saved_query,
+        crate::saved::run_saved_query_get,
+        crate::saved::run_saved_query_post,
+        crate::ws::ws_session,
+        crate::history::query_history
+    ),
+    components(
+        schemas(
+            QueryRequest,
+            QueryResponse,
+            HealthResponse,
+            LivenessResponse,
+            ReadinessResponse,
+            PoolStats,
+            QueryParams,
+            ProgressSnapshot,
+            ExplainRequest,
+            ExplainResponse,
+            ExplainFormat,
+            PlanNode,
+            SavedQuery,
+            ParamDecl,
+            ParamType,
+            StreamEvent,
+            HistoryEntry,
+            HistoryResponse,
+            ScriptRequest,
+            ScriptResponse,
+            StatementResult,
+            FingerprintStats,
+            AuditStatus
+        )
+    ),
+    tags(
+        (name = "health", description = "Health check endpoints"),
+        (name = "query", description = "SQL query execution endpoints"),
+        (name = "execute", description = "SQL command execution endpoints"),
+        (name = "saved", description = "Saved parameterized queries"),
+        (name = "admin", description = "Server administration")
+    ),
+    info(
+        title = "RSDuck - DuckDB REST API",
+        description = "A REST API for executing SQL queries and commands against DuckDB",
+        version = "1.0.0",
+        contact(
+            name = "RSDuck API",
+            url = "https://github.com/your-org/rsduck"
+        )
+    )
+)]
+pub struct ApiDoc;
+
+/// Groups of endpoints that can be enabled or disabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    /// `GET /health`, `/health/live` and `/health/ready`
+    Health,
+    /// `GET /metrics`
+    Metrics,
+    /// `GET` and `POST /query`
+    Query,
+    /// `POST /query/stream`
+    Stream,
+    /// `GET` and `POST /execute`
+    Execute,
+    /// `POST /explain`
+    Explain,
+    /// `GET` and `DELETE /cursors/{token}`, the pages of paginated queries
+    Cursors,
+    /// `GET /ws`
+    WebSocket,
+    /// `/saved-queries` management and `/q/{name}`
+    SavedQueries,
+    /// `GET /queries/{id}/progress`
+    Progress,
+    /// `GET /admin/history`
+    History,
+    /// `POST /sql`
+    Sql,
+}
+
+impl Endpoint {
+    /// Every endpoint group
+    pub const ALL: [Endpoint; 12] = [
+        Endpoint::Health,
+        Endpoint::Metrics,
+        Endpoint::Query,
+        Endpoint::Stream,
+        Endpoint::Execute,
+        Endpoint::Explain,
+        Endpoint::Cursors,
+        Endpoint::WebSocket,
+        Endpoint::SavedQueries,
+        Endpoint::Progress,
+        Endpoint::History,
+        Endpoint::Sql,
+    ];
+
+    /// Paths served by this group, relative to the prefix
+    pub fn paths(&self) -> &'static [&'static str] {
+        match self {
+            Endpoint::Health => &["/health", "/health/live", "/health/ready"],
+            Endpoint::Metrics => &["/metrics"],
+            Endpoint::Query => &["/query"],
+            Endpoint::Stream => &["/query/stream"],
+            Endpoint::Execute => &["/execute"],
+            Endpoint::Explain => &["/explain"],
+            Endpoint::Cursors => &["/cursors/{token}"],
+            Endpoint::WebSocket => &["/ws"],
+            Endpoint::SavedQueries => &["/saved-queries", "/saved-queries/{name}", "/q/{name}"],
+            Endpoint::Progress => &["/queries/{id}/progress"],
+            Endpoint::History => &["/admin/history"],
+            Endpoint::Sql => &["/sql"],
+        }
+    }
+}
+
+/// Transforms the router before it is mounted, e.g. to add a layer
+pub type RouterHook = Box<dyn FnOnce(Router<AppState>) -> Router<AppState> + Send>;
+
+/// How the rsduck router is assembled
+pub struct RouterOptions {
+    /// Path the routes are mounted under, e.g. `/analytics`; empty mounts them at the root
+    pub prefix: String,
+    /// Endpoint groups to serve
+    pub endpoints: HashSet<Endpoint>,
+    /// Serve Swagger UI at `{prefix}/swagger-ui` and the OpenAPI document at
+    /// `{prefix}/api-docs/openapi.json`
+    pub swagger_ui: bool,
+    /// Documents merged into the served OpenAPI document
+    pub openapi: Vec<utoipa::openapi::OpenApi>,
+    /// Applied in order to the rsduck routes, outside rsduck's own authentication and rate
+    /// limiting
+    pub middleware: Vec<RouterHook>,
+}
+
+impl Default for RouterOptions {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            endpoints: Endpoint::ALL.into_iter().collect(),
+            swagger_ui: true,
+            openapi: Vec::new(),
+            middleware: Vec::new(),
+        }
+    }
+}
+
+impl RouterOptions {
+    /// The prefix with a leading slash and no trailing slash, or empty
+    fn normalized_prefix(&self) -> String {
+        let prefix = self.prefix.trim_matches('/');
+        if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", prefix)
+        }
+    }
+}
+
+/// OpenAPI document for the enabled endpoints, with paths under the prefix and the documents in
+/// `options.openapi` merged in
+pub fn openapi_doc(options: &RouterOptions) -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    let prefix = options.normalized_prefix();
+    let enabled: HashSet<&str> = options
+        .endpoints
+        .iter()
+        .flat_map(|endpoint| endpoint.paths().iter().copied())
+        .collect();
+    doc.paths.paths = std::mem::take(&mut doc.paths.paths)
+        .into_iter()
+        .filter(|(path, _)| enabled.contains(path.as_str()))
+        .map(|(path, item)| (format!("{}{}", prefix, path), item))
+        .collect();
+
+    for other in &options.openapi {
+        doc.merge(other.clone());
+    }
+    doc
+}
+
+/// Build the rsduck HTTP router for `state`
+pub fn router(state: AppState, options: RouterOptions) -> Router {
+    let enabled = |endpoint| options.endpoints.contains(&endpoint);
+    let doc = options.swagger_ui.then(|| openapi_doc(&options));
+    let prefix = options.normalized_prefix();
+
+    let mut sql_routes = Router::new();
+    if enabled(Endpoint::Query) {
+        sql_routes = sql_routes.route("/query", post(execute_query_post).get(execute_query_get));
+    }
+    if enabled(Endpoint::Stream) {
+        sql_routes = sql_routes.route("/query/stream", post(stream::stream_query));
+    }
+    if enabled(Endpoint::Execute) {
+        sql_routes = sql_routes.route(
+            "/execute",
+            post(execute_command_post).get(execute_command_get),
+        );
+    }
+    if enabled(Endpoint::Explain) {
+        sql_routes = sql_routes.route("/explain", post(explain::explain_query));
+    }
+    if enabled(Endpoint::Sql) {
+        sql_routes = sql_routes.route("/sql", post(script::run_sql_script));
+    }
+    if enabled(Endpoint::Cursors) {
+        sql_routes = sql_routes.route(
+            "/cursors/{token}",
+            get(cursor::fetch_cursor).delete(cursor::close_cursor),
+        );
+    }
+    if enabled(Endpoint::WebSocket) {
+        sql_routes = sql_routes.route("/ws", get(ws::ws_session));
+    }
+    if enabled(Endpoint::SavedQueries) {
+        sql_routes = sql_routes
+            .route(
+                "/saved-queries",
+                get(saved::list_saved_queries).post(saved::create_saved_query),
+            )
+            .route(
+                "/saved-queries/{name}",
+                get(saved::get_saved_query)
+                    .put(saved::put_saved_query)
+                    .delete(saved::delete_saved_query),
+            );
+    }
+    // Route layers need at least one route
+    if sql_routes.has_routes() {
+        sql_routes = sql_routes
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                auth_middleware,
+            ))
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                rate_limit_middleware,
+            ));
+    }
+
+    let mut app = Router::new();
+    if enabled(Endpoint::Health) {
+        app = app
+            .route("/health", get(health_check))
+            .route("/health/live", get(health::liveness))
+            .route("/health/ready", get(health::readiness));
+    }
+    if enabled(Endpoint::Metrics) {
+        app = app.route("/metrics", get(get_metrics));
+    }
+    app = app.merge(sql_routes);
+
+    // Saved queries also accept keys restricted to running them
+    if enabled(Endpoint::SavedQueries) {
+        let saved_query_routes = Router::new()
+            .route(
+                "/q/{name}",
+                get(saved::run_saved_query_get).post(saved::run_saved_query_post),
+            )
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                saved_query_auth_middleware,
+            ))
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                rate_limit_middleware,
+            ));
+        app = app.merge(saved_query_routes);
+    }
+
+    // The history shows every client's statements, so it needs an admin key
+    if enabled(Endpoint::History) {
+        let admin_routes = Router::new()
+            .route("/admin/history", get(history::query_history))
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                admin_auth_middleware,
+            ))
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                rate_limit_middleware,
+            ));
+        app = app.merge(admin_routes);
+    }
+
+    // Progress streams only need authentication; rate limiting them would let a
+    // watcher use up the concurrency slot of the query it is watching
+    if enabled(Endpoint::Progress) {
+        let progress_routes = Router::new()
+            .route("/queries/{id}/progress", get(query_progress))
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                auth_middleware,
+            ));
+        app = app.merge(progress_routes);
+    }
+
+    if state.verbose_errors {
+        app = app.layer(middleware::from_fn(verbose_errors_middleware));
+    }
+    for hook in options.middleware {
+        app = hook(app);
+    }
+    if !prefix.is_empty() {
+        app = Router::new().nest(&prefix, app);
+    }
+
+    let mut app = app.with_state(state);
+    if let Some(doc) = doc {
+        app = app.merge(
+            SwaggerUi::new(format!("{}/swagger-ui", prefix))
+                .url(format!("{}/api-docs/openapi.json", prefix), doc),
+        );
+    }
+    app
+}
+
+/// Builds the application state and router for embedding rsduck in another axum application
+pub struct RsduckBuilder {
+    args: Args,
+    manager: Option<DuckDbConnectionManager>,
+    hooks: Vec<Arc<dyn ConnectionHook>>,
+    options: RouterOptions,
+}
+
+impl RsduckBuilder {
+    /// Start from server arguments, e.g. `Args::parse_from(["rsduck", "--readwrite"])`
+    pub fn new(args: Args) -> Self {
+        Self {
+            args,
+            manager: None,
+            hooks: Vec::new(),
+            options: RouterOptions::default(),
+        }
+    }
+
+    /// Mount the routes under `prefix`, e.g. `/analytics`
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.options.prefix = prefix.into();
+        self
+    }
+
+    /// Serve only these endpoint groups
+    pub fn with_endpoints(mut self, endpoints: impl IntoIterator<Item = Endpoint>) -> Self {
+        self.options.endpoints = endpoints.into_iter().collect();
+        self
+    }
+
+    /// Stop serving an endpoint group
+    pub fn without_endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.options.endpoints.remove(&endpoint);
+        self
+    }
+
+    /// Serve Swagger UI and the OpenAPI document (enabled by default)
+    pub fn with_swagger_ui(mut self, enabled: bool) -> Self {
+        self.options.swagger_ui = enabled;
+        self
+    }
+
+    /// Merge another OpenAPI document into the served one
+    pub fn with_openapi(mut self, doc: utoipa::openapi::OpenApi) -> Self {
+        self.options.openapi.push(doc);
+        self
+    }
+
+    /// Transform the rsduck routes before they are mounted, e.g. to add a layer. Hooks run in
+    /// the order they are added, outside rsduck's own authentication and rate limiting.
+    pub fn with_middleware(
+        mut self,
+        hook: impl FnOnce(Router<AppState>) -> Router<AppState> + Send + 'static,
+    ) -> Self {
+        self.options.middleware.push(Box::new(hook));
+        self
+    }
+
+    /// Open connections with `manager` instead of from the database arguments
+    pub fn with_connection_manager(mut self, manager: DuckDbConnectionManager) -> Self {
+        self.manager = Some(manager);
+        self
+    }
+
+    /// Serve an existing database handle; every pooled connection is a clone of `conn`
+    pub fn with_connection(mut self, conn: duckdb::Connection) -> Self {
+        self.manager = Some(DuckDbConnectionManager::from_args_and_connection(
+            &self.args, conn,
+        ));
+        self
+    }
+
+    /// Run `hook` on every pooled connection, e.g. to register scalar and table functions
+    pub fn with_on_connect(mut self, hook: impl ConnectionHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Build the router
+    pub fn build(self) -> anyhow::Result<Router> {
+        Ok(self.build_with_state()?.0)
+    }
+
+    /// Build the router and return the state it serves, e.g. to run the PostgreSQL or Flight SQL
+    /// listeners against the same pools
+    pub fn build_with_state(self) -> anyhow::Result<(Router, AppState)> {
+        let mut manager = self
+            .manager
+            .unwrap_or_else(|| DuckDbConnectionManager::from_args(&self.args));
+        for hook in self.hooks {
+            manager =
+                manager.with_on_connect(move |conn: &duckdb::Connection| hook.on_connect(conn));
+        }
+        let state = AppState::with_connection_manager(&self.args, manager)?;
+        Ok((router(state.clone(), self.options), state))
+    }
+}
@@ -0,0 +1,917 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{Extensions, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, instrument, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::database::{
+    BindParams, execute_sql_with_params, is_write_operation, split_statements,
+    validate_readonly_operation, validate_sandbox_operation,
+};
+use crate::handlers::{request_priority, set_cache_headers};
+use crate::{ApiError, AppState, Audit, AuditSource, CacheFormat, CacheKey, QueryResponse};
+
+/// File extension of saved query files
+const SAVED_QUERY_EXTENSION: &str = "sql";
+
+/// Type of a saved query parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    /// Any text
+    String,
+    /// 64-bit signed integer
+    Integer,
+    /// Double-precision number
+    Float,
+    /// `true` or `false`
+    Boolean,
+    /// ISO 8601 date, e.g. `2024-01-31`
+    Date,
+    /// ISO 8601 timestamp, e.g. `2024-01-31T12:00:00` or `2024-01-31 12:00:00+02:00`
+    Timestamp,
+}
+
+impl ParamType {
+    /// Name of the type as written in front-matter
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParamType::String => "string",
+            ParamType::Integer => "integer",
+            ParamType::Float => "float",
+            ParamType::Boolean => "boolean",
+            ParamType::Date => "date",
+            ParamType::Timestamp => "timestamp",
+        }
+    }
+
+    /// Check a JSON value or query-string text against the type and return its canonical form
+    pub fn coerce(self, value: &Value) -> Result<Value, String> {
+        let text = match value {
+            Value::String(s) => Some(s.trim()),
+            _ => None,
+        };
+        let coerced = match (self, value) {
+            (ParamType::String, Value::String(_)) => Some(value.clone()),
+            (ParamType::String, Value::Number(_) | Value::Bool(_)) => {
+                Some(Value::from(value.to_string()))
+            }
+            (ParamType::Integer, Value::Number(n)) => n.as_i64().map(Value::from),
+            (ParamType::Integer, _) => text.and_then(|s| s.parse::<i64>().ok()).map(Value::from),
+            (ParamType::Float, Value::Number(n)) => n.as_f64().map(Value::from),
+            (ParamType::Float, _) => text
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|f| f.is_finite())
+                .map(Value::from),
+            (ParamType::Boolean, Value::Bool(_)) => Some(value.clone()),
+            (ParamType::Boolean, _) => match text.map(str::to_ascii_lowercase).as_deref() {
+                Some("true" | "1") => Some(Value::Bool(true)),
+                Some("false" | "0") => Some(Value::Bool(false)),
+                _ => None,
+            },
+            (ParamType::Date, _) => text
+                .filter(|s| Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap().is_match(s))
+                .map(Value::from),
+            (ParamType::Timestamp, _) => text
+                .filter(|s| {
+                    Regex::new(
+                        r"^\d{4}-\d{2}-\d{2}([T ]\d{2}:\d{2}(:\d{2}(\.\d{1,9})?)?(Z|[+-]\d{2}(:?\d{2})?)?)?$",
+                    )
+                    .unwrap()
+                    .is_match(s)
+                })
+                .map(Value::from),
+            _ => None,
+        };
+        coerced.ok_or_else(|| format!("expected {}, got {}", self.as_str(), value))
+    }
+
+    /// DuckDB value for a canonical JSON value; dates and timestamps are bound as text and cast by DuckDB
+    fn to_duckdb(self, value: &Value) -> duckdb::types::Value {
+        use duckdb::types::Value as DuckValue;
+        match (self, value) {
+            (_, Value::Null) => DuckValue::Null,
+            (ParamType::Integer, Value::Number(n)) => {
+                n.as_i64().map(DuckValue::BigInt).unwrap_or(DuckValue::Null)
+            }
+            (ParamType::Float, Value::Number(n)) => {
+                n.as_f64().map(DuckValue::Double).unwrap_or(DuckValue::Null)
+            }
+            (ParamType::Boolean, Value::Bool(b)) => DuckValue::Boolean(*b),
+            (_, Value::String(s)) => DuckValue::Text(s.clone()),
+            (_, other) => DuckValue::Text(other.to_string()),
+        }
+    }
+}
+
+impl FromStr for ParamType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "string" => Ok(ParamType::String),
+            "integer" => Ok(ParamType::Integer),
+            "float" => Ok(ParamType::Float),
+            "boolean" => Ok(ParamType::Boolean),
+            "date" => Ok(ParamType::Date),
+            "timestamp" => Ok(ParamType::Timestamp),
+            other => Err(format!(
+                "Unknown parameter type '{}'; use string, integer, float, boolean, date or timestamp",
+                other
+            )),
+        }
+    }
+}
+
+/// A declared parameter of a saved query, referenced in its SQL as `$name`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ParamDecl {
+    /// Parameter name
+    #[schema(example = "region")]
+    pub name: String,
+    /// Parameter type
+    #[serde(rename = "type")]
+    pub param_type: ParamType,
+    /// Value used when the caller omits the parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+    /// Whether the parameter may be omitted without a default, binding NULL
+    #[serde(default)]
+    pub optional: bool,
+}
+
+impl ParamDecl {
+    /// Whether callers must supply the parameter
+    pub fn is_required(&self) -> bool {
+        self.default.is_none() && !self.optional
+    }
+}
+
+/// A vetted, parameterized query exposed as `/q/{name}`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SavedQuery {
+    /// Name used in `/q/{name}`; letters, digits, `_` and `-`
+    #[serde(default)]
+    #[schema(example = "sales_by_region")]
+    pub name: String,
+    /// What the query returns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "Total sales per region since a date")]
+    pub description: Option<String>,
+    /// A single SQL statement referencing its parameters as `$name`
+    #[schema(
+        example = "SELECT region, sum(amount) AS total FROM sales WHERE sold_at >= $since GROUP BY region"
+    )]
+    pub sql: String,
+    /// Declared parameters
+    #[serde(default)]
+    pub params: Vec<ParamDecl>,
+    /// Maximum number of rows to return (clamped to the server maximum)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = 100)]
+    pub limit: Option<usize>,
+    /// Cache results for this many seconds instead of the server's cache TTL; 0 disables caching
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = 60)]
+    pub cache_ttl_secs: Option<u64>,
+}
+
+impl SavedQuery {
+    /// Parse a saved query file: leading `-- key: value` comment lines followed by the SQL.
+    /// Recognized keys are `description`, `limit`, `cache_ttl_secs` and `param`, the latter as
+    /// `<name> <type>`, optionally followed by `optional` or `= <default>`.
+    pub fn parse(name: &str, contents: &str) -> Result<Self, String> {
+        let mut query = SavedQuery {
+            name: name.to_string(),
+            description: None,
+            sql: String::new(),
+            params: Vec::new(),
+            limit: None,
+            cache_ttl_secs: None,
+        };
+
+        let mut lines = contents.lines().peekable();
+        while let Some(line) = lines.peek() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                lines.next();
+                continue;
+            }
+            let Some((key, value)) = trimmed
+                .strip_prefix("--")
+                .and_then(|comment| comment.split_once(':'))
+                .map(|(key, value)| (key.trim(), value.trim()))
+            else {
+                break;
+            };
+            match key {
+                "description" => query.description = Some(value.to_string()),
+                "limit" => {
+                    query.limit = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid limit '{}'", value))?,
+                    )
+                }
+                "cache_ttl_secs" => {
+                    query.cache_ttl_secs = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid cache_ttl_secs '{}'", value))?,
+                    )
+                }
+                "param" => query.params.push(parse_param(value)?),
+                // Any other comment starts the SQL
+                _ => break,
+            }
+            lines.next();
+        }
+
+        query.sql = lines.collect::<Vec<_>>().join("\n").trim().to_string();
+        Ok(query)
+    }
+
+    /// Render the query in the file format read by [`SavedQuery::parse`]
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        if let Some(description) = &self.description {
+            out.push_str(&format!("-- description: {}\n", description));
+        }
+        if let Some(limit) = self.limit {
+            out.push_str(&format!("-- limit: {}\n", limit));
+        }
+        if let Some(ttl) = self.cache_ttl_secs {
+            out.push_str(&format!("-- cache_ttl_secs: {}\n", ttl));
+        }
+        for param in &self.params {
+            out.push_str(&format!(
+                "-- param: {} {}",
+                param.name,
+                param.param_type.as_str()
+            ));
+            if let Some(default) = &param.default {
+                out.push_str(&format!(" = {}", default));
+            } else if param.optional {
+                out.push_str(" optional");
+            }
+            out.push('\n');
+        }
+        out.push_str(&self.sql);
+        out.push('\n');
+        out
+    }
+
+    /// Check the definition: name, a single statement, and parameters matching the `$name` references
+    pub fn validate(&mut self) -> Result<(), String> {
+        if !Regex::new(r"^[A-Za-z0-9_-]{1,64}$")
+            .unwrap()
+            .is_match(&self.name)
+        {
+            return Err(format!(
+                "Invalid saved query name '{}'; use up to 64 letters, digits, '_' or '-'",
+                self.name
+            ));
+        }
+        if self
+            .description
+            .as_deref()
+            .is_some_and(|d| d.contains('\n'))
+        {
+            return Err("description must be a single line".to_string());
+        }
+        if split_statements(&self.sql).len() != 1 {
+            return Err("A saved query must contain exactly one SQL statement".to_string());
+        }
+        if self.limit == Some(0) {
+            return Err("limit must be at least 1".to_string());
+        }
+
+        let identifier = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+        let mut declared = HashSet::new();
+        for param in &mut self.params {
+            if !identifier.is_match(&param.name) {
+                return Err(format!("Invalid parameter name '{}'", param.name));
+            }
+            if !declared.insert(param.name.clone()) {
+                return Err(format!("Parameter '{}' is declared twice", param.name));
+            }
+            if let Some(default) = &param.default
+                && !default.is_null()
+            {
+                let coerced = param.param_type.coerce(default).map_err(|e| {
+                    format!("Invalid default for parameter '{}': {}", param.name, e)
+                })?;
+                param.default = Some(coerced);
+            }
+        }
+
+        let referenced = placeholders(&self.sql);
+        if let Some(missing) = referenced.iter().find(|name| !declared.contains(*name)) {
+            return Err(format!(
+                "SQL references ${} but no parameter '{}' is declared",
+                missing, missing
+            ));
+        }
+        if let Some(unused) = self.params.iter().find(|p| !referenced.contains(&p.name)) {
+            return Err(format!(
+                "Parameter '{}' is declared but not referenced as ${}",
+                unused.name, unused.name
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validate caller-supplied arguments against the declarations, filling in defaults.
+    /// Returns the canonical values, which also identify the result in the cache.
+    pub fn bind(&self, args: &Map<String, Value>) -> Result<BTreeMap<String, Value>, ApiError> {
+        if let Some(unknown) = args
+            .keys()
+            .find(|name| !self.params.iter().any(|p| &p.name == *name))
+        {
+            return Err(ApiError::bad_request(format!(
+                "Unknown parameter '{}' for saved query '{}'",
+                unknown, self.name
+            )));
+        }
+
+        let mut values = BTreeMap::new();
+        for param in &self.params {
+            let value = match args.get(&param.name) {
+                Some(Value::Null) if param.is_required() => {
+                    return Err(ApiError::bad_request(format!(
+                        "Parameter '{}' must not be null",
+                        param.name
+                    )));
+                }
+                Some(Value::Null) => Value::Null,
+                Some(value) => param.param_type.coerce(value).map_err(|e| {
+                    ApiError::bad_request(format!("Invalid parameter '{}': {}", param.name, e))
+                })?,
+                None => match &param.default {
+                    Some(default) => default.clone(),
+                    None if param.optional => Value::Null,
+                    None => {
+                        return Err(ApiError::bad_request(format!(
+                            "Missing required parameter '{}'",
+                            param.name
+                        )));
+                    }
+                },
+            };
+            values.insert(param.name.clone(), value);
+        }
+        Ok(values)
+    }
+
+    fn named_params(&self, values: &BTreeMap<String, Value>) -> BindParams {
+        BindParams::Named(
+            self.params
+                .iter()
+                .map(|param| {
+                    let value = values.get(&param.name).unwrap_or(&Value::Null);
+                    (param.name.clone(), param.param_type.to_duckdb(value))
+                })
+                .collect(),
+        )
+    }
+}
+
+fn parse_param(spec: &str) -> Result<ParamDecl, String> {
+    let mut parts = spec.splitn(3, char::is_whitespace);
+    let (Some(name), Some(param_type)) = (parts.next(), parts.next()) else {
+        return Err(format!(
+            "Invalid param '{}'; expected '<name> <type> [optional | = <default>]'",
+            spec
+        ));
+    };
+    let mut param = ParamDecl {
+        name: name.to_string(),
+        param_type: param_type.parse()?,
+        default: None,
+        optional: false,
+    };
+
+    match parts.next().map(str::trim).unwrap_or_default() {
+        "" => {}
+        "optional" => param.optional = true,
+        rest => {
+            let Some(default) = rest.strip_prefix('=').map(str::trim) else {
+                return Err(format!("Invalid param '{}'", spec));
+            };
+            // Defaults are JSON literals; bare words are taken as strings
+            param.default = Some(
+                serde_json::from_str(default).unwrap_or_else(|_| Value::from(default.to_string())),
+            );
+        }
+    }
+    Ok(param)
+}
+
+/// Names of the `$name` placeholders in `sql`, ignoring string literals, quoted identifiers and comments
+fn placeholders(sql: &str) -> HashSet<String> {
+    let quoted = Regex::new(r#"'(?:[^']|'')*'|"(?:[^"]|"")*"|--[^\n]*|/\*(?s:.*?)\*/"#).unwrap();
+    let stripped = quoted.replace_all(sql, " ");
+    Regex::new(r"\$([A-Za-z_][A-Za-z0-9_]*)")
+        .unwrap()
+        .captures_iter(&stripped)
+        .map(|captures| captures[1].to_string())
+        .collect()
+}
+
+/// Registry of saved queries, persisted as `<name>.sql` files when a directory is configured
+pub struct SavedQueries {
+    dir: Option<PathBuf>,
+    queries: Mutex<HashMap<String, Arc<SavedQuery>>>,
+}
+
+impl SavedQueries {
+    /// Create an empty registry, persisting changes to `dir` if given
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self {
+            dir,
+            queries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Load every `.sql` file of the configured directory; the file stem is the query name
+    pub fn load(&self, state: &AppState) -> anyhow::Result<usize> {
+        let Some(dir) = &self.dir else {
+            return Ok(0);
+        };
+
+        let mut loaded = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(SAVED_QUERY_EXTENSION) {
+                continue;
+            }
+            let name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default();
+            let contents = std::fs::read_to_string(&path)?;
+            let mut query = SavedQuery::parse(name, &contents)
+                .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+            check(state, &mut query).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+
+            self.lock().insert(query.name.clone(), Arc::new(query));
+            loaded += 1;
+        }
+        info!(dir = ?dir, loaded, "Saved queries loaded");
+        Ok(loaded)
+    }
+
+    /// All saved queries ordered by name
+    pub fn list(&self) -> Vec<Arc<SavedQuery>> {
+        let mut queries: Vec<_> = self.lock().values().cloned().collect();
+        queries.sort_by(|a, b| a.name.cmp(&b.name));
+        queries
+    }
+
+    /// Look up a saved query by name
+    pub fn get(&self, name: &str) -> Option<Arc<SavedQuery>> {
+        self.lock().get(name).cloned()
+    }
+
+    /// Validate and store a query; returns whether it was newly created.
+    /// Unless `replace` is set, an existing query with the same name is a conflict.
+    pub fn save(
+        &self,
+        state: &AppState,
+        mut query: SavedQuery,
+        replace: bool,
+    ) -> Result<bool, ApiError> {
+        check(state, &mut query)?;
+
+        let mut queries = self.lock();
+        let exists = queries.contains_key(&query.name);
+        if exists && !replace {
+            return Err(ApiError::conflict(format!(
+                "Saved query '{}' already exists; use PUT /saved-queries/{} to replace it",
+                query.name, query.name
+            )));
+        }
+        if let Some(dir) = &self.dir {
+            let path = dir.join(format!("{}.{}", query.name, SAVED_QUERY_EXTENSION));
+            std::fs::write(&path, query.render()).map_err(|e| {
+                ApiError::internal_server_error(format!(
+                    "Failed to write {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+        }
+        queries.insert(query.name.clone(), Arc::new(query));
+        Ok(!exists)
+    }
+
+    /// Delete a saved query; returns whether it existed
+    pub fn remove(&self, name: &str) -> Result<bool, ApiError> {
+        let mut queries = self.lock();
+        if !queries.contains_key(name) {
+            return Ok(false);
+        }
+        if let Some(dir) = &self.dir {
+            let path = dir.join(format!("{}.{}", name, SAVED_QUERY_EXTENSION));
+            if let Err(e) = std::fs::remove_file(&path)
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                return Err(ApiError::internal_server_error(format!(
+                    "Failed to remove {}: {}",
+                    path.display(),
+                    e
+                )));
+            }
+        }
+        queries.remove(name);
+        Ok(true)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<SavedQuery>>> {
+        self.queries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Validate a definition and check it against the server's read-only and sandbox restrictions
+fn check(state: &AppState, query: &mut SavedQuery) -> Result<(), ApiError> {
+    query.validate().map_err(ApiError::bad_request)?;
+    if let Some(error_msg) = validate_readonly_operation(state, &query.sql) {
+        return Err(ApiError::forbidden(error_msg));
+    }
+    if let Some(error_msg) = validate_sandbox_operation(state, &query.sql) {
+        return Err(ApiError::forbidden(error_msg));
+    }
+    Ok(())
+}
+
+/// List saved queries
+#[utoipa::path(
+    get,
+    path = "/saved-queries",
+    responses(
+        (status = 200, description = "All saved queries ordered by name", body = [SavedQuery])
+    ),
+    tag = "saved"
+)]
+pub async fn list_saved_queries(State(state): State<AppState>) -> Json<Vec<SavedQuery>> {
+    Json(
+        state
+            .saved_queries
+            .list()
+            .into_iter()
+            .map(|query| (*query).clone())
+            .collect(),
+    )
+}
+
+/// Show a saved query's definition
+#[utoipa::path(
+    get,
+    path = "/saved-queries/{name}",
+    params(
+        ("name" = String, Path, description = "Saved query name")
+    ),
+    responses(
+        (status = 200, description = "Saved query definition", body = SavedQuery),
+        (status = 404, description = "No saved query with this name")
+    ),
+    tag = "saved"
+)]
+pub async fn get_saved_query(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<SavedQuery>, Response> {
+    match state.saved_queries.get(&name) {
+        Some(query) => Ok(Json((*query).clone())),
+        None => Err(saved_query_not_found(&name).to_response(None)),
+    }
+}
+
+/// Create a saved query
+#[utoipa::path(
+    post,
+    path = "/saved-queries",
+    request_body = SavedQuery,
+    responses(
+        (status = 201, description = "Saved query created", body = SavedQuery),
+        (status = 400, description = "Invalid definition"),
+        (status = 403, description = "Statement forbidden in read-only or sandbox mode"),
+        (status = 409, description = "A saved query with this name already exists")
+    ),
+    tag = "saved"
+)]
+#[instrument(skip(state, query), fields(name = %query.name))]
+pub async fn create_saved_query(
+    State(state): State<AppState>,
+    Json(query): Json<SavedQuery>,
+) -> Response {
+    match state.saved_queries.save(&state, query.clone(), false) {
+        Ok(_) => {
+            info!("Saved query created");
+            let saved = state.saved_queries.get(&query.name).map(|q| (*q).clone());
+            (StatusCode::CREATED, Json(saved)).into_response()
+        }
+        Err(error) => {
+            warn!(error = %error, "Saved query rejected");
+            error.to_response(None)
+        }
+    }
+}
+
+/// Create or replace a saved query
+#[utoipa::path(
+    put,
+    path = "/saved-queries/{name}",
+    params(
+        ("name" = String, Path, description = "Saved query name")
+    ),
+    request_body = SavedQuery,
+    responses(
+        (status = 200, description = "Saved query replaced", body = SavedQuery),
+        (status = 201, description = "Saved query created", body = SavedQuery),
+        (status = 400, description = "Invalid definition"),
+        (status = 403, description = "Statement forbidden in read-only or sandbox mode")
+    ),
+    tag = "saved"
+)]
+#[instrument(skip(state, query))]
+pub async fn put_saved_query(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(mut query): Json<SavedQuery>,
+) -> Response {
+    if !query.name.is_empty() && query.name != name {
+        let error = ApiError::bad_request(format!(
+            "Body name '{}' does not match the path name '{}'",
+            query.name, name
+        ));
+        return error.to_response(None);
+    }
+    query.name = name.clone();
+
+    match state.saved_queries.save(&state, query, true) {
+        Ok(created) => {
+            info!(created, "Saved query stored");
+            let status = if created {
+                StatusCode::CREATED
+            } else {
+                StatusCode::OK
+            };
+            let saved = state.saved_queries.get(&name).map(|q| (*q).clone());
+            (status, Json(saved)).into_response()
+        }
+        Err(error) => {
+            warn!(error = %error, "Saved query rejected");
+            error.to_response(None)
+        }
+    }
+}
+
+/// Delete a saved query
+#[utoipa::path(
+    delete,
+    path = "/saved-queries/{name}",
+    params(
+        ("name" = String, Path, description = "Saved query name")
+    ),
+    responses(
+        (status = 204, description = "Saved query deleted"),
+        (status = 404, description = "No saved query with this name")
+    ),
+    tag = "saved"
+)]
+#[instrument(skip(state))]
+pub async fn delete_saved_query(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Response {
+    match state.saved_queries.remove(&name) {
+        Ok(true) => {
+            info!("Saved query deleted");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => saved_query_not_found(&name).to_response(None),
+        Err(error) => error.to_response(None),
+    }
+}
+
+/// Run a saved query with parameters from the query string
+#[utoipa::path(
+    get,
+    path = "/q/{name}",
+    params(
+        ("name" = String, Path, description = "Saved query name"),
+        ("X-Query-Priority" = Option<String>, Header, description = "Priority class: interactive (default) or batch")
+    ),
+    responses(
+        (status = 200, description = "Query executed successfully", body = QueryResponse),
+        (status = 400, description = "Missing, unknown or invalid parameters"),
+        (status = 404, description = "No saved query with this name"),
+        (status = 503, description = "Server busy - execution queue full or queue wait timed out")
+    ),
+    tag = "saved"
+)]
+#[instrument(skip(state, headers, extensions, args))]
+pub async fn run_saved_query_get(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    extensions: Extensions,
+    Query(args): Query<HashMap<String, String>>,
+) -> Result<(HeaderMap, Json<QueryResponse>), Response> {
+    let args = args
+        .into_iter()
+        .map(|(name, value)| (name, Value::String(value)))
+        .collect();
+    let source = AuditSource::http(&state, &format!("/q/{}", name), &headers, &extensions);
+    run_saved_query(state, headers, source, name, args).await
+}
+
+/// Run a saved query with parameters from a JSON object
+#[utoipa::path(
+    post,
+    path = "/q/{name}",
+    params(
+        ("name" = String, Path, description = "Saved query name"),
+        ("X-Query-Priority" = Option<String>, Header, description = "Priority class: interactive (default) or batch")
+    ),
+    request_body(content = Object, description = "Parameter values by name"),
+    responses(
+        (status = 200, description = "Query executed successfully", body = QueryResponse),
+        (status = 400, description = "Missing, unknown or invalid parameters"),
+        (status = 404, description = "No saved query with this name"),
+        (status = 503, description = "Server busy - execution queue full or queue wait timed out")
+    ),
+    tag = "saved"
+)]
+#[instrument(skip(state, headers, extensions, args))]
+pub async fn run_saved_query_post(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    extensions: Extensions,
+    Json(args): Json<Map<String, Value>>,
+) -> Result<(HeaderMap, Json<QueryResponse>), Response> {
+    let source = AuditSource::http(&state, &format!("/q/{}", name), &headers, &extensions);
+    run_saved_query(state, headers, source, name, args).await
+}
+
+async fn run_saved_query(
+    state: AppState,
+    headers: HeaderMap,
+    source: AuditSource,
+    name: String,
+    args: Map<String, Value>,
+) -> Result<(HeaderMap, Json<QueryResponse>), Response> {
+    let query_id = Uuid::new_v4().to_string();
+    // Unknown names run nothing, so only saved queries that exist are audited
+    let mut audit = match state.saved_queries.get(&name) {
+        Some(query) => {
+            let values: Vec<&Value> = args.values().collect();
+            Audit::begin(&state, &source, &query_id, &query.sql).with_params(&values)
+        }
+        None => Audit::default(),
+    };
+    match run_saved_query_internal(state, headers, name, args, query_id.clone()).await {
+        Ok((headers, response)) => {
+            if let Some(data) = &response.data {
+                audit.succeeded(data);
+            }
+            Ok((headers, Json(response)))
+        }
+        Err(error) => {
+            audit.failed(&error);
+            Err(error.to_response(Some(query_id)))
+        }
+    }
+}
+
+async fn run_saved_query_internal(
+    state: AppState,
+    headers: HeaderMap,
+    name: String,
+    args: Map<String, Value>,
+    query_id: String,
+) -> Result<(HeaderMap, QueryResponse), ApiError> {
+    let Some(query) = state.saved_queries.get(&name) else {
+        return Err(saved_query_not_found(&name));
+    };
+    let priority = request_priority(&state, &headers)?;
+    let values = query.bind(&args)?;
+
+    let start_time = SystemTime::now();
+    info!(query_id = %query_id, "Running saved query");
+
+    // Read-only saved queries are cached per argument set, for their own TTL if they set one
+    let is_write = is_write_operation(&query.sql);
+    let cache = state.cache.clone();
+    let mut response_headers = HeaderMap::new();
+    let cache_entry = match &cache {
+        Some(cache) if !is_write && query.cache_ttl_secs != Some(0) => {
+            let ttl = query
+                .cache_ttl_secs
+                .map(Duration::from_secs)
+                .unwrap_or(cache.ttl());
+            let params = serde_json::to_string(&values).unwrap_or_default();
+            let key = CacheKey::with_params(&query.sql, &params, query.limit, CacheFormat::Json);
+            if let Some(hit) = cache.get(&key) {
+                let execution_time_ms = start_time.elapsed().unwrap_or_default().as_millis() as u64;
+                info!(execution_time_ms, "Saved query served from result cache");
+                set_cache_headers(&mut response_headers, "HIT", hit.remaining_ttl.as_secs());
+                return Ok((
+                    response_headers,
+                    QueryResponse {
+                        success: true,
+                        data: Some((*hit.data).clone()),
+                        error: None,
+                        query_id,
+                        execution_time_ms,
+                        profile: None,
+                    },
+                ));
+            }
+            set_cache_headers(&mut response_headers, "MISS", ttl.as_secs());
+            Some((key, cache.generation(), ttl))
+        }
+        Some(_) => {
+            set_cache_headers(&mut response_headers, "BYPASS", 0);
+            None
+        }
+        None => None,
+    };
+
+    let permit = state
+        .admission_for(priority)
+        .acquire(&state.metrics)
+        .await
+        .map_err(|e| ApiError::server_busy(e.to_string()))?;
+
+    let named = query.named_params(&values);
+    let blocking_query = query.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        execute_sql_with_params(
+            &state,
+            &blocking_query.sql,
+            &named,
+            blocking_query.limit,
+            priority,
+            None,
+        )
+    })
+    .await;
+
+    let execution_time_ms = start_time.elapsed().unwrap_or_default().as_millis() as u64;
+
+    match result {
+        Ok(Ok(data)) => {
+            info!(execution_time_ms, "Saved query executed successfully");
+            if let Some(cache) = &cache {
+                match cache_entry {
+                    Some((key, generation, ttl)) => {
+                        cache.insert_with_ttl(key, data.clone(), generation, ttl)
+                    }
+                    None if is_write => cache.invalidate_all(),
+                    None => {}
+                }
+            }
+            Ok((
+                response_headers,
+                QueryResponse {
+                    success: true,
+                    data: Some(data),
+                    error: None,
+                    query_id,
+                    execution_time_ms,
+                    profile: None,
+                },
+            ))
+        }
+        Ok(Err(e)) => {
+            error!(execution_time_ms, error = %e, "Saved query failed");
+            Err(ApiError::Database(e))
+        }
+        Err(e) => {
+            error!(execution_time_ms, error = %e, "Task execution failed");
+            Err(ApiError::internal_server_error(format!(
+                "Task execution error: {}",
+                e
+            )))
+        }
+    }
+}
+
+fn saved_query_not_found(name: &str) -> ApiError {
+    ApiError::not_found(format!("Saved query '{}' does not exist", name))
+}
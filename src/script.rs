@@ -0,0 +1,342 @@
+use axum::{
+    extract::State,
+    http::{Extensions, HeaderMap},
+    response::Json,
+    response::Response,
+};
+use duckdb::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::SystemTime;
+use tracing::{debug, error, info, instrument, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::database::{
+    convert_value_to_json, effective_row_limit, get_column_names, get_column_types,
+    is_write_operation, leading_keyword, returns_rows, split_statements,
+    validate_readonly_operation, validate_sandbox_operation,
+};
+use crate::handlers::request_priority;
+use crate::{ApiError, AppState, Audit, AuditSource, DatabaseError, Priority};
+
+/// Request body for `/sql`
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ScriptRequest {
+    /// One or more statements separated by semicolons
+    #[schema(
+        example = "CREATE TEMP TABLE t AS SELECT 1 AS id; INSERT INTO t VALUES (2); SELECT * FROM t"
+    )]
+    pub sql: String,
+    /// Maximum number of rows returned by each statement
+    #[schema(example = 100)]
+    pub limit: Option<usize>,
+}
+
+/// Response structure for `/sql`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ScriptResponse {
+    /// Whether every statement succeeded
+    pub success: bool,
+    /// One result per statement, in script order
+    pub results: Vec<StatementResult>,
+    /// Unique identifier for this request
+    #[schema(example = "123e4567-e89b-12d3-a456-426614174000")]
+    pub query_id: String,
+    /// Time taken to run the whole script in milliseconds
+    #[schema(example = 42)]
+    pub execution_time_ms: u64,
+}
+
+/// Result of one statement of a `/sql` script
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StatementResult {
+    /// A query, or any other statement that produced a result set
+    Rows {
+        /// First keyword of the statement, e.g. `SELECT` or `SHOW`
+        statement_type: String,
+        /// Column names
+        columns: Vec<String>,
+        /// SQL type names of the columns
+        column_types: Vec<String>,
+        /// Row values, in column order
+        rows: Vec<Vec<Value>>,
+        /// Number of rows returned
+        row_count: usize,
+        /// Row limit that was in effect
+        limit_applied: usize,
+        /// Whether rows beyond the limit were left out
+        truncated: bool,
+    },
+    /// A statement that changed data, schema or settings
+    Count {
+        /// First keyword of the statement, e.g. `INSERT` or `CREATE`
+        statement_type: String,
+        /// Rows inserted, updated or deleted; 0 for statements that do not report a count
+        rows_affected: u64,
+    },
+}
+
+/// Why a script stopped: the error and, when a statement failed, its index
+#[derive(Debug)]
+struct ScriptError {
+    statement: Option<usize>,
+    error: ApiError,
+}
+
+impl From<ApiError> for ScriptError {
+    fn from(error: ApiError) -> Self {
+        Self {
+            statement: None,
+            error,
+        }
+    }
+}
+
+/// Run a script of one or more statements on one connection, deciding per statement whether
+/// it returns rows or an affected row count
+/// Statements run in order and stop at the first failure; earlier statements stay applied
+/// unless the script wrapped them in a transaction
+#[utoipa::path(
+    post,
+    path = "/sql",
+    request_body = ScriptRequest,
+    params(
+        ("X-Query-Priority" = Option<String>, Header, description = "Priority class: interactive (default) or batch")
+    ),
+    responses(
+        (status = 200, description = "Every statement ran; one result per statement", body = ScriptResponse),
+        (status = 400, description = "Bad request - empty script, invalid SQL, or a transaction left open"),
+        (status = 403, description = "Operation forbidden in read-only or sandbox mode"),
+        (status = 409, description = "A statement violated a constraint or conflicted with another transaction"),
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "Server busy - execution queue full or queue wait timed out")
+    ),
+    tag = "query"
+)]
+#[instrument(skip(state, headers, extensions, request), fields(query_id, sql_preview = %request.sql.chars().take(50).collect::<String>(), limit = request.limit, priority))]
+pub async fn run_sql_script(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    extensions: Extensions,
+    Json(request): Json<ScriptRequest>,
+) -> Result<Json<ScriptResponse>, Response> {
+    let query_id = Uuid::new_v4().to_string();
+    tracing::Span::current().record("query_id", &query_id);
+
+    let source = AuditSource::http(&state, "/sql", &headers, &extensions);
+    let mut audit = Audit::begin(&state, &source, &query_id, &request.sql);
+    match script_internal(state, headers, request, query_id.clone(), &mut audit).await {
+        Ok(response) => Ok(Json(response)),
+        Err(ScriptError { statement, error }) => {
+            audit.failed(&error);
+            let mut problem = error.to_problem(Some(query_id));
+            problem.statement = statement;
+            Err(error.problem_response(problem))
+        }
+    }
+}
+
+async fn script_internal(
+    state: AppState,
+    headers: HeaderMap,
+    request: ScriptRequest,
+    query_id: String,
+    audit: &mut Audit,
+) -> Result<ScriptResponse, ScriptError> {
+    let priority = request_priority(&state, &headers)?;
+    tracing::Span::current().record("priority", priority.as_str());
+
+    let start_time = SystemTime::now();
+    info!("Script execution requested");
+
+    let statements = split_statements(&request.sql);
+    if statements.is_empty() {
+        return Err(ApiError::bad_request("The script contains no SQL statements").into());
+    }
+
+    if let Some(error_msg) = validate_readonly_operation(&state, &request.sql) {
+        warn!("Read-only violation detected");
+        return Err(ApiError::forbidden(error_msg).into());
+    }
+    if let Some(error_msg) = validate_sandbox_operation(&state, &request.sql) {
+        warn!("Sandbox violation detected");
+        return Err(ApiError::forbidden(error_msg).into());
+    }
+
+    let permit = state
+        .admission_for(priority)
+        .acquire(&state.metrics)
+        .await
+        .map_err(|e| ApiError::server_busy(e.to_string()))?;
+
+    let limit = effective_row_limit(request.limit);
+    let is_write = is_write_operation(&request.sql);
+    let cache = state.cache.clone();
+    let mut audit = audit.take();
+    let result = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let result = run_script(&state, &statements, limit, priority, &mut audit);
+        if let Err(e) = &result {
+            audit.failed(&e.error);
+        }
+        result
+    })
+    .await;
+
+    let execution_time_ms = start_time.elapsed().unwrap_or_default().as_millis() as u64;
+
+    // Statements before a failure have run, so invalidate even when the script failed
+    if is_write && let Some(cache) = &cache {
+        cache.invalidate_all();
+    }
+
+    let results = match result {
+        Ok(Ok(results)) => results,
+        Ok(Err(e)) => {
+            error!(execution_time_ms, statement = e.statement, error = %e.error, "Script failed");
+            return Err(e);
+        }
+        Err(e) => {
+            error!(execution_time_ms, error = %e, "Task execution failed");
+            return Err(
+                ApiError::internal_server_error(format!("Task execution error: {}", e)).into(),
+            );
+        }
+    };
+
+    info!(
+        execution_time_ms,
+        statements = results.len(),
+        "Script executed successfully"
+    );
+
+    Ok(ScriptResponse {
+        success: true,
+        results,
+        query_id,
+        execution_time_ms,
+    })
+}
+
+/// Run each statement in order on one pooled connection, so temp tables, settings and
+/// transactions carry over from one statement to the next. Each statement is audited as it
+/// finishes, except the last, which is audited once the script is known not to leave a
+/// transaction open.
+fn run_script(
+    state: &AppState,
+    statements: &[String],
+    limit: usize,
+    priority: Priority,
+    audit: &mut Audit,
+) -> Result<Vec<StatementResult>, ScriptError> {
+    debug!("Acquiring database connection from pool");
+    let conn = state
+        .pool_for(priority)
+        .get()
+        .map_err(|e| ApiError::Database(e.into()))?;
+
+    let mut results = Vec::with_capacity(statements.len());
+    for (index, sql) in statements.iter().enumerate() {
+        debug!(statement = index, "Executing script statement");
+        match run_statement(&conn, sql, limit) {
+            Ok(result) => {
+                if index + 1 < statements.len() {
+                    audit_result(audit, index, &result);
+                }
+                results.push(result);
+            }
+            Err(e) => {
+                // Abort a transaction the script opened before returning the connection
+                let _ = conn.execute_batch("ROLLBACK");
+                let error = ApiError::Database(e);
+                audit.statement_failed(index, &error);
+                return Err(ScriptError {
+                    statement: Some(index),
+                    error,
+                });
+            }
+        }
+    }
+
+    // ROLLBACK only succeeds inside a transaction, which would otherwise leak to the
+    // connection's next user
+    let last = results.len() - 1;
+    if conn.execute_batch("ROLLBACK").is_ok() {
+        warn!("Script left a transaction open; rolled back");
+        let error = ApiError::bad_request(
+            "The script ended inside a transaction, which was rolled back; end it with COMMIT",
+        );
+        audit.statement_failed(last, &error);
+        return Err(error.into());
+    }
+    audit_result(audit, last, &results[last]);
+    Ok(results)
+}
+
+/// Record the rows a statement returned or affected in the audit log
+fn audit_result(audit: &mut Audit, index: usize, result: &StatementResult) {
+    match result {
+        StatementResult::Rows { row_count, .. } => {
+            audit.statement_completed(index, Some(*row_count as u64), None)
+        }
+        StatementResult::Count { rows_affected, .. } => {
+            audit.statement_completed(index, None, Some(*rows_affected))
+        }
+    }
+}
+
+/// Execute one statement and collect up to `limit` rows, or its affected row count
+fn run_statement(
+    conn: &Connection,
+    sql: &str,
+    limit: usize,
+) -> Result<StatementResult, DatabaseError> {
+    let statement_type = leading_keyword(sql);
+    let mut stmt = conn.prepare(sql)?;
+
+    let mut rows = stmt.query([])?;
+    let mut result_rows = Vec::new();
+    let mut truncated = false;
+    while let Some(row) = rows.next()? {
+        if result_rows.len() >= limit {
+            warn!("Query results truncated at {} rows", limit);
+            truncated = true;
+            break;
+        }
+        let column_count = row.as_ref().column_count();
+        let values = (0..column_count)
+            .map(|i| convert_value_to_json(row.get_ref(i)))
+            .collect::<Result<Vec<_>, _>>()?;
+        result_rows.push(values);
+    }
+    drop(rows);
+
+    let column_count = stmt.column_count();
+    let columns = get_column_names(&stmt, column_count)?;
+    let names: Vec<&str> = columns.iter().map(String::as_str).collect();
+    if returns_rows(&statement_type, &names) {
+        return Ok(StatementResult::Rows {
+            statement_type,
+            column_types: get_column_types(&stmt, column_count)?,
+            columns,
+            row_count: result_rows.len(),
+            rows: result_rows,
+            limit_applied: limit,
+            truncated,
+        });
+    }
+
+    let rows_affected = match (
+        names.as_slice(),
+        result_rows.first().and_then(|r| r.first()),
+    ) {
+        (["Count"], Some(value)) => value.as_u64().unwrap_or(0),
+        _ => 0,
+    };
+    Ok(StatementResult::Count {
+        statement_type,
+        rows_affected,
+    })
+}
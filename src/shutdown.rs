@@ -0,0 +1,148 @@
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::AppState;
+
+/// How long interrupted requests get to send their error responses before their connections
+/// are closed
+const INTERRUPT_GRACE: Duration = Duration::from_secs(5);
+
+/// Interval between checks for running statements while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Starts a graceful shutdown and lets servers wait for it
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    started: watch::Sender<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    /// Create a shutdown that has not started yet
+    pub fn new() -> Self {
+        Self {
+            started: watch::Sender::new(false),
+        }
+    }
+
+    /// Start the shutdown
+    pub fn begin(&self) {
+        self.started.send_replace(true);
+    }
+
+    /// Whether the shutdown has started, i.e. the server is draining
+    pub fn is_started(&self) -> bool {
+        *self.started.borrow()
+    }
+
+    /// Completes once the shutdown has started; pass it to `axum::serve(..).with_graceful_shutdown`
+    /// so the server stops accepting connections and finishes the requests it has
+    pub fn started(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut started = self.started.subscribe();
+        async move {
+            let _ = started.wait_for(|started| *started).await;
+        }
+    }
+}
+
+/// Wait for a server that is shutting down gracefully and for the statements still running,
+/// for at most `timeout`. Statements running after that are interrupted, and connections still
+/// open shortly afterwards are closed.
+pub async fn drain(
+    mut server: JoinHandle<io::Result<()>>,
+    state: &AppState,
+    timeout: Duration,
+) -> io::Result<()> {
+    let deadline = Instant::now() + timeout;
+
+    let mut result = None;
+    if let Ok(finished) = tokio::time::timeout_at(deadline, &mut server).await {
+        result = Some(join_result(finished));
+    }
+    while running_statements(state) > 0 && Instant::now() < deadline {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+
+    let running = running_statements(state);
+    if running > 0 || result.is_none() {
+        let interrupted = state.connections.interrupt_all();
+        warn!(
+            running_statements = running,
+            interrupted_connections = interrupted,
+            timeout_secs = timeout.as_secs_f64(),
+            "Drain timeout reached, interrupting running queries"
+        );
+    }
+
+    match result {
+        Some(result) => result,
+        None => match tokio::time::timeout(INTERRUPT_GRACE, &mut server).await {
+            Ok(finished) => join_result(finished),
+            Err(_) => {
+                warn!("Closing connections that are still open");
+                server.abort();
+                Ok(())
+            }
+        },
+    }
+}
+
+/// Statements executing or waiting for a connection, over both priority classes
+pub fn running_statements(state: &AppState) -> usize {
+    state.admission.in_flight()
+        + state.admission.queued()
+        + state.batch_admission.in_flight()
+        + state.batch_admission.queued()
+}
+
+fn join_result(result: Result<io::Result<()>, tokio::task::JoinError>) -> io::Result<()> {
+    result.unwrap_or_else(|e| Err(io::Error::other(e)))
+}
+
+/// Checkpoint a read-write file database and close both connection pools. Returns the number of
+/// connections that were checkpointed.
+pub fn close_database(state: &AppState) -> usize {
+    let checkpoint = state.db_path.is_some() && !state.is_readonly;
+    if let Some(history) = &state.history {
+        history.flush();
+    }
+    if let Some(audit) = &state.audit {
+        audit.flush();
+    }
+
+    let mut idle = Vec::new();
+    let mut checkpointed = 0;
+    for pool in [&state.pool, &state.batch_pool] {
+        // Connections still in use were interrupted; they are closed when returned
+        for conn in std::iter::from_fn(|| pool.try_get()) {
+            if checkpoint {
+                match conn.execute_batch("CHECKPOINT") {
+                    Ok(()) => checkpointed += 1,
+                    Err(e) => warn!(error = %e, "CHECKPOINT failed"),
+                }
+            }
+            idle.push(conn);
+        }
+    }
+
+    state.connections.close();
+    drop(idle);
+    if checkpoint {
+        info!(connections = checkpointed, "Database checkpointed");
+    }
+    info!(
+        still_open = state.connections.open_connections(),
+        "Connection pools closed"
+    );
+    checkpointed
+}
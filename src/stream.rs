@@ -0,0 +1,257 @@
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Extensions, HeaderMap, HeaderValue, header},
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::Value;
+use std::convert::Infallible;
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::database::{
+    BindParams, ResultChunk, is_query_statement, split_statements, stream_sql_with_params,
+    validate_readonly_operation, validate_sandbox_operation,
+};
+use crate::handlers::request_priority;
+use crate::{
+    ApiError, AppState, Audit, AuditSource, AuditStatus, DatabaseError, NDJSON_CONTENT_TYPE,
+    QUERY_ID_HEADER, QueryRequest, StreamEvent,
+};
+
+/// Rows handed from the blocking query task to the response body at a time
+const CHUNK_ROWS: usize = 500;
+
+/// Output of the blocking query task
+enum StreamItem {
+    Chunk(ResultChunk),
+    Done(Result<Value, DatabaseError>),
+}
+
+/// Stream query results as newline-delimited JSON
+/// The first line is a `columns` event, each row follows as a JSON array, and the last line is an
+/// `end` event, or an `error` event when the query fails after rows were sent
+#[utoipa::path(
+    post,
+    path = "/query/stream",
+    request_body = QueryRequest,
+    params(
+        ("X-Query-Priority" = Option<String>, Header, description = "Priority class: interactive (default) or batch")
+    ),
+    responses(
+        (status = 200, description = "Result rows as newline-delimited JSON", body = StreamEvent, content_type = "application/x-ndjson"),
+        (status = 400, description = "Bad request - invalid SQL, or not a single query"),
+        (status = 403, description = "Operation forbidden in read-only or sandbox mode"),
+        (status = 503, description = "Server busy - execution queue full or queue wait timed out")
+    ),
+    tag = "query"
+)]
+#[instrument(skip(state, headers, request), fields(query_id, sql_preview = %request.sql.chars().take(50).collect::<String>(), limit = request.limit, priority))]
+pub async fn stream_query(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    extensions: Extensions,
+    Json(request): Json<QueryRequest>,
+) -> Response {
+    let query_id = Uuid::new_v4().to_string();
+    tracing::Span::current().record("query_id", &query_id);
+
+    let source = AuditSource::http(&state, "/query/stream", &headers, &extensions);
+    let mut audit =
+        Audit::begin(&state, &source, &query_id, &request.sql).with_params(&request.params);
+    match stream_internal(state, headers, request, query_id.clone(), &mut audit).await {
+        Ok(response) => response,
+        Err(error) => {
+            audit.failed(&error);
+            error.to_response(Some(query_id))
+        }
+    }
+}
+
+/// Validate and start the query. `audit` is taken by the query task once it starts; errors
+/// returned before that are left for the caller to record.
+async fn stream_internal(
+    state: AppState,
+    headers: HeaderMap,
+    request: QueryRequest,
+    query_id: String,
+    audit: &mut Audit,
+) -> Result<Response, ApiError> {
+    let priority = request_priority(&state, &headers)?;
+    tracing::Span::current().record("priority", priority.as_str());
+    info!("Streaming query requested");
+
+    let statements = split_statements(&request.sql);
+    let [sql] = statements.as_slice() else {
+        return Err(ApiError::bad_request(
+            "Streaming requires exactly one SQL statement",
+        ));
+    };
+    if !is_query_statement(sql) {
+        return Err(ApiError::bad_request(
+            "Streaming is only supported for SELECT, WITH, VALUES and FROM queries",
+        ));
+    }
+    if request.profile || request.page_size.is_some() {
+        return Err(ApiError::bad_request(
+            "profile and page_size are not supported when streaming",
+        ));
+    }
+    if let Some(error_msg) = validate_readonly_operation(&state, sql) {
+        warn!("Read-only violation detected");
+        return Err(ApiError::forbidden(error_msg));
+    }
+    if let Some(error_msg) = validate_sandbox_operation(&state, sql) {
+        warn!("Sandbox violation detected");
+        return Err(ApiError::forbidden(error_msg));
+    }
+
+    let permit = state
+        .admission_for(priority)
+        .acquire(&state.metrics)
+        .await
+        .map_err(|e| ApiError::server_busy(e.to_string()))?;
+
+    let start_time = SystemTime::now();
+    let (items, mut received) = mpsc::channel(4);
+    let sql = sql.clone();
+    let params = BindParams::from_json(&request.params);
+    let limit = request.limit;
+    let mut audit = audit.take();
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let result = state
+            .pool_for(priority)
+            .get()
+            .map_err(DatabaseError::from)
+            .and_then(|conn| {
+                // A send fails once the client has gone away, which stops the query
+                stream_sql_with_params(&conn, &sql, &params, limit, CHUNK_ROWS, |chunk| {
+                    items.blocking_send(StreamItem::Chunk(chunk)).is_ok()
+                })
+            });
+
+        let execution_time_ms = start_time.elapsed().unwrap_or_default().as_millis() as u64;
+        match &result {
+            Ok(summary) => {
+                info!(execution_time_ms, "Streaming query completed");
+                audit.succeeded(summary);
+            }
+            Err(e) => {
+                error!(execution_time_ms, error = %e, "Streaming query failed");
+                audit.failed_with(AuditStatus::Error, e.error_code());
+            }
+        }
+        let _ = items.blocking_send(StreamItem::Done(result));
+    });
+
+    // Fail with a proper status when the query errors before producing any rows
+    let first = received.recv().await;
+    if let Some(StreamItem::Done(Err(e))) = first {
+        return Err(ApiError::Database(e));
+    }
+
+    let mut body = NdjsonBody {
+        query_id: query_id.clone(),
+        columns_sent: false,
+    };
+    let lines = futures::stream::unfold(
+        (first, received, false),
+        move |(item, mut received, finished)| {
+            let line = if finished {
+                None
+            } else {
+                Some(match item {
+                    Some(StreamItem::Chunk(chunk)) => (body.chunk(chunk), false),
+                    Some(StreamItem::Done(result)) => (body.done(result), true),
+                    None => (
+                        body.failed(ApiError::internal_server_error(
+                            "Query task ended unexpectedly",
+                        )),
+                        true,
+                    ),
+                })
+            };
+            async move {
+                let (line, finished) = line?;
+                let next = if finished {
+                    None
+                } else {
+                    received.recv().await
+                };
+                Some((Ok::<_, Infallible>(line), (next, received, finished)))
+            }
+        },
+    );
+
+    let mut response = Body::from_stream(lines).into_response();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(NDJSON_CONTENT_TYPE),
+    );
+    if let Ok(value) = HeaderValue::from_str(&query_id) {
+        response.headers_mut().insert(QUERY_ID_HEADER, value);
+    }
+    Ok(response)
+}
+
+/// Renders streamed results as NDJSON lines
+struct NdjsonBody {
+    query_id: String,
+    columns_sent: bool,
+}
+
+impl NdjsonBody {
+    fn chunk(&mut self, chunk: ResultChunk) -> String {
+        let mut out = self.columns(chunk.columns, chunk.column_types);
+        for row in chunk.rows {
+            out.push_str(&Value::from(row).to_string());
+            out.push('\n');
+        }
+        out
+    }
+
+    fn done(&mut self, result: Result<Value, DatabaseError>) -> String {
+        let summary = match result {
+            Ok(summary) => summary,
+            Err(e) => return self.failed(ApiError::Database(e)),
+        };
+
+        let strings = |key: &str| -> Vec<String> {
+            serde_json::from_value(summary[key].clone()).unwrap_or_default()
+        };
+        let mut out = self.columns(strings("columns"), strings("column_types"));
+        out.push_str(&line(&StreamEvent::End {
+            row_count: summary["row_count"].as_u64().unwrap_or(0) as usize,
+            limit_applied: summary["limit_applied"].as_u64().unwrap_or(0) as usize,
+            truncated: summary["truncated"].as_bool().unwrap_or(false),
+        }));
+        out
+    }
+
+    fn failed(&self, error: ApiError) -> String {
+        line(&StreamEvent::Error {
+            error: error.to_error_response(Some(self.query_id.clone())).error,
+        })
+    }
+
+    /// The `columns` line, unless it was already sent
+    fn columns(&mut self, columns: Vec<String>, column_types: Vec<String>) -> String {
+        if self.columns_sent {
+            return String::new();
+        }
+        self.columns_sent = true;
+        line(&StreamEvent::Columns {
+            columns,
+            column_types,
+        })
+    }
+}
+
+fn line(event: &StreamEvent) -> String {
+    let mut out = serde_json::to_string(event).unwrap_or_default();
+    out.push('\n');
+    out
+}
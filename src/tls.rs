@@ -0,0 +1,410 @@
+use axum::{
+    Router,
+    extract::{ConnectInfo, Request},
+    response::Response,
+    serve::{IncomingStream, Listener},
+};
+use rustls::{
+    RootCertStore, ServerConfig,
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    server::WebPkiClientVerifier,
+};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::{Ready, ready};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::{TlsAcceptor, server::TlsStream};
+use tower::Service;
+use tracing::{Span, debug, info, warn};
+
+use crate::models::Args;
+
+/// Time a client has to complete the TLS handshake before its connection is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of handshaken connections waiting to be served
+const ACCEPT_BACKLOG: usize = 128;
+
+/// What a client certificate is allowed to do, mirroring the kinds of API key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientScope {
+    /// Every endpoint, like `--api-key`
+    Full,
+    /// Every endpoint, with queries always at batch priority, like `--batch-api-key`
+    Batch,
+    /// Only saved queries at `/q/{name}`, like `--saved-query-api-key`
+    SavedQueries,
+    /// Every endpoint including administration, like `--admin-api-key`
+    Admin,
+}
+
+impl FromStr for ClientScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "full" => Ok(Self::Full),
+            "batch" => Ok(Self::Batch),
+            "saved" => Ok(Self::SavedQueries),
+            "admin" => Ok(Self::Admin),
+            other => Err(format!(
+                "unknown client scope \"{}\"; use full, batch, saved or admin",
+                other
+            )),
+        }
+    }
+}
+
+/// Parse a `--tls-client-scope` value of the form `NAME=SCOPE`
+pub fn parse_client_scope(value: &str) -> Result<(String, ClientScope), String> {
+    let (name, scope) = value
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected NAME=SCOPE, got \"{}\"", value))?;
+    if name.is_empty() {
+        return Err("client certificate name must not be empty".to_string());
+    }
+    Ok((name.to_string(), scope.parse()?))
+}
+
+/// Identity of a client that presented a verified certificate, added to its requests as an
+/// extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// Subject common name, or the whole subject when it has none
+    pub name: String,
+    /// Certificate subject, e.g. `CN=alice, O=Example`
+    pub subject: String,
+    /// Granted scope; None when the certificate is not listed in `--tls-client-scope`
+    pub scope: Option<ClientScope>,
+}
+
+/// Maps client certificate subjects to scopes
+#[derive(Debug, Default)]
+pub struct ClientScopes {
+    scopes: HashMap<String, ClientScope>,
+}
+
+impl ClientScopes {
+    /// Create a mapping from common names or full subjects to scopes. With an empty mapping
+    /// every verified certificate has full scope.
+    pub fn new(scopes: impl IntoIterator<Item = (String, ClientScope)>) -> Self {
+        Self {
+            scopes: scopes.into_iter().collect(),
+        }
+    }
+
+    /// Mapping configured by `--tls-client-scope`
+    pub fn from_args(args: &Args) -> Self {
+        Self::new(args.tls_client_scopes.iter().cloned())
+    }
+
+    /// Identity of a verified end-entity certificate, or None if it cannot be parsed
+    pub fn identify(&self, certificate: &CertificateDer<'_>) -> Option<ClientIdentity> {
+        let (_, parsed) = x509_parser::parse_x509_certificate(certificate).ok()?;
+        let subject = parsed.subject().to_string();
+        let name = parsed
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string)
+            .unwrap_or_else(|| subject.clone());
+
+        let scope = if self.scopes.is_empty() {
+            Some(ClientScope::Full)
+        } else {
+            self.scopes
+                .get(&name)
+                .or_else(|| self.scopes.get(&subject))
+                .copied()
+        };
+        Some(ClientIdentity {
+            name,
+            subject,
+            scope,
+        })
+    }
+}
+
+/// Certificate files configured by `--tls-cert`, `--tls-key` and `--tls-client-ca`
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first
+    pub cert: PathBuf,
+    /// PEM private key
+    pub key: PathBuf,
+    /// PEM bundle of CAs that client certificates must chain to; None disables mTLS
+    pub client_ca: Option<PathBuf>,
+    /// Accept clients without a certificate, leaving them to authenticate with an API key
+    pub client_cert_optional: bool,
+}
+
+impl TlsConfig {
+    /// TLS configuration from the command line, if `--tls-cert` is given
+    pub fn from_args(args: &Args) -> Option<Self> {
+        Some(Self {
+            cert: args.tls_cert.clone()?,
+            key: args.tls_key.clone()?,
+            client_ca: args.tls_client_ca.clone(),
+            client_cert_optional: args.tls_client_cert_optional,
+        })
+    }
+
+    /// Read the certificate files and build a rustls server configuration
+    pub fn server_config(&self) -> anyhow::Result<Arc<ServerConfig>> {
+        let provider = Arc::new(ring::default_provider());
+
+        let certs = CertificateDer::pem_file_iter(&self.cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| anyhow::anyhow!("{}: {}", self.cert.display(), e))?;
+        if certs.is_empty() {
+            anyhow::bail!("{}: no certificates found", self.cert.display());
+        }
+        let key = PrivateKeyDer::from_pem_file(&self.key)
+            .map_err(|e| anyhow::anyhow!("{}: {}", self.key.display(), e))?;
+
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match &self.client_ca {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in CertificateDer::pem_file_iter(path)
+                    .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?
+                {
+                    let cert = cert.map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+                    roots
+                        .add(cert)
+                        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+                }
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+                let verifier = if self.client_cert_optional {
+                    verifier.allow_unauthenticated()
+                } else {
+                    verifier
+                };
+                builder.with_client_cert_verifier(verifier.build()?)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder
+            .with_single_cert(certs, key)
+            .map_err(|e| anyhow::anyhow!("{}: {}", self.key.display(), e))?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(Arc::new(config))
+    }
+}
+
+/// TLS server configuration that can be reloaded from its files while the server runs.
+/// Connections already established keep the configuration they were accepted with.
+#[derive(Debug)]
+pub struct ReloadableTlsConfig {
+    config: TlsConfig,
+    current: Mutex<Arc<ServerConfig>>,
+}
+
+impl ReloadableTlsConfig {
+    /// Load the certificate files
+    pub fn new(config: TlsConfig) -> anyhow::Result<Self> {
+        let current = Mutex::new(config.server_config()?);
+        Ok(Self { config, current })
+    }
+
+    /// Read the certificate files again. On error the previous configuration stays in use.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let server_config = self.config.server_config()?;
+        *self.current.lock().unwrap_or_else(|e| e.into_inner()) = server_config;
+        Ok(())
+    }
+
+    /// Acceptor for new connections, using the latest configuration
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(
+            self.current
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        )
+    }
+
+    /// Reload the certificate files whenever the process receives SIGHUP
+    #[cfg(unix)]
+    pub fn reload_on_sighup(self: Arc<Self>) -> std::io::Result<()> {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match self.reload() {
+                    Ok(()) => info!("Reloaded TLS certificates"),
+                    Err(e) => warn!(error = %e, "Failed to reload TLS certificates"),
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Listener for `axum::serve` accepting TLS connections. Handshakes run concurrently, so a
+/// slow client cannot hold up others.
+pub struct TlsListener {
+    connections: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    /// Accept TLS connections on `listener`
+    pub fn new(listener: TcpListener, tls: Arc<ReloadableTlsConfig>) -> std::io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (sender, connections) = mpsc::channel(ACCEPT_BACKLOG);
+        tokio::spawn(accept_connections(listener, tls, sender));
+        Ok(Self {
+            connections,
+            local_addr,
+        })
+    }
+}
+
+async fn accept_connections(
+    listener: TcpListener,
+    tls: Arc<ReloadableTlsConfig>,
+    sender: mpsc::Sender<(TlsStream<TcpStream>, SocketAddr)>,
+) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = sender.closed() => return,
+        };
+        let (stream, addr) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                debug!(error = %e, "Failed to accept connection");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+
+        let acceptor = tls.acceptor();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let _ = sender.send((stream, addr)).await;
+                }
+                Ok(Err(e)) => debug!(client = %addr, error = %e, "TLS handshake failed"),
+                Err(_) => debug!(client = %addr, "TLS handshake timed out"),
+            }
+        });
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            // The accept task only stops once this listener is dropped
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// Make-service for serving a router over a [`TlsListener`]. Requests carry the remote
+/// address as `ConnectInfo<SocketAddr>` and, after mTLS, the client's [`ClientIdentity`].
+#[derive(Clone)]
+pub struct TlsMakeService {
+    app: Router,
+    scopes: Arc<ClientScopes>,
+}
+
+impl TlsMakeService {
+    /// Serve `app`, mapping client certificates to identities with `scopes`
+    pub fn new(app: Router, scopes: ClientScopes) -> Self {
+        Self {
+            app,
+            scopes: Arc::new(scopes),
+        }
+    }
+}
+
+impl Service<IncomingStream<'_, TlsListener>> for TlsMakeService {
+    type Response = TlsConnectionService;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, incoming: IncomingStream<'_, TlsListener>) -> Self::Future {
+        let (_, connection) = incoming.io().get_ref();
+        let identity = connection
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(|cert| self.scopes.identify(cert));
+        ready(Ok(TlsConnectionService {
+            app: self.app.clone(),
+            remote_addr: *incoming.remote_addr(),
+            identity,
+        }))
+    }
+}
+
+/// Serves the requests of one TLS connection
+#[derive(Clone)]
+pub struct TlsConnectionService {
+    app: Router,
+    remote_addr: SocketAddr,
+    identity: Option<ClientIdentity>,
+}
+
+impl Service<Request> for TlsConnectionService {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = <Router as Service<Request>>::Future;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(self.remote_addr));
+        if let Some(identity) = &self.identity {
+            request.extensions_mut().insert(identity.clone());
+        }
+        self.app.call(request)
+    }
+}
+
+/// Tracing span for an HTTP request, recording the client certificate identity when present.
+/// Use with `TraceLayer::make_span_with`.
+pub fn request_span<B>(request: &axum::http::Request<B>) -> Span {
+    let client_cert = request
+        .extensions()
+        .get::<ClientIdentity>()
+        .map(|identity| identity.name.as_str());
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        client_cert,
+    )
+}
@@ -0,0 +1,539 @@
+use axum::{
+    extract::{
+        State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
+    },
+    http::{Extensions, HeaderMap},
+    response::Response,
+};
+use duckdb::{Connection, InterruptHandle};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::{Duration, MissedTickBehavior};
+use tracing::{debug, error, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::database::{
+    is_write_operation, stream_sql_with_limit, validate_readonly_operation,
+    validate_sandbox_operation,
+};
+use crate::errors::ErrorResponse;
+use crate::handlers::request_priority;
+use crate::{
+    ApiError, AppState, Audit, AuditSource, DatabaseError, Metrics, Priority, QueryResponse,
+    ResultChunk,
+};
+
+/// Rows per `chunk` message
+const CHUNK_ROWS: usize = 500;
+/// Chunks buffered between a running query and the socket
+const CHUNK_CHANNEL_CAPACITY: usize = 4;
+/// Interval between `progress` messages while a query runs
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+/// Queries a session may queue behind the one that is running
+const MAX_PENDING_QUERIES: usize = 16;
+
+/// Messages clients send over `/ws`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Run a statement on the session's connection; `id` becomes the query ID
+    Query {
+        id: Option<String>,
+        sql: String,
+        limit: Option<usize>,
+    },
+    /// Cancel a running or queued query (the running one when `id` is omitted)
+    Cancel { id: Option<String> },
+    /// Liveness check answered with `pong`
+    Ping,
+}
+
+/// Messages the server sends over `/ws`
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// A batch of result rows
+    Chunk {
+        query_id: String,
+        #[serde(flatten)]
+        chunk: ResultChunk,
+    },
+    /// Periodic status of the running query
+    Progress {
+        query_id: String,
+        rows_sent: usize,
+        elapsed_ms: u64,
+    },
+    /// Query finished; `data` summarizes the result without its rows
+    Result(QueryResponse),
+    /// Query or protocol error
+    Error(ErrorResponse),
+    /// Reply to `ping`
+    Pong,
+}
+
+/// WebSocket endpoint for interactive query sessions
+/// Each session runs its statements one at a time on a private DuckDB connection, so temp
+/// tables, `SET` variables and transactions persist until the socket closes
+#[utoipa::path(
+    get,
+    path = "/ws",
+    params(
+        ("X-Query-Priority" = Option<String>, Header, description = "Priority class: interactive (default) or batch")
+    ),
+    responses(
+        (status = 101, description = "Switching to the WebSocket session protocol"),
+        (status = 400, description = "Bad request - not a WebSocket upgrade or invalid priority"),
+        (status = 503, description = "Too many open WebSocket sessions")
+    ),
+    tag = "query"
+)]
+#[instrument(skip(state, headers, extensions, ws))]
+pub async fn ws_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    extensions: Extensions,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let priority = match request_priority(&state, &headers) {
+        Ok(priority) => priority,
+        Err(error) => return error.to_response(None),
+    };
+    // Each session holds a DuckDB connection for as long as it is open
+    let Ok(slot) = state.ws_sessions.clone().try_acquire_owned() else {
+        warn!("WebSocket session limit reached");
+        return ApiError::server_busy("Too many open WebSocket sessions; try again later")
+            .to_response(None);
+    };
+    let source = AuditSource {
+        interface: "websocket",
+        ..AuditSource::http(&state, "/ws", &headers, &extensions)
+    };
+    ws.on_upgrade(move |socket| async move {
+        run_session(socket, state, priority, source).await;
+        drop(slot);
+    })
+}
+
+#[instrument(skip_all, fields(session_id = %Uuid::new_v4(), priority = priority.as_str()))]
+async fn run_session(
+    mut socket: WebSocket,
+    state: AppState,
+    priority: Priority,
+    source: AuditSource,
+) {
+    // Session state must not leak to other clients, so the session gets its own
+    // connection to the pool's database instead of holding a pooled one
+    let pool = state.pool_for(priority).clone();
+    let connections = state.connections.clone();
+    let opened = tokio::task::spawn_blocking(move || -> Result<Connection, DatabaseError> {
+        let conn = pool.get()?.try_clone()?;
+        connections.register(&conn);
+        Ok(conn)
+    })
+    .await
+    .map_err(DatabaseError::from)
+    .and_then(|result| result);
+
+    let conn = match opened {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!(error = %e, "Failed to open WebSocket session connection");
+            let message = ServerMessage::Error(ApiError::Database(e).to_error_response(None));
+            let _ = send(&mut socket, &message).await;
+            return;
+        }
+    };
+
+    info!("WebSocket session opened");
+    Metrics::incr(&state.metrics.ws_sessions);
+    let metrics = state.metrics.clone();
+
+    let mut session = Session {
+        interrupt: conn.interrupt_handle(),
+        conn: Arc::new(Mutex::new(conn)),
+        state,
+        priority,
+        source,
+        pending: VecDeque::new(),
+        running: None,
+    };
+    session.run(&mut socket).await;
+    session.stop_running();
+
+    Metrics::decr(&metrics.ws_sessions);
+    info!("WebSocket session closed");
+}
+
+/// A query waiting for the session's connection
+struct PendingQuery {
+    query_id: String,
+    sql: String,
+    limit: Option<usize>,
+    audit: Audit,
+}
+
+/// The query currently executing on the session's connection
+struct RunningQuery {
+    query_id: String,
+    is_write: bool,
+    started: Instant,
+    rows_sent: usize,
+    cancelled: Arc<AtomicBool>,
+    chunks: mpsc::Receiver<ResultChunk>,
+    audit: Audit,
+    task: JoinHandle<Result<serde_json::Value, ApiError>>,
+}
+
+/// Something that happened to the running query
+enum QueryEvent {
+    Chunk(ResultChunk),
+    Finished(Result<Result<serde_json::Value, ApiError>, JoinError>),
+}
+
+/// Per-socket state: the private connection and the queries waiting for it
+struct Session {
+    state: AppState,
+    priority: Priority,
+    source: AuditSource,
+    conn: Arc<Mutex<Connection>>,
+    interrupt: Arc<InterruptHandle>,
+    pending: VecDeque<PendingQuery>,
+    running: Option<RunningQuery>,
+}
+
+impl Session {
+    async fn run(&mut self, socket: &mut WebSocket) {
+        let idle_timeout = self.state.ws_idle_timeout;
+        let mut last_activity = tokio::time::Instant::now();
+        let mut progress = tokio::time::interval(PROGRESS_INTERVAL);
+        progress.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            if self.start_next() {
+                progress.reset();
+            }
+            let is_idle = self.running.is_none();
+
+            let outgoing = tokio::select! {
+                message = socket.recv() => {
+                    last_activity = tokio::time::Instant::now();
+                    match message {
+                        Some(Ok(Message::Text(text))) => self.handle_message(text.as_str()),
+                        Some(Ok(Message::Binary(_))) => vec![error_message(
+                            ApiError::bad_request("Binary messages are not supported"),
+                            None,
+                        )],
+                        Some(Ok(Message::Close(_))) | None => break,
+                        Some(Ok(_)) => Vec::new(),
+                        Some(Err(e)) => {
+                            debug!(error = %e, "WebSocket receive failed");
+                            break;
+                        }
+                    }
+                }
+                event = next_event(&mut self.running) => {
+                    last_activity = tokio::time::Instant::now();
+                    self.handle_event(event)
+                }
+                _ = progress.tick(), if !is_idle => self.progress().into_iter().collect(),
+                _ = tokio::time::sleep_until(last_activity + idle_timeout), if is_idle => {
+                    info!(idle_secs = idle_timeout.as_secs(), "Closing idle WebSocket session");
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::NORMAL,
+                            reason: "Session idle timeout".into(),
+                        })))
+                        .await;
+                    break;
+                }
+            };
+
+            for message in &outgoing {
+                if let Err(e) = send(socket, message).await {
+                    debug!(error = %e, "WebSocket send failed");
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle_message(&mut self, text: &str) -> Vec<ServerMessage> {
+        let message = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!(error = %e, "Invalid WebSocket message");
+                let error = ApiError::bad_request(format!("Invalid message: {}", e));
+                return vec![error_message(error, None)];
+            }
+        };
+
+        match message {
+            ClientMessage::Query { id, sql, limit } => {
+                self.enqueue(id, sql, limit).into_iter().collect()
+            }
+            ClientMessage::Cancel { id } => self.cancel(id).into_iter().collect(),
+            ClientMessage::Ping => vec![ServerMessage::Pong],
+        }
+    }
+
+    /// Validate a query and queue it behind the running one
+    fn enqueue(
+        &mut self,
+        id: Option<String>,
+        sql: String,
+        limit: Option<usize>,
+    ) -> Option<ServerMessage> {
+        let query_id = id.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let mut audit = Audit::begin(&self.state, &self.source, &query_id, &sql);
+
+        let rejection = if let Some(message) = validate_readonly_operation(&self.state, &sql) {
+            warn!("Read-only violation detected");
+            Some(ApiError::forbidden(message))
+        } else if let Some(message) = validate_sandbox_operation(&self.state, &sql) {
+            warn!("Sandbox violation detected");
+            Some(ApiError::forbidden(message))
+        } else if self.pending.len() >= MAX_PENDING_QUERIES {
+            Some(ApiError::server_busy(format!(
+                "Session already has {} queries queued",
+                MAX_PENDING_QUERIES
+            )))
+        } else {
+            None
+        };
+        if let Some(error) = rejection {
+            audit.failed(&error);
+            return Some(error_message(error, Some(query_id)));
+        }
+
+        debug!(query_id = %query_id, queued = self.pending.len(), "Query queued");
+        self.pending.push_back(PendingQuery {
+            query_id,
+            sql,
+            limit,
+            audit,
+        });
+        None
+    }
+
+    /// Interrupt the running query or drop a queued one.
+    /// A cancelled running query reports `QUERY_CANCELLED` once it stops.
+    fn cancel(&mut self, id: Option<String>) -> Option<ServerMessage> {
+        if let Some(running) = &self.running
+            && id.as_ref().is_none_or(|id| *id == running.query_id)
+        {
+            info!(query_id = %running.query_id, "Cancelling running query");
+            running.cancelled.store(true, Ordering::Relaxed);
+            // Aborting covers the admission wait, interrupting covers execution
+            running.task.abort();
+            self.interrupt.interrupt();
+            return None;
+        }
+
+        if let Some(id) = &id
+            && let Some(index) = self.pending.iter().position(|q| q.query_id == *id)
+        {
+            info!(query_id = %id, "Cancelling queued query");
+            let mut query = self.pending.remove(index);
+            let error = ApiError::cancelled("Query was cancelled before it started");
+            if let Some(query) = &mut query {
+                query.audit.failed(&error);
+            }
+            return Some(error_message(error, Some(id.clone())));
+        }
+
+        Some(error_message(
+            ApiError::bad_request("No matching query to cancel"),
+            id,
+        ))
+    }
+
+    /// Start the next queued query if the connection is free
+    fn start_next(&mut self) -> bool {
+        if self.running.is_some() {
+            return false;
+        }
+        let Some(query) = self.pending.pop_front() else {
+            return false;
+        };
+
+        info!(query_id = %query.query_id, "Starting session query");
+        let (sender, chunks) = mpsc::channel(CHUNK_CHANNEL_CAPACITY);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let is_write = is_write_operation(&query.sql);
+        let task = tokio::spawn(execute(
+            self.state.clone(),
+            self.priority,
+            self.conn.clone(),
+            query.sql,
+            query.limit,
+            sender,
+            cancelled.clone(),
+        ));
+
+        self.running = Some(RunningQuery {
+            query_id: query.query_id,
+            is_write,
+            started: Instant::now(),
+            rows_sent: 0,
+            cancelled,
+            chunks,
+            audit: query.audit,
+            task,
+        });
+        true
+    }
+
+    fn handle_event(&mut self, event: QueryEvent) -> Vec<ServerMessage> {
+        let Some(running) = self.running.as_mut() else {
+            return Vec::new();
+        };
+
+        let result = match event {
+            QueryEvent::Chunk(_) if running.cancelled.load(Ordering::Relaxed) => {
+                return Vec::new();
+            }
+            QueryEvent::Chunk(chunk) => {
+                running.rows_sent += chunk.rows.len();
+                return vec![ServerMessage::Chunk {
+                    query_id: running.query_id.clone(),
+                    chunk,
+                }];
+            }
+            QueryEvent::Finished(result) => result,
+        };
+
+        let Some(mut running) = self.running.take() else {
+            return Vec::new();
+        };
+        let execution_time_ms = running.started.elapsed().as_millis() as u64;
+
+        // Writes may have succeeded even if the query was cancelled or failed afterwards
+        if running.is_write
+            && let Some(cache) = &self.state.cache
+        {
+            cache.invalidate_all();
+        }
+
+        if running.cancelled.load(Ordering::Relaxed) {
+            info!(query_id = %running.query_id, execution_time_ms, "Session query cancelled");
+            let error = ApiError::cancelled("Query was cancelled");
+            running.audit.failed(&error);
+            return vec![error_message(error, Some(running.query_id))];
+        }
+
+        match result.unwrap_or_else(|e| Err(ApiError::Database(DatabaseError::TaskJoin(e)))) {
+            Ok(data) => {
+                info!(
+                    query_id = %running.query_id,
+                    execution_time_ms,
+                    row_count = running.rows_sent,
+                    "Session query executed successfully"
+                );
+                running.audit.succeeded(&data);
+                vec![ServerMessage::Result(QueryResponse {
+                    success: true,
+                    data: Some(data),
+                    error: None,
+                    query_id: running.query_id,
+                    execution_time_ms,
+                    profile: None,
+                })]
+            }
+            Err(e) => {
+                error!(
+                    query_id = %running.query_id,
+                    execution_time_ms,
+                    error = %e,
+                    "Session query failed"
+                );
+                running.audit.failed(&e);
+                vec![error_message(e, Some(running.query_id))]
+            }
+        }
+    }
+
+    fn progress(&self) -> Option<ServerMessage> {
+        self.running
+            .as_ref()
+            .map(|running| ServerMessage::Progress {
+                query_id: running.query_id.clone(),
+                rows_sent: running.rows_sent,
+                elapsed_ms: running.started.elapsed().as_millis() as u64,
+            })
+    }
+
+    /// Interrupt whatever is still running when the socket goes away
+    fn stop_running(&mut self) {
+        self.pending.clear();
+        if let Some(running) = self.running.take() {
+            debug!(query_id = %running.query_id, "Interrupting query of closed session");
+            running.cancelled.store(true, Ordering::Relaxed);
+            running.task.abort();
+            self.interrupt.interrupt();
+        }
+    }
+}
+
+/// Wait for an execution slot, then stream the query's rows into `chunks`
+async fn execute(
+    state: AppState,
+    priority: Priority,
+    conn: Arc<Mutex<Connection>>,
+    sql: String,
+    limit: Option<usize>,
+    chunks: mpsc::Sender<ResultChunk>,
+    cancelled: Arc<AtomicBool>,
+) -> Result<serde_json::Value, ApiError> {
+    let permit = state
+        .admission_for(priority)
+        .acquire(&state.metrics)
+        .await
+        .map_err(|e| ApiError::server_busy(e.to_string()))?;
+
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(ApiError::cancelled("Query was cancelled"));
+        }
+        stream_sql_with_limit(&conn, &sql, limit, CHUNK_ROWS, |chunk| {
+            !cancelled.load(Ordering::Relaxed) && chunks.blocking_send(chunk).is_ok()
+        })
+        .map_err(ApiError::Database)
+    })
+    .await
+    .map_err(|e| ApiError::Database(DatabaseError::TaskJoin(e)))?
+}
+
+/// Next chunk of the running query, or its outcome once every chunk was received
+async fn next_event(running: &mut Option<RunningQuery>) -> QueryEvent {
+    let Some(running) = running else {
+        return std::future::pending().await;
+    };
+    match running.chunks.recv().await {
+        Some(chunk) => QueryEvent::Chunk(chunk),
+        None => QueryEvent::Finished((&mut running.task).await),
+    }
+}
+
+fn error_message(error: ApiError, query_id: Option<String>) -> ServerMessage {
+    ServerMessage::Error(error.to_error_response(query_id))
+}
+
+async fn send(socket: &mut WebSocket, message: &ServerMessage) -> Result<(), axum::Error> {
+    let text = match serde_json::to_string(message) {
+        Ok(text) => text,
+        Err(e) => {
+            error!(error = %e, "Failed to serialize WebSocket message");
+            return Ok(());
+        }
+    };
+    socket.send(Message::Text(text.into())).await
+}
@@ -1,15 +1,11 @@
-use axum_test::TestServer;
+use axum_test::{TestServer, TestWebSocket};
+use clap::Parser;
 use rsduck::{AppState, Args};
 use serde_json::{Value, json};
 
 #[tokio::test]
 async fn test_health_check() {
-    let args = Args {
-        database: None,
-        readwrite: false,
-        port: 3001,
-        host: "0.0.0.0".to_string(),
-    };
+    let args = default_args();
 
     let state = AppState::new(&args).expect("Failed to create app state");
     let app = create_test_app(state);
@@ -23,16 +19,83 @@ async fn test_health_check() {
     assert_eq!(body["status"], "healthy");
     assert_eq!(body["readonly_mode"], false);
     assert!(body["timestamp"].is_number());
+    assert_eq!(body["queries_in_flight"], 0);
+    assert_eq!(body["queries_queued"], 0);
+}
+
+#[tokio::test]
+async fn test_liveness_and_readiness_probes() {
+    let state = AppState::new(&default_args()).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let response = server.get("/health/live").await;
+    assert_eq!(response.status_code(), 200);
+    let body: Value = response.json();
+    assert_eq!(body["status"], "alive");
+    assert!(body["uptime_secs"].is_number());
+
+    let response = server.get("/health/ready").await;
+    assert_eq!(response.status_code(), 200);
+    let body: Value = response.json();
+    assert_eq!(body["status"], "ready");
+    assert!(body["reason"].is_null());
+    assert_eq!(body["draining"], false);
+    assert!(body["duckdb_version"].as_str().unwrap().starts_with('v'));
+    assert!(body["loaded_extensions"].is_array());
+    assert!(body["database_size_bytes"].is_number());
+    assert!(body["temp_directory"].is_string());
+    assert!(body["free_disk_bytes"].as_u64().unwrap() > 0);
+    assert_eq!(body["pool"]["max_size"], 10);
+    assert!(body["pool"]["connections"].as_u64().unwrap() >= 1);
+    assert_eq!(body["batch_pool"]["queries_in_flight"], 0);
+}
+
+#[tokio::test]
+async fn test_readiness_fails_when_pool_exhausted_or_draining() {
+    let state = AppState::new(&default_args()).expect("Failed to create app state");
+    let app = create_test_app(state.clone());
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let held: Vec<_> = (0..state.pool.max_size())
+        .map(|_| state.pool.get().unwrap())
+        .collect();
+    let response = server.get("/health/ready").await;
+    assert_eq!(response.status_code(), 503);
+    let body: Value = response.json();
+    assert_eq!(body["status"], "not_ready");
+    assert!(body["reason"].as_str().unwrap().contains("connection"));
+    assert_eq!(body["pool"]["idle_connections"], 0);
+    drop(held);
+    assert_eq!(server.get("/health/ready").await.status_code(), 200);
+
+    state.shutdown.begin();
+    let response = server.get("/health/ready").await;
+    assert_eq!(response.status_code(), 503);
+    let body: Value = response.json();
+    assert_eq!(body["draining"], true);
+    // Liveness is unaffected by draining
+    assert_eq!(server.get("/health/live").await.status_code(), 200);
+}
+
+#[tokio::test]
+async fn test_readiness_checks_free_disk_space() {
+    let mut args = default_args();
+    args.ready_min_free_disk_mb = u64::MAX / (1024 * 1024);
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let response = server.get("/health/ready").await;
+    assert_eq!(response.status_code(), 503);
+    let body: Value = response.json();
+    assert!(body["reason"].as_str().unwrap().contains("free"));
+    assert!(body["free_disk_bytes"].is_number());
 }
 
 #[tokio::test]
 async fn test_simple_query() {
-    let args = Args {
-        database: None,
-        readwrite: false,
-        port: 3001,
-        host: "0.0.0.0".to_string(),
-    };
+    let args = default_args();
 
     let state = AppState::new(&args).expect("Failed to create app state");
     let app = create_test_app(state);
@@ -56,12 +119,7 @@ async fn test_simple_query() {
 
 #[tokio::test]
 async fn test_query_with_limit() {
-    let args = Args {
-        database: None,
-        readwrite: false,
-        port: 3001,
-        host: "0.0.0.0".to_string(),
-    };
+    let args = default_args();
 
     let state = AppState::new(&args).expect("Failed to create app state");
     let app = create_test_app(state);
@@ -84,19 +142,11 @@ async fn test_query_with_limit() {
 
 #[tokio::test]
 async fn test_readonly_protection() {
-    let args = Args {
-        database: None,
-        readwrite: false, // Force readonly mode
-        port: 3001,
-        host: "0.0.0.0".to_string(),
-    };
+    let args = default_args();
 
     // Create a custom state with readonly forced
-    let state = AppState {
-        pool: AppState::new(&args).unwrap().pool,
-        db_path: None,
-        is_readonly: true, // Force readonly
-    };
+    let mut state = AppState::new(&args).unwrap();
+    state.is_readonly = true; // Force readonly
 
     let app = create_test_app(state);
     let server = TestServer::new(app).expect("Failed to create test server");
@@ -120,20 +170,413 @@ async fn test_readonly_protection() {
 }
 
 #[tokio::test]
-async fn test_sql_injection_protection() {
-    let args = Args {
-        database: None,
-        readwrite: false,
-        port: 3001,
-        host: "0.0.0.0".to_string(),
+async fn test_audit_log_records_statements_and_rejections() {
+    let audit_path =
+        std::env::temp_dir().join(format!("rsduck-audit-{}.jsonl", uuid::Uuid::new_v4()));
+    let mut args = default_args();
+    args.audit_log = Some(audit_path.clone());
+    args.audit_redact = true;
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let server = TestServer::new(create_test_app(state.clone())).unwrap();
+
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT 42 AS answer, 'secret' AS word" }))
+        .await;
+    assert_eq!(response.status_code(), 200);
+    let query_id = response.json::<Value>()["query_id"].clone();
+    let response = server
+        .post("/execute")
+        .json(&json!({ "sql": "CREATE TEMP TABLE audited AS SELECT * FROM range(3)" }))
+        .await;
+    assert_eq!(response.status_code(), 200);
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT * FROM missing_table" }))
+        .await;
+    assert_eq!(response.status_code(), 400);
+
+    let mut readonly = state.clone();
+    readonly.is_readonly = true;
+    let readonly_server = TestServer::new(create_test_app(readonly)).unwrap();
+    let response = readonly_server
+        .post("/query")
+        .json(&json!({ "sql": "DROP TABLE audited" }))
+        .await;
+    assert_eq!(response.status_code(), 403);
+
+    // Every statement of a request gets its own record
+    let response = server
+        .post("/execute")
+        .json(&json!({ "sql": "INSERT INTO audited VALUES (3); DELETE FROM audited WHERE range = 0" }))
+        .await;
+    assert_eq!(response.status_code(), 200);
+
+    state.audit.as_ref().unwrap().flush();
+    let records: Vec<Value> = std::fs::read_to_string(&audit_path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    std::fs::remove_file(&audit_path).ok();
+    assert_eq!(records.len(), 6, "{:?}", records);
+
+    assert_eq!(records[0]["query_id"], query_id);
+    assert_eq!(records[0]["interface"], "http");
+    assert_eq!(records[0]["endpoint"], "/query");
+    assert_eq!(records[0]["sql"], "SELECT ? AS answer, ? AS word");
+    assert_eq!(records[0]["redacted"], true);
+    assert_eq!(records[0]["statement_type"], "SELECT");
+    assert_eq!(records[0]["rows_returned"], 1);
+    assert_eq!(records[0]["status"], "ok");
+    assert!(records[0]["client"].as_str().unwrap().starts_with("ip:"));
+
+    assert_eq!(records[1]["endpoint"], "/execute");
+    assert_eq!(records[1]["statement_type"], "CREATE");
+    assert_eq!(records[1]["status"], "ok");
+
+    assert_eq!(records[2]["status"], "error");
+    assert!(records[2]["error_code"].is_string());
+
+    assert_eq!(records[3]["statement_type"], "DROP");
+    assert_eq!(records[3]["status"], "rejected");
+    assert_eq!(records[3]["error_code"], "FORBIDDEN");
+
+    assert_eq!(records[4]["query_id"], records[5]["query_id"]);
+    assert_eq!(records[4]["statement_type"], "INSERT");
+    assert_eq!(records[4]["sql"], "INSERT INTO audited VALUES (?)");
+    assert_eq!(records[5]["statement_type"], "DELETE");
+    assert_eq!(records[5]["rows_affected"], 1);
+    assert!(records.iter().all(|record| record["status"] != "cancelled"));
+}
+
+#[tokio::test]
+async fn test_audit_redact_covers_dollar_quoted_strings() {
+    let audit_path =
+        std::env::temp_dir().join(format!("rsduck-audit-{}.jsonl", uuid::Uuid::new_v4()));
+    let mut args = default_args();
+    args.audit_log = Some(audit_path.clone());
+    args.audit_redact = true;
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let server = TestServer::new(create_test_app(state.clone())).unwrap();
+
+    let response = server
+        .post("/query")
+        .json(&json!({
+            "sql": "SELECT $$hunter2$$ AS a, $pw$it's 'secret'$pw$ AS b, 'x' AS \"word\" -- note"
+        }))
+        .await;
+    assert_eq!(response.status_code(), 200);
+
+    state.audit.as_ref().unwrap().flush();
+    let record: Value =
+        serde_json::from_str(std::fs::read_to_string(&audit_path).unwrap().trim()).unwrap();
+    std::fs::remove_file(&audit_path).ok();
+    assert_eq!(
+        record["sql"],
+        "SELECT ? AS a, ? AS b, ? AS \"word\" -- note"
+    );
+}
+
+#[tokio::test]
+async fn test_audit_log_rotation() {
+    let audit_path =
+        std::env::temp_dir().join(format!("rsduck-audit-{}.jsonl", uuid::Uuid::new_v4()));
+    let log = rsduck::AuditLog::open(
+        rsduck::AuditSink::File {
+            path: audit_path.clone(),
+            max_bytes: 1024,
+            max_files: 2,
+        },
+        false,
+        100,
+        std::sync::Arc::new(rsduck::Metrics::new()),
+    )
+    .unwrap();
+    let record: rsduck::AuditRecord = serde_json::from_value(json!({
+        "timestamp_ms": 0,
+        "query_id": "q",
+        "interface": "http",
+        "endpoint": "/query",
+        "client": "ip:127.0.0.1",
+        "source_ip": "127.0.0.1",
+        "sql": "SELECT 1",
+        "redacted": false,
+        "statement_type": "SELECT",
+        "params_hash": null,
+        "rows_returned": 1,
+        "rows_affected": null,
+        "duration_ms": 0,
+        "status": "ok",
+        "error_code": null
+    }))
+    .unwrap();
+    for _ in 0..50 {
+        log.write(&record);
+    }
+    log.flush();
+
+    let rotated = |n: usize| std::path::PathBuf::from(format!("{}.{}", audit_path.display(), n));
+    for path in [audit_path.clone(), rotated(1), rotated(2)] {
+        let len = std::fs::metadata(&path).unwrap().len();
+        assert!(
+            len > 0 && len <= 1024,
+            "{} is {} bytes",
+            path.display(),
+            len
+        );
+    }
+    assert!(!rotated(3).exists());
+    for path in [audit_path.clone(), rotated(1), rotated(2)] {
+        std::fs::remove_file(path).ok();
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_audit_log_drops_records_when_queue_is_full() {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    // A FIFO nobody reads from stalls the writer once the pipe buffer fills
+    let fifo = std::env::temp_dir().join(format!("rsduck-audit-{}.fifo", uuid::Uuid::new_v4()));
+    let path = std::ffi::CString::new(fifo.to_str().unwrap()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+    let reader = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&fifo)
+        .unwrap();
+
+    let metrics = std::sync::Arc::new(rsduck::Metrics::new());
+    let log = rsduck::AuditLog::open(
+        rsduck::AuditSink::File {
+            path: fifo.clone(),
+            max_bytes: 0,
+            max_files: 1,
+        },
+        false,
+        4,
+        metrics.clone(),
+    )
+    .unwrap();
+    let record: rsduck::AuditRecord = serde_json::from_value(json!({
+        "timestamp_ms": 0,
+        "query_id": "q",
+        "interface": "http",
+        "endpoint": "/query",
+        "client": "ip:127.0.0.1",
+        "source_ip": "127.0.0.1",
+        "sql": format!("SELECT '{}'", "x".repeat(1000)),
+        "redacted": false,
+        "statement_type": "SELECT",
+        "params_hash": null,
+        "rows_returned": 1,
+        "rows_affected": null,
+        "duration_ms": 0,
+        "status": "ok",
+        "error_code": null
+    }))
+    .unwrap();
+
+    // Writing never blocks, however far behind the sink is
+    for _ in 0..1000 {
+        log.write(&record);
+    }
+    let dropped = metrics
+        .audit_dropped_total
+        .load(std::sync::atomic::Ordering::Relaxed);
+    assert!(dropped > 0 && dropped < 1000, "dropped {}", dropped);
+    assert!(
+        metrics
+            .render()
+            .contains(&format!("rsduck_audit_dropped_total {}", dropped))
+    );
+
+    // Closing the reader fails the stalled writes, which drains the queue
+    drop(reader);
+    log.flush();
+    std::fs::remove_file(&fifo).ok();
+}
+
+#[tokio::test]
+async fn test_query_history_filters_and_fingerprints() {
+    let mut args = default_args();
+    args.slow_query_threshold_ms = Some(0);
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let server = TestServer::new(create_test_app(state)).unwrap();
+
+    for sql in [
+        "SELECT 1 AS n",
+        "SELECT  2 AS n -- another literal",
+        "SELECT * FROM range(3) WHERE range IN (1, 2)",
+        "SELECT * FROM missing_table",
+    ] {
+        server.post("/query").json(&json!({ "sql": sql })).await;
+    }
+
+    let response = server.get("/admin/history").await;
+    assert_eq!(response.status_code(), 200);
+    let body: Value = response.json();
+    assert_eq!(body["matched"], 4);
+    let entries = body["entries"].as_array().unwrap();
+    assert_eq!(entries[0]["query"], "SELECT * FROM missing_table");
+    assert_eq!(entries[0]["status"], "error");
+    assert_eq!(
+        entries[1]["query"],
+        "SELECT * FROM range(?) WHERE range IN (?)"
+    );
+    assert_eq!(entries[1]["rows_returned"], 2);
+    assert_eq!(entries[2]["fingerprint"], entries[3]["fingerprint"]);
+    // Fingerprints are truncated SHA-256, so they do not change between builds
+    assert_eq!(entries[3]["query"], "SELECT ? AS n");
+    assert_eq!(entries[3]["fingerprint"], "4168b13b9bb1f11d");
+
+    let body: Value = server
+        .get("/admin/history")
+        .add_query_param("status", "error")
+        .await
+        .json();
+    assert_eq!(body["matched"], 1);
+    assert_eq!(body["entries"][0]["interface"], "http");
+
+    let body: Value = server
+        .get("/admin/history")
+        .add_query_param("group_by", "fingerprint")
+        .add_query_param("statement_type", "select")
+        .await
+        .json();
+    let fingerprints = body["fingerprints"].as_array().unwrap();
+    assert_eq!(fingerprints.len(), 3);
+    assert!(body["entries"].as_array().unwrap().is_empty());
+    let repeated = fingerprints
+        .iter()
+        .find(|f| f["query"] == "SELECT ? AS n")
+        .unwrap();
+    assert_eq!(repeated["count"], 2);
+    assert_eq!(repeated["errors"], 0);
+    assert!(repeated["p95_ms"].as_u64().unwrap() >= repeated["p50_ms"].as_u64().unwrap());
+    assert!(repeated["total_time_ms"].is_number());
+
+    let mut args = default_args();
+    args.history_size = 0;
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let server = TestServer::new(create_test_app(state)).unwrap();
+    assert_eq!(server.get("/admin/history").await.status_code(), 404);
+}
+
+#[tokio::test]
+async fn test_query_history_requires_admin_key() {
+    let args = Args::parse_from([
+        "rsduck",
+        "--api-key",
+        "analyst",
+        "--admin-api-key",
+        "operator",
+        "--saved-query-api-key",
+        "app",
+    ]);
+    assert!(Args::try_parse_from(["rsduck", "--admin-api-key", "operator"]).is_err());
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let server = TestServer::new(create_test_app(state)).unwrap();
+
+    let history = |key: Option<&'static str>| {
+        let request = server.get("/admin/history");
+        match key {
+            Some(key) => request.add_header("x-api-key", key),
+            None => request,
+        }
     };
+    history(None).await.assert_status_unauthorized();
+    history(Some("guess")).await.assert_status_unauthorized();
+    // Other clients' statements are only visible to admin keys
+    let response = history(Some("analyst")).await;
+    response.assert_status_forbidden();
+    assert_eq!(response.json::<Value>()["error"]["code"], "FORBIDDEN");
+    history(Some("app")).await.assert_status_forbidden();
+    history(Some("operator")).await.assert_status_ok();
+
+    // Admin keys work everywhere a regular key does
+    server
+        .post("/query")
+        .add_header("x-api-key", "operator")
+        .json(&json!({ "sql": "SELECT 1" }))
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_query_history_persisted_to_table() {
+    let db_path =
+        std::env::temp_dir().join(format!("rsduck-history-{}.duckdb", uuid::Uuid::new_v4()));
+    let mut args = default_args();
+    args.database = Some(db_path.clone());
+    args.readwrite = true;
+    args.history_table = Some("query_history".to_string());
+
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let server = TestServer::new(create_test_app(state.clone())).unwrap();
+    server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT 42 AS answer" }))
+        .await;
+    rsduck::shutdown::close_database(&state);
+    drop(server);
+    drop(state);
+
+    let state = AppState::new(&args).expect("Failed to reopen app state");
+    let server = TestServer::new(create_test_app(state)).unwrap();
+    let body: Value = server.get("/admin/history").await.json();
+    assert_eq!(body["entries"][0]["query"], "SELECT ? AS answer");
+    assert_eq!(body["entries"][0]["status"], "ok");
+
+    let body: Value = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT count(*) AS n FROM query_history" }))
+        .await
+        .json();
+    assert_eq!(body["data"]["rows"][0][0], 1);
+
+    let mut readonly_args = args.clone();
+    readonly_args.readwrite = false;
+    assert!(AppState::new(&readonly_args).is_err());
+    std::fs::remove_file(&db_path).ok();
+}
+
+#[tokio::test]
+async fn test_query_history_table_in_memory_with_busy_pool() {
+    let mut args = default_args();
+    args.history_table = Some("query_history".to_string());
+    args.slow_query_threshold_ms = Some(0);
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let server = TestServer::new(create_test_app(state.clone())).unwrap();
+    server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT 42 AS answer" }))
+        .await
+        .assert_status_ok();
+
+    // Writing the history needs none of the pooled connections admission hands out
+    let held: Vec<_> = (0..state.pool.max_size())
+        .map(|_| state.pool.get().unwrap())
+        .collect();
+    let started = std::time::Instant::now();
+    state.history.as_ref().unwrap().flush();
+    assert!(started.elapsed() < std::time::Duration::from_secs(2));
+
+    // ...and it lands in the same in-memory database as the pooled connections
+    let n: i64 = held[0]
+        .query_row("SELECT count(*) FROM query_history", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(n, 1);
+}
+
+#[tokio::test]
+async fn test_sql_injection_protection() {
+    let args = default_args();
 
     // Create a custom state with readonly forced
-    let state = AppState {
-        pool: AppState::new(&args).unwrap().pool,
-        db_path: None,
-        is_readonly: true, // Force readonly
-    };
+    let mut state = AppState::new(&args).unwrap();
+    state.is_readonly = true; // Force readonly
 
     let app = create_test_app(state);
     let server = TestServer::new(app).expect("Failed to create test server");
@@ -159,12 +602,7 @@ async fn test_sql_injection_protection() {
 
 #[tokio::test]
 async fn test_missing_sql_parameter() {
-    let args = Args {
-        database: None,
-        readwrite: false,
-        port: 3001,
-        host: "0.0.0.0".to_string(),
-    };
+    let args = default_args();
 
     let state = AppState::new(&args).expect("Failed to create app state");
     let app = create_test_app(state);
@@ -184,14 +622,113 @@ async fn test_missing_sql_parameter() {
     );
 }
 
+#[tokio::test]
+async fn test_database_errors_are_classified_problem_details() {
+    let args = Args::parse_from(["rsduck", "--readwrite"]);
+
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let server = TestServer::new(create_test_app(state)).expect("Failed to create test server");
+
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT * FROM secret_table" }))
+        .await;
+    assert_eq!(response.status_code(), 400);
+    assert_eq!(
+        response.header("content-type").to_str().unwrap(),
+        "application/problem+json"
+    );
+    let body: Value = response.json();
+    assert_eq!(body["type"], "urn:rsduck:error:catalog-error");
+    assert_eq!(body["title"], "Catalog error");
+    assert_eq!(body["status"], 400);
+    assert_eq!(body["success"], false);
+    assert_eq!(body["error"]["code"], "CATALOG_ERROR");
+    assert!(body["error"]["details"].is_null());
+    assert!(!body.to_string().contains("secret_table"));
+
+    // Syntax errors point at the offending token
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT 1,\n  2 FROM FROM t" }))
+        .await;
+    assert_eq!(response.status_code(), 400);
+    let body: Value = response.json();
+    assert_eq!(body["error"]["code"], "SYNTAX_ERROR");
+    assert_eq!(body["position"], json!({"line": 2, "column": 10}));
+
+    // Columns count characters, so multibyte text before the error neither shifts nor breaks them
+    for (sql, column) in [
+        ("SELECT 'éé', 'éééééééééééééééééé' || FRM", 38),
+        ("SELECT '日本語日本' AS a,  FRM x", 23),
+    ] {
+        let response = server.post("/query").json(&json!({ "sql": sql })).await;
+        assert_eq!(response.status_code(), 400, "{}", sql);
+        let body: Value = response.json();
+        assert_eq!(body["position"]["column"], column, "{}", sql);
+    }
+
+    // Constraint violations are conflicts
+    server
+        .post("/execute")
+        .json(&json!({ "sql": "CREATE TABLE users (id INTEGER PRIMARY KEY)" }))
+        .await
+        .assert_status_ok();
+    server
+        .post("/execute")
+        .json(&json!({ "sql": "INSERT INTO users VALUES (1)" }))
+        .await
+        .assert_status_ok();
+    let response = server
+        .post("/execute")
+        .json(&json!({ "sql": "INSERT INTO users VALUES (1)" }))
+        .await;
+    assert_eq!(response.status_code(), 409);
+    let body: Value = response.json();
+    assert_eq!(body["error"]["code"], "CONSTRAINT_VIOLATION");
+
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT 'abc'::INTEGER" }))
+        .await;
+    assert_eq!(response.status_code(), 400);
+    let body: Value = response.json();
+    assert_eq!(body["error"]["code"], "CONVERSION_ERROR");
+
+    // Non-database errors use the same format
+    let response = server.get("/query").await;
+    assert_eq!(
+        response.header("content-type").to_str().unwrap(),
+        "application/problem+json"
+    );
+    let body: Value = response.json();
+    assert_eq!(body["type"], "urn:rsduck:error:bad-request");
+    assert_eq!(body["title"], "Bad Request");
+}
+
+#[tokio::test]
+async fn test_verbose_errors_include_database_message() {
+    let mut args = default_args();
+    args.verbose_errors = true;
+
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let server = TestServer::new(create_test_app(state)).expect("Failed to create test server");
+
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT * FROM secret_table" }))
+        .await;
+    assert_eq!(response.status_code(), 400);
+    let body: Value = response.json();
+    assert_eq!(body["error"]["code"], "CATALOG_ERROR");
+    let details = body["error"]["details"].as_str().unwrap();
+    assert!(details.contains("Table with name secret_table does not exist"));
+    assert!(body["detail"].as_str().unwrap().contains("secret_table"));
+}
+
 #[tokio::test]
 async fn test_decimal_type_handling() {
-    let args = Args {
-        database: None,
-        readwrite: false,
-        port: 3001,
-        host: "0.0.0.0".to_string(),
-    };
+    let args = default_args();
 
     let state = AppState::new(&args).expect("Failed to create app state");
     let app = create_test_app(state);
@@ -210,15 +747,19 @@ async fn test_decimal_type_handling() {
     assert_eq!(body["data"]["columns"], json!(["decimal_col", "date_col"]));
     assert!(body["data"]["column_types"].is_array());
     assert_eq!(body["data"]["column_types"].as_array().unwrap().len(), 2);
-    
+
     let rows = &body["data"]["rows"];
     assert!(rows.is_array());
     let first_row = &rows[0];
-    
+
     // The decimal should be converted to JSON number, not string
-    assert!(first_row[0].is_number(), "DECIMAL should be a number, got: {:?}", first_row[0]);
+    assert!(
+        first_row[0].is_number(),
+        "DECIMAL should be a number, got: {:?}",
+        first_row[0]
+    );
     assert_ne!(first_row[0].as_str().unwrap_or(""), "<UNSUPPORTED_TYPE>");
-    
+
     // The date should be converted to string, not <UNSUPPORTED_TYPE>
     assert!(first_row[1].is_string());
     assert_ne!(first_row[1].as_str().unwrap(), "<UNSUPPORTED_TYPE>");
@@ -226,12 +767,7 @@ async fn test_decimal_type_handling() {
 
 #[tokio::test]
 async fn test_column_types_included() {
-    let args = Args {
-        database: None,
-        readwrite: false,
-        port: 3001,
-        host: "0.0.0.0".to_string(),
-    };
+    let args = default_args();
 
     let state = AppState::new(&args).expect("Failed to create app state");
     let app = create_test_app(state);
@@ -247,21 +783,28 @@ async fn test_column_types_included() {
 
     let body: Value = response.json();
     assert_eq!(body["success"], true);
-    
+
     // Check that both columns and column_types are present
-    assert_eq!(body["data"]["columns"], json!(["int_col", "text_col", "bool_col"]));
+    assert_eq!(
+        body["data"]["columns"],
+        json!(["int_col", "text_col", "bool_col"])
+    );
     assert!(body["data"]["column_types"].is_array());
-    
+
     let column_types = body["data"]["column_types"].as_array().unwrap();
     assert_eq!(column_types.len(), 3);
-    
+
     // Column types should be SQL type names
     for column_type in column_types {
         assert!(column_type.is_string());
         let type_str = column_type.as_str().unwrap();
         assert!(!type_str.is_empty());
         // Should be uppercase SQL type names
-        assert!(type_str.chars().all(|c| c.is_uppercase() || c.is_ascii_digit()));
+        assert!(
+            type_str
+                .chars()
+                .all(|c| c.is_uppercase() || c.is_ascii_digit())
+        );
         // Should not be debug format (no parentheses or lowercase)
         assert!(!type_str.contains("("));
         assert!(!type_str.contains("["));
@@ -270,12 +813,7 @@ async fn test_column_types_included() {
 
 #[tokio::test]
 async fn test_specific_sql_type_names() {
-    let args = Args {
-        database: None,
-        readwrite: false,
-        port: 3001,
-        host: "0.0.0.0".to_string(),
-    };
+    let args = default_args();
 
     let state = AppState::new(&args).expect("Failed to create app state");
     let app = create_test_app(state);
@@ -291,30 +829,23 @@ async fn test_specific_sql_type_names() {
 
     let body: Value = response.json();
     assert_eq!(body["success"], true);
-    
+
     let column_types = body["data"]["column_types"].as_array().unwrap();
     assert_eq!(column_types.len(), 4);
-    
+
     // Verify that we get proper SQL type names
-    let type_names: Vec<&str> = column_types.iter()
-        .map(|v| v.as_str().unwrap())
-        .collect();
-    
+    let type_names: Vec<&str> = column_types.iter().map(|v| v.as_str().unwrap()).collect();
+
     // Should contain SQL-like type names
     assert!(type_names.iter().any(|&t| t == "INTEGER" || t == "BIGINT"));
-    assert!(type_names.iter().any(|&t| t == "VARCHAR"));  
-    assert!(type_names.iter().any(|&t| t == "DECIMAL"));
-    assert!(type_names.iter().any(|&t| t == "BOOLEAN"));
+    assert!(type_names.contains(&"VARCHAR"));
+    assert!(type_names.contains(&"DECIMAL"));
+    assert!(type_names.contains(&"BOOLEAN"));
 }
 
 #[tokio::test]
 async fn test_decimal_values_as_numbers() {
-    let args = Args {
-        database: None,
-        readwrite: false,
-        port: 3001,
-        host: "0.0.0.0".to_string(),
-    };
+    let args = default_args();
 
     let state = AppState::new(&args).expect("Failed to create app state");
     let app = create_test_app(state);
@@ -330,23 +861,39 @@ async fn test_decimal_values_as_numbers() {
 
     let body: Value = response.json();
     assert_eq!(body["success"], true);
-    
+
     let rows = &body["data"]["rows"];
     assert!(rows.is_array());
     let first_row = &rows[0];
-    
+
     // DECIMAL should be a JSON number, not a string
-    assert!(first_row[0].is_number(), "DECIMAL value should be a number, got: {:?}", first_row[0]);
-    
+    assert!(
+        first_row[0].is_number(),
+        "DECIMAL value should be a number, got: {:?}",
+        first_row[0]
+    );
+
     // Integer should be a JSON number
-    assert!(first_row[1].is_number(), "Integer value should be a number, got: {:?}", first_row[1]);
-    
+    assert!(
+        first_row[1].is_number(),
+        "Integer value should be a number, got: {:?}",
+        first_row[1]
+    );
+
     // Text should be a string
-    assert!(first_row[2].is_string(), "Text value should be a string, got: {:?}", first_row[2]);
-    
+    assert!(
+        first_row[2].is_string(),
+        "Text value should be a string, got: {:?}",
+        first_row[2]
+    );
+
     // Verify the actual decimal value
     if let Some(decimal_val) = first_row[0].as_f64() {
-        assert!((decimal_val - 123.45).abs() < 0.001, "DECIMAL value should be 123.45, got: {}", decimal_val);
+        assert!(
+            (decimal_val - 123.45).abs() < 0.001,
+            "DECIMAL value should be 123.45, got: {}",
+            decimal_val
+        );
     } else {
         panic!("DECIMAL value could not be converted to f64");
     }
@@ -354,12 +901,7 @@ async fn test_decimal_values_as_numbers() {
 
 #[tokio::test]
 async fn test_comprehensive_duckdb_types() {
-    let args = Args {
-        database: None,
-        readwrite: false,
-        port: 3001,
-        host: "0.0.0.0".to_string(),
-    };
+    let args = default_args();
 
     let state = AppState::new(&args).expect("Failed to create app state");
     let app = create_test_app(state);
@@ -415,45 +957,45 @@ async fn test_comprehensive_duckdb_types() {
 
     let body: Value = response.json();
     assert_eq!(body["success"], true);
-    
+
     let columns = body["data"]["columns"].as_array().unwrap();
     let column_types = body["data"]["column_types"].as_array().unwrap();
     let rows = body["data"]["rows"].as_array().unwrap();
-    
+
     assert_eq!(columns.len(), column_types.len());
     assert!(!rows.is_empty());
-    
+
     let first_row = &rows[0];
-    
+
     // Check that we have values for all columns
     assert_eq!(first_row.as_array().unwrap().len(), columns.len());
-    
+
     // Look for any UNSUPPORTED_TYPE values
     let mut unsupported_types = Vec::new();
     let mut unsupported_values = Vec::new();
-    
+
     for (i, value) in first_row.as_array().unwrap().iter().enumerate() {
         let column_name = columns[i].as_str().unwrap();
         let column_type = column_types[i].as_str().unwrap();
-        
+
         if value.is_string() && value.as_str().unwrap() == "<UNSUPPORTED_TYPE>" {
             unsupported_values.push(format!("Column '{}' (type: {})", column_name, column_type));
         }
-        
+
         if column_type == "UNKNOWN" {
             unsupported_types.push(format!("Column '{}' has unknown type", column_name));
         }
     }
-    
+
     // Print detailed information about what we found
     println!("=== COMPREHENSIVE TYPE TEST RESULTS ===");
     println!("Total columns tested: {}", columns.len());
-    
+
     for (i, _) in columns.iter().enumerate() {
         let column_name = columns[i].as_str().unwrap();
         let column_type = column_types[i].as_str().unwrap();
         let value = &first_row.as_array().unwrap()[i];
-        
+
         let value_type = if value.is_null() {
             "NULL"
         } else if value.as_bool().is_some() {
@@ -469,9 +1011,9 @@ async fn test_comprehensive_duckdb_types() {
         } else {
             "UNKNOWN"
         };
-        
+
         println!("  {}: {} -> JSON {}", column_name, column_type, value_type);
-        
+
         if value.is_string() && value.as_str().unwrap() == "<UNSUPPORTED_TYPE>" {
             println!("    ❌ UNSUPPORTED VALUE");
         } else if column_type == "UNKNOWN" {
@@ -480,42 +1022,3190 @@ async fn test_comprehensive_duckdb_types() {
             println!("    ✅ OK");
         }
     }
-    
+
     if !unsupported_values.is_empty() {
         println!("\n❌ UNSUPPORTED VALUES FOUND:");
         for item in &unsupported_values {
             println!("  - {}", item);
         }
     }
-    
+
     if !unsupported_types.is_empty() {
         println!("\n⚠️  UNKNOWN TYPES FOUND:");
         for item in &unsupported_types {
             println!("  - {}", item);
         }
     }
-    
+
     if unsupported_values.is_empty() && unsupported_types.is_empty() {
         println!("\n✅ ALL TYPES ARE PROPERLY SUPPORTED!");
     }
-    
+
     // The test should pass even if we find unsupported types - this is for discovery
     // But we should fail if basic types are unsupported
-    assert!(unsupported_values.len() < 5, "Too many unsupported values found: {:?}", unsupported_values);
+    assert!(
+        unsupported_values.len() < 5,
+        "Too many unsupported values found: {:?}",
+        unsupported_values
+    );
 }
 
-fn create_test_app(state: AppState) -> axum::Router {
-    use axum::routing::{get, post};
-    use rsduck::{
-        execute_command_get, execute_command_post, execute_query_get, execute_query_post,
-        health_check,
-    };
+#[tokio::test]
+async fn test_sandbox_blocks_files_outside_allowed_directories() {
+    let allowed_dir = std::env::temp_dir().join(format!("rsduck-sandbox-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&allowed_dir).expect("Failed to create sandbox directory");
+    let csv_path = allowed_dir.join("data.csv");
+    std::fs::write(&csv_path, "id,name\n1,alice\n2,bob\n").expect("Failed to write CSV");
+
+    let mut args = default_args();
+    args.sandbox = true;
+    args.allowed_directories = vec![allowed_dir.clone()];
+
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    // Files inside the allowed directory can still be read
+    let query = serde_json::json!({
+        "sql": format!("SELECT * FROM read_csv('{}')", csv_path.display())
+    });
+    let response = server.post("/query").json(&query).await;
+    assert_eq!(response.status_code(), 200);
+    let body: Value = response.json();
+    assert_eq!(body["data"]["row_count"], 2);
+
+    // File-reading table functions are rejected everywhere else
+    for sql in [
+        "SELECT * FROM read_csv('/etc/passwd')",
+        "SELECT * FROM read_text('/etc/hostname')",
+        "SELECT * FROM read_blob('/etc/hosts')",
+    ] {
+        let response = server.post("/query").json(&json!({ "sql": sql })).await;
+        assert_eq!(response.status_code(), 403, "{} should be rejected", sql);
+        let body: Value = response.json();
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "PERMISSION_DENIED");
+    }
+
+    std::fs::remove_dir_all(&allowed_dir).ok();
+}
+
+#[tokio::test]
+async fn test_sandbox_configuration_is_locked() {
+    let mut args = default_args();
+    args.sandbox = true;
+
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    for sql in [
+        "SET enable_external_access = true",
+        "SET allowed_directories = ['/']",
+        "SET lock_configuration = false",
+    ] {
+        let response = server.post("/execute").json(&json!({ "sql": sql })).await;
+        assert_eq!(response.status_code(), 400, "{} should be rejected", sql);
+    }
+
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT current_setting('enable_external_access') AS v" }))
+        .await;
+    assert_eq!(response.status_code(), 200);
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows"], json!([[false]]));
+}
+
+#[tokio::test]
+async fn test_sandbox_blocks_extension_loading() {
+    let mut args = default_args();
+    args.sandbox = true;
+
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    for sql in [
+        "INSTALL httpfs",
+        "LOAD httpfs",
+        "/* sneaky */ FORCE INSTALL httpfs",
+    ] {
+        let response = server.post("/execute").json(&json!({ "sql": sql })).await;
+        assert_eq!(response.status_code(), 403, "{} should be forbidden", sql);
+        let body: Value = response.json();
+        assert!(
+            body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("sandbox mode")
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_rate_limit_returns_429_with_retry_after() {
+    let mut args = default_args();
+    args.rate_limit_rps = 0.5;
+    args.rate_limit_burst = 2;
+
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let query = json!({ "sql": "SELECT 1" });
+    for _ in 0..2 {
+        let response = server.post("/query").json(&query).await;
+        assert_eq!(response.status_code(), 200);
+    }
+
+    let response = server.post("/query").json(&query).await;
+    assert_eq!(response.status_code(), 429);
+    let retry_after: u64 = response
+        .header("retry-after")
+        .to_str()
+        .unwrap()
+        .parse()
+        .expect("Retry-After should be a number of seconds");
+    assert!(retry_after >= 1);
+
+    let body: Value = response.json();
+    assert_eq!(body["success"], false);
+    assert_eq!(body["error"]["code"], "RATE_LIMITED");
+
+    // Keys the server does not accept identify nobody, so rotating them still hits the IP's bucket
+    for key in ["rotated-1", "rotated-2"] {
+        let response = server
+            .post("/query")
+            .add_header("x-api-key", key)
+            .json(&query)
+            .await;
+        assert_eq!(response.status_code(), 429);
+    }
+
+    // Health checks are never rate limited
+    let response = server.get("/health").await;
+    assert_eq!(response.status_code(), 200);
+
+    let metrics = server.get("/metrics").await.text();
+    assert!(metrics.contains("rsduck_rate_limited_requests_total 3"));
+}
 
-    axum::Router::new()
-        .route("/health", get(health_check))
-        .route("/query", post(execute_query_post))
-        .route("/query", get(execute_query_get))
-        .route("/execute", post(execute_command_post))
-        .route("/execute", get(execute_command_get))
-        .with_state(state)
+#[tokio::test]
+async fn test_rate_limit_buckets_per_verified_api_key() {
+    let args = Args::parse_from([
+        "rsduck",
+        "--rate-limit-rps",
+        "0.5",
+        "--rate-limit-burst",
+        "1",
+        "--api-key",
+        "alpha",
+        "--api-key",
+        "beta",
+    ]);
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let server = TestServer::new(create_test_app(state)).expect("Failed to create test server");
+
+    let query = json!({ "sql": "SELECT 1" });
+    let send = |key: &'static str| {
+        server
+            .post("/query")
+            .add_header("x-api-key", key)
+            .json(&query)
+    };
+    assert_eq!(send("alpha").await.status_code(), 200);
+    assert_eq!(send("alpha").await.status_code(), 429);
+
+    // Each accepted key has its own bucket
+    assert_eq!(send("beta").await.status_code(), 200);
+
+    // Invalid keys share the peer's bucket: the first is refused by authentication, the next
+    // by the limiter before its key is even checked
+    assert_eq!(send("guess-1").await.status_code(), 401);
+    assert_eq!(send("guess-2").await.status_code(), 429);
+}
+
+#[test]
+fn test_key_identity_is_keyed_by_secret() {
+    let configured = |secret: &str| {
+        let args = Args::parse_from(["rsduck", "--key-identity-secret", secret]);
+        AppState::new(&args).expect("Failed to create app state")
+    };
+    let first = configured("first-secret");
+    let identity = rsduck::rate_limit::key_identity(&first, "alpha");
+    assert!(identity.starts_with("key:"));
+    assert_eq!(identity.len(), "key:".len() + 16);
+    assert!(!identity.contains("alpha"));
+
+    // A configured secret keeps identities stable across restarts
+    let restarted = configured("first-secret");
+    assert_eq!(
+        rsduck::rate_limit::key_identity(&restarted, "alpha"),
+        identity
+    );
+    assert_ne!(rsduck::rate_limit::key_identity(&first, "beta"), identity);
+
+    // Without the secret, the identity of a guessed key cannot be computed
+    let other = configured("second-secret");
+    assert_ne!(rsduck::rate_limit::key_identity(&other, "alpha"), identity);
+    let random = AppState::new(&default_args()).expect("Failed to create app state");
+    assert_ne!(rsduck::rate_limit::key_identity(&random, "alpha"), identity);
+}
+
+#[tokio::test]
+async fn test_concurrency_limit_per_client() {
+    let limiter = rsduck::RateLimiter::new(rsduck::RateLimitConfig {
+        requests_per_second: 0.0,
+        burst: 1,
+        max_concurrent: 2,
+    });
+
+    assert!(limiter.acquire("ip:10.0.0.1").is_ok());
+    assert!(limiter.acquire("ip:10.0.0.1").is_ok());
+    assert_eq!(
+        limiter.acquire("ip:10.0.0.1"),
+        Err(rsduck::rate_limit::Rejection::TooManyConcurrent)
+    );
+    // Other clients are unaffected
+    assert!(limiter.acquire("ip:10.0.0.2").is_ok());
+
+    limiter.release("ip:10.0.0.1");
+    assert!(limiter.acquire("ip:10.0.0.1").is_ok());
+}
+
+#[tokio::test]
+async fn test_admission_queue_full_returns_server_busy() {
+    let args = default_args();
+    let mut state = AppState::new(&args).expect("Failed to create app state");
+    state.admission =
+        std::sync::Arc::new(rsduck::AdmissionController::new(rsduck::AdmissionConfig {
+            max_in_flight: 1,
+            max_queue_length: 0,
+            queue_timeout: std::time::Duration::from_secs(5),
+        }));
+
+    // Occupy the only execution slot
+    let _permit = state
+        .admission
+        .acquire(&state.metrics)
+        .await
+        .expect("First statement should be admitted");
+
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT 1" }))
+        .await;
+    assert_eq!(response.status_code(), 503);
+    let body: Value = response.json();
+    assert_eq!(body["success"], false);
+    assert_eq!(body["error"]["code"], "SERVER_BUSY");
+    assert!(body["query_id"].is_string());
+
+    let body: Value = server.get("/health").await.json();
+    assert_eq!(body["queries_in_flight"], 1);
+    assert_eq!(body["queries_queued"], 0);
+
+    let metrics = server.get("/metrics").await.text();
+    assert!(metrics.contains("rsduck_queue_rejected_total 1"));
+}
+
+#[tokio::test]
+async fn test_admission_queue_wait_timeout() {
+    let metrics = std::sync::Arc::new(rsduck::Metrics::new());
+    let admission = rsduck::AdmissionController::new(rsduck::AdmissionConfig {
+        max_in_flight: 1,
+        max_queue_length: 1,
+        queue_timeout: std::time::Duration::from_millis(50),
+    });
+
+    let permit = admission.acquire(&metrics).await.unwrap();
+    let result = admission.acquire(&metrics).await;
+    assert!(matches!(result, Err(rsduck::AdmissionError::Timeout(_))));
+    assert_eq!(admission.queued(), 0);
+
+    // Releasing the slot lets the next statement straight in
+    drop(permit);
+    assert_eq!(admission.in_flight(), 0);
+    assert!(admission.acquire(&metrics).await.is_ok());
+    assert!(metrics.render().contains("rsduck_queue_timeouts_total 1"));
+}
+
+#[tokio::test]
+async fn test_batch_priority_uses_batch_pool() {
+    let mut args = default_args();
+    args.batch_threads = 2;
+    args.batch_api_keys = vec!["export-job".to_string()];
+
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let query = json!({ "sql": "SELECT current_setting('threads') AS threads" });
+
+    let response = server
+        .post("/query")
+        .add_header("x-query-priority", "batch")
+        .json(&query)
+        .await;
+    assert_eq!(response.status_code(), 200);
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows"], json!([[2]]));
+
+    // Batch API keys are scheduled as batch without the header
+    let response = server
+        .post("/query")
+        .add_header("x-api-key", "export-job")
+        .json(&query)
+        .await;
+    assert_eq!(response.status_code(), 200);
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows"], json!([[2]]));
+
+    let response = server
+        .post("/query")
+        .add_header("x-query-priority", "urgent")
+        .json(&query)
+        .await;
+    assert_eq!(response.status_code(), 400);
+}
+
+#[tokio::test]
+async fn test_batch_priority_shares_interactive_database() {
+    for sandbox in [false, true] {
+        let mut args = default_args();
+        args.batch_threads = 2;
+        args.sandbox = sandbox;
+        let state = AppState::new(&args).expect("Failed to create app state");
+        let server = TestServer::new(create_test_app(state)).expect("Failed to create test server");
+
+        server
+            .post("/execute")
+            .json(&json!({"sql": "CREATE TABLE exports AS SELECT 5 AS n"}))
+            .await
+            .assert_status_ok();
+        let response = server
+            .post("/query")
+            .add_header("x-query-priority", "batch")
+            .json(&json!({"sql": "SELECT n FROM exports"}))
+            .await;
+        assert_eq!(response.status_code(), 200, "sandbox: {}", sandbox);
+        let body: Value = response.json();
+        assert_eq!(body["data"]["rows"], json!([[5]]), "sandbox: {}", sandbox);
+    }
+}
+
+#[tokio::test]
+async fn test_batch_queue_does_not_starve_interactive() {
+    let args = default_args();
+    let mut state = AppState::new(&args).expect("Failed to create app state");
+    state.batch_admission =
+        std::sync::Arc::new(rsduck::AdmissionController::new(rsduck::AdmissionConfig {
+            max_in_flight: 1,
+            max_queue_length: 0,
+            queue_timeout: std::time::Duration::from_secs(5),
+        }));
+
+    // Saturate batch capacity
+    let _permit = state
+        .batch_admission
+        .acquire(&state.metrics)
+        .await
+        .expect("First batch statement should be admitted");
+
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+    let query = json!({ "sql": "SELECT 1" });
+
+    let response = server
+        .post("/query")
+        .add_header("x-query-priority", "batch")
+        .json(&query)
+        .await;
+    assert_eq!(response.status_code(), 503);
+
+    let response = server.post("/query").json(&query).await;
+    assert_eq!(response.status_code(), 200);
+
+    let body: Value = server.get("/health").await.json();
+    assert_eq!(body["batch_queries_in_flight"], 1);
+    assert_eq!(body["queries_in_flight"], 0);
+}
+
+#[tokio::test]
+async fn test_result_cache_hit_miss_and_invalidation() {
+    let mut args = default_args();
+    args.cache_ttl_secs = 60;
+
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT 42 AS answer" }))
+        .await;
+    assert_eq!(response.status_code(), 200);
+    assert_eq!(response.header("x-cache"), "MISS");
+    assert_eq!(response.header("cache-control"), "private, max-age=60");
+
+    // Comments and whitespace are normalized away in the cache key
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "/* tile 7 */ SELECT   42 AS answer -- refresh" }))
+        .await;
+    assert_eq!(response.status_code(), 200);
+    assert_eq!(response.header("x-cache"), "HIT");
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows"], json!([[42]]));
+
+    // Literals are part of the key byte for byte, even when they look like whitespace or comments
+    for (sql, expected) in [
+        ("SELECT 'a  b' AS v", "a  b"),
+        ("SELECT 'a b' AS v", "a b"),
+        ("SELECT 'x--1' AS v", "x--1"),
+        ("SELECT 'x--2' AS v", "x--2"),
+        ("SELECT 'y/*z*/' AS v", "y/*z*/"),
+        ("SELECT 'y' AS v", "y"),
+    ] {
+        let response = server.post("/query").json(&json!({ "sql": sql })).await;
+        assert_eq!(response.header("x-cache"), "MISS", "{}", sql);
+        let body: Value = response.json();
+        assert_eq!(body["data"]["rows"], json!([[expected]]));
+    }
+
+    // A different limit is a different result
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT 42 AS answer", "limit": 5 }))
+        .await;
+    assert_eq!(response.header("x-cache"), "MISS");
+
+    // Writes are never cached
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "CREATE TEMP TABLE t AS SELECT 1 AS x" }))
+        .await;
+    assert_eq!(response.header("x-cache"), "BYPASS");
+    assert_eq!(response.header("cache-control"), "no-store");
+
+    // A successful /execute invalidates everything
+    let response = server
+        .post("/execute")
+        .json(&json!({ "sql": "CREATE TEMP TABLE u (id INT)" }))
+        .await;
+    assert_eq!(response.status_code(), 200);
+
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT 42 AS answer" }))
+        .await;
+    assert_eq!(response.header("x-cache"), "MISS");
+
+    let metrics = server.get("/metrics").await.text();
+    assert!(metrics.contains("rsduck_cache_hits_total 1"));
+    assert!(metrics.contains("rsduck_cache_misses_total 9"));
+}
+
+#[tokio::test]
+async fn test_result_cache_disabled_by_default() {
+    let state = AppState::new(&default_args()).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT 1" }))
+        .await;
+    assert_eq!(response.status_code(), 200);
+    assert!(response.maybe_header("x-cache").is_none());
+}
+
+#[tokio::test]
+async fn test_api_key_required_when_configured() {
+    let args = Args::parse_from(["rsduck", "--api-key", "secret", "--batch-api-key", "etl"]);
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+    let query = json!({ "sql": "SELECT 1" });
+
+    let response = server.post("/query").json(&query).await;
+    assert_eq!(response.status_code(), 401);
+    let body: Value = response.json();
+    assert_eq!(body["error"]["code"], "UNAUTHORIZED");
+
+    let response = server
+        .post("/query")
+        .add_header("x-api-key", "wrong")
+        .json(&query)
+        .await;
+    assert_eq!(response.status_code(), 401);
+
+    for key in ["secret", "etl"] {
+        let response = server
+            .post("/query")
+            .add_header("x-api-key", key)
+            .json(&query)
+            .await;
+        assert_eq!(response.status_code(), 200);
+    }
+
+    // Health and metrics stay open for probes and scrapers
+    assert_eq!(server.get("/health").await.status_code(), 200);
+}
+
+#[tokio::test]
+async fn test_pg_wire_simple_query_protocol() {
+    let db_path = std::env::temp_dir().join(format!("rsduck-pg-{}.duckdb", uuid::Uuid::new_v4()));
+    let args = Args::parse_from([
+        "rsduck",
+        "--database",
+        db_path.to_str().unwrap(),
+        "--readwrite",
+    ]);
+    let port = start_pg_server(AppState::new(&args).expect("Failed to create app state")).await;
+    let client = pg_connect(port, None).await.expect("Failed to connect");
+
+    let messages = client
+        .simple_query(
+            "CREATE TABLE sales (id INTEGER, amount DECIMAL(10,2), day DATE, note VARCHAR);
+             INSERT INTO sales VALUES (1, 12.50, '2023-12-25', 'a;b'), (2, NULL, '2024-01-01', NULL);
+             SELECT id, amount, day, note FROM sales ORDER BY id",
+        )
+        .await
+        .expect("Simple query failed");
+
+    let tags: Vec<u64> = messages
+        .iter()
+        .filter_map(|m| match m {
+            tokio_postgres::SimpleQueryMessage::CommandComplete(rows) => Some(*rows),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(tags, vec![0, 2, 2]);
+
+    let rows: Vec<_> = messages
+        .iter()
+        .filter_map(|m| match m {
+            tokio_postgres::SimpleQueryMessage::Row(row) => Some(row),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(rows[0].get("amount"), Some("12.50"));
+    assert_eq!(rows[0].get("day"), Some("2023-12-25"));
+    assert_eq!(rows[0].get("note"), Some("a;b"));
+    assert_eq!(rows[1].get("amount"), None);
+
+    // Errors are reported without dropping the connection
+    let error = client
+        .simple_query("SELECT * FROM missing_table")
+        .await
+        .expect_err("Query should fail");
+    assert_eq!(
+        error.code(),
+        Some(&tokio_postgres::error::SqlState::UNDEFINED_TABLE)
+    );
+    assert!(client.simple_query("SELECT 1").await.is_ok());
+
+    drop(client);
+    std::fs::remove_file(&db_path).ok();
+}
+
+#[tokio::test]
+async fn test_pg_wire_writes_invalidate_result_cache() {
+    let db_path = std::env::temp_dir().join(format!("rsduck-pg-{}.duckdb", uuid::Uuid::new_v4()));
+    let args = Args::parse_from([
+        "rsduck",
+        "--database",
+        db_path.to_str().unwrap(),
+        "--readwrite",
+        "--cache-ttl-secs",
+        "60",
+    ]);
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let port = start_pg_server(state.clone()).await;
+    let server = TestServer::new(create_test_app(state)).expect("Failed to create test server");
+    let client = pg_connect(port, None).await.expect("Failed to connect");
+
+    client
+        .batch_execute("CREATE TABLE events (id INTEGER); INSERT INTO events VALUES (1)")
+        .await
+        .unwrap();
+    let count = json!({ "sql": "SELECT count(*) AS n FROM events" });
+    let response = server.post("/query").json(&count).await;
+    assert_eq!(response.header("x-cache"), "MISS");
+
+    client
+        .batch_execute("INSERT INTO events VALUES (2)")
+        .await
+        .unwrap();
+    let response = server.post("/query").json(&count).await;
+    assert_eq!(response.header("x-cache"), "MISS");
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows"], json!([[2]]));
+
+    std::fs::remove_file(&db_path).ok();
+}
+
+#[tokio::test]
+async fn test_pg_wire_extended_query_protocol() {
+    use tokio_postgres::types::Type;
+
+    let port =
+        start_pg_server(AppState::new(&default_args()).expect("Failed to create app state")).await;
+    let client = pg_connect(port, None).await.expect("Failed to connect");
+
+    // Untyped parameters are described as text and cast by DuckDB
+    let row = client
+        .query_one(
+            "SELECT $1::INTEGER + 1 AS n, upper($2) AS s, 2.5::DOUBLE AS f, true AS b",
+            &[&"41", &"duck"],
+        )
+        .await
+        .expect("Prepared query failed");
+    assert_eq!(row.get::<_, i32>("n"), 42);
+    assert_eq!(row.get::<_, &str>("s"), "DUCK");
+    assert_eq!(row.get::<_, f64>("f"), 2.5);
+    assert!(row.get::<_, bool>("b"));
+
+    // Typed parameters are sent in binary
+    let rows = client
+        .query_typed(
+            "SELECT i FROM range(10) t(i) WHERE i >= $1 AND i < $2",
+            &[(&7i64, Type::INT8), (&100i64, Type::INT8)],
+        )
+        .await
+        .expect("Typed query failed");
+    let values: Vec<i64> = rows.iter().map(|row| row.get(0)).collect();
+    assert_eq!(values, vec![7, 8, 9]);
+
+    // Statements can be prepared once and executed repeatedly
+    let statement = client
+        .prepare_typed("SELECT $1 * 2 AS doubled", &[Type::INT4])
+        .await
+        .expect("Prepare failed");
+    for n in [1i32, 21] {
+        let row = client
+            .query_one(&statement, &[&n])
+            .await
+            .expect("Execute failed");
+        assert_eq!(row.get::<_, i32>("doubled"), n * 2);
+    }
+}
+
+#[tokio::test]
+async fn test_pg_wire_idle_transaction_holds_admission_and_times_out() {
+    let db_path = std::env::temp_dir().join(format!("rsduck-pg-{}.duckdb", uuid::Uuid::new_v4()));
+    let args = Args::parse_from([
+        "rsduck",
+        "--database",
+        db_path.to_str().unwrap(),
+        "--readwrite",
+        "--pg-idle-in-transaction-timeout-secs",
+        "1",
+    ]);
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let port = start_pg_server(state.clone()).await;
+    let client = pg_connect(port, None).await.expect("Failed to connect");
+
+    client
+        .batch_execute("CREATE TABLE t (id INTEGER); BEGIN; INSERT INTO t VALUES (1)")
+        .await
+        .unwrap();
+    // The pinned connection counts against admission while the transaction is open
+    assert_eq!(state.admission.in_flight(), 1);
+
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    assert!(client.is_closed());
+    assert!(client.simple_query("COMMIT").await.is_err());
+    assert_eq!(state.admission.in_flight(), 0);
+
+    // The transaction was rolled back before its connection went back to the pool
+    let client = pg_connect(port, None).await.expect("Failed to connect");
+    let row = client
+        .query_one("SELECT count(*)::INTEGER FROM t", &[])
+        .await
+        .unwrap();
+    assert_eq!(row.get::<_, i32>(0), 0);
+
+    // Statements outside a transaction release their slot immediately
+    assert_eq!(state.admission.in_flight(), 0);
+
+    drop(client);
+    std::fs::remove_file(&db_path).ok();
+}
+
+#[tokio::test]
+async fn test_pg_wire_authentication_and_read_only() {
+    let db_path = std::env::temp_dir().join(format!("rsduck-pg-{}.duckdb", uuid::Uuid::new_v4()));
+    {
+        let conn = duckdb::Connection::open(&db_path).expect("Failed to create database");
+        conn.execute_batch("CREATE TABLE t AS SELECT 1 AS id")
+            .expect("Failed to seed database");
+    }
+
+    let args = Args::parse_from([
+        "rsduck",
+        "--database",
+        db_path.to_str().unwrap(),
+        "--api-key",
+        "secret",
+    ]);
+    let port = start_pg_server(AppState::new(&args).expect("Failed to create app state")).await;
+
+    let error = pg_connect(port, Some("wrong"))
+        .await
+        .expect_err("Wrong password should be rejected");
+    assert_eq!(
+        error.code(),
+        Some(&tokio_postgres::error::SqlState::INVALID_PASSWORD)
+    );
+
+    let client = pg_connect(port, Some("secret"))
+        .await
+        .expect("Failed to connect");
+    let row = client
+        .query_one("SELECT count(*) AS n FROM t", &[])
+        .await
+        .expect("Read failed");
+    assert_eq!(row.get::<_, i64>("n"), 1);
+
+    let error = client
+        .execute("INSERT INTO t VALUES (2)", &[])
+        .await
+        .expect_err("Writes should be rejected in read-only mode");
+    assert_eq!(
+        error.code(),
+        Some(&tokio_postgres::error::SqlState::READ_ONLY_SQL_TRANSACTION)
+    );
+
+    drop(client);
+    std::fs::remove_file(&db_path).ok();
+}
+
+#[tokio::test]
+async fn test_flight_sql_queries_prepared_statements_and_ingest() {
+    use arrow_flight::sql::{
+        CommandGetDbSchemas, CommandGetTables, CommandStatementIngest, SqlInfo,
+        TableDefinitionOptions, TableExistsOption, TableNotExistOption,
+    };
+    use duckdb::arrow::array::{AsArray, Int32Array, Int64Array, RecordBatch, StringArray};
+    use duckdb::arrow::datatypes::{DataType, Field, Int64Type, Schema};
+    use std::sync::Arc;
+
+    let db_path =
+        std::env::temp_dir().join(format!("rsduck-flight-{}.duckdb", uuid::Uuid::new_v4()));
+    let args = Args::parse_from([
+        "rsduck",
+        "--database",
+        db_path.to_str().unwrap(),
+        "--readwrite",
+    ]);
+    let port = start_flight_server(AppState::new(&args).expect("Failed to create app state")).await;
+    let mut client = flight_connect(port).await;
+
+    let affected = client
+        .execute_update(
+            "CREATE TABLE users (id INTEGER, name VARCHAR)".to_string(),
+            None,
+        )
+        .await
+        .expect("CREATE failed");
+    assert_eq!(affected, 0);
+    let affected = client
+        .execute_update(
+            "INSERT INTO users VALUES (1, 'Ada'), (2, 'Grace'), (3, 'Linus')".to_string(),
+            None,
+        )
+        .await
+        .expect("INSERT failed");
+    assert_eq!(affected, 3);
+
+    let info = client
+        .execute("SELECT id, name FROM users ORDER BY id".to_string(), None)
+        .await
+        .expect("Query failed");
+    let schema = info.clone().try_decode_schema().expect("Invalid schema");
+    assert_eq!(schema.field(0).name(), "id");
+    assert_eq!(schema.field(0).data_type(), &DataType::Int32);
+    let batches = flight_fetch(&mut client, info).await;
+    let names: Vec<String> = batches
+        .iter()
+        .flat_map(|b| b.column(1).as_string::<i32>().iter().flatten())
+        .map(str::to_string)
+        .collect();
+    assert_eq!(names, vec!["Ada", "Grace", "Linus"]);
+
+    // Prepared statements bind one parameter set per row of the parameter batch
+    let mut prepared = client
+        .prepare("SELECT name FROM users WHERE id = $1".to_string(), None)
+        .await
+        .expect("Prepare failed");
+    assert_eq!(prepared.parameter_schema().unwrap().fields().len(), 1);
+    assert_eq!(prepared.dataset_schema().unwrap().field(0).name(), "name");
+    let params = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![Field::new("$1", DataType::Int64, false)])),
+        vec![Arc::new(Int64Array::from(vec![2]))],
+    )
+    .unwrap();
+    prepared.set_parameters(params).unwrap();
+    let info = prepared.execute().await.expect("Prepared execute failed");
+    let batches = flight_fetch(&mut client, info).await;
+    assert_eq!(batches[0].column(0).as_string::<i32>().value(0), "Grace");
+    prepared.close().await.expect("Close failed");
+
+    // Bulk ingest creates the table from the Arrow schema
+    let events = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("kind", DataType::Utf8, true),
+        ])),
+        vec![
+            Arc::new(Int32Array::from(vec![1, 2, 3, 4])),
+            Arc::new(StringArray::from(vec![
+                Some("click"),
+                None,
+                Some("view"),
+                Some("click"),
+            ])),
+        ],
+    )
+    .unwrap();
+    let command = CommandStatementIngest {
+        table_definition_options: Some(TableDefinitionOptions {
+            if_not_exist: TableNotExistOption::Create.into(),
+            if_exists: TableExistsOption::Fail.into(),
+        }),
+        table: "events".to_string(),
+        ..Default::default()
+    };
+    let ingested = client
+        .execute_ingest(command.clone(), futures::stream::iter([Ok(events.clone())]))
+        .await
+        .expect("Ingest failed");
+    assert_eq!(ingested, 4);
+    assert!(
+        client
+            .execute_ingest(command, futures::stream::iter([Ok(events)]))
+            .await
+            .is_err(),
+        "Ingesting into an existing table without append should fail"
+    );
+
+    let info = client
+        .execute(
+            "SELECT count(*) AS clicks FROM events WHERE kind = 'click'".to_string(),
+            None,
+        )
+        .await
+        .unwrap();
+    let batches = flight_fetch(&mut client, info).await;
+    assert_eq!(batches[0].column(0).as_primitive::<Int64Type>().value(0), 2);
+
+    // Catalog metadata
+    let info = client
+        .get_db_schemas(CommandGetDbSchemas::default())
+        .await
+        .expect("GetDbSchemas failed");
+    let batches = flight_fetch(&mut client, info).await;
+    let schemas: Vec<&str> = batches
+        .iter()
+        .flat_map(|b| b.column(1).as_string::<i32>().iter().flatten())
+        .collect();
+    assert!(schemas.contains(&"main"));
+
+    let info = client
+        .get_tables(CommandGetTables {
+            table_name_filter_pattern: Some("user%".to_string()),
+            include_schema: true,
+            ..Default::default()
+        })
+        .await
+        .expect("GetTables failed");
+    let batches = flight_fetch(&mut client, info).await;
+    let tables: Vec<&str> = batches
+        .iter()
+        .flat_map(|b| b.column(2).as_string::<i32>().iter().flatten())
+        .collect();
+    assert_eq!(tables, vec!["users"]);
+    assert_eq!(batches[0].column(3).as_string::<i32>().value(0), "TABLE");
+
+    let info = client
+        .get_sql_info(vec![SqlInfo::FlightSqlServerName])
+        .await
+        .expect("GetSqlInfo failed");
+    let batches = flight_fetch(&mut client, info).await;
+    assert_eq!(batches[0].num_rows(), 1);
+
+    drop(client);
+    std::fs::remove_file(&db_path).ok();
+}
+
+#[tokio::test]
+async fn test_flight_sql_writes_invalidate_result_cache() {
+    use arrow_flight::sql::{
+        CommandStatementIngest, TableDefinitionOptions, TableExistsOption, TableNotExistOption,
+    };
+    use duckdb::arrow::array::{Int32Array, RecordBatch};
+    use duckdb::arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    let db_path =
+        std::env::temp_dir().join(format!("rsduck-flight-{}.duckdb", uuid::Uuid::new_v4()));
+    let args = Args::parse_from([
+        "rsduck",
+        "--database",
+        db_path.to_str().unwrap(),
+        "--readwrite",
+        "--cache-ttl-secs",
+        "60",
+    ]);
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let port = start_flight_server(state.clone()).await;
+    let server = TestServer::new(create_test_app(state)).expect("Failed to create test server");
+    let mut client = flight_connect(port).await;
+
+    client
+        .execute_update("CREATE TABLE events (id INTEGER)".to_string(), None)
+        .await
+        .unwrap();
+    let count = json!({ "sql": "SELECT count(*) AS n FROM events" });
+    let response = server.post("/query").json(&count).await;
+    assert_eq!(response.header("x-cache"), "MISS");
+
+    client
+        .execute_update("INSERT INTO events VALUES (1)".to_string(), None)
+        .await
+        .unwrap();
+    let response = server.post("/query").json(&count).await;
+    assert_eq!(response.header("x-cache"), "MISS");
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows"], json!([[1]]));
+
+    let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int32, false)]));
+    let batch = RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(vec![2, 3]))]).unwrap();
+    let command = CommandStatementIngest {
+        table_definition_options: Some(TableDefinitionOptions {
+            if_not_exist: TableNotExistOption::Fail.into(),
+            if_exists: TableExistsOption::Append.into(),
+        }),
+        table: "events".to_string(),
+        ..Default::default()
+    };
+    client
+        .execute_ingest(command, futures::stream::iter([Ok(batch)]))
+        .await
+        .expect("Ingest failed");
+    let response = server.post("/query").json(&count).await;
+    assert_eq!(response.header("x-cache"), "MISS");
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows"], json!([[3]]));
+
+    std::fs::remove_file(&db_path).ok();
+}
+
+#[tokio::test]
+async fn test_flight_sql_authentication_and_read_only() {
+    let db_path =
+        std::env::temp_dir().join(format!("rsduck-flight-{}.duckdb", uuid::Uuid::new_v4()));
+    {
+        let conn = duckdb::Connection::open(&db_path).unwrap();
+        conn.execute_batch("CREATE TABLE t (x INTEGER); INSERT INTO t VALUES (1), (2)")
+            .unwrap();
+    }
+    let args = Args::parse_from([
+        "rsduck",
+        "--database",
+        db_path.to_str().unwrap(),
+        "--api-key",
+        "secret",
+    ]);
+    let port = start_flight_server(AppState::new(&args).expect("Failed to create app state")).await;
+
+    let mut client = flight_connect(port).await;
+    let error = client
+        .execute("SELECT * FROM t".to_string(), None)
+        .await
+        .expect_err("Unauthenticated query should fail");
+    assert!(error.to_string().contains("Unauthenticated"), "{}", error);
+    assert!(client.handshake("analyst", "wrong").await.is_err());
+
+    client
+        .handshake("analyst", "secret")
+        .await
+        .expect("Handshake failed");
+    let info = client
+        .execute("SELECT sum(x) FROM t".to_string(), None)
+        .await
+        .expect("Authenticated query failed");
+    let batches = flight_fetch(&mut client, info).await;
+    assert_eq!(batches[0].num_rows(), 1);
+
+    let error = client
+        .execute_update("INSERT INTO t VALUES (3)".to_string(), None)
+        .await
+        .expect_err("Write should be rejected on a read-only database");
+    assert!(error.to_string().contains("PermissionDenied"), "{}", error);
+
+    drop(client);
+    std::fs::remove_file(&db_path).ok();
+}
+
+#[tokio::test]
+async fn test_flight_sql_prepared_statements_are_scoped_and_audited() {
+    use arrow_flight::sql::{
+        CommandStatementIngest, TableDefinitionOptions, TableExistsOption, TableNotExistOption,
+    };
+    use duckdb::arrow::array::{Int32Array, RecordBatch};
+    use duckdb::arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    let audit_path =
+        std::env::temp_dir().join(format!("rsduck-audit-{}.jsonl", uuid::Uuid::new_v4()));
+    let mut args = default_args();
+    args.readwrite = true;
+    args.api_keys = vec!["alice-key".to_string(), "bob-key".to_string()];
+    args.audit_log = Some(audit_path.clone());
+    args.flight_prepared_idle_timeout_secs = 1;
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let port = start_flight_server(state.clone()).await;
+
+    let mut alice = flight_connect(port).await;
+    alice.handshake("alice", "alice-key").await.unwrap();
+    let mut bob = flight_connect(port).await;
+    bob.handshake("bob", "bob-key").await.unwrap();
+
+    // A prepared statement handle only works for the client that created it
+    let mut prepared = alice
+        .prepare("SELECT 42 AS answer".to_string(), None)
+        .await
+        .expect("Prepare failed");
+    let info = prepared.execute().await.expect("Prepared execute failed");
+    let ticket = info.endpoint[0].ticket.clone().unwrap();
+    let Err(error) = bob.do_get(ticket.clone()).await else {
+        panic!("Another client's handle should be unknown");
+    };
+    assert!(error.to_string().contains("NotFound"), "{}", error);
+    assert_eq!(flight_fetch(&mut alice, info).await[0].num_rows(), 1);
+
+    // Handles left unused past the idle timeout are dropped
+    tokio::time::sleep(std::time::Duration::from_millis(1200)).await;
+    let Err(error) = alice.do_get(ticket).await else {
+        panic!("An idle handle should expire");
+    };
+    assert!(error.to_string().contains("NotFound"), "{}", error);
+
+    // Bulk ingest is audited as an insert by the verified client
+    let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int32, false)]));
+    let batch = RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(vec![1, 2]))]).unwrap();
+    let command = CommandStatementIngest {
+        table_definition_options: Some(TableDefinitionOptions {
+            if_not_exist: TableNotExistOption::Create.into(),
+            if_exists: TableExistsOption::Fail.into(),
+        }),
+        table: "events".to_string(),
+        ..Default::default()
+    };
+    let ingested = bob
+        .execute_ingest(command, futures::stream::iter([Ok(batch)]))
+        .await
+        .expect("Ingest failed");
+    assert_eq!(ingested, 2);
+
+    state.audit.as_ref().unwrap().flush();
+    let records: Vec<Value> = std::fs::read_to_string(&audit_path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    std::fs::remove_file(&audit_path).ok();
+    let ingest = records.last().unwrap();
+    assert_eq!(ingest["interface"], "flight");
+    assert_eq!(ingest["sql"], "INSERT INTO \"events\"");
+    assert_eq!(ingest["statement_type"], "INSERT");
+    assert_eq!(ingest["rows_affected"], 2);
+    assert_eq!(ingest["status"], "ok");
+    assert_eq!(
+        ingest["client"],
+        rsduck::rate_limit::key_identity(&state, "bob-key")
+    );
+    assert_ne!(records[0]["client"], ingest["client"]);
+}
+
+#[tokio::test]
+async fn test_flight_sql_audits_unverified_keys_by_ip() {
+    let audit_path =
+        std::env::temp_dir().join(format!("rsduck-audit-{}.jsonl", uuid::Uuid::new_v4()));
+    let mut args = default_args();
+    args.audit_log = Some(audit_path.clone());
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let port = start_flight_server(state.clone()).await;
+
+    // Without configured keys any key is accepted, but it is not an identity
+    let mut client = flight_connect(port).await;
+    client.set_header("x-api-key", "made-up");
+    let info = client.execute("SELECT 1".to_string(), None).await.unwrap();
+    flight_fetch(&mut client, info).await;
+
+    state.audit.as_ref().unwrap().flush();
+    let log = std::fs::read_to_string(&audit_path).unwrap();
+    std::fs::remove_file(&audit_path).ok();
+    let record: Value = serde_json::from_str(log.lines().next().unwrap()).unwrap();
+    assert_eq!(record["client"], "ip:127.0.0.1");
+}
+
+#[tokio::test]
+async fn test_ws_session_keeps_state_and_streams_chunks() {
+    let mut args = default_args();
+    args.api_keys = vec!["notebook-key".to_string()];
+
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::builder()
+        .http_transport()
+        .build(app)
+        .expect("Failed to create test server");
+
+    // The upgrade request goes through API key authentication
+    server
+        .get_websocket("/ws")
+        .await
+        .assert_status_unauthorized();
+
+    let mut ws = server
+        .get_websocket("/ws")
+        .add_header("x-api-key", "notebook-key")
+        .await
+        .into_websocket()
+        .await;
+
+    ws.send_json(&json!({ "type": "ping" })).await;
+    let pong: Value = ws.receive_json().await;
+    assert_eq!(pong, json!({ "type": "pong" }));
+
+    let (_, result) = ws_query(
+        &mut ws,
+        "create",
+        "CREATE TEMP TABLE notes AS SELECT range AS id FROM range(1200)",
+    )
+    .await;
+    assert_eq!(result["type"], "result");
+    assert_eq!(result["success"], true);
+    assert_eq!(result["query_id"], "create");
+
+    // Rows arrive in chunks; the final result carries the summary
+    let (chunks, result) = ws_query(&mut ws, "select", "SELECT id FROM notes ORDER BY id").await;
+    let offsets: Vec<u64> = chunks
+        .iter()
+        .map(|c| c["offset"].as_u64().unwrap())
+        .collect();
+    assert_eq!(offsets, vec![0, 500, 1000]);
+    assert!(chunks.iter().all(|c| c["query_id"] == "select"));
+    assert_eq!(chunks[0]["columns"], json!(["id"]));
+    assert_eq!(chunks[0]["rows"][0], json!([0]));
+    assert_eq!(chunks[2]["rows"].as_array().unwrap().len(), 200);
+    assert_eq!(result["data"]["row_count"], 1200);
+    assert_eq!(result["data"]["columns"], json!(["id"]));
+    assert!(result["data"].get("rows").is_none());
+
+    // Variables and transactions persist on the session's connection
+    ws_query(&mut ws, "set", "SET VARIABLE answer = 42").await;
+    let (chunks, _) = ws_query(&mut ws, "get", "SELECT getvariable('answer') AS v").await;
+    assert_eq!(chunks[0]["rows"], json!([[42]]));
+
+    ws_query(&mut ws, "begin", "BEGIN TRANSACTION").await;
+    ws_query(&mut ws, "insert", "INSERT INTO notes VALUES (5000)").await;
+    ws_query(&mut ws, "rollback", "ROLLBACK").await;
+    let (chunks, _) = ws_query(&mut ws, "count", "SELECT count(*) FROM notes").await;
+    assert_eq!(chunks[0]["rows"], json!([[1200]]));
+
+    // The session's temp table is invisible to other clients
+    let response = server
+        .post("/query")
+        .add_header("x-api-key", "notebook-key")
+        .json(&json!({ "sql": "SELECT * FROM notes" }))
+        .await;
+    assert_eq!(response.status_code(), 400);
+
+    // Errors reuse the HTTP error shape
+    let (_, error) = ws_query(&mut ws, "missing", "SELECT * FROM no_such_table").await;
+    assert_eq!(error["type"], "error");
+    assert_eq!(error["success"], false);
+    assert_eq!(error["query_id"], "missing");
+    assert_eq!(error["error"]["code"], "CATALOG_ERROR");
+
+    ws.send_text("not json").await;
+    let error: Value = ws.receive_json().await;
+    assert_eq!(error["error"]["code"], "BAD_REQUEST");
+    assert_eq!(error["query_id"], Value::Null);
+}
+
+#[tokio::test]
+async fn test_ws_cancel_and_idle_timeout() {
+    let mut args = default_args();
+    args.ws_idle_timeout_secs = 1;
+
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::builder()
+        .http_transport()
+        .build(app)
+        .expect("Failed to create test server");
+
+    let mut ws = server.get_websocket("/ws").await.into_websocket().await;
+
+    ws.send_json(&json!({
+        "type": "query",
+        "id": "slow",
+        "sql": "SELECT sum(hash(a.range * b.range)) FROM range(100000) a, range(100000) b"
+    }))
+    .await;
+
+    // Long-running queries report progress, and are not closed as idle
+    let progress: Value = ws.receive_json().await;
+    assert_eq!(progress["type"], "progress");
+    assert_eq!(progress["query_id"], "slow");
+    assert!(progress["elapsed_ms"].as_u64().unwrap() >= 1000);
+
+    ws.send_json(&json!({ "type": "cancel", "id": "slow" }))
+        .await;
+    let cancelled: Value = loop {
+        let message: Value = ws.receive_json().await;
+        if message["type"] != "progress" {
+            break message;
+        }
+    };
+    assert_eq!(cancelled["type"], "error");
+    assert_eq!(cancelled["query_id"], "slow");
+    assert_eq!(cancelled["error"]["code"], "QUERY_CANCELLED");
+
+    // The session remains usable after a cancel
+    let (chunks, result) = ws_query(&mut ws, "after", "SELECT 1 AS one").await;
+    assert_eq!(result["type"], "result");
+    assert_eq!(chunks[0]["rows"], json!([[1]]));
+
+    ws.send_json(&json!({ "type": "cancel" })).await;
+    let error: Value = ws.receive_json().await;
+    assert_eq!(error["error"]["code"], "BAD_REQUEST");
+
+    // Idle sessions are closed to free their connection
+    match ws.receive_message().await {
+        axum_test::WsMessage::Close(Some(frame)) => {
+            assert_eq!(frame.reason.as_str(), "Session idle timeout")
+        }
+        other => panic!("expected close frame, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_ws_session_limit_and_shutdown_interrupts() {
+    let mut args = default_args();
+    args.max_ws_sessions = 1;
+
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state.clone());
+    let server = TestServer::builder()
+        .http_transport()
+        .build(app)
+        .expect("Failed to create test server");
+
+    let mut ws = server.get_websocket("/ws").await.into_websocket().await;
+    server
+        .get_websocket("/ws")
+        .await
+        .assert_status_service_unavailable();
+
+    // The session's private connection is interrupted along with the pooled ones
+    ws.send_json(&json!({
+        "type": "query",
+        "id": "slow",
+        "sql": "SELECT sum(hash(a.range * b.range)) FROM range(100000) a, range(100000) b"
+    }))
+    .await;
+    let progress: Value = ws.receive_json().await;
+    assert_eq!(progress["type"], "progress");
+    assert!(state.connections.interrupt_all() > 0);
+    let interrupted: Value = loop {
+        let message: Value = ws.receive_json().await;
+        if message["type"] != "progress" {
+            break message;
+        }
+    };
+    assert_eq!(interrupted["type"], "error");
+    assert_eq!(interrupted["query_id"], "slow");
+
+    // Closing the session frees its slot
+    ws.close().await;
+    let started = std::time::Instant::now();
+    while state.ws_sessions.available_permits() == 0 {
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let mut ws = server.get_websocket("/ws").await.into_websocket().await;
+    let (_, result) = ws_query(&mut ws, "after", "SELECT 1 AS one").await;
+    assert_eq!(result["type"], "result");
+}
+
+#[tokio::test]
+async fn test_query_progress_stream() {
+    let args = default_args();
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::builder()
+        .http_transport()
+        .build(app)
+        .expect("Failed to create test server");
+
+    let query = server
+        .post("/query")
+        .add_header("x-query-id", "report-42")
+        .json(&json!({
+            "sql": "SELECT count(*) AS n FROM range(5000) a, range(5000) b WHERE hash(a.range + b.range) % 7 = 0"
+        }));
+    let watch = async {
+        // Give the query time to register before subscribing
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        server.get("/queries/report-42/progress").await
+    };
+    let (query, watch) = tokio::join!(query, watch);
+
+    query.assert_status_ok();
+    let body: Value = query.json();
+    assert_eq!(body["query_id"], "report-42");
+
+    watch.assert_status_ok();
+    assert!(
+        watch
+            .header("content-type")
+            .to_str()
+            .unwrap()
+            .starts_with("text/event-stream")
+    );
+    let events = watch.text();
+    assert!(events.contains("event: progress"), "{}", events);
+    assert!(!events.contains("percentage"), "{}", events);
+    assert!(events.contains("\"query_id\":\"report-42\""));
+    let result = events
+        .split("\n\n")
+        .find(|event| event.starts_with("event: result"))
+        .expect("missing result event");
+    let data: Value = serde_json::from_str(result.split("data: ").nth(1).unwrap()).unwrap();
+    assert_eq!(data["success"], true);
+    assert_eq!(data["data"]["row_count"], 1);
+    assert!(data["data"].get("rows").is_none());
+
+    // Finished queries stay visible briefly and report their outcome straight away
+    let events = server.get("/queries/report-42/progress").await.text();
+    assert!(events.starts_with("event: result"), "{}", events);
+
+    // IDs cannot be reused while the previous query is remembered
+    server
+        .post("/query")
+        .add_header("x-query-id", "report-42")
+        .json(&json!({ "sql": "SELECT 1" }))
+        .await
+        .assert_status_bad_request();
+
+    // Failures are reported as error events
+    server
+        .post("/query")
+        .add_header("x-query-id", "broken")
+        .json(&json!({ "sql": "SELECT * FROM missing_table" }))
+        .await
+        .assert_status_bad_request();
+    let events = server.get("/queries/broken/progress").await.text();
+    assert!(events.starts_with("event: error"), "{}", events);
+
+    let response = server.get("/queries/unknown/progress").await;
+    response.assert_status_not_found();
+    let body: Value = response.json();
+    assert_eq!(body["error"]["code"], "NOT_FOUND");
+
+    server
+        .post("/query")
+        .add_header("x-query-id", "no spaces allowed")
+        .json(&json!({ "sql": "SELECT 1" }))
+        .await
+        .assert_status_bad_request();
+}
+
+#[tokio::test]
+async fn test_explain_plans() {
+    let args = default_args();
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let sql = "SELECT range % 3 AS k, count(*) AS n FROM range(1000) WHERE range >= 10 GROUP BY k";
+
+    // Plain EXPLAIN: operator tree with estimates only
+    let response = server.post("/explain").json(&json!({ "sql": sql })).await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["success"], true);
+    assert_eq!(body["analyze"], false);
+    assert_eq!(body["format"], "json");
+    assert!(body["text"].is_null());
+    let root = &body["plan"][0];
+    assert_eq!(root["operator"], "HASH_GROUP_BY");
+    assert_eq!(root["extra_info"]["Aggregates"], "count_star()");
+    assert!(root["estimated_cardinality"].is_u64());
+    assert!(root["actual_cardinality"].is_null());
+    assert!(root["children"][0]["operator"].is_string());
+
+    // EXPLAIN ANALYZE: the same tree with actual cardinalities and timings
+    let response = server
+        .post("/explain")
+        .json(&json!({ "sql": sql, "analyze": true }))
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["analyze"], true);
+    let root = &body["plan"][0];
+    assert_eq!(root["operator"], "HASH_GROUP_BY");
+    assert_eq!(root["actual_cardinality"], 3);
+    assert!(root["timing_ms"].is_f64());
+    let mut node = root;
+    while !node["children"][0].is_null() {
+        node = &node["children"][0];
+    }
+    assert_eq!(node["actual_cardinality"], 1000);
+
+    // Text format returns DuckDB's rendered plan
+    let response = server
+        .post("/explain")
+        .json(&json!({ "sql": sql, "format": "text" }))
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert!(body["plan"].is_null());
+    assert!(body["text"].as_str().unwrap().contains("HASH_GROUP_BY"));
+
+    // One statement, without its own EXPLAIN
+    server
+        .post("/explain")
+        .json(&json!({ "sql": "SELECT 1; SELECT 2" }))
+        .await
+        .assert_status_bad_request();
+    server
+        .post("/explain")
+        .json(&json!({ "sql": "EXPLAIN SELECT 1" }))
+        .await
+        .assert_status_bad_request();
+    server
+        .post("/explain")
+        .json(&json!({ "sql": "SELECT * FROM missing_table" }))
+        .await
+        .assert_status_bad_request();
+}
+
+#[tokio::test]
+async fn test_explain_analyze_respects_readonly() {
+    let args = default_args();
+    let mut state = AppState::new(&args).expect("Failed to create app state");
+    state.is_readonly = true;
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    for analyze in [false, true] {
+        let response = server
+            .post("/explain")
+            .json(&json!({ "sql": "CREATE TABLE t AS SELECT 1 AS a", "analyze": analyze }))
+            .await;
+        response.assert_status_forbidden();
+        let body: Value = response.json();
+        assert_eq!(body["error"]["code"], "FORBIDDEN");
+    }
+
+    server
+        .post("/explain")
+        .json(&json!({ "sql": "SELECT 42", "analyze": true }))
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_sql_script_routes_each_statement() {
+    let args = Args::parse_from(["rsduck", "--readwrite"]);
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let server = TestServer::new(create_test_app(state)).expect("Failed to create test server");
+
+    let script = "CREATE TABLE items (id INTEGER, name VARCHAR);
+        INSERT INTO items VALUES (1, 'a; b'), (2, 'c'), (3, 'd');
+        UPDATE items SET name = 'e' WHERE id > 1;
+        SELECT id, name FROM items ORDER BY id;
+        SET threads = 2;
+        SHOW TABLES";
+    let response = server
+        .post("/sql")
+        .json(&json!({ "sql": script, "limit": 2 }))
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["success"], true);
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 6);
+    assert_eq!(
+        results[0],
+        json!({"kind": "count", "statement_type": "CREATE", "rows_affected": 0})
+    );
+    assert_eq!(
+        results[1],
+        json!({"kind": "count", "statement_type": "INSERT", "rows_affected": 3})
+    );
+    assert_eq!(
+        results[2],
+        json!({"kind": "count", "statement_type": "UPDATE", "rows_affected": 2})
+    );
+    assert_eq!(results[3]["kind"], "rows");
+    assert_eq!(results[3]["statement_type"], "SELECT");
+    assert_eq!(results[3]["columns"], json!(["id", "name"]));
+    assert_eq!(results[3]["column_types"], json!(["INTEGER", "VARCHAR"]));
+    assert_eq!(results[3]["rows"], json!([[1, "a; b"], [2, "e"]]));
+    assert_eq!(results[3]["truncated"], true);
+    assert_eq!(results[4]["kind"], "count");
+    assert_eq!(results[4]["statement_type"], "SET");
+    assert_eq!(results[5]["kind"], "rows");
+    assert_eq!(results[5]["rows"], json!([["items"]]));
+
+    // Statements run on one connection, so transactions span them
+    let response = server
+        .post("/sql")
+        .json(&json!({ "sql": "BEGIN; DELETE FROM items; ROLLBACK; SELECT count(*) AS n FROM items" }))
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["results"][1]["rows_affected"], 3);
+    assert_eq!(body["results"][3]["rows"], json!([[3]]));
+
+    // A failing statement stops the script and is identified by index
+    let response = server
+        .post("/sql")
+        .json(
+            &json!({ "sql": "INSERT INTO items VALUES (4, 'f'); SELECT * FROM missing; SELECT 1" }),
+        )
+        .await;
+    assert_eq!(response.status_code(), 400);
+    let body: Value = response.json();
+    assert_eq!(body["error"]["code"], "CATALOG_ERROR");
+    assert_eq!(body["statement"], 1);
+    let body: Value = server
+        .post("/sql")
+        .json(&json!({ "sql": "SELECT count(*) FROM items" }))
+        .await
+        .json();
+    assert_eq!(body["results"][0]["rows"], json!([[4]]));
+
+    // Transactions cannot outlive the script
+    let response = server
+        .post("/sql")
+        .json(&json!({ "sql": "BEGIN; DELETE FROM items" }))
+        .await;
+    assert_eq!(response.status_code(), 400);
+    let body: Value = server
+        .post("/sql")
+        .json(&json!({ "sql": "SELECT count(*) FROM items" }))
+        .await
+        .json();
+    assert_eq!(body["results"][0]["rows"], json!([[4]]));
+
+    let response = server
+        .post("/sql")
+        .json(&json!({ "sql": " ; -- nothing" }))
+        .await;
+    assert_eq!(response.status_code(), 400);
+}
+
+#[tokio::test]
+async fn test_sql_script_audits_each_statement() {
+    let audit_path =
+        std::env::temp_dir().join(format!("rsduck-audit-{}.jsonl", uuid::Uuid::new_v4()));
+    let mut args = Args::parse_from(["rsduck", "--readwrite"]);
+    args.audit_log = Some(audit_path.clone());
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let server = TestServer::new(create_test_app(state.clone())).unwrap();
+
+    server
+        .post("/sql")
+        .json(&json!({ "sql": "CREATE TABLE t AS SELECT * FROM range(3); INSERT INTO t VALUES (5); SELECT * FROM t" }))
+        .await
+        .assert_status_ok();
+    let response = server
+        .post("/sql")
+        .json(&json!({ "sql": "SELECT 1; SELECT * FROM missing; SELECT 2" }))
+        .await;
+    assert_eq!(response.status_code(), 400);
+
+    state.audit.as_ref().unwrap().flush();
+    let records: Vec<Value> = std::fs::read_to_string(&audit_path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    std::fs::remove_file(&audit_path).ok();
+
+    let summary: Vec<_> = records
+        .iter()
+        .map(|r| {
+            (
+                r["statement_type"].as_str().unwrap(),
+                r["status"].as_str().unwrap(),
+                r["rows_returned"].as_u64(),
+                r["rows_affected"].as_u64(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("CREATE", "ok", None, Some(3)),
+            ("INSERT", "ok", None, Some(1)),
+            ("SELECT", "ok", Some(4), None),
+            ("SELECT", "ok", Some(1), None),
+            // The failing statement is recorded; the one after it never ran
+            ("SELECT", "error", None, None),
+        ]
+    );
+    assert_eq!(records[1]["sql"], "INSERT INTO t VALUES (5)");
+    assert_eq!(records[4]["sql"], "SELECT * FROM missing");
+    assert_eq!(records[4]["error_code"], "CATALOG_ERROR");
+}
+
+#[tokio::test]
+async fn test_sql_script_respects_readonly() {
+    let mut state = AppState::new(&default_args()).expect("Failed to create app state");
+    state.is_readonly = true;
+    let server = TestServer::new(create_test_app(state)).expect("Failed to create test server");
+
+    let response = server
+        .post("/sql")
+        .json(&json!({ "sql": "SELECT 1; CREATE TABLE t (a INTEGER)" }))
+        .await;
+    response.assert_status_forbidden();
+
+    let body: Value = server
+        .post("/sql")
+        .json(&json!({ "sql": "SELECT 1 AS a; SELECT 2 AS b" }))
+        .await
+        .json();
+    assert_eq!(body["results"][0]["rows"], json!([[1]]));
+    assert_eq!(body["results"][1]["rows"], json!([[2]]));
+}
+
+#[tokio::test]
+async fn test_query_profile() {
+    let args = default_args();
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let sql = "SELECT range % 3 AS k, count(*) AS n FROM range(100000) GROUP BY k ORDER BY k";
+
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": sql, "profile": true }))
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["data"]["row_count"], 3);
+    let profile = &body["profile"];
+    for phase in [
+        "queue_wait_ms",
+        "connection_wait_ms",
+        "prepare_ms",
+        "execute_ms",
+        "serialize_ms",
+    ] {
+        assert!(profile[phase].as_f64().unwrap() >= 0.0, "{}", phase);
+    }
+    let duckdb = &profile["duckdb"];
+    assert!(duckdb["latency_ms"].as_f64().unwrap() > 0.0);
+    assert!(duckdb["peak_buffer_memory_bytes"].is_u64());
+    assert_eq!(duckdb["operators"][0]["operator"], "ORDER_BY");
+    assert_eq!(duckdb["operators"][0]["actual_cardinality"], 3);
+    assert!(duckdb["operators"][0]["timing_ms"].is_f64());
+
+    // Profiling is switched off again before the connection returns to the pool
+    let response = server
+        .get("/query")
+        .add_query_param(
+            "sql",
+            "SELECT current_setting('profiling_output') AS output",
+        )
+        .await;
+    let body: Value = response.json();
+    assert!(body.get("profile").is_none());
+    assert_eq!(body["data"]["rows"][0][0], "");
+
+    // GET accepts the same option
+    let response = server
+        .get("/query")
+        .add_query_param("sql", "SELECT 1")
+        .add_query_param("profile", "true")
+        .await;
+    let body: Value = response.json();
+    assert!(body["profile"]["duckdb"]["operators"].is_array());
+}
+
+#[tokio::test]
+async fn test_query_profile_in_sandbox() {
+    let mut args = default_args();
+    args.sandbox = true;
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    // Settings are locked, so only the server-side timings are available
+    let response = server
+        .post("/query")
+        .json(&json!({ "sql": "SELECT 42 AS answer", "profile": true }))
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows"][0][0], 42);
+    assert!(body["profile"]["execute_ms"].is_f64());
+    assert!(body["profile"]["duckdb"].is_null());
+}
+
+#[tokio::test]
+async fn test_cursor_pagination() {
+    let mut args = default_args();
+    args.api_keys = vec!["owner-key".to_string(), "other-key".to_string()];
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let response = server
+        .post("/query")
+        .add_header("x-api-key", "owner-key")
+        .json(
+            &json!({ "sql": "SELECT range AS i FROM range(100) ORDER BY i DESC", "page_size": 40 }),
+        )
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    let data = &body["data"];
+    assert_eq!(data["columns"], json!(["i"]));
+    assert_eq!(data["row_count"], 40);
+    assert_eq!(data["offset"], 0);
+    assert_eq!(data["total_rows"], 100);
+    assert_eq!(data["rows"][0][0], 99);
+    let token = data["next_cursor"]
+        .as_str()
+        .expect("missing cursor")
+        .to_string();
+
+    // Cursors belong to the client that opened them
+    server
+        .get(&format!("/cursors/{}", token))
+        .add_header("x-api-key", "other-key")
+        .await
+        .assert_status_not_found();
+
+    let mut seen = 40;
+    let mut next = Some(token.clone());
+    while let Some(token) = next {
+        let response = server
+            .get(&format!("/cursors/{}", token))
+            .add_header("x-api-key", "owner-key")
+            .await;
+        response.assert_status_ok();
+        let body: Value = response.json();
+        let data = &body["data"];
+        assert_eq!(data["offset"], seen);
+        assert_eq!(data["rows"][0][0], 99 - seen);
+        seen += data["row_count"].as_u64().unwrap();
+        next = data["next_cursor"].as_str().map(str::to_string);
+    }
+    assert_eq!(seen, 100);
+
+    // Reading to the end closes the cursor
+    server
+        .get(&format!("/cursors/{}", token))
+        .add_header("x-api-key", "owner-key")
+        .await
+        .assert_status_not_found();
+
+    // Results that fit in one page do not open a cursor
+    let response = server
+        .post("/query")
+        .add_header("x-api-key", "owner-key")
+        .json(&json!({ "sql": "SELECT 1 AS one", "page_size": 40 }))
+        .await;
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows"], json!([[1]]));
+    assert!(body["data"]["next_cursor"].is_null());
+
+    // Only single queries can be paginated
+    server
+        .post("/query")
+        .add_header("x-api-key", "owner-key")
+        .json(&json!({ "sql": "CREATE TABLE paged (i INTEGER)", "page_size": 10 }))
+        .await
+        .assert_status_bad_request();
+    server
+        .post("/query")
+        .add_header("x-api-key", "owner-key")
+        .json(&json!({ "sql": "SELECT 1", "page_size": 10, "profile": true }))
+        .await
+        .assert_status_bad_request();
+}
+
+#[tokio::test]
+async fn test_cursor_close_limit_and_expiry() {
+    let mut args = default_args();
+    args.max_cursors_per_client = 2;
+    args.cursor_idle_timeout_secs = 1;
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state.clone());
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let open = || async {
+        server
+            .get("/query")
+            .add_query_param("sql", "SELECT * FROM range(10)")
+            .add_query_param("page_size", "3")
+            .await
+    };
+
+    let first: Value = open().await.json();
+    let first = first["data"]["next_cursor"].as_str().unwrap().to_string();
+    open().await.assert_status_ok();
+
+    let response = open().await;
+    response.assert_status(axum::http::StatusCode::TOO_MANY_REQUESTS);
+    let body: Value = response.json();
+    assert_eq!(body["error"]["code"], "RATE_LIMITED");
+
+    // Unverified API keys do not make a new client
+    let response = server
+        .get("/query")
+        .add_header("x-api-key", "made-up")
+        .add_query_param("sql", "SELECT * FROM range(10)")
+        .add_query_param("page_size", "3")
+        .await;
+    response.assert_status(axum::http::StatusCode::TOO_MANY_REQUESTS);
+
+    // Closing a cursor frees its slot and its connection, which shutdown could interrupt
+    let open_connections = state.connections.open_connections();
+    server
+        .delete(&format!("/cursors/{}", first))
+        .await
+        .assert_status(axum::http::StatusCode::NO_CONTENT);
+    assert_eq!(state.connections.open_connections(), open_connections - 1);
+    server
+        .delete(&format!("/cursors/{}", first))
+        .await
+        .assert_status_not_found();
+    let third: Value = open().await.json();
+    let third = third["data"]["next_cursor"].as_str().unwrap().to_string();
+
+    // Idle cursors expire
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    let response = server.get(&format!("/cursors/{}", third)).await;
+    response.assert_status_not_found();
+    let body: Value = response.json();
+    assert_eq!(body["error"]["code"], "NOT_FOUND");
+    open().await.assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_cursor_server_wide_limit() {
+    let args = Args::parse_from([
+        "rsduck",
+        "--max-cursors",
+        "2",
+        "--api-key",
+        "alpha",
+        "--api-key",
+        "beta",
+    ]);
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let server = TestServer::new(create_test_app(state)).expect("Failed to create test server");
+
+    let open = |key: &'static str| {
+        server
+            .get("/query")
+            .add_header("x-api-key", key)
+            .add_query_param("sql", "SELECT * FROM range(10)")
+            .add_query_param("page_size", "3")
+    };
+    open("alpha").await.assert_status_ok();
+    open("beta").await.assert_status_ok();
+
+    let response = open("beta").await;
+    response.assert_status(axum::http::StatusCode::SERVICE_UNAVAILABLE);
+    let body: Value = response.json();
+    assert_eq!(body["error"]["code"], "SERVER_BUSY");
+}
+
+#[tokio::test]
+async fn test_saved_queries_from_directory() {
+    let dir = std::env::temp_dir().join(format!("rsduck-saved-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("numbers.sql"),
+        "-- description: Numbers in a range\n\
+         -- limit: 5\n\
+         -- cache_ttl_secs: 0\n\
+         -- param: lo integer\n\
+         -- param: hi integer = 100\n\
+         -- param: label string optional\n\
+         -- Comments after the front-matter belong to the SQL\n\
+         SELECT i, coalesce($label, 'n') AS label FROM range($lo, $hi) t(i)\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("since.sql"),
+        "-- param: day date\n-- param: at timestamp optional\n\
+         SELECT ($day::DATE + 1)::VARCHAR AS next_day, $at::TIMESTAMP::VARCHAR AS at, '$not_a_param' AS text\n",
+    )
+    .unwrap();
+
+    let mut args = default_args();
+    args.saved_queries_dir = Some(dir.clone());
+    args.cache_ttl_secs = 60;
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    // Query-string parameters are converted to their declared types
+    let response = server
+        .get("/q/numbers")
+        .add_query_param("lo", "3")
+        .add_query_param("hi", "6")
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header("x-cache"), "BYPASS");
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows"], json!([[3, "n"], [4, "n"], [5, "n"]]));
+
+    // Defaults apply and the saved row limit caps the result
+    let body: Value = server
+        .post("/q/numbers")
+        .json(&json!({ "lo": 0, "label": "x" }))
+        .await
+        .json();
+    assert_eq!(body["data"]["row_count"], 5);
+    assert_eq!(body["data"]["truncated"], true);
+    assert_eq!(body["data"]["rows"][0], json!([0, "x"]));
+
+    // Missing, unknown and mistyped parameters are rejected
+    for query in [
+        json!({}),
+        json!({ "lo": 1, "extra": 2 }),
+        json!({ "lo": "one" }),
+    ] {
+        let response = server.post("/q/numbers").json(&query).await;
+        response.assert_status_bad_request();
+        let body: Value = response.json();
+        assert_eq!(body["error"]["code"], "BAD_REQUEST");
+    }
+
+    let response = server
+        .get("/q/since")
+        .add_query_param("day", "2024-02-28")
+        .add_query_param("at", "2024-02-28 12:30:00")
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header("x-cache"), "MISS");
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows"][0][0], "2024-02-29");
+    assert_eq!(body["data"]["rows"][0][1], "2024-02-28 12:30:00");
+    assert_eq!(body["data"]["rows"][0][2], "$not_a_param");
+    server
+        .get("/q/since")
+        .add_query_param("day", "2024-02-28")
+        .add_query_param("at", "2024-02-28 12:30:00")
+        .await
+        .assert_header("x-cache", "HIT");
+    server
+        .get("/q/since")
+        .add_query_param("day", "yesterday")
+        .await
+        .assert_status_bad_request();
+
+    server.get("/q/missing").await.assert_status_not_found();
+
+    // Invalid files stop the server from starting
+    std::fs::write(dir.join("broken.sql"), "-- param: x integer\nSELECT $y\n").unwrap();
+    assert!(AppState::new(&args).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_saved_query_crud() {
+    let dir = std::env::temp_dir().join(format!("rsduck-saved-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut args = default_args();
+    args.saved_queries_dir = Some(dir.clone());
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let definition = json!({
+        "name": "squares",
+        "description": "Squares up to n",
+        "sql": "SELECT i * i AS square FROM range($n) t(i)",
+        "params": [{ "name": "n", "type": "integer", "default": "3" }]
+    });
+    let response = server.post("/saved-queries").json(&definition).await;
+    response.assert_status(axum::http::StatusCode::CREATED);
+    let body: Value = response.json();
+    assert_eq!(body["params"][0]["default"], 3);
+    assert!(dir.join("squares.sql").exists());
+
+    let response = server.post("/saved-queries").json(&definition).await;
+    response.assert_status(axum::http::StatusCode::CONFLICT);
+    let body: Value = response.json();
+    assert_eq!(body["error"]["code"], "CONFLICT");
+
+    let body: Value = server.get("/q/squares").await.json();
+    assert_eq!(body["data"]["rows"], json!([[0], [1], [4]]));
+
+    // Replacing keeps the name from the path
+    server
+        .put("/saved-queries/squares")
+        .json(&json!({
+            "sql": "SELECT i * i * i AS cube FROM range($n) t(i)",
+            "params": [{ "name": "n", "type": "integer" }]
+        }))
+        .await
+        .assert_status_ok();
+    let body: Value = server.get("/saved-queries").await.json();
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert!(body[0].get("description").is_none());
+    let body: Value = server
+        .get("/q/squares")
+        .add_query_param("n", "3")
+        .await
+        .json();
+    assert_eq!(body["data"]["rows"], json!([[0], [1], [8]]));
+
+    // Files written through the API load on the next start
+    let reloaded = AppState::new(&args).expect("Failed to reload app state");
+    let saved = reloaded.saved_queries.get("squares").unwrap();
+    assert_eq!(saved.params[0].param_type, rsduck::ParamType::Integer);
+
+    // Declarations must match the placeholders
+    for invalid in [
+        json!({ "name": "bad name", "sql": "SELECT 1" }),
+        json!({ "name": "undeclared", "sql": "SELECT $x" }),
+        json!({ "name": "unused", "sql": "SELECT 1", "params": [{ "name": "x", "type": "string" }] }),
+        json!({ "name": "two", "sql": "SELECT 1; SELECT 2" }),
+        json!({ "name": "bad_default", "sql": "SELECT $d", "params": [{ "name": "d", "type": "date", "default": "soon" }] }),
+    ] {
+        server
+            .post("/saved-queries")
+            .json(&invalid)
+            .await
+            .assert_status_bad_request();
+    }
+
+    server
+        .delete("/saved-queries/squares")
+        .await
+        .assert_status(axum::http::StatusCode::NO_CONTENT);
+    assert!(!dir.join("squares.sql").exists());
+    server.get("/q/squares").await.assert_status_not_found();
+    server
+        .get("/saved-queries/squares")
+        .await
+        .assert_status_not_found();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_saved_query_api_keys() {
+    let db_path =
+        std::env::temp_dir().join(format!("rsduck-saved-{}.duckdb", uuid::Uuid::new_v4()));
+    {
+        let conn = duckdb::Connection::open(&db_path).unwrap();
+        conn.execute_batch("CREATE TABLE t AS SELECT 1 AS x")
+            .unwrap();
+    }
+
+    let mut args = default_args();
+    args.database = Some(db_path.clone());
+    args.api_keys = vec!["admin-key".to_string()];
+    args.saved_query_api_keys = vec!["app-key".to_string()];
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let pg_port = start_pg_server(state.clone()).await;
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    // Writes cannot be saved on a read-only database
+    server
+        .post("/saved-queries")
+        .add_header("x-api-key", "admin-key")
+        .json(&json!({ "name": "wipe", "sql": "DELETE FROM t" }))
+        .await
+        .assert_status_forbidden();
+    server
+        .post("/saved-queries")
+        .add_header("x-api-key", "admin-key")
+        .json(&json!({ "name": "all", "sql": "SELECT x FROM t" }))
+        .await
+        .assert_status(axum::http::StatusCode::CREATED);
+
+    let body: Value = server
+        .get("/q/all")
+        .add_header("x-api-key", "app-key")
+        .await
+        .json();
+    assert_eq!(body["data"]["rows"], json!([[1]]));
+    server.get("/q/all").await.assert_status_unauthorized();
+
+    // Restricted keys cannot run ad-hoc SQL or manage saved queries
+    for response in [
+        server
+            .get("/query")
+            .add_query_param("sql", "SELECT 1")
+            .add_header("x-api-key", "app-key")
+            .await,
+        server
+            .get("/saved-queries")
+            .add_header("x-api-key", "app-key")
+            .await,
+    ] {
+        response.assert_status_forbidden();
+        let body: Value = response.json();
+        assert_eq!(body["error"]["code"], "FORBIDDEN");
+    }
+    assert!(pg_connect(pg_port, Some("app-key")).await.is_err());
+
+    // A restricted key without full keys would leave every other endpoint open
+    assert!(
+        Args::try_parse_from(["rsduck", "--saved-query-api-key", "app-key"]).is_err(),
+        "--saved-query-api-key requires --api-key"
+    );
+    let _ = std::fs::remove_file(&db_path);
+}
+
+#[tokio::test]
+async fn test_bind_params() {
+    let state = AppState::new(&default_args()).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    server
+        .post("/execute")
+        .json(&json!({"sql": "CREATE TABLE items (id INTEGER, name VARCHAR)"}))
+        .await
+        .assert_status_ok();
+    let response = server
+        .post("/execute")
+        .json(&json!({
+            "sql": "INSERT INTO items VALUES (?, ?), (?, ?)",
+            "params": [1, "one", 2, "it's two"]
+        }))
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows_affected"], 2);
+
+    let response = server
+        .post("/query")
+        .json(&json!({"sql": "SELECT name FROM items WHERE id = $1", "params": [2]}))
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows"], json!([["it's two"]]));
+
+    // Cached results are keyed by params as well as SQL
+    let response = server
+        .post("/query")
+        .json(&json!({"sql": "SELECT name FROM items WHERE id = $1", "params": [1]}))
+        .await;
+    let body: Value = response.json();
+    assert_eq!(body["data"]["rows"], json!([["one"]]));
+
+    let response = server
+        .post("/query")
+        .json(&json!({"sql": "SELECT ? + ?", "params": [1]}))
+        .await;
+    response.assert_status_bad_request();
+}
+
+#[tokio::test]
+async fn test_stream_query_ndjson() {
+    let state = AppState::new(&default_args()).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let response = server
+        .post("/query/stream")
+        .json(&json!({"sql": "SELECT range AS n FROM range(?)", "params": [1200], "limit": 1000}))
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header("content-type"), "application/x-ndjson");
+    assert!(response.headers().contains_key("x-query-id"));
+
+    let lines: Vec<Value> = response
+        .text()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 1002);
+    assert_eq!(
+        lines[0],
+        json!({"type": "columns", "columns": ["n"], "column_types": ["BIGINT"]})
+    );
+    assert_eq!(lines[1], json!([0]));
+    assert_eq!(lines[1000], json!([999]));
+    assert_eq!(
+        lines[1001],
+        json!({"type": "end", "row_count": 1000, "limit_applied": 1000, "truncated": true})
+    );
+
+    // Errors before the first row are reported with a status
+    let response = server
+        .post("/query/stream")
+        .json(&json!({"sql": "SELECT * FROM missing_table"}))
+        .await;
+    response.assert_status_bad_request();
+    let body: Value = response.json();
+    assert_eq!(body["error"]["code"], "CATALOG_ERROR");
+
+    let response = server
+        .post("/query/stream")
+        .json(&json!({"sql": "CREATE TABLE t (x INTEGER)"}))
+        .await;
+    response.assert_status_bad_request();
+}
+
+#[test]
+fn test_cli_subcommands() {
+    use rsduck::{Cli, Command, OutputFormat};
+
+    // Server flags without a subcommand keep working
+    let cli = Cli::parse_from(["rsduck", "--port", "8080", "--readwrite"]);
+    assert!(cli.command.is_none());
+    assert_eq!(cli.serve.port, 8080);
+
+    let cli = Cli::parse_from(["rsduck", "serve", "--port", "8081"]);
+    assert!(matches!(cli.command, Some(Command::Serve(args)) if args.port == 8081));
+
+    let cli = Cli::parse_from([
+        "rsduck",
+        "query",
+        "--url",
+        "http://db:3001",
+        "--format",
+        "csv",
+        "SELECT 1",
+    ]);
+    let Some(Command::Query(query)) = cli.command else {
+        panic!("expected the query subcommand");
+    };
+    assert_eq!(query.connect.url, "http://db:3001");
+    assert_eq!(query.format, OutputFormat::Csv);
+    assert_eq!(query.sql.as_deref(), Some("SELECT 1"));
+
+    assert!(matches!(
+        Cli::parse_from(["rsduck", "repl"]).command,
+        Some(Command::Repl(_))
+    ));
+    assert!(Cli::try_parse_from(["rsduck", "--port", "8080", "repl"]).is_err());
+}
+
+#[test]
+fn test_cli_output_formats() {
+    use rsduck::OutputFormat;
+    use rsduck::cli::{format_result, result_summary};
+
+    let data: rsduck_client::QueryData = serde_json::from_value(json!({
+        "columns": ["id", "name"],
+        "column_types": ["INTEGER", "VARCHAR"],
+        "rows": [[1, "Alice, A."], [2, null]],
+        "row_count": 2,
+        "limit_applied": 10000
+    }))
+    .unwrap();
+
+    assert_eq!(
+        format_result(&data, OutputFormat::Csv),
+        "id,name\n1,\"Alice, A.\"\n2,\n"
+    );
+    let json_output: Value =
+        serde_json::from_str(&format_result(&data, OutputFormat::Json)).unwrap();
+    assert_eq!(
+        json_output,
+        json!([{"id": 1, "name": "Alice, A."}, {"id": 2, "name": null}])
+    );
+    let table = format_result(&data, OutputFormat::Table);
+    assert!(table.contains("name"));
+    assert!(table.contains("Alice, A."));
+    assert!(table.contains("NULL"));
+    assert_eq!(result_summary(&data), "2 rows");
+}
+
+#[test]
+fn test_repl_statement_completion() {
+    use rsduck::cli::is_complete;
+
+    assert!(is_complete("SELECT 1;"));
+    assert!(is_complete("SELECT 1;  -- done"));
+    assert!(is_complete("SELECT 1;\nSELECT 2;"));
+    assert!(!is_complete(""));
+    assert!(!is_complete("SELECT 1"));
+    assert!(!is_complete("SELECT 1;\nSELECT 2"));
+    assert!(!is_complete("SELECT 'a;"));
+    assert!(!is_complete("SELECT 1 /* ; */"));
+    assert!(!is_complete("SELECT 1 -- ;"));
+}
+
+#[test]
+fn test_repl_rejects_session_state() {
+    use rsduck::cli::session_state_error;
+
+    for sql in [
+        "BEGIN",
+        "begin transaction",
+        "COMMIT",
+        "ROLLBACK",
+        "SET threads = 4",
+        "RESET threads",
+        "USE other",
+        "CREATE TEMP TABLE t AS SELECT 1",
+        "/* scratch */ CREATE OR REPLACE TEMPORARY VIEW v AS SELECT 1",
+    ] {
+        let error = session_state_error(sql).unwrap_or_else(|| panic!("{} was accepted", sql));
+        assert!(error.contains("POST /sql"), "{}", error);
+    }
+    for sql in [
+        "SELECT 1",
+        "SET GLOBAL threads = 4",
+        "CREATE TABLE temp AS SELECT 1",
+        "SELECT 'BEGIN'",
+        "INSERT INTO settings VALUES ('SET')",
+    ] {
+        assert_eq!(session_state_error(sql), None, "{}", sql);
+    }
+}
+
+#[tokio::test]
+async fn test_router_builder_embedding() {
+    use axum::routing::get;
+    use rsduck::{DuckDbConnectionManager, Endpoint, RsduckBuilder};
+
+    let db_path = std::env::temp_dir().join(format!("rsduck_embed_{}.duckdb", std::process::id()));
+    let _ = std::fs::remove_file(&db_path);
+
+    let mut extra_doc = utoipa::openapi::OpenApi::default();
+    extra_doc
+        .paths
+        .paths
+        .insert("/orders".to_string(), utoipa::openapi::PathItem::default());
+    let analytics = RsduckBuilder::new(default_args())
+        .with_prefix("/analytics/")
+        .without_endpoint(Endpoint::Execute)
+        .with_openapi(extra_doc)
+        .with_middleware(|routes| {
+            routes.layer(axum::middleware::map_response(
+                |mut response: axum::response::Response| async move {
+                    response
+                        .headers_mut()
+                        .insert("x-embedded", "yes".parse().unwrap());
+                    response
+                },
+            ))
+        })
+        .with_connection_manager(DuckDbConnectionManager::new(Some(db_path.clone()), false))
+        .build()
+        .expect("Failed to build router");
+    let app = axum::Router::new()
+        .route("/orders", get(|| async { "orders" }))
+        .merge(analytics);
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    server.get("/orders").await.assert_text("orders");
+
+    let response = server
+        .post("/analytics/query")
+        .json(&json!({"sql": "SELECT 1 AS one"}))
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header("x-embedded"), "yes");
+    server
+        .post("/query")
+        .json(&json!({"sql": "SELECT 1"}))
+        .await
+        .assert_status_not_found();
+    server
+        .post("/analytics/execute")
+        .json(&json!({"sql": "SELECT 1"}))
+        .await
+        .assert_status_not_found();
+
+    // The connection manager decides the database and its access mode
+    let health: Value = server.get("/analytics/health").await.json();
+    assert_eq!(health["database_path"], db_path.to_string_lossy().as_ref());
+    assert_eq!(health["readonly_mode"], false);
+
+    let doc: Value = server.get("/analytics/api-docs/openapi.json").await.json();
+    let paths = doc["paths"].as_object().unwrap();
+    assert!(paths.contains_key("/analytics/query"));
+    assert!(paths.contains_key("/orders"));
+    assert!(!paths.contains_key("/analytics/execute"));
+    assert!(!paths.contains_key("/query"));
+
+    let _ = std::fs::remove_file(&db_path);
+}
+
+#[tokio::test]
+async fn test_on_connect_hooks() {
+    use rsduck::RsduckBuilder;
+
+    let app = RsduckBuilder::new(default_args())
+        .with_on_connect(|conn: &duckdb::Connection| {
+            conn.execute_batch("CREATE TEMP MACRO double_it(x) AS x * 2")
+        })
+        .build()
+        .expect("Failed to build router");
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    // Both pools open their connections through the hook
+    for priority in ["interactive", "batch"] {
+        let response = server
+            .post("/query")
+            .add_header("X-Query-Priority", priority)
+            .json(&json!({"sql": "SELECT double_it(21) AS answer"}))
+            .await;
+        response.assert_status_ok();
+        let body: Value = response.json();
+        assert_eq!(body["data"]["rows"], json!([[42]]), "{} pool", priority);
+    }
+}
+
+#[tokio::test]
+async fn test_in_memory_pool_shares_one_database() {
+    let state = AppState::new(&default_args()).expect("Failed to create app state");
+
+    // Hold one connection so the next one is a different pooled connection
+    let first = state.pool.get().unwrap();
+    first
+        .execute_batch("CREATE TABLE shared_t AS SELECT 7 AS v")
+        .unwrap();
+    let second = state.pool.get().unwrap();
+    let v: i32 = second
+        .query_row("SELECT v FROM shared_t", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(v, 7);
+}
+
+#[tokio::test]
+async fn test_state_from_existing_connection() {
+    let conn = duckdb::Connection::open_in_memory().unwrap();
+    conn.execute_batch("CREATE TABLE events (id INTEGER); INSERT INTO events VALUES (1), (2)")
+        .unwrap();
+
+    let state = AppState::from_connection(&default_args(), conn).expect("Failed to create state");
+    assert!(state.db_path.is_none());
+    assert!(!state.is_readonly);
+    let server = TestServer::new(create_test_app(state)).expect("Failed to create test server");
+
+    // Every pooled connection is a clone of the handle, so all of them see the same tables
+    server
+        .post("/execute")
+        .json(&json!({"sql": "INSERT INTO events VALUES (3)"}))
+        .await
+        .assert_status_ok();
+    for priority in ["interactive", "batch"] {
+        let response = server
+            .post("/query")
+            .add_header("X-Query-Priority", priority)
+            .json(&json!({"sql": "SELECT count(*) AS n FROM events"}))
+            .await;
+        response.assert_status_ok();
+        let body: Value = response.json();
+        assert_eq!(body["data"]["rows"], json!([[3]]), "{} pool", priority);
+    }
+
+    // The sandbox is applied once to the shared database
+    let mut args = default_args();
+    args.sandbox = true;
+    let conn = duckdb::Connection::open_in_memory().unwrap();
+    let state = AppState::from_connection(&args, conn).expect("Failed to create state");
+    assert!(state.is_sandboxed);
+    let first = state.pool.get().unwrap();
+    let second = state.pool.get().unwrap();
+    assert!(
+        second
+            .execute_batch("SET enable_external_access = true")
+            .is_err()
+    );
+    drop(first);
+}
+
+#[tokio::test]
+async fn test_tls_serves_https_and_reloads_certificates() {
+    let dir = std::env::temp_dir().join(format!("rsduck-tls-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    let ca = TestCa::new("Test CA");
+    ca.write_server_cert(&cert_path, &key_path);
+
+    let args = Args::try_parse_from([
+        "rsduck",
+        "--tls-cert",
+        cert_path.to_str().unwrap(),
+        "--tls-key",
+        key_path.to_str().unwrap(),
+    ])
+    .unwrap();
+    let (addr, tls) = start_tls_server(&args).await;
+
+    let response = https_client(addr, &ca, None)
+        .get(format!("https://localhost:{}/health", addr.port()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    // Plain HTTP is not served
+    let plain = reqwest::get(format!("http://{}/health", addr)).await;
+    assert!(plain.is_err());
+
+    // Reloading picks up a certificate from another CA for new connections
+    let rotated_ca = TestCa::new("Rotated CA");
+    rotated_ca.write_server_cert(&cert_path, &key_path);
+    tls.reload().unwrap();
+    let response = https_client(addr, &rotated_ca, None)
+        .get(format!("https://localhost:{}/health", addr.port()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let stale = https_client(addr, &ca, None)
+        .get(format!("https://localhost:{}/health", addr.port()))
+        .send()
+        .await;
+    assert!(stale.is_err());
+
+    // A broken key keeps the previous certificate in use
+    std::fs::write(&key_path, "not a key").unwrap();
+    assert!(tls.reload().is_err());
+    let response = https_client(addr, &rotated_ca, None)
+        .get(format!("https://localhost:{}/health", addr.port()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_mtls_client_certificate_scopes() {
+    let dir = std::env::temp_dir().join(format!("rsduck-mtls-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    let client_ca_path = dir.join("clients.pem");
+    let ca = TestCa::new("Test CA");
+    ca.write_server_cert(&cert_path, &key_path);
+    std::fs::write(&client_ca_path, ca.pem()).unwrap();
+
+    let tls_args = [
+        "rsduck",
+        "--api-key",
+        "secret",
+        "--batch-threads",
+        "2",
+        "--tls-cert",
+        cert_path.to_str().unwrap(),
+        "--tls-key",
+        key_path.to_str().unwrap(),
+        "--tls-client-ca",
+        client_ca_path.to_str().unwrap(),
+        "--tls-client-scope",
+        "alice=full",
+        "--tls-client-scope",
+        "etl=batch",
+        "--tls-client-scope",
+        "reports=saved",
+        "--tls-client-scope",
+        "ops=admin",
+    ];
+    assert!(
+        Args::try_parse_from(["rsduck", "--tls-client-scope", "alice=root"]).is_err(),
+        "Unknown scopes are rejected"
+    );
+    let args = Args::try_parse_from(tls_args).unwrap();
+    let (addr, _tls) = start_tls_server(&args).await;
+    let url = |path: &str| format!("https://localhost:{}{}", addr.port(), path);
+    let query = json!({ "sql": "SELECT current_setting('threads') AS threads" });
+
+    // A client certificate is required
+    let anonymous = https_client(addr, &ca, None)
+        .post(url("/query"))
+        .header("x-api-key", "secret")
+        .json(&query)
+        .send()
+        .await;
+    assert!(anonymous.is_err());
+
+    // Full scope needs no API key
+    let alice = https_client(addr, &ca, Some(ca.client_cert("alice")));
+    let response = alice
+        .post(url("/saved-queries"))
+        .json(&json!({ "name": "one", "sql": "SELECT 1 AS one" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 201);
+
+    // Batch scope runs queries at batch priority whatever the client asks for
+    let etl = https_client(addr, &ca, Some(ca.client_cert("etl")));
+    let response = etl
+        .post(url("/query"))
+        .header("x-query-priority", "interactive")
+        .json(&query)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["data"]["rows"], json!([[2]]));
+
+    // Saved-query scope is limited to /q/{name}
+    let reports = https_client(addr, &ca, Some(ca.client_cert("reports")));
+    let response = reports
+        .post(url("/query"))
+        .json(&query)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+    let response = reports.get(url("/q/one")).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["data"]["rows"], json!([[1]]));
+
+    // Only admin scope reads the query history
+    let response = alice.get(url("/admin/history")).send().await.unwrap();
+    assert_eq!(response.status(), 403);
+    let ops = https_client(addr, &ca, Some(ca.client_cert("ops")));
+    let response = ops.get(url("/admin/history")).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+
+    // Certificates without a scope are rejected, even with a valid API key
+    let mallory = https_client(addr, &ca, Some(ca.client_cert("mallory")));
+    let response = mallory
+        .post(url("/query"))
+        .header("x-api-key", "secret")
+        .json(&query)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+
+    // Certificates from another CA fail the handshake
+    let other_ca = TestCa::new("Other CA");
+    let outsider = https_client(addr, &ca, Some(other_ca.client_cert("alice")))
+        .post(url("/query"))
+        .json(&query)
+        .send()
+        .await;
+    assert!(outsider.is_err());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_mtls_optional_client_certificate() {
+    let dir = std::env::temp_dir().join(format!("rsduck-mtls-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    let client_ca_path = dir.join("clients.pem");
+    let ca = TestCa::new("Test CA");
+    ca.write_server_cert(&cert_path, &key_path);
+    std::fs::write(&client_ca_path, ca.pem()).unwrap();
+
+    let args = Args::try_parse_from([
+        "rsduck",
+        "--api-key",
+        "secret",
+        "--tls-cert",
+        cert_path.to_str().unwrap(),
+        "--tls-key",
+        key_path.to_str().unwrap(),
+        "--tls-client-ca",
+        client_ca_path.to_str().unwrap(),
+        "--tls-client-cert-optional",
+    ])
+    .unwrap();
+    let (addr, _tls) = start_tls_server(&args).await;
+    let url = format!("https://localhost:{}/query", addr.port());
+    let query = json!({ "sql": "SELECT 1" });
+
+    // Without a certificate the API key is checked
+    let anonymous = https_client(addr, &ca, None);
+    let response = anonymous.post(&url).json(&query).send().await.unwrap();
+    assert_eq!(response.status(), 401);
+    let response = anonymous
+        .post(&url)
+        .header("x-api-key", "secret")
+        .json(&query)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    // Without --tls-client-scope every verified certificate has full scope
+    let response = https_client(addr, &ca, Some(ca.client_cert("anyone")))
+        .post(&url)
+        .json(&query)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_unix_socket_listener() {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let dir = std::env::temp_dir().join(format!("rsduck-uds-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket_path = dir.join("rsduck.sock");
+    let args = Args::try_parse_from([
+        "rsduck",
+        "--unix-socket",
+        socket_path.to_str().unwrap(),
+        "--unix-socket-mode",
+        "600",
+    ])
+    .unwrap();
+    assert!(Args::try_parse_from(["rsduck", "--unix-socket-mode", "999"]).is_err());
+
+    let listener = rsduck::HttpListener::bind(&args).await.unwrap();
+    assert_eq!(listener.socket_path(), Some(socket_path.as_path()));
+    assert_eq!(listener.tcp_addr(), None);
+    let mode = std::fs::metadata(&socket_path)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    // A second server cannot take over a socket that is in use
+    assert!(rsduck::HttpListener::bind(&args).await.is_err());
+
+    let rsduck::HttpListener::Unix { listener, .. } = listener else {
+        panic!("Expected a Unix socket listener");
+    };
+    let state = AppState::new(&args).expect("Failed to create app state");
+    let app = rsduck::unix_socket_app(create_test_app(state.clone()));
+    let server = tokio::spawn(async move { axum::serve(listener, app.into_make_service()).await });
+
+    let mut stream = tokio::net::UnixStream::connect(&socket_path).await.unwrap();
+    stream
+        .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("\"healthy\""));
+
+    // Clients of the socket share a fixed local identity
+    let mut stream = tokio::net::UnixStream::connect(&socket_path).await.unwrap();
+    let body = r#"{"sql": "SELECT 42"}"#;
+    stream
+        .write_all(
+            format!(
+                "POST /query HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    let history = state
+        .history
+        .as_ref()
+        .unwrap()
+        .query(&rsduck::HistoryFilter::default());
+    assert_eq!(history.entries[0].client, "ip:127.0.0.1");
+
+    // A socket file left behind by a stopped server is replaced
+    server.abort();
+    let _ = server.await;
+    let listener = rsduck::HttpListener::bind(&args).await.unwrap();
+    assert_eq!(listener.socket_path(), Some(socket_path.as_path()));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_inherited_listener_sockets() {
+    use std::os::fd::IntoRawFd;
+
+    let dir = std::env::temp_dir().join(format!("rsduck-uds-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket_path = dir.join("activated.sock");
+
+    // Sockets are told apart the way those passed through LISTEN_FDS are
+    let unix = std::os::unix::net::UnixListener::bind(&socket_path).unwrap();
+    let listener = unsafe { rsduck::HttpListener::from_raw_fd(unix.into_raw_fd()) }.unwrap();
+    assert!(matches!(listener, rsduck::HttpListener::Unix { .. }));
+    assert_eq!(
+        listener.to_string(),
+        format!("unix:{}", socket_path.display())
+    );
+    // The socket file belongs to whoever passed the socket
+    assert_eq!(listener.socket_path(), None);
+
+    let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = tcp.local_addr().unwrap();
+    let listener = unsafe { rsduck::HttpListener::from_raw_fd(tcp.into_raw_fd()) }.unwrap();
+    assert_eq!(listener.tcp_addr(), Some(addr));
+
+    let rsduck::HttpListener::Tcp(listener) = listener else {
+        panic!("Expected a TCP listener");
+    };
+    let state = AppState::new(&default_args()).expect("Failed to create app state");
+    let app = create_test_app(state);
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    let response = reqwest::get(format!("http://{}/health", addr))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_pg_wire_and_flight_listeners_stop_on_shutdown() {
+    let state = AppState::new(&default_args()).expect("Failed to create app state");
+    let pg_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let pg_port = pg_listener.local_addr().unwrap().port();
+    let pg_server = tokio::spawn(rsduck::pgwire::serve(pg_listener, state.clone()));
+    let flight_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let flight_port = flight_listener.local_addr().unwrap().port();
+    let flight_server = tokio::spawn(rsduck::flight::serve(flight_listener, state.clone()));
+
+    let client = pg_connect(pg_port, None).await.unwrap();
+    client.simple_query("SELECT 1").await.unwrap();
+    let mut flight_client = flight_connect(flight_port).await;
+    let info = flight_client
+        .execute("SELECT 1 AS n".to_string(), None)
+        .await
+        .unwrap();
+    flight_fetch(&mut flight_client, info).await;
+
+    // Idle sessions are closed, so both listeners finish well within the drain timeout
+    state.shutdown.begin();
+    let timeout = std::time::Duration::from_secs(5);
+    tokio::time::timeout(timeout, pg_server)
+        .await
+        .expect("pgwire listener kept running")
+        .unwrap()
+        .unwrap();
+    tokio::time::timeout(timeout, flight_server)
+        .await
+        .expect("Flight SQL listener kept running")
+        .unwrap()
+        .unwrap();
+
+    assert!(client.simple_query("SELECT 1").await.is_err());
+    assert!(pg_connect(pg_port, None).await.is_err());
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown_drains_and_interrupts() {
+    let state = AppState::new(&default_args()).expect("Failed to create app state");
+    let app = create_test_app(state.clone());
+    let shutdown = rsduck::Shutdown::new();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown.started())
+            .into_future(),
+    );
+
+    let query = |sql: &str| {
+        let request = reqwest::Client::new()
+            .post(format!("http://{}/query", addr))
+            .json(&json!({ "sql": sql }));
+        tokio::spawn(request.send())
+    };
+    let quick = query("SELECT sum(hash(a.range * b.range)) AS h FROM range(2000) a, range(2000) b");
+    let endless =
+        query("SELECT sum(hash(a.range * b.range)) FROM range(100000) a, range(100000) b");
+    while rsduck::shutdown::running_statements(&state) < 2 {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let started = std::time::Instant::now();
+    shutdown.begin();
+    rsduck::shutdown::drain(server, &state, std::time::Duration::from_secs(3))
+        .await
+        .unwrap();
+    assert!(started.elapsed() < std::time::Duration::from_secs(8));
+    assert_eq!(rsduck::shutdown::running_statements(&state), 0);
+
+    // Queries finishing within the drain timeout complete normally
+    let response = quick.await.unwrap().unwrap();
+    assert_eq!(response.status(), 200);
+    // Queries still running at the timeout are interrupted and answered with an error
+    let response = endless.await.unwrap().unwrap();
+    assert_ne!(response.status(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["success"], false);
+
+    // The listener is closed
+    assert!(
+        reqwest::get(format!("http://{}/health", addr))
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_shutdown_checkpoints_and_closes_pools() {
+    let db_path =
+        std::env::temp_dir().join(format!("rsduck-shutdown-{}.duckdb", uuid::Uuid::new_v4()));
+    let wal_path = db_path.with_extension("duckdb.wal");
+    let mut args = default_args();
+    args.readwrite = true;
+    let conn = duckdb::Connection::open(&db_path).unwrap();
+    let state = AppState::from_connection(&args, conn).expect("Failed to create app state");
+
+    state
+        .pool
+        .get()
+        .unwrap()
+        .execute_batch("CREATE TABLE answers AS SELECT 42 AS answer")
+        .unwrap();
+    assert!(std::fs::metadata(&wal_path).is_ok_and(|wal| wal.len() > 0));
+
+    assert!(rsduck::shutdown::close_database(&state) > 0);
+    assert!(std::fs::metadata(&wal_path).map_or(true, |wal| wal.len() == 0));
+    assert!(state.connections.is_closed());
+    assert_eq!(state.connections.open_connections(), 0);
+    assert!(
+        state
+            .pool
+            .get_timeout(std::time::Duration::from_millis(100))
+            .is_err()
+    );
+
+    let reopened = duckdb::Connection::open(&db_path).unwrap();
+    let answer: i32 = reopened
+        .query_row("SELECT answer FROM answers", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(answer, 42);
+
+    drop(reopened);
+    let _ = std::fs::remove_file(&db_path);
+    let _ = std::fs::remove_file(&wal_path);
+}
+
+fn default_args() -> Args {
+    Args::parse_from(["rsduck"])
+}
+
+fn create_test_app(state: AppState) -> axum::Router {
+    rsduck::router(state, rsduck::RouterOptions::default())
+}
+
+/// Start a PostgreSQL wire protocol listener on an ephemeral port
+async fn start_pg_server(state: AppState) -> u16 {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind PostgreSQL listener");
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(rsduck::pgwire::serve(listener, state));
+    port
+}
+
+async fn pg_connect(
+    port: u16,
+    password: Option<&str>,
+) -> Result<tokio_postgres::Client, tokio_postgres::Error> {
+    let mut config = tokio_postgres::Config::new();
+    config
+        .host("127.0.0.1")
+        .port(port)
+        .user("test")
+        .dbname("rsduck");
+    if let Some(password) = password {
+        config.password(password);
+    }
+    let (client, connection) = config.connect(tokio_postgres::NoTls).await?;
+    tokio::spawn(connection);
+    Ok(client)
+}
+
+/// Start an Arrow Flight SQL listener on an ephemeral port
+async fn start_flight_server(state: AppState) -> u16 {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind Flight SQL listener");
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(rsduck::flight::serve(listener, state));
+    port
+}
+
+async fn flight_connect(
+    port: u16,
+) -> arrow_flight::sql::client::FlightSqlServiceClient<tonic::transport::Channel> {
+    let channel = tonic::transport::Channel::from_shared(format!("http://127.0.0.1:{}", port))
+        .unwrap()
+        .connect()
+        .await
+        .expect("Failed to connect");
+    arrow_flight::sql::client::FlightSqlServiceClient::new(channel)
+}
+
+/// Fetch every record batch of every endpoint in `info`
+async fn flight_fetch(
+    client: &mut arrow_flight::sql::client::FlightSqlServiceClient<tonic::transport::Channel>,
+    info: arrow_flight::FlightInfo,
+) -> Vec<duckdb::arrow::array::RecordBatch> {
+    use futures::TryStreamExt;
+
+    let mut batches = Vec::new();
+    for endpoint in info.endpoint {
+        let ticket = endpoint.ticket.expect("Endpoint without ticket");
+        let stream = client.do_get(ticket).await.expect("DoGet failed");
+        batches.extend(
+            stream
+                .try_collect::<Vec<_>>()
+                .await
+                .expect("Reading batches failed"),
+        );
+    }
+    batches
+}
+/// Run a query over a WebSocket session, returning its chunks and the final `result` or `error`
+async fn ws_query(ws: &mut TestWebSocket, id: &str, sql: &str) -> (Vec<Value>, Value) {
+    ws.send_json(&json!({ "type": "query", "id": id, "sql": sql }))
+        .await;
+    let mut chunks = Vec::new();
+    loop {
+        let message: Value = ws.receive_json().await;
+        match message["type"].as_str() {
+            Some("chunk") => chunks.push(message),
+            Some("progress") => continue,
+            _ => return (chunks, message),
+        }
+    }
+}
+
+/// Certificate authority issuing test server and client certificates
+struct TestCa {
+    issuer: rcgen::CertifiedIssuer<'static, rcgen::KeyPair>,
+}
+
+impl TestCa {
+    fn new(name: &str) -> Self {
+        let mut params = rcgen::CertificateParams::default();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params.key_usages = vec![
+            rcgen::KeyUsagePurpose::KeyCertSign,
+            rcgen::KeyUsagePurpose::DigitalSignature,
+        ];
+        let key = rcgen::KeyPair::generate().unwrap();
+        Self {
+            issuer: rcgen::CertifiedIssuer::self_signed(params, key).unwrap(),
+        }
+    }
+
+    fn pem(&self) -> String {
+        self.issuer.pem()
+    }
+
+    /// Issue a certificate, returning its PEM certificate and private key
+    fn issue(
+        &self,
+        common_name: &str,
+        names: Vec<String>,
+        usage: rcgen::ExtendedKeyUsagePurpose,
+    ) -> (String, String) {
+        let mut params = rcgen::CertificateParams::new(names).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, common_name);
+        params.extended_key_usages = vec![usage];
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &self.issuer).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    fn write_server_cert(&self, cert_path: &std::path::Path, key_path: &std::path::Path) {
+        let (cert, key) = self.issue(
+            "localhost",
+            vec!["localhost".to_string()],
+            rcgen::ExtendedKeyUsagePurpose::ServerAuth,
+        );
+        std::fs::write(cert_path, cert).unwrap();
+        std::fs::write(key_path, key).unwrap();
+    }
+
+    fn client_cert(&self, common_name: &str) -> reqwest::Identity {
+        let (cert, key) = self.issue(
+            common_name,
+            Vec::new(),
+            rcgen::ExtendedKeyUsagePurpose::ClientAuth,
+        );
+        reqwest::Identity::from_pem(format!("{}{}", cert, key).as_bytes()).unwrap()
+    }
+}
+
+/// Serve the API over TLS on an ephemeral port
+async fn start_tls_server(
+    args: &Args,
+) -> (
+    std::net::SocketAddr,
+    std::sync::Arc<rsduck::ReloadableTlsConfig>,
+) {
+    let state = AppState::new(args).expect("Failed to create app state");
+    let app = create_test_app(state);
+    let config = rsduck::TlsConfig::from_args(args).expect("TLS arguments missing");
+    let tls = std::sync::Arc::new(rsduck::ReloadableTlsConfig::new(config).unwrap());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let listener = rsduck::TlsListener::new(listener, tls.clone()).unwrap();
+    let make_service = rsduck::TlsMakeService::new(app, rsduck::ClientScopes::from_args(args));
+    tokio::spawn(async move { axum::serve(listener, make_service).await });
+    (addr, tls)
+}
+
+/// HTTPS client trusting only `ca`, resolving `localhost` to `addr`
+fn https_client(
+    addr: std::net::SocketAddr,
+    ca: &TestCa,
+    identity: Option<reqwest::Identity>,
+) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .use_rustls_tls()
+        .tls_built_in_root_certs(false)
+        .add_root_certificate(reqwest::Certificate::from_pem(ca.pem().as_bytes()).unwrap())
+        .resolve("localhost", addr);
+    if let Some(identity) = identity {
+        builder = builder.identity(identity);
+    }
+    builder.build().unwrap()
 }